@@ -6,11 +6,18 @@ use std::env;
 
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rustc-check-cfg=cfg(has_specialisation)");
+    println!("cargo:rustc-check-cfg=cfg(threadsafe)");
     if let Some(channel) = version_check::Channel::read() {
         if channel.supports_features() {
             println!("cargo:rustc-cfg=has_specialisation");
         }
     }
+    // Function pointers are only allowed in `const fn` from Rust 1.61.
+    println!("cargo:rustc-check-cfg=cfg(has_const_fn_ptr)");
+    if version_check::is_min_version("1.61.0").unwrap_or(false) {
+        println!("cargo:rustc-cfg=has_const_fn_ptr");
+    }
     let pkgname = env::var("CARGO_PKG_NAME").expect("Cargo didn't set the CARGO_PKG_NAME env var!");
     let test_rc = env::var("IM_TEST_RC").is_ok();
     match pkgname.as_str() {
@@ -0,0 +1,218 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A derive macro for the `Diff` trait of the [`im`][im] and
+//! [`im-rc`][im-rc] crates.
+//!
+//! Don't use this crate directly: turn on the `derive` feature of `im`
+//! or `im-rc`, and use the derive macro from there, as
+//! `im::diff::Diff`.
+//!
+//! [im]: https://crates.io/crates/im
+//! [im-rc]: https://crates.io/crates/im-rc
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Error, Fields, Ident, Lit, Meta,
+    NestedMeta, Path,
+};
+
+/// Derive `Diff` for a struct with named fields, field by field.
+///
+/// This declares an enum with a variant for each field, named after the
+/// field in `UpperCamelCase` and holding the changes to that field, and
+/// implements `Diff` for the struct with that enum as its `Change` type.
+/// Every field's type must implement `Diff` itself, and the struct's
+/// changes list the fields which changed in the order they're declared.
+///
+/// The enum is called the struct's name followed by `Change`, and has
+/// the struct's visibility. The struct can be given these options in a
+/// `#[diff(...)]` attribute:
+///
+/// * `change = "Name"` names the enum something else.
+/// * `derive(Trait, ...)` derives traits for the enum, such as `Debug`
+///   and `PartialEq`.
+/// * `crate = "path"` gives the path to the crate defining `Diff`, which
+///   is `::im` unless it's given, so `im-rc` users need
+///   `#[diff(crate = "::im_rc")]`.
+///
+/// A field marked `#[diff(skip)]` is left out of the diff, though
+/// `PartialEq` may still compare it.
+#[proc_macro_derive(Diff, attributes(diff))]
+pub fn derive_diff(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(output) => output.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+struct Options {
+    krate: Path,
+    change: Ident,
+    derives: Vec<Path>,
+}
+
+fn expand(input: &DeriveInput) -> Result<TokenStream2, Error> {
+    let fields = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => &fields.named,
+            _ => {
+                return Err(Error::new_spanned(
+                    &input.ident,
+                    "Diff can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "Diff can only be derived for structs",
+            ))
+        }
+    };
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "Diff can't be derived for generic structs",
+        ));
+    }
+
+    let Options {
+        krate,
+        change,
+        derives,
+    } = options(input)?;
+    let name = &input.ident;
+    let vis = &input.vis;
+    let mut names = Vec::new();
+    let mut variants = Vec::new();
+    let mut types = Vec::new();
+    let mut docs = Vec::new();
+    for field in fields {
+        if is_skipped(&field.attrs)? {
+            continue;
+        }
+        // A named field always has a name.
+        let field_name = field.ident.as_ref().unwrap();
+        variants.push(variant_name(field_name));
+        docs.push(format!(
+            "Changes to the `{}` field.",
+            field_name.to_string().trim_start_matches("r#")
+        ));
+        names.push(field_name);
+        types.push(&field.ty);
+    }
+    let doc = format!("The changes to a [`{0}`]({0}), field by field.", name);
+
+    Ok(quote! {
+        #[doc = #doc]
+        #[derive(#(#derives),*)]
+        #vis enum #change {
+            #(
+                #[doc = #docs]
+                #variants(::std::vec::Vec<<#types as #krate::diff::Diff>::Change>),
+            )*
+        }
+
+        impl #krate::diff::Diff for #name {
+            type Change = #change;
+
+            fn changes(&self, other: &Self) -> ::std::vec::Vec<Self::Change> {
+                let mut changes = ::std::vec::Vec::new();
+                #(
+                    let field = #krate::diff::Diff::changes(&self.#names, &other.#names);
+                    if !field.is_empty() {
+                        changes.push(#change::#variants(field));
+                    }
+                )*
+                changes
+            }
+        }
+    })
+}
+
+/// Read the options given to the struct.
+fn options(input: &DeriveInput) -> Result<Options, Error> {
+    let mut options = Options {
+        krate: parse_quote!(::im),
+        change: format_ident!("{}Change", input.ident),
+        derives: Vec::new(),
+    };
+    for nested in diff_attributes(&input.attrs)? {
+        match nested {
+            NestedMeta::Meta(Meta::NameValue(ref pair)) if pair.path.is_ident("crate") => {
+                options.krate = string(&pair.lit)?.parse()?;
+            }
+            NestedMeta::Meta(Meta::NameValue(ref pair)) if pair.path.is_ident("change") => {
+                options.change = string(&pair.lit)?.parse()?;
+            }
+            NestedMeta::Meta(Meta::List(ref list)) if list.path.is_ident("derive") => {
+                for derive in &list.nested {
+                    match derive {
+                        NestedMeta::Meta(Meta::Path(path)) => options.derives.push(path.clone()),
+                        other => {
+                            return Err(Error::new_spanned(other, "expected a trait to derive"))
+                        }
+                    }
+                }
+            }
+            other => return Err(Error::new_spanned(other, "unknown option for a struct")),
+        }
+    }
+    Ok(options)
+}
+
+/// Test whether a field is marked `#[diff(skip)]`.
+fn is_skipped(attrs: &[Attribute]) -> Result<bool, Error> {
+    let mut skip = false;
+    for nested in diff_attributes(attrs)? {
+        match nested {
+            NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("skip") => skip = true,
+            other => return Err(Error::new_spanned(other, "unknown option for a field")),
+        }
+    }
+    Ok(skip)
+}
+
+/// Collect the options from every `#[diff(...)]` attribute.
+fn diff_attributes(attrs: &[Attribute]) -> Result<Vec<NestedMeta>, Error> {
+    let mut options = Vec::new();
+    for attr in attrs {
+        if !attr.path.is_ident("diff") {
+            continue;
+        }
+        match attr.parse_meta()? {
+            Meta::List(list) => options.extend(list.nested),
+            other => return Err(Error::new_spanned(other, "expected `#[diff(...)]`")),
+        }
+    }
+    Ok(options)
+}
+
+fn string(lit: &Lit) -> Result<syn::LitStr, Error> {
+    match lit {
+        Lit::Str(string) => Ok(string.clone()),
+        other => Err(Error::new_spanned(other, "expected a string")),
+    }
+}
+
+/// Turn a field name into a variant name, in `UpperCamelCase`.
+fn variant_name(field: &Ident) -> Ident {
+    let field = field.to_string();
+    let field = field.trim_start_matches("r#");
+    let mut name = String::new();
+    for word in field.split('_') {
+        let mut chars = word.chars();
+        if let Some(first) = chars.next() {
+            name.extend(first.to_uppercase());
+            name.push_str(chars.as_str());
+        }
+    }
+    format_ident!("{}", name)
+}
@@ -6,15 +6,26 @@ use std::hash::{BuildHasher, Hash};
 
 use ::arbitrary::{size_hint, Arbitrary, Result, Unstructured};
 
+use crate::config::{HashLevelSize, OrdChunkSize, VectorChunkSize};
+use crate::hashset::Value as HashValue;
+use crate::ordset::Value as OrdValue;
 use crate::{HashMap, HashSet, OrdMap, OrdSet, Vector};
 
-impl<'a, A: Arbitrary<'a> + Clone> Arbitrary<'a> for Vector<A> {
+impl<'a, A, N> Arbitrary<'a> for Vector<A, N>
+where
+    A: Arbitrary<'a> + Clone,
+    N: VectorChunkSize<A> + 'static,
+{
     fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
-        u.arbitrary_iter()?.collect()
+        u.arbitrary_iter()?
+            .collect::<Result<Vec<_>>>()
+            .map(Self::from_iter_with_node_size)
     }
 
     fn arbitrary_take_rest(u: Unstructured<'a>) -> Result<Self> {
-        u.arbitrary_take_rest_iter()?.collect()
+        u.arbitrary_take_rest_iter()?
+            .collect::<Result<Vec<_>>>()
+            .map(Self::from_iter_with_node_size)
     }
 
     fn size_hint(depth: usize) -> (usize, Option<usize>) {
@@ -24,13 +35,22 @@ impl<'a, A: Arbitrary<'a> + Clone> Arbitrary<'a> for Vector<A> {
     }
 }
 
-impl<'a, K: Arbitrary<'a> + Ord + Clone, V: Arbitrary<'a> + Clone> Arbitrary<'a> for OrdMap<K, V> {
+impl<'a, K, V, N> Arbitrary<'a> for OrdMap<K, V, N>
+where
+    K: Arbitrary<'a> + Ord + Clone,
+    V: Arbitrary<'a> + Clone,
+    N: OrdChunkSize<(K, V)> + 'static,
+{
     fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
-        u.arbitrary_iter()?.collect()
+        u.arbitrary_iter()?
+            .collect::<Result<Vec<_>>>()
+            .map(Self::from_iter_with_node_size)
     }
 
     fn arbitrary_take_rest(u: Unstructured<'a>) -> Result<Self> {
-        u.arbitrary_take_rest_iter()?.collect()
+        u.arbitrary_take_rest_iter()?
+            .collect::<Result<Vec<_>>>()
+            .map(Self::from_iter_with_node_size)
     }
 
     fn size_hint(depth: usize) -> (usize, Option<usize>) {
@@ -40,13 +60,21 @@ impl<'a, K: Arbitrary<'a> + Ord + Clone, V: Arbitrary<'a> + Clone> Arbitrary<'a>
     }
 }
 
-impl<'a, A: Arbitrary<'a> + Ord + Clone> Arbitrary<'a> for OrdSet<A> {
+impl<'a, A, N> Arbitrary<'a> for OrdSet<A, N>
+where
+    A: Arbitrary<'a> + Ord + Clone,
+    N: OrdChunkSize<OrdValue<A>> + 'static,
+{
     fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
-        u.arbitrary_iter()?.collect()
+        u.arbitrary_iter()?
+            .collect::<Result<Vec<_>>>()
+            .map(Self::from_iter_with_node_size)
     }
 
     fn arbitrary_take_rest(u: Unstructured<'a>) -> Result<Self> {
-        u.arbitrary_take_rest_iter()?.collect()
+        u.arbitrary_take_rest_iter()?
+            .collect::<Result<Vec<_>>>()
+            .map(Self::from_iter_with_node_size)
     }
 
     fn size_hint(depth: usize) -> (usize, Option<usize>) {
@@ -56,18 +84,23 @@ impl<'a, A: Arbitrary<'a> + Ord + Clone> Arbitrary<'a> for OrdSet<A> {
     }
 }
 
-impl<'a, K, V, S> Arbitrary<'a> for HashMap<K, V, S>
+impl<'a, K, V, S, N> Arbitrary<'a> for HashMap<K, V, S, N>
 where
     K: Arbitrary<'a> + Hash + Eq + Clone,
     V: Arbitrary<'a> + Clone,
     S: BuildHasher + Default + 'static,
+    N: HashLevelSize<(K, V)> + 'static,
 {
     fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
-        u.arbitrary_iter()?.collect()
+        u.arbitrary_iter()?
+            .collect::<Result<Vec<_>>>()
+            .map(Self::from_iter_with_node_size)
     }
 
     fn arbitrary_take_rest(u: Unstructured<'a>) -> Result<Self> {
-        u.arbitrary_take_rest_iter()?.collect()
+        u.arbitrary_take_rest_iter()?
+            .collect::<Result<Vec<_>>>()
+            .map(Self::from_iter_with_node_size)
     }
 
     fn size_hint(depth: usize) -> (usize, Option<usize>) {
@@ -77,17 +110,22 @@ where
     }
 }
 
-impl<'a, A, S> Arbitrary<'a> for HashSet<A, S>
+impl<'a, A, S, N> Arbitrary<'a> for HashSet<A, S, N>
 where
     A: Arbitrary<'a> + Hash + Eq + Clone,
     S: BuildHasher + Default + 'static,
+    N: HashLevelSize<HashValue<A>> + 'static,
 {
     fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
-        u.arbitrary_iter()?.collect()
+        u.arbitrary_iter()?
+            .collect::<Result<Vec<_>>>()
+            .map(Self::from_iter_with_node_size)
     }
 
     fn arbitrary_take_rest(u: Unstructured<'a>) -> Result<Self> {
-        u.arbitrary_take_rest_iter()?.collect()
+        u.arbitrary_take_rest_iter()?
+            .collect::<Result<Vec<_>>>()
+            .map(Self::from_iter_with_node_size)
     }
 
     fn size_hint(depth: usize) -> (usize, Option<usize>) {
@@ -2,19 +2,166 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use typenum::*;
+//! Node sizes.
+//!
+//! Each collection takes the size of its nodes as an optional type
+//! parameter, given as a [`typenum`][typenum] number, which defaults to
+//! the sizes given here. Larger nodes make lookups and iteration faster
+//! and use less memory per value, while smaller nodes make updates to
+//! shared collections cheaper, because less has to be copied.
+//!
+//! ```
+//! # use im::{OrdMap, Vector};
+//! use im::config::{U128, U16};
+//!
+//! // Large values, updated often: keep B-tree nodes small.
+//! let mut map: OrdMap<i32, [u8; 256], U16> = OrdMap::with_node_size();
+//! map.insert(1, [0; 256]);
+//!
+//! // Bytes, mostly read: use large chunks.
+//! let bytes: Vector<u8, U128> = Vector::from_iter_with_node_size(0..=255);
+//! assert_eq!(Some(&255), bytes.last());
+//! ```
+//!
+//! Constructors which don't mention a node size, like `new`,
+//! `Default::default` and `FromIterator::from_iter`, always build
+//! collections of the default size, so that the compiler never has to
+//! guess one. Each collection has a `with_node_size` constructor, and
+//! a `from_iter_with_node_size` for building one from an iterator, for
+//! the other sizes.
+//!
+//! Collections with different node sizes are different types, and don't
+//! share structure with each other.
+//!
+//! The supported sizes are re-exported from this module, so you don't
+//! need to depend on `typenum` yourself.
+//!
+//! [typenum]: https://docs.rs/typenum/
 
-/// The branching factor of RRB-trees
-pub(crate) type VectorChunkSize = U64;
+use bitmaps::Bits;
+use sized_chunks::types::ChunkLength;
+use typenum::{Unsigned, U129, U17, U33, U65, U9};
 
-/// The branching factor of B-trees
-pub(crate) type OrdChunkSize = U64; // Must be an even number!
+use crate::nodes::{btree, hamt, rrb};
+use crate::util::PoolRef;
 
-/// The level size of HAMTs, in bits
-/// Branching factor is 2 ^ HashLevelSize.
-pub(crate) type HashLevelSize = U5;
+pub use typenum::{U128, U16, U3, U32, U4, U5, U6, U64, U8};
+
+mod sealed {
+    use typenum::*;
+
+    pub trait Sealed {}
+
+    macro_rules! sealed {
+        ($($size:ty),*) => {
+            $(impl Sealed for $size {})*
+        };
+    }
+
+    sealed!(U3, U4, U5, U6, U8, U16, U32, U64, U128);
+}
+
+/// The default branching factor of B-trees, used by
+/// [`OrdMap`][crate::OrdMap] and [`OrdSet`][crate::OrdSet].
+pub type DefaultOrdChunkSize = U64;
+
+/// A node size for B-trees.
+///
+/// This is implemented for the even numbers `U4`, `U8`, `U16`, `U32`,
+/// `U64` and `U128`.
+pub trait OrdChunkSize<A>: sealed::Sealed + ChunkLength<A> + Sized {
+    #[doc(hidden)]
+    type Branches: ChunkLength<Option<PoolRef<btree::Node<A, Self>>>>;
+}
+
+macro_rules! ord_chunk_sizes {
+    ($($size:ty => $branches:ty),*) => {
+        $(
+            impl<A> OrdChunkSize<A> for $size {
+                type Branches = $branches;
+            }
+        )*
+    };
+}
+
+ord_chunk_sizes!(U4 => U5, U8 => U9, U16 => U17, U32 => U33, U64 => U65, U128 => U129);
+
+/// The default chunk size of RRB-trees, used by [`Vector`][crate::Vector].
+pub type DefaultVectorChunkSize = U64;
+
+/// A chunk size for RRB-trees.
+///
+/// This is implemented for `U8`, `U16`, `U32`, `U64` and `U128`.
+pub trait VectorChunkSize<A>:
+    sealed::Sealed + ChunkLength<A> + ChunkLength<usize> + ChunkLength<rrb::Node<A, Self>> + Sized
+{
+}
+
+impl<A> VectorChunkSize<A> for U8 {}
+impl<A> VectorChunkSize<A> for U16 {}
+impl<A> VectorChunkSize<A> for U32 {}
+impl<A> VectorChunkSize<A> for U64 {}
+impl<A> VectorChunkSize<A> for U128 {}
+
+/// The default level size of hash array mapped tries, used by
+/// [`HashMap`][crate::HashMap] and [`HashSet`][crate::HashSet].
+pub type DefaultHashLevelSize = U5;
+
+/// A level size for hash array mapped tries, in bits.
+///
+/// Each node of the trie consumes this many bits of a value's hash, and
+/// so has room for 2<sup>n</sup> children. This is implemented for `U3`,
+/// `U4`, `U5` and `U6`, giving nodes of 8 to 64 children.
+pub trait HashLevelSize<A>: sealed::Sealed + Unsigned + Sized {
+    #[doc(hidden)]
+    type Width: Bits + ChunkLength<hamt::Entry<A, Self>>;
+}
+
+impl<A> HashLevelSize<A> for U3 {
+    type Width = U8;
+}
+impl<A> HashLevelSize<A> for U4 {
+    type Width = U16;
+}
+impl<A> HashLevelSize<A> for U5 {
+    type Width = U32;
+}
+impl<A> HashLevelSize<A> for U6 {
+    type Width = U64;
+}
+
+/// The largest number of values a hash map or set keeps in a flat array
+/// before switching to a HAMT.
+pub(crate) type HashSmallSize = U8;
 
 /// The size of per-instance memory pools if the `pool` feature is enabled.
 /// This is set to 0, meaning you have to opt in to using a pool by constructing
 /// with eg. `Vector::with_pool(pool)` even if the `pool` feature is enabled.
 pub(crate) const POOL_SIZE: usize = 0;
+
+#[cfg(test)]
+mod test {
+    use std::collections::hash_map::RandomState;
+    use std::iter::FromIterator;
+
+    use crate::{HashMap, HashSet, OrdMap, OrdSet, Vector};
+
+    // None of these should need a type annotation for the node size.
+    #[test]
+    fn constructors_infer_default_node_size() {
+        let vector = Vector::from_iter(0..3);
+        assert_eq!(3, vector.len());
+        let mut ord_map = OrdMap::default();
+        ord_map.insert(1, 2);
+        assert_eq!(1, ord_map.len());
+        let mut ord_set = OrdSet::default();
+        ord_set.insert(1);
+        assert_eq!(1, ord_set.len());
+        let mut hash_map = HashMap::with_hasher(RandomState::new());
+        hash_map.insert(1, 2);
+        assert_eq!(1, hash_map.len());
+        let mut hash_set = HashSet::with_hasher(RandomState::new());
+        hash_set.insert(1);
+        assert_eq!(1, hash_set.len());
+    }
+}
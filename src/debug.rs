@@ -0,0 +1,462 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Tools for inspecting the internal structure of data structures.
+//!
+//! Every collection has a `stats()` method, which walks its tree and
+//! returns a [`Stats`][Stats] summary of its shape: how deep it is, how
+//! many nodes of each kind it has and how full they are.
+//!
+//! To see the shape for yourself, add one or more collections to a
+//! [`Graph`][Graph] and render it with [Graphviz](https://graphviz.org/).
+//! Nodes which are shared between the collections in the graph are
+//! highlighted, which makes it easy to see how much structure two
+//! snapshots have in common.
+//!
+//! Every collection also has a `check_invariants()` method, which walks
+//! its tree and verifies that it's internally consistent, returning an
+//! [`InvariantViolation`][InvariantViolation] describing the first
+//! problem it finds. This is meant for fuzzers and property tests: if
+//! you ever see one of these from a collection built only through its
+//! public API, you've found a bug in this crate.
+//!
+//! This module requires the `debug` feature flag.
+//!
+//! # Examples
+//!
+//! ```
+//! # use im::debug::Graph;
+//! # use im::Vector;
+//! let first: Vector<i64> = (0..1000).collect();
+//! let mut second = first.clone();
+//! second.set(0, -1);
+//!
+//! let stats = first.stats();
+//! assert_eq!(1000, stats.len);
+//! assert!(stats.fill_factor() > 0.9);
+//!
+//! let mut graph = Graph::new();
+//! graph.add("first", &first).add("second", &second);
+//! let dot = graph.to_string();
+//! assert!(dot.starts_with("digraph"));
+//! ```
+
+use std::collections::HashMap as StdHashMap;
+use std::collections::HashSet as StdHashSet;
+use std::error;
+use std::fmt::{Display, Error, Formatter};
+
+pub(crate) use self::private::{NodeInfo, NodeKind, Walk, Walker};
+
+mod private {
+    /// The kind of a node in a data structure's tree.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum NodeKind {
+        /// A node containing only values.
+        Leaf,
+        /// A node containing references to other nodes.
+        Branch,
+        /// An RRB branch node with a size table.
+        Relaxed,
+        /// A HAMT node holding values whose hashes collide.
+        Collision,
+    }
+
+    /// A description of a node in a data structure's tree.
+    #[derive(Clone, Copy, Debug)]
+    pub struct NodeInfo {
+        pub kind: NodeKind,
+        /// The distance from the collection itself, which is at depth 0.
+        pub depth: usize,
+        pub entries: usize,
+        pub capacity: usize,
+    }
+
+    /// A visitor over the nodes of a data structure's tree.
+    pub trait Walker {
+        /// Visit a node at `address`, whose parent is at `parent`, or 0
+        /// for the top level nodes of a collection.
+        ///
+        /// Returns `true` if the node's children should be visited.
+        fn node(&mut self, parent: usize, address: usize, node: NodeInfo) -> bool;
+    }
+
+    pub trait Walk {
+        fn walk(&self, walker: &mut dyn Walker);
+    }
+}
+
+/// A data structure which can be inspected by this module.
+///
+/// This is implemented for all the collections in this crate.
+pub trait Inspect: Walk {}
+
+impl<A: Walk> Inspect for A {}
+
+/// A summary of the shape of a data structure's tree.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of values in the data structure.
+    pub len: usize,
+    /// The number of levels of nodes in the tree.
+    pub depth: usize,
+    /// The total number of nodes in the tree.
+    pub nodes: usize,
+    /// The number of nodes containing only values.
+    pub leaf_nodes: usize,
+    /// The number of nodes containing references to other nodes,
+    /// including relaxed nodes.
+    pub branch_nodes: usize,
+    /// The number of RRB branch nodes with a size table, which are
+    /// slower to index into than dense nodes. Always 0 for anything but
+    /// a [`Vector`][Vector].
+    ///
+    /// [Vector]: ../vector/struct.Vector.html
+    pub relaxed_nodes: usize,
+    /// The number of HAMT nodes holding values with colliding hashes.
+    /// Always 0 for anything but a [`HashMap`][HashMap] or
+    /// [`HashSet`][HashSet].
+    ///
+    /// [HashMap]: ../hashmap/struct.HashMap.html
+    /// [HashSet]: ../hashset/struct.HashSet.html
+    pub collision_nodes: usize,
+    /// The number of slots in use across all nodes.
+    pub entries: usize,
+    /// The number of slots available across all nodes.
+    pub capacity: usize,
+    /// The number of nodes with each number of slots in use, so that
+    /// `occupancy[n]` is the number of nodes with `n` entries.
+    pub occupancy: Vec<usize>,
+}
+
+impl Stats {
+    pub(crate) fn collect<C>(len: usize, collection: &C) -> Self
+    where
+        C: Walk + ?Sized,
+    {
+        let mut stats = Stats {
+            len,
+            ..Default::default()
+        };
+        collection.walk(&mut stats);
+        stats
+    }
+
+    /// Get the fraction of available slots in use across all nodes, or
+    /// 1 if there are no nodes.
+    #[must_use]
+    pub fn fill_factor(&self) -> f64 {
+        if self.capacity == 0 {
+            1.0
+        } else {
+            self.entries as f64 / self.capacity as f64
+        }
+    }
+}
+
+impl Walker for Stats {
+    fn node(&mut self, _parent: usize, _address: usize, node: NodeInfo) -> bool {
+        self.depth = self.depth.max(node.depth);
+        self.nodes += 1;
+        match node.kind {
+            NodeKind::Leaf => self.leaf_nodes += 1,
+            NodeKind::Branch => self.branch_nodes += 1,
+            NodeKind::Relaxed => {
+                self.branch_nodes += 1;
+                self.relaxed_nodes += 1;
+            }
+            NodeKind::Collision => self.collision_nodes += 1,
+        }
+        self.entries += node.entries;
+        self.capacity += node.capacity;
+        if self.occupancy.len() <= node.entries {
+            self.occupancy.resize(node.entries + 1, 0);
+        }
+        self.occupancy[node.entries] += 1;
+        true
+    }
+}
+
+/// A description of a broken internal invariant in a data structure,
+/// returned by the `check_invariants()` method of each collection.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvariantViolation {
+    structure: &'static str,
+    path: Vec<usize>,
+    message: String,
+}
+
+impl InvariantViolation {
+    pub(crate) fn new(structure: &'static str, path: &[usize], message: String) -> Self {
+        InvariantViolation {
+            structure,
+            path: path.to_vec(),
+            message,
+        }
+    }
+
+    /// The kind of tree in which the violation was found: a B-tree for
+    /// ordered collections, a HAMT for hashed collections, and an RRB
+    /// tree for vectors.
+    #[must_use]
+    pub fn structure(&self) -> &str {
+        self.structure
+    }
+
+    /// The path to the offending node from the root of the tree, as the
+    /// index of the child taken at each level.
+    #[must_use]
+    pub fn path(&self) -> &[usize] {
+        &self.path
+    }
+
+    /// A description of the violation.
+    #[must_use]
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl Display for InvariantViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(
+            f,
+            "{} invariant violated at {:?}: {}",
+            self.structure, self.path, self.message
+        )
+    }
+}
+
+impl error::Error for InvariantViolation {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Id {
+    Root(usize),
+    Node(usize),
+}
+
+impl Display for Id {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match self {
+            Id::Root(index) => write!(f, "root{}", index),
+            Id::Node(address) => write!(f, "node{:x}", address),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct GraphNode {
+    address: usize,
+    info: NodeInfo,
+    root: usize,
+    shared: bool,
+}
+
+/// A Graphviz rendering of the trees of one or more data structures.
+///
+/// The graph is written in the DOT language by its
+/// [`Display`][Display] implementation. Each data structure added to it
+/// is drawn as a box with its name, pointing to the nodes of its tree.
+/// Nodes reachable from more than one data structure are filled in.
+///
+/// Nodes are identified by their address in memory, so a graph is only
+/// meaningful as long as none of the data structures added to it have
+/// been changed or dropped.
+///
+/// [Display]: https://doc.rust-lang.org/std/fmt/trait.Display.html
+#[derive(Clone, Debug, Default)]
+pub struct Graph {
+    roots: Vec<(String, usize)>,
+    nodes: Vec<GraphNode>,
+    index: StdHashMap<usize, usize>,
+    edges: Vec<(Id, usize)>,
+    seen_edges: StdHashSet<(Id, usize)>,
+}
+
+impl Graph {
+    /// Construct an empty graph.
+    #[must_use]
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add a data structure to the graph under the given name.
+    pub fn add<C>(&mut self, name: &str, collection: &C) -> &mut Self
+    where
+        C: Inspect + ?Sized,
+    {
+        let mut stats = Stats::default();
+        collection.walk(&mut stats);
+        self.roots.push((name.to_string(), stats.nodes));
+        let root = self.roots.len() - 1;
+        collection.walk(&mut GraphWalker { graph: self, root });
+        self
+    }
+}
+
+struct GraphWalker<'a> {
+    graph: &'a mut Graph,
+    root: usize,
+}
+
+impl<'a> Walker for GraphWalker<'a> {
+    fn node(&mut self, parent: usize, address: usize, info: NodeInfo) -> bool {
+        let graph = &mut *self.graph;
+        let from = if parent == 0 {
+            Id::Root(self.root)
+        } else {
+            Id::Node(parent)
+        };
+        if graph.seen_edges.insert((from, address)) {
+            graph.edges.push((from, address));
+        }
+        match graph.index.get(&address) {
+            None => {
+                graph.index.insert(address, graph.nodes.len());
+                graph.nodes.push(GraphNode {
+                    address,
+                    info,
+                    root: self.root,
+                    shared: false,
+                });
+                true
+            }
+            Some(&index) => {
+                let node = &mut graph.nodes[index];
+                if node.root == self.root || node.shared {
+                    return false;
+                }
+                // Walk the node again to mark everything below it as
+                // shared too.
+                node.root = self.root;
+                node.shared = true;
+                true
+            }
+        }
+    }
+}
+
+impl Display for Graph {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        writeln!(f, "digraph im {{")?;
+        writeln!(f, "    node [shape=box, fontname=monospace];")?;
+        for (index, (name, nodes)) in self.roots.iter().enumerate() {
+            writeln!(
+                f,
+                "    {} [label=\"{}\\n{} nodes\", shape=doubleoctagon];",
+                Id::Root(index),
+                name.replace('\\', "\\\\").replace('"', "\\\""),
+                nodes
+            )?;
+        }
+        for node in &self.nodes {
+            let kind = match node.info.kind {
+                NodeKind::Leaf => "leaf",
+                NodeKind::Branch => "branch",
+                NodeKind::Relaxed => "relaxed",
+                NodeKind::Collision => "collision",
+            };
+            write!(
+                f,
+                "    {} [label=\"{}\\n{}/{}\"",
+                Id::Node(node.address),
+                kind,
+                node.info.entries,
+                node.info.capacity
+            )?;
+            if node.shared {
+                write!(f, ", style=filled, fillcolor=lightblue")?;
+            }
+            writeln!(f, "];")?;
+        }
+        for (from, to) in &self.edges {
+            writeln!(f, "    {} -> {};", from, Id::Node(*to))?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::LolHasher;
+    use crate::{HashMap, HashSet, OrdMap, OrdSet, Vector};
+    use std::hash::BuildHasherDefault;
+    use typenum::U8;
+
+    #[test]
+    fn dense_vector_stats() {
+        let vector: Vector<i32> = (0..100_000).collect();
+        let stats = vector.stats();
+        assert_eq!(100_000, stats.len);
+        assert!(stats.relaxed_nodes <= stats.depth);
+        assert_eq!(0, stats.collision_nodes);
+        assert_eq!(stats.nodes, stats.leaf_nodes + stats.branch_nodes);
+        assert_eq!(stats.nodes, stats.occupancy.iter().sum::<usize>());
+        assert!(stats.fill_factor() > 0.9);
+        assert_eq!(0, Vector::<i32>::new().stats().nodes);
+    }
+
+    #[test]
+    fn relaxed_vector_stats() {
+        let mut vector = Vector::new();
+        for i in 0..100 {
+            let mut chunk: Vector<i32> = (0..10_000).collect();
+            vector.append(chunk.slice(i * 37..10_000 - i * 41));
+        }
+        let stats = vector.stats();
+        assert_eq!(vector.len(), stats.len);
+        assert!(stats.relaxed_nodes > stats.depth);
+        vector.compact();
+        let stats = vector.stats();
+        assert!(stats.relaxed_nodes <= stats.depth);
+    }
+
+    #[test]
+    fn btree_stats() {
+        let map: OrdMap<i32, i32> = (0..10_000).map(|i| (i, i)).collect();
+        let stats = map.stats();
+        assert_eq!(10_000, stats.len);
+        assert_eq!(10_000, stats.entries);
+        assert!(stats.depth >= 3);
+        assert_eq!(stats.nodes, stats.leaf_nodes + stats.branch_nodes);
+        let set: OrdSet<i32> = (0..10_000).collect();
+        assert_eq!(stats, set.stats());
+    }
+
+    #[test]
+    fn hamt_stats() {
+        let map: HashMap<i32, i32, BuildHasherDefault<LolHasher>> =
+            (0..10_000).map(|i| (i, i)).collect();
+        let stats = map.stats();
+        assert_eq!(10_000, stats.len);
+        assert_eq!(0, stats.collision_nodes);
+
+        let mut set: HashSet<i32, BuildHasherDefault<LolHasher<U8>>> = Default::default();
+        set.extend(0..1000);
+        let stats = set.stats();
+        assert_eq!(1000, stats.len);
+        assert!(stats.collision_nodes > 0);
+    }
+
+    #[test]
+    fn graph_highlights_shared_nodes() {
+        let first: OrdMap<i32, i32> = (0..10_000).map(|i| (i, i)).collect();
+        let mut second = first.clone();
+        second.insert(-1, -1);
+        let mut graph = Graph::new();
+        graph.add("first", &first).add("second \"copy\"", &second);
+        let dot = graph.to_string();
+        assert!(dot.starts_with("digraph im {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert!(dot.contains("root0 [label=\"first\\n"));
+        assert!(dot.contains("root1 [label=\"second \\\"copy\\\"\\n"));
+        assert!(dot.contains("fillcolor"));
+
+        let shared = graph.nodes.iter().filter(|node| node.shared).count();
+        let changed = first.stats().depth;
+        assert_eq!(first.stats().nodes - changed, shared);
+        assert_eq!(first.stats().nodes + changed, graph.nodes.len());
+    }
+}
@@ -0,0 +1,626 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A common description of the differences between two values.
+//!
+//! Each collection has a `diff` method of its own, which borrows from
+//! both collections and describes the differences in its own terms. The
+//! [`Diff`][Diff] trait describes them in one owned form for every
+//! collection, and, where a collection holds values which implement
+//! `Diff` themselves, describes a changed value by the changes inside it
+//! rather than as a whole new value, so nested collections are compared
+//! all the way down.
+//!
+//! Strings, numbers and the other primitive types implement `Diff` by
+//! reporting a [`Replace`][Replace] when they differ. Structs can
+//! implement it field by field with `#[derive(Diff)]`, when the `derive`
+//! feature is on, or with the [`diff_struct!`][diff_struct] macro, which
+//! needs no procedural macro crate.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[macro_use] extern crate im;
+//! # use im::diff::{Diff, MapChange, Replace, VectorChange};
+//! let old = ordmap!{"a" => vector![1, 2, 3], "b" => vector![4]};
+//! let new = old.update("a", vector![1, 5, 3]).without("b");
+//! assert_eq!(
+//!     vec![
+//!         MapChange::Update(
+//!             "a",
+//!             vec![VectorChange::Update {
+//!                 index: 1,
+//!                 changes: vec![Replace { old: 2, new: 5 }],
+//!             }],
+//!         ),
+//!         MapChange::Remove("b", vector![4]),
+//!     ],
+//!     old.changes(&new)
+//! );
+//! ```
+//!
+//! The derive macro declares an enum describing the changes to each
+//! field, named after the struct with `Change` on the end, and takes
+//! its options from a `#[diff(...)]` attribute: `derive(...)` derives
+//! traits for the enum, `change = "Name"` names it something else, and
+//! `#[diff(skip)]` leaves a field out. In `im-rc`, the struct also needs
+//! `#[diff(crate = "::im_rc")]`.
+//!
+//! ```
+//! # #[cfg(feature = "derive")]
+//! # {
+//! # use im::diff::{Diff, MapChange};
+//! # use im::OrdMap;
+//! #[derive(Clone, PartialEq, Diff)]
+//! #[diff(derive(Debug, PartialEq))]
+//! struct State {
+//!     title: String,
+//!     scores: OrdMap<String, u32>,
+//!     #[diff(skip)]
+//!     views: u64,
+//! }
+//!
+//! let old = State { title: "Scores".to_string(), scores: OrdMap::new(), views: 0 };
+//! let mut new = old.clone();
+//! new.scores.insert("Joe".to_string(), 3);
+//! new.views += 1;
+//! assert_eq!(
+//!     vec![StateChange::Scores(vec![MapChange::Add("Joe".to_string(), 3)])],
+//!     old.changes(&new)
+//! );
+//! # }
+//! ```
+//!
+//! [Diff]: ./trait.Diff.html
+//! [Replace]: ./struct.Replace.html
+//! [diff_struct]: ../macro.diff_struct.html
+
+use std::hash::{BuildHasher, Hash};
+
+use crate::config::{HashLevelSize, OrdChunkSize, VectorChunkSize};
+use crate::hashmap::HashMap;
+use crate::hashset::{DiffItem as HashSetDiffItem, HashSet, Value as HashSetValue};
+use crate::ordmap::{DiffItem as MapDiffItem, OrdMap};
+use crate::ordset::{DiffItem as OrdSetDiffItem, OrdSet, Value as OrdSetValue};
+use crate::vector::{DiffItem as VectorDiffItem, Vector};
+
+#[cfg(feature = "derive")]
+pub use im_derive::Diff;
+
+/// A value which can describe how it differs from another of its type.
+pub trait Diff {
+    /// The description of a single change.
+    type Change;
+
+    /// Find the changes which turn this value into `other`.
+    ///
+    /// Two equal values have no changes.
+    fn changes(&self, other: &Self) -> Vec<Self::Change>;
+}
+
+/// A value which was replaced as a whole.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Replace<A> {
+    /// The old value.
+    pub old: A,
+    /// The new value.
+    pub new: A,
+}
+
+/// A change to a key in a map.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MapChange<K, V: Diff> {
+    /// The key was added with this value.
+    Add(K, V),
+    /// The key was removed, and had this value.
+    Remove(K, V),
+    /// The key's value was changed in these ways.
+    Update(K, Vec<V::Change>),
+}
+
+/// A change to a set.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SetChange<A> {
+    /// The value was added.
+    Add(A),
+    /// The value was removed.
+    Remove(A),
+}
+
+/// A change to a vector.
+///
+/// Each `index` is an index in the old vector, as for the items of
+/// [`Vector::diff`][Vector::diff]. Where a run of values was replaced by
+/// another, each pair of old and new values is reported as an
+/// [`Update`][VectorChange::Update], and whatever is left over of the
+/// longer run as an insert or a delete.
+///
+/// [Vector::diff]: ../vector/enum.Vector.html#method.diff
+/// [VectorChange::Update]: #variant.Update
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VectorChange<A: Diff> {
+    /// These values were inserted before `index`.
+    Insert {
+        /// The index of the value they were inserted before.
+        index: usize,
+        /// The inserted values.
+        values: Vec<A>,
+    },
+    /// These values, starting at `index`, were removed.
+    Delete {
+        /// The index of the first removed value.
+        index: usize,
+        /// The removed values.
+        values: Vec<A>,
+    },
+    /// The value at `index` was changed in these ways.
+    Update {
+        /// The index of the value.
+        index: usize,
+        /// The changes to the value.
+        changes: Vec<A::Change>,
+    },
+}
+
+macro_rules! diff_by_replacing {
+    ($($type:ty),*) => {
+        $(
+            impl Diff for $type {
+                type Change = Replace<$type>;
+
+                fn changes(&self, other: &Self) -> Vec<Self::Change> {
+                    if self == other {
+                        Vec::new()
+                    } else {
+                        vec![Replace {
+                            old: self.clone(),
+                            new: other.clone(),
+                        }]
+                    }
+                }
+            }
+        )*
+    };
+}
+
+diff_by_replacing!(
+    (),
+    bool,
+    char,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    f32,
+    f64,
+    String,
+    &'static str
+);
+
+// Describe the items of a map diff, leaving out updates where nothing
+// inside the value changed, which can happen when the value's `Diff`
+// ignores something its `PartialEq` compares, like a field left out of
+// `diff_struct!`. The primitive types agree with `PartialEq`, NaN
+// included, as their `Diff` is implemented with `==`.
+fn map_changes<'a, K, V, I>(items: I) -> Vec<MapChange<K, V>>
+where
+    K: Clone + 'a,
+    V: Diff + Clone + 'a,
+    I: Iterator<Item = MapDiffItem<'a, K, V>>,
+{
+    let mut changes = Vec::new();
+    for item in items {
+        match item {
+            MapDiffItem::Add(key, value) => {
+                changes.push(MapChange::Add(key.clone(), value.clone()))
+            }
+            MapDiffItem::Update { old, new } => {
+                let inner = old.1.changes(new.1);
+                if !inner.is_empty() {
+                    changes.push(MapChange::Update(new.0.clone(), inner));
+                }
+            }
+            MapDiffItem::Remove(key, value) => {
+                changes.push(MapChange::Remove(key.clone(), value.clone()))
+            }
+        }
+    }
+    changes
+}
+
+impl<K, V, N> Diff for OrdMap<K, V, N>
+where
+    K: Ord + Clone,
+    V: Diff + PartialEq + Clone,
+    N: OrdChunkSize<(K, V)>,
+{
+    type Change = MapChange<K, V>;
+
+    fn changes(&self, other: &Self) -> Vec<Self::Change> {
+        map_changes(self.diff(other))
+    }
+}
+
+impl<K, V, S, N> Diff for HashMap<K, V, S, N>
+where
+    K: Hash + Eq + Clone,
+    V: Diff + PartialEq + Clone,
+    S: BuildHasher,
+    N: HashLevelSize<(K, V)>,
+{
+    type Change = MapChange<K, V>;
+
+    fn changes(&self, other: &Self) -> Vec<Self::Change> {
+        map_changes(self.diff(other))
+    }
+}
+
+impl<A, N> Diff for OrdSet<A, N>
+where
+    A: Ord + Clone,
+    N: OrdChunkSize<OrdSetValue<A>>,
+{
+    type Change = SetChange<A>;
+
+    fn changes(&self, other: &Self) -> Vec<Self::Change> {
+        let mut changes = Vec::new();
+        for item in self.diff(other) {
+            match item {
+                OrdSetDiffItem::Add(value) => changes.push(SetChange::Add(value.clone())),
+                OrdSetDiffItem::Update { old, new } => {
+                    changes.push(SetChange::Remove(old.clone()));
+                    changes.push(SetChange::Add(new.clone()));
+                }
+                OrdSetDiffItem::Remove(value) => changes.push(SetChange::Remove(value.clone())),
+            }
+        }
+        changes
+    }
+}
+
+impl<A, S, N> Diff for HashSet<A, S, N>
+where
+    A: Hash + Eq + Clone,
+    S: BuildHasher,
+    N: HashLevelSize<HashSetValue<A>>,
+{
+    type Change = SetChange<A>;
+
+    fn changes(&self, other: &Self) -> Vec<Self::Change> {
+        self.diff(other)
+            .map(|item| match item {
+                HashSetDiffItem::Add(value) => SetChange::Add(value.clone()),
+                HashSetDiffItem::Remove(value) => SetChange::Remove(value.clone()),
+            })
+            .collect()
+    }
+}
+
+impl<A, N> Diff for Vector<A, N>
+where
+    A: Diff + PartialEq + Clone,
+    N: VectorChunkSize<A>,
+{
+    type Change = VectorChange<A>;
+
+    fn changes(&self, other: &Self) -> Vec<Self::Change> {
+        let owned = |values: &[&A]| values.iter().map(|value| (*value).clone()).collect();
+        let mut changes = Vec::new();
+        for item in self.diff(other) {
+            match item {
+                VectorDiffItem::Insert { index, values } => changes.push(VectorChange::Insert {
+                    index,
+                    values: owned(&values),
+                }),
+                VectorDiffItem::Delete { index, values } => changes.push(VectorChange::Delete {
+                    index,
+                    values: owned(&values),
+                }),
+                VectorDiffItem::Replace { index, old, new } => {
+                    let paired = old.len().min(new.len());
+                    for (offset, (old, new)) in old.iter().zip(&new).enumerate() {
+                        let inner = old.changes(new);
+                        if !inner.is_empty() {
+                            changes.push(VectorChange::Update {
+                                index: index + offset,
+                                changes: inner,
+                            });
+                        }
+                    }
+                    if old.len() > paired {
+                        changes.push(VectorChange::Delete {
+                            index: index + paired,
+                            values: owned(&old[paired..]),
+                        });
+                    } else if new.len() > paired {
+                        changes.push(VectorChange::Insert {
+                            index: index + paired,
+                            values: owned(&new[paired..]),
+                        });
+                    }
+                }
+            }
+        }
+        changes
+    }
+}
+
+/// Implement [`Diff`][Diff] for a struct, field by field.
+///
+/// The macro declares an enum with a variant for each field, holding
+/// the changes to that field, and implements `Diff` for the struct with
+/// that enum as its `Change` type. Each variant names the field it's
+/// for and the field's type, which must implement `Diff` itself, and
+/// the struct's changes list the fields which changed in the order
+/// they're given.
+///
+/// This does the same job as `#[derive(Diff)]` from the `derive`
+/// feature, without a procedural macro crate. Fields which aren't listed
+/// are left out of the diff, though `PartialEq` may still compare them.
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate im;
+/// # use im::diff::{Diff, MapChange, Replace};
+/// # use im::OrdMap;
+/// #[derive(Clone, PartialEq)]
+/// struct State {
+///     title: String,
+///     scores: OrdMap<String, u32>,
+/// }
+///
+/// diff_struct! {
+///     #[derive(Debug, PartialEq)]
+///     enum StateChange for State {
+///         Title(title: String),
+///         Scores(scores: OrdMap<String, u32>),
+///     }
+/// }
+///
+/// let old = State { title: "Scores".to_string(), scores: OrdMap::new() };
+/// let mut new = old.clone();
+/// new.scores.insert("Joe".to_string(), 3);
+/// assert_eq!(
+///     vec![StateChange::Scores(vec![MapChange::Add("Joe".to_string(), 3)])],
+///     old.changes(&new)
+/// );
+/// ```
+///
+/// [Diff]: ./diff/trait.Diff.html
+#[macro_export]
+macro_rules! diff_struct {
+    (
+        $(#[$attr:meta])*
+        $vis:vis enum $change:ident for $struct:ty {
+            $($(#[$variant_attr:meta])* $variant:ident($field:ident: $type:ty)),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        $vis enum $change {
+            $(
+                $(#[$variant_attr])*
+                $variant(::std::vec::Vec<<$type as $crate::diff::Diff>::Change>),
+            )*
+        }
+
+        impl $crate::diff::Diff for $struct {
+            type Change = $change;
+
+            fn changes(&self, other: &Self) -> ::std::vec::Vec<Self::Change> {
+                let mut changes = ::std::vec::Vec::new();
+                $(
+                    let field = $crate::diff::Diff::changes(&self.$field, &other.$field);
+                    if !field.is_empty() {
+                        changes.push($change::$variant(field));
+                    }
+                )*
+                changes
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::proptest::{ord_map, vector};
+    use ::proptest::num::i16;
+    use ::proptest::proptest;
+
+    #[derive(Clone, PartialEq)]
+    struct Document {
+        title: String,
+        lines: Vector<String>,
+        tags: OrdSet<&'static str>,
+    }
+
+    diff_struct! {
+        #[derive(Debug, PartialEq)]
+        enum DocumentChange for Document {
+            Title(title: String),
+            Lines(lines: Vector<String>),
+            Tags(tags: OrdSet<&'static str>),
+        }
+    }
+
+    #[test]
+    fn struct_reports_changed_fields() {
+        let old = Document {
+            title: "Notes".to_string(),
+            lines: vector!["a".to_string(), "b".to_string()],
+            tags: ordset!["draft"],
+        };
+        let mut new = old.clone();
+        assert_eq!(Vec::<DocumentChange>::new(), old.changes(&new));
+        new.lines.set(1, "c".to_string());
+        new.lines.push_back("d".to_string());
+        new.tags.remove("draft");
+        assert_eq!(
+            vec![
+                DocumentChange::Lines(vec![
+                    VectorChange::Update {
+                        index: 1,
+                        changes: vec![Replace {
+                            old: "b".to_string(),
+                            new: "c".to_string()
+                        }]
+                    },
+                    VectorChange::Insert {
+                        index: 2,
+                        values: vec!["d".to_string()]
+                    },
+                ]),
+                DocumentChange::Tags(vec![SetChange::Remove("draft")]),
+            ],
+            old.changes(&new)
+        );
+    }
+
+    #[derive(Clone, PartialEq)]
+    struct Counter {
+        value: f64,
+        reads: u32,
+    }
+
+    diff_struct! {
+        #[derive(Debug, PartialEq)]
+        enum CounterChange for Counter {
+            Value(value: f64),
+        }
+    }
+
+    #[test]
+    fn map_updates_agree_with_value_diff() {
+        let old = ordmap! {1 => Counter { value: 1.0, reads: 0 }};
+        // Only a field the diff leaves out has changed.
+        let new = ordmap! {1 => Counter { value: 1.0, reads: 5 }};
+        assert!(old.changes(&new).is_empty());
+        // NaN isn't equal to itself, by `Diff` or by `PartialEq`.
+        let nan = ordmap! {1 => Counter { value: f64::NAN, reads: 0 }};
+        assert_eq!(1, nan.changes(&nan.update(1, nan[&1].clone())).len());
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(Clone, PartialEq, Diff)]
+    #[diff(crate = "crate", change = "Changed", derive(Debug, PartialEq))]
+    struct Derived {
+        title: String,
+        r#type: u8,
+        line_count: usize,
+        lines: Vector<String>,
+        #[diff(skip)]
+        reads: u32,
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn derived_diff_reports_changed_fields() {
+        let old = Derived {
+            title: "Notes".to_string(),
+            r#type: 0,
+            line_count: 1,
+            lines: vector!["a".to_string()],
+            reads: 0,
+        };
+        let mut new = old.clone();
+        new.reads = 1;
+        assert_eq!(Vec::<Changed>::new(), old.changes(&new));
+        new.r#type = 1;
+        new.line_count = 2;
+        new.lines.push_back("b".to_string());
+        assert_eq!(
+            vec![
+                Changed::Type(vec![Replace { old: 0, new: 1 }]),
+                Changed::LineCount(vec![Replace { old: 1, new: 2 }]),
+                Changed::Lines(vec![VectorChange::Insert {
+                    index: 1,
+                    values: vec!["b".to_string()]
+                }]),
+            ],
+            old.changes(&new)
+        );
+        // Nested in a map, it reports the same changes.
+        let nested = ordmap! {1 => old.clone()};
+        let changes = nested.changes(&nested.update(1, new.clone()));
+        assert!(vec![MapChange::Update(1, old.changes(&new))] == changes);
+    }
+
+    #[test]
+    fn nested_maps_report_inner_changes() {
+        let old = hashmap! {1 => ordmap!{"a" => 1, "b" => 2}, 2 => ordmap!{}};
+        let new = old.update(1, ordmap! {"a" => 1, "b" => 3});
+        assert_eq!(
+            vec![MapChange::Update(
+                1,
+                vec![MapChange::Update("b", vec![Replace { old: 2, new: 3 }])]
+            )],
+            old.changes(&new)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn vector_changes_rebuild_new(
+            ref old in vector(i16::ANY, 0..500),
+            ref new in vector(i16::ANY, 0..500)
+        ) {
+            // Replay the changes, last first, so each index is still an
+            // index in the old vector when it's used.
+            let mut rebuilt = old.clone();
+            for change in old.changes(new).into_iter().rev() {
+                match change {
+                    VectorChange::Insert { index, values } => {
+                        for value in values.into_iter().rev() {
+                            rebuilt.insert(index, value);
+                        }
+                    }
+                    VectorChange::Delete { index, values } => {
+                        for _ in values {
+                            rebuilt.remove(index);
+                        }
+                    }
+                    VectorChange::Update { index, changes } => {
+                        for Replace { new, .. } in changes {
+                            rebuilt.set(index, new);
+                        }
+                    }
+                }
+            }
+            assert_eq!(new, &rebuilt);
+        }
+
+        #[test]
+        fn map_changes_rebuild_new(
+            ref old in ord_map(i16::ANY, i16::ANY, 0..500),
+            ref new in ord_map(i16::ANY, i16::ANY, 0..500)
+        ) {
+            let mut rebuilt = old.clone();
+            for change in old.changes(new) {
+                match change {
+                    MapChange::Add(key, value) => {
+                        rebuilt.insert(key, value);
+                    }
+                    MapChange::Remove(key, _) => {
+                        rebuilt.remove(&key);
+                    }
+                    MapChange::Update(key, changes) => {
+                        for Replace { new, .. } in changes {
+                            rebuilt.insert(key, new);
+                        }
+                    }
+                }
+            }
+            assert_eq!(new, &rebuilt);
+        }
+    }
+}
@@ -0,0 +1,208 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Dropping collections on a background thread.
+//!
+//! Dropping the last reference to a large collection frees every node it
+//! doesn't share with another collection, on the thread which dropped it.
+//! For a collection with millions of values, that can take long enough to
+//! notice. A [`DropQueue`][DropQueue] hands the collection over to a thread
+//! of its own instead, so the dropping thread only pays for sending it
+//! there.
+//!
+//! ```
+//! # use im::drop_queue::DropQueue;
+//! # use im::Vector;
+//! let queue = DropQueue::new();
+//! let snapshot: Vector<u64> = (0..1_000_000).collect();
+//! // Returns immediately: the vector is freed on the queue's thread.
+//! queue.defer(snapshot);
+//! queue.flush();
+//! assert_eq!(0, queue.pending());
+//! ```
+//!
+//! Nodes shared with collections which are still alive aren't freed, and
+//! it's safe for other threads to keep using those collections while the
+//! queue drops its copy.
+//!
+//! This module is only available in `im`, as the collections in `im-rc`
+//! can't be sent to another thread.
+
+use std::fmt::{Debug, Formatter};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{Builder, JoinHandle};
+
+enum Job {
+    Drop(Box<dyn Send>),
+    Flush(Sender<()>),
+}
+
+/// A handle to a thread which drops the values given to it.
+///
+/// The thread is started when the queue is created and stopped when it's
+/// dropped, after it has dropped everything still waiting in the queue.
+pub struct DropQueue {
+    sender: Mutex<Sender<Job>>,
+    pending: Arc<AtomicUsize>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DropQueue {
+    /// Start a new drop queue on a thread of its own.
+    ///
+    /// Panics if the thread can't be started.
+    #[must_use]
+    pub fn new() -> Self {
+        let (sender, receiver) = channel();
+        let pending = Arc::new(AtomicUsize::new(0));
+        let thread_pending = pending.clone();
+        let thread = Builder::new()
+            .name("im-drop-queue".to_string())
+            .spawn(move || {
+                for job in receiver {
+                    match job {
+                        Job::Drop(value) => {
+                            // A panicking destructor shouldn't take the
+                            // rest of the queue down with it.
+                            let _ = catch_unwind(AssertUnwindSafe(move || drop(value)));
+                            thread_pending.fetch_sub(1, Ordering::Release);
+                        }
+                        Job::Flush(done) => {
+                            let _ = done.send(());
+                        }
+                    }
+                }
+            })
+            .expect("DropQueue::new: failed to start the drop thread");
+        DropQueue {
+            sender: Mutex::new(sender),
+            pending,
+            thread: Some(thread),
+        }
+    }
+
+    /// Send a value to the queue's thread to be dropped.
+    ///
+    /// If the thread has gone away, the value is dropped on the current
+    /// thread instead.
+    pub fn defer<A>(&self, value: A)
+    where
+        A: Send + 'static,
+    {
+        self.pending.fetch_add(1, Ordering::Relaxed);
+        if let Err(job) = self.send(Job::Drop(Box::new(value))) {
+            self.pending.fetch_sub(1, Ordering::Release);
+            drop(job);
+        }
+    }
+
+    /// The number of values sent to the queue which haven't been dropped
+    /// yet.
+    #[must_use]
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::Acquire)
+    }
+
+    /// Wait until every value sent to the queue before this call has been
+    /// dropped.
+    pub fn flush(&self) {
+        let (done, wait) = channel();
+        if self.send(Job::Flush(done)).is_ok() {
+            let _ = wait.recv();
+        }
+    }
+
+    fn send(&self, job: Job) -> Result<(), Job> {
+        match self.sender.lock() {
+            Ok(sender) => sender.send(job).map_err(|error| error.0),
+            Err(_) => Err(job),
+        }
+    }
+}
+
+impl Default for DropQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for DropQueue {
+    fn drop(&mut self) {
+        // Closing the channel ends the thread's loop once it's empty.
+        let (closed, _) = channel();
+        if let Ok(mut sender) = self.sender.lock() {
+            *sender = closed;
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Debug for DropQueue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DropQueue")
+            .field("pending", &self.pending())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{HashMap, OrdMap, Vector};
+    use std::thread::{current, ThreadId};
+
+    struct Witness(Arc<Mutex<Option<ThreadId>>>);
+
+    impl Drop for Witness {
+        fn drop(&mut self) {
+            *self.0.lock().unwrap() = Some(current().id());
+        }
+    }
+
+    #[test]
+    fn drops_on_another_thread() {
+        let queue = DropQueue::new();
+        let dropped_on = Arc::new(Mutex::new(None));
+        let mut vec = Vector::new();
+        vec.push_back(Arc::new(Witness(dropped_on.clone())));
+        vec.extend((0..10_000).map(|_| Arc::new(Witness(Arc::new(Mutex::new(None))))));
+        queue.defer(vec);
+        queue.flush();
+        assert_eq!(0, queue.pending());
+        let thread = dropped_on.lock().unwrap().expect("value wasn't dropped");
+        assert_ne!(current().id(), thread);
+    }
+
+    #[test]
+    fn leaves_shared_structure_alone() {
+        let queue = DropQueue::new();
+        let map: OrdMap<i32, i32> = (0..10_000).map(|i| (i, i)).collect();
+        let hash: HashMap<i32, i32> = map.clone().into_iter().collect();
+        queue.defer(map.clone());
+        queue.defer(hash.clone());
+        drop(queue);
+        assert_eq!(10_000, map.len());
+        assert_eq!(Some(&5000), hash.get(&5000));
+    }
+
+    #[test]
+    fn survives_a_panicking_drop() {
+        struct Bomb;
+        impl Drop for Bomb {
+            fn drop(&mut self) {
+                panic!("boom");
+            }
+        }
+        let queue = DropQueue::new();
+        queue.defer(Bomb);
+        queue.defer(Vector::unit(1));
+        queue.flush();
+        assert_eq!(0, queue.pending());
+    }
+}
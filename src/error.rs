@@ -0,0 +1,126 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Errors returned by the fallible methods of [`Vector`][crate::Vector]
+//! and its focuses.
+//!
+//! Methods like [`Vector::insert`][crate::Vector::insert] panic when given
+//! an index that's out of bounds, the same way their counterparts on
+//! [`Vec`][Vec] do. Each of them has a `try_` (or `checked_`) variant
+//! which returns an [`Error`][Error] instead, for when the index comes
+//! from somewhere you don't control.
+//!
+//! The maps and sets don't need any: they have no methods taking an
+//! index, and the only lookups of theirs which panic, indexing a map
+//! with a key it doesn't have, already have [`get`][crate::OrdMap::get]
+//! and [`get_mut`][crate::OrdMap::get_mut] as their non-panicking
+//! counterparts.
+//!
+//! ```
+//! # #[macro_use] extern crate im;
+//! # use im::error::Error;
+//! let mut vec = vector![1, 2, 3];
+//! assert_eq!(Err(Error::IndexOutOfBounds { index: 5, len: 3 }), vec.try_remove(5));
+//! assert_eq!(Ok(2), vec.try_remove(1));
+//! ```
+//!
+//! Running out of memory isn't among the errors: nodes are allocated with
+//! [`Rc`][std::rc::Rc] and [`Arc`][std::sync::Arc], which have no fallible
+//! constructors on stable Rust, so an allocation failure aborts the
+//! process just as it does for the standard library's collections. For
+//! the same reason, there's no `try_push_back` or `try_push_front`: the
+//! only way they could fail is by running out of memory.
+
+use std::error;
+use std::fmt::{Display, Formatter};
+use std::ops::{Bound, Range, RangeBounds};
+
+/// The error type for the fallible methods of `Vector` and its focuses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Error {
+    /// An index was outside the collection.
+    IndexOutOfBounds {
+        /// The offending index.
+        index: usize,
+        /// The length of the collection, or of the focus, at the time.
+        len: usize,
+    },
+    /// A range was outside the collection, or ended before it started.
+    RangeOutOfBounds {
+        /// The first index of the range.
+        start: usize,
+        /// The index one past the end of the range.
+        end: usize,
+        /// The length of the collection, or of the focus, at the time.
+        len: usize,
+    },
+    /// The same index was given more than once to a method which needs
+    /// them to be distinct, like [`FocusMut::pair`][crate::vector::FocusMut::pair].
+    DuplicateIndex {
+        /// The repeated index.
+        index: usize,
+    },
+}
+
+impl Error {
+    pub(crate) fn check_index(index: usize, len: usize) -> Result<(), Error> {
+        if index < len {
+            Ok(())
+        } else {
+            Err(Error::IndexOutOfBounds { index, len })
+        }
+    }
+
+    /// Like `check_index`, but allows the index one past the end, for
+    /// methods which insert or split.
+    pub(crate) fn check_position(index: usize, len: usize) -> Result<(), Error> {
+        if index <= len {
+            Ok(())
+        } else {
+            Err(Error::IndexOutOfBounds { index, len })
+        }
+    }
+
+    /// Resolve `range` against a collection of length `len`, making sure
+    /// it's inside the collection and doesn't run backwards.
+    pub(crate) fn check_range<R>(range: &R, len: usize) -> Result<Range<usize>, Error>
+    where
+        R: RangeBounds<usize>,
+    {
+        let start = match range.start_bound() {
+            Bound::Included(i) => *i,
+            Bound::Excluded(i) => i.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(i) => i.saturating_add(1),
+            Bound::Excluded(i) => *i,
+            Bound::Unbounded => len,
+        };
+        if start <= end && end <= len {
+            Ok(start..end)
+        } else {
+            Err(Error::RangeOutOfBounds { start, end, len })
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::IndexOutOfBounds { index, len } => {
+                write!(f, "index {} out of bounds for length {}", index, len)
+            }
+            Error::RangeOutOfBounds { start, end, len } => write!(
+                f,
+                "range {}..{} out of bounds for length {}",
+                start, end, len
+            ),
+            Error::DuplicateIndex { index } => write!(f, "index {} given more than once", index),
+        }
+    }
+}
+
+impl error::Error for Error {}
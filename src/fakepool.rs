@@ -9,18 +9,20 @@ use std::ops::Deref;
 use std::rc::Rc as RRc;
 use std::sync::Arc as RArc;
 
-use crate::nodes::chunk::Chunk;
+use sized_chunks::{types::ChunkLength, Chunk};
+
+use crate::util::{Counted, NodeType, Token};
 
 pub(crate) trait PoolDefault: Default {}
 pub(crate) trait PoolClone: Clone {}
 
-impl<A> PoolDefault for Chunk<A> {}
-impl<A> PoolClone for Chunk<A> where A: Clone {}
+impl<A, N: ChunkLength<A>> PoolDefault for Chunk<A, N> {}
+impl<A, N: ChunkLength<A>> PoolClone for Chunk<A, N> where A: Clone {}
 
 pub(crate) struct Pool<A>(PhantomData<A>);
 
 impl<A> Pool<A> {
-    pub(crate) fn new(_size: usize) -> Self {
+    pub(crate) const fn new(_size: usize) -> Self {
         Pool(PhantomData)
     }
 
@@ -39,37 +41,45 @@ impl<A> Clone for Pool<A> {
 
 // Rc
 
-#[derive(Default)]
-pub(crate) struct Rc<A>(RRc<A>);
+// Public, though unreachable, because it shows up in the bounds of
+// `config::OrdChunkSize`.
+#[allow(unreachable_pub)]
+pub struct Rc<A>(RRc<Counted<A>>);
 
 impl<A> Rc<A> {
     #[inline(always)]
     pub(crate) fn default(_pool: &Pool<A>) -> Self
     where
-        A: PoolDefault,
+        A: PoolDefault + NodeType,
     {
-        Self(Default::default())
+        Self::new(_pool, Default::default())
     }
 
     #[inline(always)]
-    pub(crate) fn new(_pool: &Pool<A>, value: A) -> Self {
-        Rc(RRc::new(value))
+    pub(crate) fn new(_pool: &Pool<A>, value: A) -> Self
+    where
+        A: NodeType,
+    {
+        Rc(RRc::new(Counted::new(value)))
     }
 
     #[inline(always)]
     pub(crate) fn clone_from(_pool: &Pool<A>, value: &A) -> Self
     where
-        A: PoolClone,
+        A: PoolClone + NodeType,
     {
-        Rc(RRc::new(value.clone()))
+        Rc(RRc::new(Counted {
+            value: value.clone(),
+            token: Token::cloned::<A>(),
+        }))
     }
 
     #[inline(always)]
     pub(crate) fn make_mut<'a>(_pool: &Pool<A>, this: &'a mut Self) -> &'a mut A
     where
-        A: PoolClone,
+        A: PoolClone + NodeType,
     {
-        RRc::make_mut(&mut this.0)
+        &mut RRc::make_mut(&mut this.0).value
     }
 
     #[inline(always)]
@@ -79,9 +89,11 @@ impl<A> Rc<A> {
 
     pub(crate) fn unwrap_or_clone(this: Self) -> A
     where
-        A: PoolClone,
+        A: PoolClone + NodeType,
     {
-        RRc::try_unwrap(this.0).unwrap_or_else(|r| (*r).clone())
+        RRc::try_unwrap(this.0)
+            .map(Counted::into_inner)
+            .unwrap_or_else(|r| r.value.clone())
     }
 }
 
@@ -96,7 +108,7 @@ impl<A> Deref for Rc<A> {
     type Target = A;
     #[inline(always)]
     fn deref(&self) -> &Self::Target {
-        self.0.deref()
+        &self.0.deref().value
     }
 }
 
@@ -118,43 +130,51 @@ where
 {
     #[inline(always)]
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
-        self.0.fmt(f)
+        (**self).fmt(f)
     }
 }
 
 // Arc
 
-#[derive(Default)]
-pub(crate) struct Arc<A>(RArc<A>);
+// Public, though unreachable, because it shows up in the bounds of
+// `config::OrdChunkSize`.
+#[allow(unreachable_pub)]
+pub struct Arc<A>(RArc<Counted<A>>);
 
 impl<A> Arc<A> {
     #[inline(always)]
     pub(crate) fn default(_pool: &Pool<A>) -> Self
     where
-        A: PoolDefault,
+        A: PoolDefault + NodeType,
     {
-        Self(Default::default())
+        Self::new(_pool, Default::default())
     }
 
     #[inline(always)]
-    pub(crate) fn new(_pool: &Pool<A>, value: A) -> Self {
-        Self(RArc::new(value))
+    pub(crate) fn new(_pool: &Pool<A>, value: A) -> Self
+    where
+        A: NodeType,
+    {
+        Self(RArc::new(Counted::new(value)))
     }
 
     #[inline(always)]
     pub(crate) fn clone_from(_pool: &Pool<A>, value: &A) -> Self
     where
-        A: PoolClone,
+        A: PoolClone + NodeType,
     {
-        Self(RArc::new(value.clone()))
+        Self(RArc::new(Counted {
+            value: value.clone(),
+            token: Token::cloned::<A>(),
+        }))
     }
 
     #[inline(always)]
     pub(crate) fn make_mut<'a>(_pool: &Pool<A>, this: &'a mut Self) -> &'a mut A
     where
-        A: PoolClone,
+        A: PoolClone + NodeType,
     {
-        RArc::make_mut(&mut this.0)
+        &mut RArc::make_mut(&mut this.0).value
     }
 
     #[inline(always)]
@@ -164,9 +184,11 @@ impl<A> Arc<A> {
 
     pub(crate) fn unwrap_or_clone(this: Self) -> A
     where
-        A: PoolClone,
+        A: PoolClone + NodeType,
     {
-        RArc::try_unwrap(this.0).unwrap_or_else(|r| (*r).clone())
+        RArc::try_unwrap(this.0)
+            .map(Counted::into_inner)
+            .unwrap_or_else(|r| r.value.clone())
     }
 }
 
@@ -181,7 +203,7 @@ impl<A> Deref for Arc<A> {
     type Target = A;
     #[inline(always)]
     fn deref(&self) -> &Self::Target {
-        self.0.deref()
+        &self.0.deref().value
     }
 }
 
@@ -203,6 +225,6 @@ where
 {
     #[inline(always)]
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
-        self.0.fmt(f)
+        (**self).fmt(f)
     }
 }
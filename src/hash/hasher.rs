@@ -0,0 +1,120 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// A shared hasher which isn't allocated until a hash map or set first
+// needs it, so that empty maps and sets don't allocate anything.
+
+use std::cell::UnsafeCell;
+use std::sync::Once;
+
+use crate::util::Ref;
+
+/// A reference counted hasher, which is either given up front or built
+/// with `make` the first time it's asked for.
+pub(crate) struct LazyHasher<S> {
+    init: Once,
+    hasher: UnsafeCell<Option<Ref<S>>>,
+    make: Option<fn() -> S>,
+}
+
+// The cell is only ever written to inside `init.call_once`, and only read
+// after `init` has completed or when it was filled in up front.
+#[allow(unsafe_code)]
+unsafe impl<S> Sync for LazyHasher<S> where Ref<S>: Send + Sync {}
+
+impl<S> LazyHasher<S> {
+    def_const_fn! {
+        #[cfg(has_const_fn_ptr)]
+        /// A hasher which will be built with `make` when it's first needed.
+        pub(crate) fn new(make: fn() -> S) -> Self {
+            LazyHasher {
+                init: Once::new(),
+                hasher: UnsafeCell::new(None),
+                make: Some(make),
+            }
+        }
+    }
+
+    /// Get the hasher, building it first if it hasn't been built yet.
+    #[allow(unsafe_code)]
+    pub(crate) fn get(&self) -> &Ref<S> {
+        self.init.call_once(|| {
+            // Safety: nothing else can be touching the cell while
+            // `call_once` runs this closure.
+            let hasher = unsafe { &mut *self.hasher.get() };
+            if hasher.is_none() {
+                let make = self
+                    .make
+                    .expect("LazyHasher has neither a hasher nor a way to make one");
+                *hasher = Some(Ref::new(make()));
+            }
+        });
+        // Safety: `init` has completed, so the cell won't be written to again.
+        unsafe { &*self.hasher.get() }.as_ref().unwrap()
+    }
+
+    /// Get the hasher if it's been built, without building it.
+    pub(crate) fn get_if_built(&self) -> Option<&Ref<S>> {
+        if self.is_built() {
+            Some(self.get())
+        } else {
+            None
+        }
+    }
+
+    fn is_built(&self) -> bool {
+        self.make.is_none() || self.init.is_completed()
+    }
+}
+
+impl<S> From<Ref<S>> for LazyHasher<S> {
+    fn from(hasher: Ref<S>) -> Self {
+        LazyHasher {
+            init: Once::new(),
+            hasher: UnsafeCell::new(Some(hasher)),
+            make: None,
+        }
+    }
+}
+
+impl<S> Clone for LazyHasher<S> {
+    /// Build the hasher if it hasn't been built yet, and share it with the
+    /// clone, so that a collection and its clones always hash alike.
+    fn clone(&self) -> Self {
+        LazyHasher::from(self.get().clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::hash_map::RandomState;
+
+    #[test]
+    fn builds_on_first_use_only() {
+        let hasher: LazyHasher<RandomState> = LazyHasher::new(RandomState::new);
+        assert!(hasher.get_if_built().is_none());
+        let built = hasher.get().clone();
+        assert!(Ref::ptr_eq(&built, hasher.get_if_built().unwrap()));
+        assert!(Ref::ptr_eq(&built, hasher.clone().get()));
+    }
+
+    #[test]
+    fn clones_share_the_hasher() {
+        let hasher: LazyHasher<RandomState> = LazyHasher::new(RandomState::new);
+        let clone = hasher.clone();
+        assert!(Ref::ptr_eq(
+            hasher.get_if_built().unwrap(),
+            clone.get_if_built().unwrap()
+        ));
+    }
+
+    #[test]
+    fn given_hasher_is_shared() {
+        let given = Ref::new(RandomState::new());
+        let hasher = LazyHasher::from(given.clone());
+        assert!(Ref::ptr_eq(&given, hasher.get_if_built().unwrap()));
+        assert!(Ref::ptr_eq(&given, hasher.clone().get()));
+    }
+}
@@ -31,9 +31,17 @@ use std::iter::FusedIterator;
 use std::iter::{FromIterator, IntoIterator, Sum};
 use std::ops::{Add, Deref, Mul};
 
-use crate::nodes::hamt::{hash_key, Drain as NodeDrain, HashValue, Iter as NodeIter, Node};
+use crate::config::{DefaultHashLevelSize, HashLevelSize, POOL_SIZE};
+#[cfg(any(test, feature = "debug"))]
+use crate::debug::{InvariantViolation, Stats, Walk, Walker};
+use crate::hash::hasher::LazyHasher;
+use crate::memory::{self, HeapNodes, HeapSize, MemoryReport, Visitor};
+use crate::nodes::hamt::{
+    hash_key, Drain as NodeDrain, HashValue, Iter as NodeIter, Lockstep, Node, Root,
+};
 use crate::ordset::OrdSet;
-use crate::util::{Pool, PoolRef, Ref};
+use crate::patch::Patch;
+use crate::util::{transaction, Pool, Ref};
 use crate::Vector;
 
 /// Construct a set from a sequence of values.
@@ -71,7 +79,7 @@ macro_rules! hashset {
     }};
 }
 
-def_pool!(HashSetPool<A>, Node<Value<A>>);
+def_pool!(HashSetPool<A; N: HashLevelSize<Value<A>> = DefaultHashLevelSize>, Node<Value<A>, N>);
 
 /// An unordered set.
 ///
@@ -91,15 +99,22 @@ def_pool!(HashSetPool<A>, Node<Value<A>>);
 /// [std::cmp::Eq]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
 /// [std::hash::Hash]: https://doc.rust-lang.org/std/hash/trait.Hash.html
 /// [std::collections::hash_map::RandomState]: https://doc.rust-lang.org/std/collections/hash_map/struct.RandomState.html
-pub struct HashSet<A, S = RandomState> {
-    hasher: Ref<S>,
-    pool: HashSetPool<A>,
-    root: PoolRef<Node<Value<A>>>,
+pub struct HashSet<A, S = RandomState, N: HashLevelSize<Value<A>> = DefaultHashLevelSize> {
+    /// The hasher, which isn't built until the first insert unless it
+    /// was given up front.
+    hasher: LazyHasher<S>,
+    pool: HashSetPool<A, N>,
+    /// The root, which isn't allocated until the first insert, and
+    /// stays a flat array while the set is small.
+    root: Root<Value<A>, N>,
     size: usize,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
-struct Value<A>(A);
+/// A value as a [`HashSet`][HashSet] stores it in its hash array mapped
+/// trie. This type only shows up in the bound on a set's level size,
+/// which is written `N: HashLevelSize<Value<A>>`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Value<A>(A);
 
 impl<A> Deref for Value<A> {
     type Target = A;
@@ -126,10 +141,26 @@ where
 }
 
 impl<A> HashSet<A, RandomState> {
-    /// Construct an empty set.
-    #[must_use]
-    pub fn new() -> Self {
-        Self::default()
+    def_const_fn! {
+        #[cfg(all(has_const_fn_ptr, not(feature = "pool")))]
+        /// Construct an empty set.
+        ///
+        /// An empty set doesn't allocate any memory, not even for its
+        /// hasher, until something is inserted into it.
+        ///
+        /// Like [`HashMap::new`][HashMap::new], this is a `const fn` on
+        /// Rust 1.61 and later as long as the `pool` feature is off.
+        ///
+        /// [HashMap::new]: ../hashmap/struct.HashMap.html#method.new
+        #[must_use]
+        pub fn new() -> Self {
+            HashSet {
+                hasher: LazyHasher::new(RandomState::new),
+                pool: HashSetPool(Pool::new(POOL_SIZE)),
+                root: Root::Empty,
+                size: 0,
+            }
+        }
     }
 
     /// Construct an empty set using a specific memory pool.
@@ -138,9 +169,9 @@ impl<A> HashSet<A, RandomState> {
     pub fn with_pool(pool: &HashSetPool<A>) -> Self {
         Self {
             pool: pool.clone(),
-            hasher: Default::default(),
+            hasher: LazyHasher::new(RandomState::new),
             size: 0,
-            root: PoolRef::default(&pool.0),
+            root: Root::Empty,
         }
     }
 }
@@ -168,6 +199,29 @@ where
 }
 
 impl<A, S> HashSet<A, S> {
+    /// Construct an empty hash set using the provided hasher.
+    #[inline]
+    #[must_use]
+    pub fn with_hasher<RS>(hasher: RS) -> Self
+    where
+        Ref<S>: From<RS>,
+    {
+        Self::with_hasher_and_node_size(hasher)
+    }
+
+    /// Construct an empty hash set using the provided memory pool and hasher.
+    #[cfg(feature = "pool")]
+    #[inline]
+    #[must_use]
+    pub fn with_pool_hasher<RS>(pool: &HashSetPool<A>, hasher: RS) -> Self
+    where
+        Ref<S>: From<RS>,
+    {
+        Self::with_pool_hasher_and_node_size(pool, hasher)
+    }
+}
+
+impl<A, S, N: HashLevelSize<Value<A>>> HashSet<A, S, N> {
     /// Test whether a set is empty.
     ///
     /// Time: O(1)
@@ -217,7 +271,7 @@ impl<A, S> HashSet<A, S> {
     ///
     /// Time: O(1)
     pub fn ptr_eq(&self, other: &Self) -> bool {
-        std::ptr::eq(self, other) || PoolRef::ptr_eq(&self.root, &other.root)
+        std::ptr::eq(self, other) || Root::ptr_eq(&self.root, &other.root)
     }
 
     /// Get a reference to the memory pool used by this set.
@@ -225,66 +279,122 @@ impl<A, S> HashSet<A, S> {
     /// Note that if you didn't specifically construct it with a pool, you'll
     /// get back a reference to a pool of size 0.
     #[cfg(feature = "pool")]
-    pub fn pool(&self) -> &HashSetPool<A> {
+    pub fn pool(&self) -> &HashSetPool<A, N> {
         &self.pool
     }
 
-    /// Construct an empty hash set using the provided hasher.
+    /// Construct an empty hash set with a node size other than the
+    /// default.
+    ///
+    /// [`new`][new], [`with_hasher`][with_hasher], [`Default`][Default]
+    /// and [`FromIterator`][FromIterator] always build sets of the
+    /// default node size, so that the type of a set can be inferred
+    /// from its values and hasher alone.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use im::hashset::HashSet;
+    /// # use std::collections::hash_map::RandomState;
+    /// use im::config::U4;
+    ///
+    /// let mut set = HashSet::<i32, RandomState, U4>::with_node_size();
+    /// set.insert(1);
+    /// assert!(set.contains(&1));
+    /// ```
+    ///
+    /// [new]: #method.new
+    /// [with_hasher]: #method.with_hasher
+    /// [Default]: https://doc.rust-lang.org/std/default/trait.Default.html
+    /// [FromIterator]: https://doc.rust-lang.org/std/iter/trait.FromIterator.html
     #[inline]
     #[must_use]
-    pub fn with_hasher<RS>(hasher: RS) -> Self
+    pub fn with_node_size() -> Self
+    where
+        S: Default,
+    {
+        HashSet {
+            size: 0,
+            pool: HashSetPool::default(),
+            root: Root::Empty,
+            hasher: LazyHasher::new(S::default),
+        }
+    }
+
+    /// Construct a hash set with a node size other than the default
+    /// from an iterator.
+    ///
+    /// Time: O(n)
+    #[must_use]
+    pub fn from_iter_with_node_size<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = A>,
+        A: Hash + Eq + Clone,
+        S: BuildHasher + Default,
+    {
+        let mut set = Self::with_node_size();
+        for value in iter {
+            set.insert(value);
+        }
+        set
+    }
+
+    /// Construct an empty hash set with a node size other than the
+    /// default, using the provided hasher.
+    #[inline]
+    #[must_use]
+    pub fn with_hasher_and_node_size<RS>(hasher: RS) -> Self
     where
         Ref<S>: From<RS>,
     {
-        let pool = HashSetPool::default();
-        let root = PoolRef::default(&pool.0);
         HashSet {
             size: 0,
-            pool,
-            root,
-            hasher: From::from(hasher),
+            pool: HashSetPool::default(),
+            root: Root::Empty,
+            hasher: LazyHasher::from(Ref::from(hasher)),
         }
     }
 
-    /// Construct an empty hash set using the provided memory pool and hasher.
+    /// Construct an empty hash set with a node size other than the
+    /// default, using the provided memory pool and hasher.
     #[cfg(feature = "pool")]
     #[inline]
     #[must_use]
-    pub fn with_pool_hasher<RS>(pool: &HashSetPool<A>, hasher: RS) -> Self
+    pub fn with_pool_hasher_and_node_size<RS>(pool: &HashSetPool<A, N>, hasher: RS) -> Self
     where
         Ref<S>: From<RS>,
     {
-        let root = PoolRef::default(&pool.0);
         HashSet {
             size: 0,
             pool: pool.clone(),
-            root,
-            hasher: From::from(hasher),
+            root: Root::Empty,
+            hasher: LazyHasher::from(Ref::from(hasher)),
         }
     }
 
     /// Get a reference to the set's [`BuildHasher`][BuildHasher].
     ///
+    /// If the set hasn't built its hasher yet, this builds it.
+    ///
     /// [BuildHasher]: https://doc.rust-lang.org/std/hash/trait.BuildHasher.html
     #[must_use]
     pub fn hasher(&self) -> &Ref<S> {
-        &self.hasher
+        self.hasher.get()
     }
 
     /// Construct an empty hash set using the same hasher as the current hash set.
     #[inline]
     #[must_use]
-    pub fn new_from<A1>(&self) -> HashSet<A1, S>
+    pub fn new_from<A1>(&self) -> HashSet<A1, S, N>
     where
         A1: Hash + Eq + Clone,
+        N: HashLevelSize<Value<A1>>,
     {
-        let pool = HashSetPool::default();
-        let root = PoolRef::default(&pool.0);
         HashSet {
             size: 0,
-            pool,
-            root,
-            hasher: self.hasher.clone(),
+            pool: HashSetPool::default(),
+            root: Root::Empty,
+            hasher: LazyHasher::from(self.hasher.get().clone()),
         }
     }
 
@@ -306,11 +416,112 @@ impl<A, S> HashSet<A, S> {
     /// ```
     pub fn clear(&mut self) {
         if !self.is_empty() {
-            self.root = PoolRef::default(&self.pool.0);
+            self.root = Root::Empty;
             self.size = 0;
         }
     }
 
+    /// Get the number of bytes of heap memory used by the set's nodes.
+    ///
+    /// Nodes shared with other sets are counted in full. Heap memory
+    /// owned by the values themselves isn't counted: use
+    /// [`heap_size_with`][heap_size_with] to include it.
+    ///
+    /// Time: O(n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::hashset::HashSet;
+    /// let set: HashSet<i64> = (0..1000).collect();
+    /// assert!(set.heap_size() >= 1000 * std::mem::size_of::<i64>());
+    /// ```
+    ///
+    /// [heap_size_with]: #method.heap_size_with
+    #[must_use]
+    pub fn heap_size(&self) -> usize {
+        self.heap_size_with(|_| 0)
+    }
+
+    /// Get the number of bytes of heap memory used by the set's nodes,
+    /// plus the number of bytes `value_size` reports for each value.
+    ///
+    /// Time: O(n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::hashset::HashSet;
+    /// let set = hashset![String::from("foo"), String::from("bar")];
+    /// assert_eq!(
+    ///     set.heap_size() + 6,
+    ///     set.heap_size_with(|string| string.capacity())
+    /// );
+    /// ```
+    pub fn heap_size_with<F>(&self, mut value_size: F) -> usize
+    where
+        F: FnMut(&A) -> usize,
+    {
+        let mut visitor = HeapSize::new(|value: &Value<A>| value_size(value));
+        self.visit(&mut visitor);
+        visitor.bytes()
+    }
+
+    /// Get the number of bytes of heap memory used by nodes which this
+    /// set shares with `other`.
+    ///
+    /// Time: O(n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::hashset::HashSet;
+    /// let set: HashSet<i64> = (0..1000).collect();
+    /// let mut other = set.clone();
+    /// assert_eq!(set.heap_size(), set.shared_with(&other));
+    /// other.insert(1000);
+    /// assert!(set.shared_with(&other) < set.heap_size());
+    /// ```
+    #[must_use]
+    pub fn shared_with(&self, other: &Self) -> usize {
+        let mut report = MemoryReport::new();
+        report.add(self);
+        report.add(other);
+        report.shared_bytes()
+    }
+
+    /// Get a summary of the shape of the set's tree.
+    ///
+    /// This method requires the `debug` feature flag.
+    ///
+    /// Time: O(n / 64)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::hashset::HashSet;
+    /// let set: HashSet<i64> = (0..1000).collect();
+    /// let stats = set.stats();
+    /// assert_eq!(1000, stats.len);
+    /// assert!(stats.depth >= 2);
+    /// ```
+    #[cfg(any(test, feature = "debug"))]
+    #[must_use]
+    pub fn stats(&self) -> Stats {
+        Stats::collect(self.len(), self)
+    }
+
+    fn visit(&self, visitor: &mut dyn Visitor<Value<A>>) {
+        if let Some(hasher) = self.hasher.get_if_built() {
+            visitor.node(memory::address(&**hasher), memory::size_of::<S>());
+        }
+        self.root.visit(visitor);
+    }
+
     /// Get an iterator over the values in a hash set.
     ///
     /// Please note that the order is consistent between sets using
@@ -319,35 +530,94 @@ impl<A, S> HashSet<A, S> {
     /// They will, however, come out in the same order every time for
     /// the same set.
     #[must_use]
-    pub fn iter(&self) -> Iter<'_, A> {
+    pub fn iter(&self) -> Iter<'_, A, N> {
         Iter {
             it: NodeIter::new(&self.root, self.size),
         }
     }
 }
 
-impl<A, S> HashSet<A, S>
+impl<A, S, N: HashLevelSize<Value<A>>> HashSet<A, S, N>
 where
     A: Hash + Eq,
     S: BuildHasher,
 {
+    /// Verify the internal consistency of a set, returning a
+    /// description of the first problem found, if any.
+    ///
+    /// This method walks the HAMT making up the set and verifies
+    /// that all of its invariants hold.
+    ///
+    /// This method requires the `debug` feature flag.
+    ///
+    /// Time: O(n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::hashset::HashSet;
+    /// let set: HashSet<i64> = (0..1000).collect();
+    /// assert_eq!(Ok(()), set.check_invariants());
+    /// ```
+    #[cfg(any(test, feature = "debug"))]
+    pub fn check_invariants(&self) -> Result<(), InvariantViolation> {
+        let len = self.root.check_invariants(&**self.hasher.get())?;
+        if self.size != len {
+            return Err(InvariantViolation::new(
+                "HAMT",
+                &[],
+                format!("set size is {}, but it has {} values", self.size, len),
+            ));
+        }
+        Ok(())
+    }
+
     fn test_eq(&self, other: &Self) -> bool {
-        if self.len() != other.len() {
+        if self.len() != other.len() || Root::known_unequal(&self.root, &other.root) {
             return false;
         }
-        let mut seen = collections::HashSet::new();
-        for value in self.iter() {
-            if !other.contains(value) {
-                return false;
-            }
-            seen.insert(value);
+        if Root::ptr_eq(&self.root, &other.root) {
+            return true;
         }
-        for value in other.iter() {
-            if !seen.contains(&value) {
-                return false;
-            }
+        // With the lengths equal, every value of ours being in the other
+        // set means it has no others. Nodes the two sets share at the same
+        // place in the tree hold the same values, so they're skipped.
+        Lockstep::new(&self.root, &other.root)
+            .filter_map(|(ours, _)| ours)
+            .all(|value| other.contains(&value.0))
+    }
+
+    /// Get an iterator over the differences between this set and
+    /// another, i.e. the set of values to add or remove to this set in
+    /// order to make it equal to the other set.
+    ///
+    /// This function will avoid visiting nodes which are shared
+    /// between the two sets, meaning that even very large sets can be
+    /// compared quickly if most of their structure is shared. The
+    /// differences come in no particular order.
+    ///
+    /// Time: O(n log n) (where n is the number of elements across the
+    /// two sets, minus the number of elements belonging to nodes shared
+    /// between them)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::hashset::{DiffItem, HashSet};
+    /// let old = hashset!{1, 2};
+    /// let new = old.update(3);
+    /// assert_eq!(vec![DiffItem::Add(&3)], old.diff(&new).collect::<Vec<_>>());
+    /// ```
+    #[must_use]
+    pub fn diff<'a>(&'a self, other: &'a Self) -> DiffIter<'a, A, S, N> {
+        DiffIter {
+            old: self,
+            new: other,
+            it: Lockstep::new(&self.root, &other.root),
+            added: None,
         }
-        true
     }
 
     /// Test if a value is part of a set.
@@ -359,7 +629,8 @@ where
         BA: Hash + Eq + ?Sized,
         A: Borrow<BA>,
     {
-        self.root.get(hash_key(&*self.hasher, a), 0, a).is_some()
+        let hasher = &self.hasher;
+        self.root.get(|| hash_key(&**hasher.get(), a), a).is_some()
     }
 
     /// Test whether a set is a subset of another set, meaning that
@@ -389,7 +660,7 @@ where
     }
 }
 
-impl<A, S> HashSet<A, S>
+impl<A, S, N: HashLevelSize<Value<A>>> HashSet<A, S, N>
 where
     A: Hash + Eq + Clone,
     S: BuildHasher,
@@ -399,9 +670,8 @@ where
     /// Time: O(log n)
     #[inline]
     pub fn insert(&mut self, a: A) -> Option<A> {
-        let hash = hash_key(&*self.hasher, &a);
-        let root = PoolRef::make_mut(&self.pool.0, &mut self.root);
-        match root.insert(&self.pool.0, hash, 0, Value(a)) {
+        let hash = hash_key(&**self.hasher.get(), &a);
+        match self.root.insert(&self.pool.0, hash, Value(a)) {
             None => {
                 self.size += 1;
                 None
@@ -418,14 +688,60 @@ where
         BA: Hash + Eq + ?Sized,
         A: Borrow<BA>,
     {
-        let root = PoolRef::make_mut(&self.pool.0, &mut self.root);
-        let result = root.remove(&self.pool.0, hash_key(&*self.hasher, a), 0, a);
+        let hasher = &self.hasher;
+        let result = self
+            .root
+            .remove(&self.pool.0, || hash_key(&**hasher.get(), a), a);
         if result.is_some() {
             self.size -= 1;
+            if self.size == 0 {
+                self.root = Root::Empty;
+            }
         }
         result.map(|v| v.0)
     }
 
+    /// Apply a patch to the set, adding or removing each value it
+    /// changes.
+    ///
+    /// Time: O(k log n) for a patch of k changes
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::hashset::HashSet;
+    /// # use im::patch::Patch;
+    /// let old = hashset![1, 2, 3];
+    /// let new = hashset![2, 3, 4];
+    /// let patch: Patch<_, _> = old.diff(&new).collect();
+    /// let mut set = old.clone();
+    /// set.apply_patch(&patch);
+    /// assert_eq!(new, set);
+    /// ```
+    pub fn apply_patch(&mut self, patch: &Patch<A, ()>) {
+        for (value, change) in patch.changes() {
+            if change.new_value().is_some() {
+                self.insert(value.clone());
+            } else {
+                self.remove(value);
+            }
+        }
+    }
+
+    /// Edit the set in a [transaction][transactions]: `f` edits a
+    /// copy of the set, which replaces it only if `f` returns `Ok`.
+    ///
+    /// Time: O(1), plus the edits
+    ///
+    /// [transactions]: crate#transactions
+    pub fn transaction<F, R, E>(&mut self, f: F) -> Result<R, E>
+    where
+        F: FnOnce(&mut Self) -> Result<R, E>,
+    {
+        transaction(self, f)
+    }
+
     /// Construct a new set from the current set with the given value
     /// added.
     ///
@@ -489,12 +805,14 @@ where
         F: FnMut(&A) -> bool,
     {
         let old_root = self.root.clone();
-        let root = PoolRef::make_mut(&self.pool.0, &mut self.root);
         for (value, hash) in NodeIter::new(&old_root, self.size) {
-            if !f(value) && root.remove(&self.pool.0, hash, 0, value).is_some() {
+            if !f(value) && self.root.remove(&self.pool.0, || hash, value).is_some() {
                 self.size -= 1;
             }
         }
+        if self.size == 0 {
+            self.root = Root::Empty;
+        }
     }
 
     /// Construct the union of two sets.
@@ -533,7 +851,7 @@ where
         I: IntoIterator<Item = Self>,
         S: Default,
     {
-        i.into_iter().fold(Self::default(), Self::union)
+        i.into_iter().fold(Self::with_node_size(), Self::union)
     }
 
     /// Construct the symmetric difference between two sets.
@@ -635,7 +953,20 @@ where
 
 // Core traits
 
-impl<A, S> Clone for HashSet<A, S>
+#[cfg(any(test, feature = "debug"))]
+impl<A, S, N: HashLevelSize<Value<A>>> Walk for HashSet<A, S, N> {
+    fn walk(&self, walker: &mut dyn Walker) {
+        self.root.walk(walker, 0, 1)
+    }
+}
+
+impl<A, S, N: HashLevelSize<Value<A>>> HeapNodes for HashSet<A, S, N> {
+    fn visit_heap_nodes(&self, node: &mut dyn FnMut(usize, usize) -> bool) {
+        memory::visit_nodes(|visitor| self.visit(visitor), node)
+    }
+}
+
+impl<A, S, N: HashLevelSize<Value<A>>> Clone for HashSet<A, S, N>
 where
     A: Clone,
 {
@@ -653,7 +984,9 @@ where
     }
 }
 
-impl<A, S> PartialEq for HashSet<A, S>
+/// Values which the two sets share aren't compared, which assumes that
+/// every value is equal to itself.
+impl<A, S, N: HashLevelSize<Value<A>>> PartialEq for HashSet<A, S, N>
 where
     A: Hash + Eq,
     S: BuildHasher + Default,
@@ -663,51 +996,71 @@ where
     }
 }
 
-impl<A, S> Eq for HashSet<A, S>
+impl<A, S, N: HashLevelSize<Value<A>>> Eq for HashSet<A, S, N>
 where
     A: Hash + Eq,
     S: BuildHasher + Default,
 {
 }
 
-impl<A, S> PartialOrd for HashSet<A, S>
+impl<A, S, N: HashLevelSize<Value<A>>> PartialOrd for HashSet<A, S, N>
 where
     A: Hash + Eq + Clone + PartialOrd,
     S: BuildHasher + Default,
 {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        if Ref::ptr_eq(&self.hasher, &other.hasher) {
-            return self.iter().partial_cmp(other.iter());
+        for pair in Lockstep::new(&self.root, &other.root) {
+            match pair {
+                (Some(left), Some(right)) => match left.partial_cmp(right) {
+                    Some(Ordering::Equal) => {}
+                    ordering => return ordering,
+                },
+                (Some(_), None) => return Some(Ordering::Greater),
+                (None, _) => return Some(Ordering::Less),
+            }
         }
-        self.iter().partial_cmp(other.iter())
+        Some(Ordering::Equal)
     }
 }
 
-impl<A, S> Ord for HashSet<A, S>
+impl<A, S, N: HashLevelSize<Value<A>>> Ord for HashSet<A, S, N>
 where
     A: Hash + Eq + Clone + Ord,
     S: BuildHasher + Default,
 {
     fn cmp(&self, other: &Self) -> Ordering {
-        if Ref::ptr_eq(&self.hasher, &other.hasher) {
-            return self.iter().cmp(other.iter());
+        for pair in Lockstep::new(&self.root, &other.root) {
+            match pair {
+                (Some(left), Some(right)) => match left.cmp(right) {
+                    Ordering::Equal => {}
+                    ordering => return ordering,
+                },
+                (Some(_), None) => return Ordering::Greater,
+                (None, _) => return Ordering::Less,
+            }
         }
-        self.iter().cmp(other.iter())
+        Ordering::Equal
     }
 }
 
-impl<A, S> Hash for HashSet<A, S>
+impl<A, S, N: HashLevelSize<Value<A>>> Hash for HashSet<A, S, N>
 where
     A: Hash + Eq,
     S: BuildHasher + Default,
 {
+    /// Hash the set.
+    ///
+    /// The hash doesn't depend on the order of the set's values, so two
+    /// equal sets hash the same even if they were built with different
+    /// hashers. With the `hash-cache` feature, the hash of every node in
+    /// the set is cached, so only nodes which have changed since the last
+    /// time are hashed again.
     fn hash<H>(&self, state: &mut H)
     where
         H: Hasher,
     {
-        for i in self.iter() {
-            i.hash(state);
-        }
+        state.write_usize(self.len());
+        state.write_u64(self.root.structural_hash());
     }
 }
 
@@ -716,66 +1069,59 @@ where
     S: BuildHasher + Default,
 {
     fn default() -> Self {
-        let pool = HashSetPool::default();
-        let root = PoolRef::default(&pool.0);
-        HashSet {
-            hasher: Ref::<S>::default(),
-            pool,
-            root,
-            size: 0,
-        }
+        Self::with_node_size()
     }
 }
 
-impl<A, S> Add for HashSet<A, S>
+impl<A, S, N: HashLevelSize<Value<A>>> Add for HashSet<A, S, N>
 where
     A: Hash + Eq + Clone,
     S: BuildHasher,
 {
-    type Output = HashSet<A, S>;
+    type Output = HashSet<A, S, N>;
 
     fn add(self, other: Self) -> Self::Output {
         self.union(other)
     }
 }
 
-impl<A, S> Mul for HashSet<A, S>
+impl<A, S, N: HashLevelSize<Value<A>>> Mul for HashSet<A, S, N>
 where
     A: Hash + Eq + Clone,
     S: BuildHasher,
 {
-    type Output = HashSet<A, S>;
+    type Output = HashSet<A, S, N>;
 
     fn mul(self, other: Self) -> Self::Output {
         self.intersection(other)
     }
 }
 
-impl<'a, A, S> Add for &'a HashSet<A, S>
+impl<'a, A, S, N: HashLevelSize<Value<A>>> Add for &'a HashSet<A, S, N>
 where
     A: Hash + Eq + Clone,
     S: BuildHasher,
 {
-    type Output = HashSet<A, S>;
+    type Output = HashSet<A, S, N>;
 
     fn add(self, other: Self) -> Self::Output {
         self.clone().union(other.clone())
     }
 }
 
-impl<'a, A, S> Mul for &'a HashSet<A, S>
+impl<'a, A, S, N: HashLevelSize<Value<A>>> Mul for &'a HashSet<A, S, N>
 where
     A: Hash + Eq + Clone,
     S: BuildHasher,
 {
-    type Output = HashSet<A, S>;
+    type Output = HashSet<A, S, N>;
 
     fn mul(self, other: Self) -> Self::Output {
         self.clone().intersection(other.clone())
     }
 }
 
-impl<A, S> Sum for HashSet<A, S>
+impl<A, S, N: HashLevelSize<Value<A>>> Sum for HashSet<A, S, N>
 where
     A: Hash + Eq + Clone,
     S: BuildHasher + Default,
@@ -784,11 +1130,11 @@ where
     where
         I: Iterator<Item = Self>,
     {
-        it.fold(Self::default(), |a, b| a + b)
+        it.fold(Self::with_node_size(), |a, b| a + b)
     }
 }
 
-impl<A, S, R> Extend<R> for HashSet<A, S>
+impl<A, S, R, N: HashLevelSize<Value<A>>> Extend<R> for HashSet<A, S, N>
 where
     A: Hash + Eq + Clone + From<R>,
     S: BuildHasher,
@@ -804,7 +1150,7 @@ where
 }
 
 #[cfg(not(has_specialisation))]
-impl<A, S> Debug for HashSet<A, S>
+impl<A, S, N: HashLevelSize<Value<A>>> Debug for HashSet<A, S, N>
 where
     A: Hash + Eq + Debug,
     S: BuildHasher,
@@ -815,7 +1161,7 @@ where
 }
 
 #[cfg(has_specialisation)]
-impl<A, S> Debug for HashSet<A, S>
+impl<A, S, N: HashLevelSize<Value<A>>> Debug for HashSet<A, S, N>
 where
     A: Hash + Eq + Debug,
     S: BuildHasher,
@@ -826,7 +1172,7 @@ where
 }
 
 #[cfg(has_specialisation)]
-impl<A, S> Debug for HashSet<A, S>
+impl<A, S, N: HashLevelSize<Value<A>>> Debug for HashSet<A, S, N>
 where
     A: Hash + Eq + Debug + Ord,
     S: BuildHasher,
@@ -839,11 +1185,11 @@ where
 // Iterators
 
 /// An iterator over the elements of a set.
-pub struct Iter<'a, A> {
-    it: NodeIter<'a, Value<A>>,
+pub struct Iter<'a, A, N: HashLevelSize<Value<A>> = DefaultHashLevelSize> {
+    it: NodeIter<'a, Value<A>, N>,
 }
 
-impl<'a, A> Iterator for Iter<'a, A>
+impl<'a, A, N: HashLevelSize<Value<A>>> Iterator for Iter<'a, A, N>
 where
     A: 'a,
 {
@@ -858,19 +1204,70 @@ where
     }
 }
 
-impl<'a, A> ExactSizeIterator for Iter<'a, A> {}
+impl<'a, A, N: HashLevelSize<Value<A>>> ExactSizeIterator for Iter<'a, A, N> {}
+
+impl<'a, A, N: HashLevelSize<Value<A>>> FusedIterator for Iter<'a, A, N> {}
+
+/// An iterator over the differences between two sets.
+pub struct DiffIter<'a, A, S, N: HashLevelSize<Value<A>> = DefaultHashLevelSize> {
+    old: &'a HashSet<A, S, N>,
+    new: &'a HashSet<A, S, N>,
+    it: Lockstep<'a, Value<A>, N>,
+    added: Option<&'a A>,
+}
+
+/// A description of a difference between two sets.
+#[derive(PartialEq, Eq, Debug)]
+pub enum DiffItem<'a, A> {
+    /// This value has been added to the new set.
+    Add(&'a A),
+    /// This value has been removed from the new set.
+    Remove(&'a A),
+}
+
+impl<'a, A, S, N: HashLevelSize<Value<A>>> Iterator for DiffIter<'a, A, S, N>
+where
+    A: Hash + Eq,
+    S: BuildHasher,
+{
+    type Item = DiffItem<'a, A>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(value) = self.added.take() {
+            return Some(DiffItem::Add(value));
+        }
+        loop {
+            let (old, new) = self.it.next()?;
+            let removed = old.filter(|value| !self.new.contains(&value.0));
+            let added = new.filter(|value| !self.old.contains(&value.0));
+            match (removed, added) {
+                (Some(removed), added) => {
+                    self.added = added.map(|value| &value.0);
+                    return Some(DiffItem::Remove(&removed.0));
+                }
+                (None, Some(added)) => return Some(DiffItem::Add(&added.0)),
+                (None, None) => {}
+            }
+        }
+    }
+}
 
-impl<'a, A> FusedIterator for Iter<'a, A> {}
+impl<'a, A, S, N: HashLevelSize<Value<A>>> FusedIterator for DiffIter<'a, A, S, N>
+where
+    A: Hash + Eq,
+    S: BuildHasher,
+{
+}
 
 /// A consuming iterator over the elements of a set.
-pub struct ConsumingIter<A>
+pub struct ConsumingIter<A, N: HashLevelSize<Value<A>> = DefaultHashLevelSize>
 where
     A: Hash + Eq + Clone,
 {
-    it: NodeDrain<Value<A>>,
+    it: NodeDrain<Value<A>, N>,
 }
 
-impl<A> Iterator for ConsumingIter<A>
+impl<A, N: HashLevelSize<Value<A>>> Iterator for ConsumingIter<A, N>
 where
     A: Hash + Eq + Clone,
 {
@@ -885,9 +1282,12 @@ where
     }
 }
 
-impl<A> ExactSizeIterator for ConsumingIter<A> where A: Hash + Eq + Clone {}
+impl<A, N: HashLevelSize<Value<A>>> ExactSizeIterator for ConsumingIter<A, N> where
+    A: Hash + Eq + Clone
+{
+}
 
-impl<A> FusedIterator for ConsumingIter<A> where A: Hash + Eq + Clone {}
+impl<A, N: HashLevelSize<Value<A>>> FusedIterator for ConsumingIter<A, N> where A: Hash + Eq + Clone {}
 
 // Iterator conversions
 
@@ -900,34 +1300,30 @@ where
     where
         T: IntoIterator<Item = RA>,
     {
-        let mut set = Self::default();
-        for value in i {
-            set.insert(From::from(value));
-        }
-        set
+        Self::from_iter_with_node_size(i.into_iter().map(From::from))
     }
 }
 
-impl<'a, A, S> IntoIterator for &'a HashSet<A, S>
+impl<'a, A, S, N: HashLevelSize<Value<A>>> IntoIterator for &'a HashSet<A, S, N>
 where
     A: Hash + Eq,
     S: BuildHasher,
 {
     type Item = &'a A;
-    type IntoIter = Iter<'a, A>;
+    type IntoIter = Iter<'a, A, N>;
 
     fn into_iter(self) -> Self::IntoIter {
         self.iter()
     }
 }
 
-impl<A, S> IntoIterator for HashSet<A, S>
+impl<A, S, N: HashLevelSize<Value<A>>> IntoIterator for HashSet<A, S, N>
 where
     A: Hash + Eq + Clone,
     S: BuildHasher,
 {
     type Item = A;
-    type IntoIter = ConsumingIter<Self::Item>;
+    type IntoIter = ConsumingIter<Self::Item, N>;
 
     fn into_iter(self) -> Self::IntoIter {
         ConsumingIter {
@@ -938,15 +1334,16 @@ where
 
 // Conversions
 
-impl<'s, 'a, A, OA, SA, SB> From<&'s HashSet<&'a A, SA>> for HashSet<OA, SB>
+impl<'s, 'a, A, OA, SA, SB, N> From<&'s HashSet<&'a A, SA, N>> for HashSet<OA, SB, N>
 where
     A: ToOwned<Owned = OA> + Hash + Eq + ?Sized,
     OA: Borrow<A> + Hash + Eq + Clone,
     SA: BuildHasher,
     SB: BuildHasher + Default,
+    N: HashLevelSize<Value<&'a A>> + HashLevelSize<Value<OA>>,
 {
-    fn from(set: &HashSet<&A, SA>) -> Self {
-        set.iter().map(|a| (*a).to_owned()).collect()
+    fn from(set: &'s HashSet<&'a A, SA, N>) -> Self {
+        Self::from_iter_with_node_size(set.iter().map(|a| (*a).to_owned()))
     }
 }
 
@@ -1070,6 +1467,71 @@ mod test {
     use ::proptest::proptest;
     use std::hash::BuildHasherDefault;
 
+    #[test]
+    fn comparisons_skip_shared_nodes() {
+        let set: HashSet<i32> = (0..10_000).collect();
+        let versions = vec![
+            set.without(&9999),
+            set.without(&5000),
+            set.update(10_000),
+            set.without(&5000).update(5000),
+            set.without(&5000).update(10_000),
+            HashSet::new(),
+            set.clone(),
+        ];
+        for left in &versions {
+            for right in &versions {
+                assert_eq!(left.iter().cmp(right.iter()), left.cmp(right));
+                let same = left.len() == right.len() && left.iter().all(|v| right.contains(v));
+                assert_eq!(same, left == right);
+            }
+        }
+    }
+
+    #[test]
+    fn wide_levels() {
+        let mut set: HashSet<i32, BuildHasherDefault<LolHasher>, typenum::U6> =
+            HashSet::from_iter_with_node_size(0..10_000);
+        assert_eq!(Ok(()), set.check_invariants());
+        set.retain(|i| i % 3 == 0);
+        assert_eq!(Ok(()), set.check_invariants());
+        assert_eq!(3334, set.len());
+        assert!((0..10_000).all(|i| set.contains(&i) == (i % 3 == 0)));
+    }
+
+    #[test]
+    fn hash_ignores_hasher() {
+        fn hash_of<A: Hash>(value: &A) -> u64 {
+            let mut hasher = collections::hash_map::DefaultHasher::new();
+            value.hash(&mut hasher);
+            hasher.finish()
+        }
+        let random: HashSet<i32> = (0..10_000).collect();
+        let lol: HashSet<i32, BuildHasherDefault<LolHasher>> = (0..10_000).collect();
+        assert_eq!(hash_of(&random), hash_of(&lol));
+        let mut changed = random.clone();
+        changed.remove(&5000);
+        assert_ne!(hash_of(&random), hash_of(&changed));
+        assert_ne!(random, changed);
+        changed.insert(5000);
+        assert_eq!(hash_of(&random), hash_of(&changed));
+        assert_eq!(random, changed);
+    }
+
+    #[test]
+    fn clones_of_an_empty_set_hash_alike() {
+        let empty: HashSet<i32> = HashSet::new();
+        let mut left = empty.clone();
+        let mut right = empty.clone();
+        for i in 0..1000 {
+            left.insert(i);
+            right.insert(i);
+        }
+        assert_eq!(left, right);
+        assert_eq!(Ordering::Equal, left.cmp(&right));
+        assert_eq!(0, left.diff(&right).count());
+    }
+
     #[test]
     fn insert_failing() {
         let mut set: HashSet<i16, BuildHasherDefault<LolHasher>> = Default::default();
@@ -0,0 +1,233 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Counters for node allocations, copies and frees.
+//!
+//! Updating a collection copies every node on the path to the update
+//! which it shares with another collection. That's what makes the old
+//! version stay the same, but when the sharing is accidental, say because
+//! a clone was kept around for longer than it needed to be, it turns a
+//! cheap update into an expensive one without any visible sign.
+//!
+//! With the `instrument` feature, every node allocated, copied or freed
+//! by the current thread is counted, by the kind of tree it belongs to,
+//! which makes that sort of thing easy to catch in a test:
+//!
+//! ```
+//! # use im::Vector;
+//! # use im::instrument::{self, Tree};
+//! let mut vec: Vector<i32> = (0..10_000).collect();
+//! instrument::reset();
+//! vec.set(5000, 0);
+//! assert_eq!(0, instrument::counts(Tree::Rrb).clones);
+//!
+//! let snapshot = vec.clone();
+//! vec.set(5000, 1);
+//! assert!(instrument::counts(Tree::Rrb).clones > 0);
+//! # drop(snapshot);
+//! ```
+//!
+//! For more detail, [`set_hook`][set_hook] installs a function to be
+//! called for every counted operation, which can capture a backtrace to
+//! find out where the copying happens.
+//!
+//! The counters are kept per thread, so tests running in parallel don't
+//! see each other's operations. A node freed on another thread, such as
+//! a [`DropQueue`][crate::drop_queue::DropQueue]'s, is counted there.
+//!
+//! In `im-rc`, nodes allocated from a pool with the `pool` feature aren't
+//! counted, as they're handled entirely by `refpool`.
+//!
+//! This module requires the `instrument` feature flag, which also adds a
+//! small amount of bookkeeping to every node.
+
+use std::cell::{Cell, RefCell};
+
+/// The kind of tree a node belongs to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Tree {
+    /// The B-trees of [`OrdMap`][crate::OrdMap] and [`OrdSet`][crate::OrdSet].
+    BTree,
+    /// The hash array mapped tries of [`HashMap`][crate::HashMap] and
+    /// [`HashSet`][crate::HashSet].
+    Hamt,
+    /// The RRB trees of [`Vector`][crate::Vector].
+    Rrb,
+}
+
+impl Tree {
+    fn index(self) -> usize {
+        match self {
+            Tree::BTree => 0,
+            Tree::Hamt => 1,
+            Tree::Rrb => 2,
+        }
+    }
+}
+
+/// Something that happened to a node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Operation {
+    /// A new node was allocated.
+    Allocate,
+    /// A shared node was copied so that it could be updated.
+    Clone,
+    /// A node was freed.
+    Free,
+}
+
+/// The number of node operations counted for a kind of tree.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Counts {
+    /// The number of new nodes allocated, not counting copies.
+    pub allocations: usize,
+    /// The number of shared nodes copied on write.
+    pub clones: usize,
+    /// The number of nodes freed.
+    pub frees: usize,
+}
+
+type Hook = Box<dyn FnMut(Tree, Operation)>;
+
+thread_local! {
+    static COUNTS: Cell<[Counts; 3]> = Cell::new([Counts::default(); 3]);
+    static HOOK: RefCell<Option<Hook>> = RefCell::new(None);
+}
+
+/// Get the operations counted on the current thread for nodes of the given
+/// kind since the last [`reset`][reset].
+#[must_use]
+pub fn counts(tree: Tree) -> Counts {
+    COUNTS.with(|counts| counts.get()[tree.index()])
+}
+
+/// Set the current thread's counters back to zero.
+pub fn reset() {
+    COUNTS.with(|counts| counts.set([Counts::default(); 3]));
+}
+
+/// Install a function to be called for every node operation on the current
+/// thread, replacing any previous one, or remove it with `None`.
+///
+/// Operations performed by the hook itself aren't reported to it, though
+/// they're still counted.
+pub fn set_hook(hook: Option<Box<dyn FnMut(Tree, Operation)>>) {
+    HOOK.with(|current| *current.borrow_mut() = hook);
+}
+
+pub(crate) fn record(tree: Tree, operation: Operation) {
+    // Nodes can be dropped during thread teardown, after the thread
+    // locals have gone away.
+    let _ = COUNTS.try_with(|counts| {
+        let mut all = counts.get();
+        let count = &mut all[tree.index()];
+        match operation {
+            Operation::Allocate => count.allocations += 1,
+            Operation::Clone => count.clones += 1,
+            Operation::Free => count.frees += 1,
+        }
+        counts.set(all);
+    });
+    let _ = HOOK.try_with(|hook| {
+        if let Ok(mut hook) = hook.try_borrow_mut() {
+            if let Some(hook) = hook.as_mut() {
+                hook(tree, operation);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::LolHasher;
+    use crate::{HashMap, OrdMap, Vector};
+    use std::hash::BuildHasherDefault;
+    use std::rc::Rc;
+    use typenum::U8;
+
+    #[test]
+    fn counts_copy_on_write() {
+        let mut map: OrdMap<i32, i32> = (0..10_000).map(|i| (i, i)).collect();
+        reset();
+        map.insert(5000, 0);
+        assert_eq!(0, counts(Tree::BTree).clones);
+
+        let snapshot = map.clone();
+        map.insert(5000, 1);
+        let after = counts(Tree::BTree);
+        assert!(after.clones >= 2);
+        assert_eq!(0, after.frees);
+
+        reset();
+        drop(snapshot);
+        assert_eq!(after.clones, counts(Tree::BTree).frees);
+        assert_eq!(Counts::default(), counts(Tree::Rrb));
+    }
+
+    #[test]
+    fn every_allocation_is_freed() {
+        reset();
+        {
+            let vec: Vector<i32> = (0..10_000).collect();
+            let mut other = vec.clone();
+            other.push_front(1);
+            let map: HashMap<i32, i32> = (0..1000).map(|i| (i, i)).collect();
+            let mut other_map = map.clone();
+            other_map.remove(&500);
+        }
+        for tree in &[Tree::Rrb, Tree::Hamt] {
+            let counts = counts(*tree);
+            assert!(counts.allocations > 0);
+            assert_eq!(counts.allocations + counts.clones, counts.frees);
+        }
+    }
+
+    #[test]
+    fn counts_small_roots() {
+        reset();
+        let map: HashMap<i32, i32> = (0..3).map(|i| (i, i)).collect();
+        assert_eq!(1, counts(Tree::Hamt).allocations);
+        let mut other = map.clone();
+        other.insert(3, 3);
+        assert_eq!(1, counts(Tree::Hamt).clones);
+        drop((map, other));
+        assert_eq!(2, counts(Tree::Hamt).frees);
+    }
+
+    #[test]
+    fn counts_collision_nodes() {
+        // Every key hashes to zero, so they all share a collision node.
+        let mut map: HashMap<i32, i32, BuildHasherDefault<LolHasher<U8>>> =
+            (0..20).map(|i| (i * 256, i)).collect();
+        reset();
+        let snapshot = map.clone();
+        map.insert(0, -1);
+        // A node at each of the seven levels of a 32 bit hash, and the
+        // collision node below them.
+        assert_eq!(8, counts(Tree::Hamt).clones);
+        drop(snapshot);
+        assert_eq!(8, counts(Tree::Hamt).frees);
+        drop(map);
+        assert_eq!(16, counts(Tree::Hamt).frees);
+    }
+
+    #[test]
+    fn calls_the_hook() {
+        let seen = Rc::new(Cell::new(0));
+        let hook_seen = seen.clone();
+        set_hook(Some(Box::new(move |tree, operation| {
+            assert_eq!(Tree::BTree, tree);
+            if operation == Operation::Clone {
+                hook_seen.set(hook_seen.get() + 1);
+            }
+        })));
+        let mut map: OrdMap<i32, i32> = (0..1000).map(|i| (i, i)).collect();
+        let snapshot = map.clone();
+        map.remove(&10);
+        set_hook(None);
+        assert!(seen.get() > 0);
+        drop(snapshot);
+    }
+}
@@ -211,6 +211,11 @@
 //! I've used in these docs to save myself from having to type
 //! 'amortised' everywhere.
 //!
+//! The 64 above is the default node size. Each collection takes its
+//! node size as an optional type parameter, so you can trade lookup
+//! speed against the cost of updating shared data: see the
+//! [`config`][config] module.
+//!
 //! ### Lists
 //!
 //! Lists are sequences of single elements which maintain the order in
@@ -280,6 +285,35 @@
 //! collector (which, in our case, is just a simple
 //! [`Rc`][std::rc::Rc]).
 //!
+//! ### Transactions
+//!
+//! Every collection has a `transaction` method, which gives a function a
+//! copy of the collection to edit. The copy replaces the collection if
+//! the function returns `Ok`, and is thrown away if it returns `Err` or
+//! panics, leaving the collection exactly as it was. The copy shares all
+//! of its structure with the original until it's changed, so making it
+//! takes O(1) time, and so does throwing it away: a failed transaction
+//! only costs the nodes it had already copied.
+//!
+//! ```
+//! # #[macro_use] extern crate im;
+//! # use im::hashmap::HashMap;
+//! let mut map = hashmap!{1 => 10};
+//! let failed: Result<(), &str> = map.transaction(|map| {
+//!     map.insert(2, 20);
+//!     Err("changed my mind")
+//! });
+//! assert_eq!(Err("changed my mind"), failed);
+//! assert_eq!(hashmap!{1 => 10}, map);
+//!
+//! let done: Result<(), &str> = map.transaction(|map| {
+//!     map.insert(2, 20);
+//!     Ok(())
+//! });
+//! assert_eq!(Ok(()), done);
+//! assert_eq!(hashmap!{1 => 10, 2 => 20}, map);
+//! ```
+//!
 //! ## Thread Safety
 //!
 //! The data structures in the `im` crate are thread safe, through
@@ -291,6 +325,11 @@
 //! [`Sync`][std::marker::Sync]. This yields approximately a 20-25% increase in
 //! general performance.
 //!
+//! To share a collection that's being updated between threads, keep it
+//! in an [`Atom`][sync::Atom], which hands out snapshots of the current
+//! version without taking a lock, and swaps in new ones. This isn't
+//! available in `im-rc`.
+//!
 //! ## Feature Flags
 //!
 //! `im` comes with optional support for the following crates through Cargo
@@ -303,12 +342,16 @@
 //!
 //! | Feature | Description |
 //! | ------- | ----------- |
-//! | [`pool`](https://crates.io/crates/refpool) | Constructors and pool types for [`refpool`](https://crates.io/crates/refpool) memory pools (only available in `im-rc`) |
+//! | [`pool`](https://crates.io/crates/refpool) | Constructors and pool types for [`refpool`](https://crates.io/crates/refpool) memory pools (in `im`, a thread safe pool is used instead of `refpool`) |
 //! | [`proptest`](https://crates.io/crates/proptest) | Strategies for all `im` datatypes under a `proptest` namespace, eg. `im::vector::proptest::vector()` |
 //! | [`quickcheck`](https://crates.io/crates/quickcheck) | [`quickcheck::Arbitrary`](https://docs.rs/quickcheck/latest/quickcheck/trait.Arbitrary.html) implementations for all `im` datatypes (not available in `im-rc`) |
-//! | [`rayon`](https://crates.io/crates/rayon) | parallel iterator implementations for [`Vector`][vector::Vector] (not available in `im-rc`) |
+//! | [`rayon`](https://crates.io/crates/rayon) | parallel iterator implementations for [`Vector`][vector::Vector] with the default chunk size (not available in `im-rc`) |
 //! | [`serde`](https://crates.io/crates/serde) | [`Serialize`](https://docs.rs/serde/latest/serde/trait.Serialize.html) and [`Deserialize`](https://docs.rs/serde/latest/serde/trait.Deserialize.html) implementations for all `im` datatypes |
 //! | [`arbitrary`](https://crates.io/crates/arbitrary/) | [`arbitrary::Arbitrary`](https://docs.rs/arbitrary/latest/arbitrary/trait.Arbitrary.html) implementations for all `im` datatypes |
+//! | `hash-cache` | Cache the hash of every node of a [`HashMap`][hashmap::HashMap] or [`HashSet`][hashset::HashSet], so that hashing a map or set used as a key again only rehashes what's changed, and comparing two whose hashes differ returns early |
+//! | `instrument` | Per thread counters of node allocations, copies and frees, in the [`instrument`][instrument] module |
+//! | `merkle` | Cached digests of the nodes of an [`OrdMap`][ordmap::OrdMap], for inclusion proofs and for finding where two maps differ, in the [`merkle`][merkle] module |
+//! | `derive` | `#[derive(Diff)]` for structs, implementing [`Diff`][diff::Diff] field by field, from the `im-derive` crate |
 //!
 //! [std::collections]: https://doc.rust-lang.org/std/collections/index.html
 //! [std::collections::VecDeque]: https://doc.rust-lang.org/std/collections/struct.VecDeque.html
@@ -344,10 +387,10 @@
 #[macro_use]
 extern crate pretty_assertions;
 
-mod config;
+pub mod config;
+pub mod error;
 mod nodes;
 mod sort;
-mod sync;
 
 #[macro_use]
 mod util;
@@ -367,6 +410,31 @@ pub mod vector;
 
 pub mod iter;
 
+pub mod memory;
+
+pub mod patch;
+
+pub mod diff;
+
+pub mod versioned;
+
+#[cfg(threadsafe)]
+pub mod sync;
+#[cfg(not(threadsafe))]
+mod sync;
+
+#[cfg(threadsafe)]
+pub mod drop_queue;
+
+#[cfg(feature = "instrument")]
+pub mod instrument;
+
+#[cfg(feature = "merkle")]
+pub mod merkle;
+
+#[cfg(any(test, feature = "debug"))]
+pub mod debug;
+
 #[cfg(any(test, feature = "proptest"))]
 pub mod proptest;
 
@@ -374,6 +442,9 @@ pub mod proptest;
 #[doc(hidden)]
 pub mod ser;
 
+#[cfg(any(test, feature = "serde"))]
+pub mod snapshot;
+
 #[cfg(feature = "arbitrary")]
 #[doc(hidden)]
 pub mod arbitrary;
@@ -382,14 +453,14 @@ pub mod arbitrary;
 #[doc(hidden)]
 pub mod quickcheck;
 
-#[cfg(any(threadsafe, not(feature = "pool")))]
+#[cfg(not(feature = "pool"))]
 mod fakepool;
 
 #[cfg(all(threadsafe, feature = "pool"))]
-compile_error!(
-    "The `pool` feature is not threadsafe but you've enabled it on a threadsafe version of `im`."
-);
+mod syncpool;
 
+#[doc(inline)]
+pub use crate::diff::Diff;
 pub use crate::hashmap::HashMap;
 pub use crate::hashset::HashSet;
 pub use crate::ordmap::OrdMap;
@@ -0,0 +1,392 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Memory accounting for persistent data structures.
+//!
+//! Every collection in this crate is a tree of reference counted nodes,
+//! and a clone of a collection shares all of those nodes with the
+//! original until one of them is changed. This means the question "how
+//! much memory does this collection use?" has two answers: the size of
+//! all the nodes reachable from it, and the size of the nodes which
+//! aren't also reachable from some other collection you're holding on
+//! to.
+//!
+//! Each collection has a `heap_size()` method for the first answer, and
+//! a `shared_with()` method to find out how much of that is shared with
+//! another collection. To account for many snapshots at once, add them
+//! all to a [`MemoryReport`][MemoryReport], which counts each node only
+//! once no matter how many snapshots it's reachable from.
+//!
+//! Nodes are identified by their address in memory, so a report is only
+//! meaningful as long as none of the collections added to it have been
+//! changed or dropped.
+//!
+//! # Examples
+//!
+//! ```
+//! # use im::memory::MemoryReport;
+//! # use im::Vector;
+//! let first: Vector<i64> = (0..10_000).collect();
+//! let mut second = first.clone();
+//! second.set(0, -1);
+//!
+//! let mut report = MemoryReport::new();
+//! report.add(&first);
+//! report.add(&second);
+//!
+//! // Most of the nodes are shared between the two vectors, so the
+//! // total is much less than twice the size of one of them.
+//! assert!(report.total_bytes() < first.heap_size() * 2);
+//! assert!(report.shared_bytes() > 0);
+//! ```
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap as StdHashMap;
+use std::collections::HashSet as StdHashSet;
+use std::mem;
+
+/// A visitor over the heap allocations making up a data structure.
+pub(crate) trait Visitor<A> {
+    /// Visit an allocation of `bytes` bytes at `address`.
+    ///
+    /// Returns `true` if the allocation hasn't been visited before and
+    /// its contents should be visited too.
+    fn node(&mut self, address: usize, bytes: usize) -> bool;
+
+    /// Visit a value stored in the data structure.
+    fn value(&mut self, value: &A);
+}
+
+/// Get the address of a value, for use as the identity of a node.
+pub(crate) fn address<A>(value: &A) -> usize {
+    value as *const A as usize
+}
+
+/// Get the size of the allocation holding a value of type `A`.
+pub(crate) fn size_of<A>() -> usize {
+    mem::size_of::<A>()
+}
+
+/// A visitor summing up the size of every distinct node it sees, plus
+/// whatever a user supplied function reports for each value.
+pub(crate) struct HeapSize<F> {
+    seen: StdHashSet<usize>,
+    bytes: usize,
+    value_size: F,
+}
+
+impl<F> HeapSize<F> {
+    pub(crate) fn new(value_size: F) -> Self {
+        HeapSize {
+            seen: StdHashSet::new(),
+            bytes: 0,
+            value_size,
+        }
+    }
+
+    pub(crate) fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
+
+impl<A, F> Visitor<A> for HeapSize<F>
+where
+    F: FnMut(&A) -> usize,
+{
+    fn node(&mut self, address: usize, bytes: usize) -> bool {
+        if self.seen.insert(address) {
+            self.bytes += bytes;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn value(&mut self, value: &A) {
+        self.bytes += (self.value_size)(value);
+    }
+}
+
+/// Adapts a node callback into a [`Visitor`][Visitor] which ignores
+/// values.
+struct Nodes<'a>(&'a mut dyn FnMut(usize, usize) -> bool);
+
+impl<'a, A> Visitor<A> for Nodes<'a> {
+    fn node(&mut self, address: usize, bytes: usize) -> bool {
+        (self.0)(address, bytes)
+    }
+
+    fn value(&mut self, _value: &A) {}
+}
+
+/// Visit every node of a data structure with a callback, ignoring the
+/// values it contains.
+pub(crate) fn visit_nodes<A, F>(visit: F, callback: &mut dyn FnMut(usize, usize) -> bool)
+where
+    F: FnOnce(&mut dyn Visitor<A>),
+{
+    visit(&mut Nodes(callback))
+}
+
+/// A data structure which can be measured by a [`MemoryReport`][MemoryReport].
+///
+/// This is implemented for all the collections in this crate.
+pub trait HeapNodes {
+    /// Call `node` with the address and size of each heap allocation
+    /// reachable from this data structure.
+    ///
+    /// If `node` returns `false`, the contents of the allocation it was
+    /// called for aren't visited.
+    #[doc(hidden)]
+    fn visit_heap_nodes(&self, node: &mut dyn FnMut(usize, usize) -> bool);
+}
+
+/// A report of the memory used by a set of data structures.
+///
+/// Each node is counted once, however many of the data structures added
+/// to the report share it, so this gives you the actual memory footprint
+/// of keeping a number of snapshots of a data structure alive at the same
+/// time.
+///
+/// Only the nodes of the data structures are counted, not any heap memory
+/// owned by the values stored in them.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryReport {
+    nodes: StdHashMap<usize, Allocation>,
+    roots: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Allocation {
+    bytes: usize,
+    /// The last data structure this node was reached from.
+    root: usize,
+    /// Whether this node is reachable from more than one data structure.
+    shared: bool,
+}
+
+impl MemoryReport {
+    /// Construct an empty report.
+    #[must_use]
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add a data structure to the report.
+    ///
+    /// Time: O(n) in the number of nodes which weren't already reachable
+    /// from more than one of the data structures in the report.
+    pub fn add<C>(&mut self, collection: &C)
+    where
+        C: HeapNodes + ?Sized,
+    {
+        self.roots += 1;
+        let root = self.roots;
+        let nodes = &mut self.nodes;
+        collection.visit_heap_nodes(&mut |address, bytes| match nodes.entry(address) {
+            Entry::Vacant(entry) => {
+                entry.insert(Allocation {
+                    bytes,
+                    root,
+                    shared: false,
+                });
+                true
+            }
+            Entry::Occupied(mut entry) => {
+                let node = entry.get_mut();
+                if node.root == root || node.shared {
+                    // Either we've been here before from this data
+                    // structure, or the node and everything below it is
+                    // already known to be shared.
+                    return false;
+                }
+                // A node we first reached from another data structure:
+                // it and everything below it are now shared.
+                node.root = root;
+                node.shared = true;
+                true
+            }
+        });
+    }
+
+    /// Get the number of data structures added to the report.
+    #[must_use]
+    pub fn roots(&self) -> usize {
+        self.roots
+    }
+
+    /// Get the number of distinct nodes in the report.
+    #[must_use]
+    pub fn total_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Get the number of bytes used by all the distinct nodes in the
+    /// report.
+    #[must_use]
+    pub fn total_bytes(&self) -> usize {
+        self.nodes.values().map(|node| node.bytes).sum()
+    }
+
+    /// Get the number of nodes which are reachable from more than one of
+    /// the data structures in the report.
+    #[must_use]
+    pub fn shared_nodes(&self) -> usize {
+        self.nodes.values().filter(|node| node.shared).count()
+    }
+
+    /// Get the number of bytes used by nodes which are reachable from
+    /// more than one of the data structures in the report.
+    #[must_use]
+    pub fn shared_bytes(&self) -> usize {
+        self.nodes
+            .values()
+            .filter(|node| node.shared)
+            .map(|node| node.bytes)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{HashMap, HashSet, OrdMap, OrdSet, Vector};
+
+    fn check_snapshots<C>(first: &C, second: &C)
+    where
+        C: HeapNodes,
+    {
+        let mut alone = MemoryReport::new();
+        alone.add(first);
+        let mut report = MemoryReport::new();
+        report.add(first);
+        report.add(second);
+        assert_eq!(2, report.roots());
+        assert!(report.total_nodes() >= alone.total_nodes());
+        assert!(report.shared_bytes() <= alone.total_bytes());
+        assert!(report.shared_nodes() <= alone.total_nodes());
+    }
+
+    #[test]
+    fn clones_share_everything() {
+        let vector: Vector<i32> = (0..10_000).collect();
+        assert_eq!(vector.heap_size(), vector.shared_with(&vector.clone()));
+        let ordmap: OrdMap<i32, i32> = (0..10_000).map(|i| (i, i)).collect();
+        assert_eq!(ordmap.heap_size(), ordmap.shared_with(&ordmap.clone()));
+        let ordset: OrdSet<i32> = (0..10_000).collect();
+        assert_eq!(ordset.heap_size(), ordset.shared_with(&ordset.clone()));
+        let hashmap: HashMap<i32, i32> = (0..10_000).map(|i| (i, i)).collect();
+        assert_eq!(hashmap.heap_size(), hashmap.shared_with(&hashmap.clone()));
+        let hashset: HashSet<i32> = (0..10_000).collect();
+        assert_eq!(hashset.heap_size(), hashset.shared_with(&hashset.clone()));
+    }
+
+    #[test]
+    fn unrelated_collections_share_nothing() {
+        let left: OrdMap<i32, i32> = (0..10_000).map(|i| (i, i)).collect();
+        let right: OrdMap<i32, i32> = (0..10_000).map(|i| (i, i)).collect();
+        assert_eq!(0, left.shared_with(&right));
+        let mut report = MemoryReport::new();
+        report.add(&left);
+        report.add(&right);
+        assert_eq!(left.heap_size() + right.heap_size(), report.total_bytes());
+    }
+
+    #[test]
+    fn snapshots_share_unchanged_nodes() {
+        let first: Vector<i32> = (0..10_000).collect();
+        let mut second = first.clone();
+        second.set(5000, -1);
+        check_snapshots(&first, &second);
+        let shared = first.shared_with(&second);
+        assert!(shared > 0);
+        assert!(shared < first.heap_size());
+
+        let first: HashMap<i32, i32> = (0..10_000).map(|i| (i, i)).collect();
+        let mut second = first.clone();
+        second.insert(5000, -1);
+        check_snapshots(&first, &second);
+        let shared = first.shared_with(&second);
+        assert!(shared > 0);
+        assert!(shared < first.heap_size());
+
+        let first: OrdSet<i32> = (0..10_000).collect();
+        let mut second = first.clone();
+        second.remove(&5000);
+        check_snapshots(&first, &second);
+        let shared = first.shared_with(&second);
+        assert!(shared > 0);
+        assert!(shared < first.heap_size());
+    }
+
+    #[test]
+    fn report_counts_many_snapshots_once() {
+        let mut snapshots = vec![(0..10_000).collect::<Vector<i32>>()];
+        for i in 0..10 {
+            let mut next = snapshots.last().unwrap().clone();
+            next.set(i * 1000, -1);
+            snapshots.push(next);
+        }
+        let mut report = MemoryReport::new();
+        for snapshot in &snapshots {
+            report.add(snapshot);
+        }
+        let sum: usize = snapshots.iter().map(Vector::heap_size).sum();
+        assert_eq!(11, report.roots());
+        assert!(report.total_bytes() < sum / 2);
+        assert!(report.total_bytes() >= snapshots[0].heap_size());
+    }
+
+    #[test]
+    fn heap_size_with_values() {
+        let vector: Vector<String> = (0..1000).map(|i| i.to_string()).collect();
+        let strings: usize = vector.iter().map(String::capacity).sum();
+        assert_eq!(
+            vector.heap_size() + strings,
+            vector.heap_size_with(String::capacity)
+        );
+        let set: HashSet<String> = vector.iter().cloned().collect();
+        let strings: usize = set.iter().map(String::capacity).sum();
+        assert_eq!(
+            set.heap_size() + strings,
+            set.heap_size_with(String::capacity)
+        );
+    }
+
+    #[test]
+    fn empty_collections() {
+        let vector: Vector<i32> = Vector::new();
+        assert_eq!(0, vector.heap_size());
+        let mut map: OrdMap<i32, i32> = OrdMap::new();
+        assert_eq!(0, map.heap_size());
+        map.insert(1, 1);
+        assert!(map.heap_size() > 0);
+        map.remove(&1);
+        assert_eq!(0, map.heap_size());
+        let mut set: OrdSet<i32> = (0..1000).collect();
+        set.clear();
+        assert_eq!(0, set.heap_size());
+        set.insert(1);
+        assert_eq!(Some(1), set.split_off(&0).remove(&1));
+        assert_eq!(0, set.heap_size());
+
+        // Hash maps don't build their hasher until the first insert or
+        // clone, and then only hold on to the hasher.
+        let hasher_size = size_of::<std::collections::hash_map::RandomState>();
+        let mut map: HashMap<i32, i32> = HashMap::new();
+        assert_eq!(0, map.heap_size());
+        assert_eq!(None, map.remove(&1));
+        assert_eq!(hasher_size, map.clone().heap_size());
+        assert_eq!(hasher_size, map.heap_size());
+        map.insert(1, 1);
+        map.retain(|_, _| false);
+        assert_eq!(hasher_size, map.heap_size());
+        let mut set: HashSet<i32> = (0..1000).collect();
+        for i in 0..1000 {
+            set.remove(&i);
+        }
+        assert_eq!(hasher_size, set.heap_size());
+        assert_eq!(0, set.iter().count());
+    }
+}
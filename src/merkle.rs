@@ -0,0 +1,551 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Merkle digests of an [`OrdMap`][OrdMap].
+//!
+//! With the `merkle` feature, every node of an [`OrdMap`][OrdMap] can
+//! remember a digest of the entries below it, computed by a
+//! [`Digester`][Digester] of your choosing. Nodes which haven't changed
+//! keep their digests, so once a map has been digested, the digest of an
+//! updated copy only costs as much as the update did.
+//!
+//! On top of the digests, a map can:
+//!
+//! * produce a digest of all of its entries with
+//!   [`root_digest`][OrdMap::root_digest], or of a range of keys with
+//!   [`range_digest`][OrdMap::range_digest];
+//! * [`prove`][OrdMap::prove] that it contains an entry to someone who
+//!   only knows its root digest, with a [`Proof`][Proof];
+//! * [`reconcile`][OrdMap::reconcile] itself against a copy held elsewhere,
+//!   by exchanging range digests from the top down until it's found the
+//!   ranges of keys where the two differ.
+//!
+//! A digest is a fold of the entry digests in key order, so it depends
+//! only on the entries of the map and not on the shape of its tree, which
+//! is what lets two maps built in different ways compare digests. That
+//! requires [`Digester::combine`][Digester::combine] to be associative.
+//!
+//! ```
+//! # #[macro_use] extern crate im;
+//! # use im::merkle::Digester;
+//! # use std::collections::hash_map::DefaultHasher;
+//! # use std::hash::{Hash, Hasher};
+//! // A polynomial rolling hash: associative, though not cryptographic.
+//! struct Rolling;
+//!
+//! impl Digester<i32, i32> for Rolling {
+//!     type Digest = (u64, u64);
+//!
+//!     fn empty() -> Self::Digest {
+//!         (0, 1)
+//!     }
+//!
+//!     fn entry(key: &i32, value: &i32) -> Self::Digest {
+//!         let mut hasher = DefaultHasher::new();
+//!         (key, value).hash(&mut hasher);
+//!         (hasher.finish(), 0x100000001b3)
+//!     }
+//!
+//!     fn combine(left: &Self::Digest, right: &Self::Digest) -> Self::Digest {
+//!         (
+//!             left.0.wrapping_mul(right.1).wrapping_add(right.0),
+//!             left.1.wrapping_mul(right.1),
+//!         )
+//!     }
+//! }
+//!
+//! let ours = ordmap! {1 => 1, 2 => 2, 3 => 3};
+//! let mut theirs = ours.clone();
+//! theirs.insert(2, 20);
+//! assert_ne!(ours.root_digest::<Rolling>(), theirs.root_digest::<Rolling>());
+//!
+//! let ranges = ours.reconcile::<Rolling, _>(|range| theirs.range_digest::<Rolling, _, _>(range.clone()));
+//! assert!(ranges.iter().all(|range| !ours.range(range.clone()).any(|(k, _)| *k != 2)));
+//! ```
+
+use std::borrow::Borrow;
+use std::ops::{Bound, RangeBounds};
+
+use crate::config::OrdChunkSize;
+use crate::nodes::btree::Node;
+use crate::ordmap::OrdMap;
+
+/// A way of digesting the entries of an [`OrdMap`][OrdMap].
+///
+/// The digester is only ever used as a type, and digests are cached per
+/// digester type, so a map can be digested in more than one way at once.
+///
+/// [`combine`][Digester::combine] must be associative, and
+/// [`empty`][Digester::empty] must be its identity, for digests not to
+/// depend on the shape of the tree.
+pub trait Digester<K, V>: 'static {
+    /// The type of the digests.
+    type Digest: Clone + PartialEq + Send + 'static;
+
+    /// The digest of no entries at all.
+    fn empty() -> Self::Digest;
+
+    /// The digest of a single entry.
+    fn entry(key: &K, value: &V) -> Self::Digest;
+
+    /// The digest of the entries of `left` followed by those of `right`.
+    fn combine(left: &Self::Digest, right: &Self::Digest) -> Self::Digest;
+}
+
+/// A range of keys, as returned by [`OrdMap::reconcile`][OrdMap::reconcile].
+pub type KeyRange<K> = (Bound<K>, Bound<K>);
+
+/// A proof that a map contains an entry, which can be checked against the
+/// map's root digest.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Proof<D> {
+    /// For each node on the way from the entry up to the root, the digests
+    /// of the entries to the left and to the right of the path.
+    pub steps: Vec<(D, D)>,
+}
+
+impl<D: PartialEq> Proof<D> {
+    /// Check that the proof shows an entry with this key and value to be
+    /// in a map with the root digest `root`.
+    #[must_use]
+    pub fn verify<K, V, T>(&self, key: &K, value: &V, root: &D) -> bool
+    where
+        T: Digester<K, V, Digest = D>,
+    {
+        let digest = self
+            .steps
+            .iter()
+            .fold(T::entry(key, value), |digest, (left, right)| {
+                T::combine(&T::combine(left, &digest), right)
+            });
+        digest == *root
+    }
+}
+
+fn node_digest<K, V, N, T>(node: &Node<(K, V), N>) -> T::Digest
+where
+    N: OrdChunkSize<(K, V)>,
+    T: Digester<K, V>,
+{
+    if let Some(digest) = node.cached_digest::<T, T::Digest>() {
+        return digest;
+    }
+    let digest = fold_slots::<K, V, N, T>(node, 0, node.keys().len() * 2 + 1);
+    node.cache_digest::<T, T::Digest>(digest.clone());
+    digest
+}
+
+/// Fold the digests of a node's children and entries, numbered from left
+/// to right so that child `i` is slot `2 * i` and entry `i` is slot
+/// `2 * i + 1`, over the slots `from..to`.
+fn fold_slots<K, V, N, T>(node: &Node<(K, V), N>, from: usize, to: usize) -> T::Digest
+where
+    N: OrdChunkSize<(K, V)>,
+    T: Digester<K, V>,
+{
+    (from..to).fold(T::empty(), |digest, slot| {
+        if slot & 1 == 1 {
+            let (key, value) = &node.keys()[slot >> 1];
+            T::combine(&digest, &T::entry(key, value))
+        } else if let Some(child) = &node.children()[slot >> 1] {
+            T::combine(&digest, &node_digest::<K, V, N, T>(child))
+        } else {
+            digest
+        }
+    })
+}
+
+fn above_lower<K, BK>(key: &K, lower: Bound<&BK>) -> bool
+where
+    K: Borrow<BK>,
+    BK: Ord + ?Sized,
+{
+    match lower {
+        Bound::Included(bound) => key.borrow() >= bound,
+        Bound::Excluded(bound) => key.borrow() > bound,
+        Bound::Unbounded => true,
+    }
+}
+
+fn below_upper<K, BK>(key: &K, upper: Bound<&BK>) -> bool
+where
+    K: Borrow<BK>,
+    BK: Ord + ?Sized,
+{
+    match upper {
+        Bound::Included(bound) => key.borrow() <= bound,
+        Bound::Excluded(bound) => key.borrow() < bound,
+        Bound::Unbounded => true,
+    }
+}
+
+fn range_digest<K, V, N, T, BK>(
+    node: &Node<(K, V), N>,
+    lower: Bound<&BK>,
+    upper: Bound<&BK>,
+) -> T::Digest
+where
+    N: OrdChunkSize<(K, V)>,
+    T: Digester<K, V>,
+    K: Borrow<BK>,
+    BK: Ord + ?Sized,
+{
+    if let (Bound::Unbounded, Bound::Unbounded) = (lower, upper) {
+        return node_digest::<K, V, N, T>(node);
+    }
+    let keys = node.keys();
+    let mut digest = T::empty();
+    for (index, child) in node.children().iter().enumerate() {
+        // Everything from here on is above the range.
+        if index > 0 && !below_upper(&keys[index - 1].0, upper) {
+            break;
+        }
+        let next = keys.get(index).map(|(key, _)| key);
+        if let Some(child) = child {
+            // The child is below the range if the key after it is.
+            if next.map_or(true, |key| above_lower(key, lower)) {
+                let child_lower = if index > 0 && above_lower(&keys[index - 1].0, lower) {
+                    Bound::Unbounded
+                } else {
+                    lower
+                };
+                let child_upper = match next {
+                    Some(key) if below_upper(key, upper) => Bound::Unbounded,
+                    _ => upper,
+                };
+                let child_digest = range_digest::<K, V, N, T, BK>(child, child_lower, child_upper);
+                digest = T::combine(&digest, &child_digest);
+            }
+        }
+        if let Some((key, value)) = keys.get(index) {
+            if above_lower(key, lower) && below_upper(key, upper) {
+                digest = T::combine(&digest, &T::entry(key, value));
+            }
+        }
+    }
+    digest
+}
+
+fn clone_bound<K: Clone>(bound: Bound<&K>) -> Bound<K> {
+    match bound {
+        Bound::Included(key) => Bound::Included(key.clone()),
+        Bound::Excluded(key) => Bound::Excluded(key.clone()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+fn reconcile_node<K, V, N, T, F>(
+    node: &Node<(K, V), N>,
+    lower: Bound<&K>,
+    upper: Bound<&K>,
+    remote: &mut F,
+    ranges: &mut Vec<KeyRange<K>>,
+) where
+    K: Clone,
+    N: OrdChunkSize<(K, V)>,
+    T: Digester<K, V>,
+    F: FnMut(&KeyRange<K>) -> T::Digest,
+{
+    let keys = node.keys();
+    for (index, child) in node.children().iter().enumerate() {
+        let child_lower = match index {
+            0 => lower,
+            _ => Bound::Excluded(&keys[index - 1].0),
+        };
+        let child_upper = match keys.get(index) {
+            Some((key, _)) => Bound::Excluded(key),
+            None => upper,
+        };
+        let range = (clone_bound(child_lower), clone_bound(child_upper));
+        let local = match child {
+            Some(child) => node_digest::<K, V, N, T>(child),
+            None => T::empty(),
+        };
+        if remote(&range) != local {
+            match child {
+                Some(child) => {
+                    reconcile_node::<K, V, N, T, F>(child, child_lower, child_upper, remote, ranges)
+                }
+                None => ranges.push(range),
+            }
+        }
+        if let Some((key, value)) = keys.get(index) {
+            let range = (Bound::Included(key.clone()), Bound::Included(key.clone()));
+            if remote(&range) != T::entry(key, value) {
+                ranges.push(range);
+            }
+        }
+    }
+}
+
+impl<K, V, N> OrdMap<K, V, N>
+where
+    K: Ord,
+    N: OrdChunkSize<(K, V)>,
+{
+    /// Get the digest of every entry in the map.
+    ///
+    /// Time: O(n) the first time, O(log n) after an update
+    #[must_use]
+    pub fn root_digest<T>(&self) -> T::Digest
+    where
+        T: Digester<K, V>,
+    {
+        match self.root() {
+            Some(root) => node_digest::<K, V, N, T>(root),
+            None => T::empty(),
+        }
+    }
+
+    /// Get the digest of the entries with keys in the given range.
+    ///
+    /// Time: O(log n) once the map has been digested
+    #[must_use]
+    pub fn range_digest<T, R, BK>(&self, range: R) -> T::Digest
+    where
+        T: Digester<K, V>,
+        R: RangeBounds<BK>,
+        K: Borrow<BK>,
+        BK: Ord + ?Sized,
+    {
+        match self.root() {
+            Some(root) => {
+                range_digest::<K, V, N, T, BK>(root, range.start_bound(), range.end_bound())
+            }
+            None => T::empty(),
+        }
+    }
+
+    /// Prove that the map contains the entry for `key`, or return `None`
+    /// if it doesn't.
+    ///
+    /// The proof can be checked with [`Proof::verify`][Proof::verify]
+    /// against the map's [`root_digest`][OrdMap::root_digest].
+    ///
+    /// Time: O(log n) once the map has been digested
+    #[must_use]
+    pub fn prove<T, BK>(&self, key: &BK) -> Option<Proof<T::Digest>>
+    where
+        T: Digester<K, V>,
+        K: Borrow<BK>,
+        BK: Ord + ?Sized,
+    {
+        let mut path = Vec::new();
+        let mut node = self.root()?;
+        let found = loop {
+            match node
+                .keys()
+                .binary_search_by(|(other, _)| other.borrow().cmp(key))
+            {
+                Ok(index) => break 2 * index + 1,
+                Err(index) => {
+                    path.push((node, 2 * index));
+                    node = node.children()[index].as_ref()?;
+                }
+            }
+        };
+        path.push((node, found));
+        let steps = path
+            .into_iter()
+            .rev()
+            .map(|(node, slot)| {
+                (
+                    fold_slots::<K, V, N, T>(node, 0, slot),
+                    fold_slots::<K, V, N, T>(node, slot + 1, node.keys().len() * 2 + 1),
+                )
+            })
+            .collect();
+        Some(Proof { steps })
+    }
+
+    /// Find the ranges of keys in which this map differs from another,
+    /// given a function which returns the other map's
+    /// [`range_digest`][OrdMap::range_digest] for a range.
+    ///
+    /// The digests are compared from the top of this map's tree down, so
+    /// `remote` is only asked about the parts of the tree which differ.
+    /// Every key whose entry differs between the two maps, including keys
+    /// only one of them has, falls in one of the ranges returned. Each
+    /// range either holds a single key or holds no keys in this map.
+    ///
+    /// If the other map is somewhere else, `remote` is where a request for
+    /// the digest goes over the wire; copying the other map's entries in
+    /// the returned ranges over this map's makes the two equal.
+    #[must_use]
+    pub fn reconcile<T, F>(&self, mut remote: F) -> Vec<KeyRange<K>>
+    where
+        T: Digester<K, V>,
+        K: Clone,
+        F: FnMut(&KeyRange<K>) -> T::Digest,
+    {
+        let mut ranges = Vec::new();
+        let everything = (Bound::Unbounded, Bound::Unbounded);
+        if remote(&everything) == self.root_digest::<T>() {
+            return ranges;
+        }
+        match self.root() {
+            Some(root) => reconcile_node::<K, V, N, T, F>(
+                root,
+                Bound::Unbounded,
+                Bound::Unbounded,
+                &mut remote,
+                &mut ranges,
+            ),
+            None => ranges.push(everything),
+        }
+        ranges
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::proptest::collection::btree_map;
+    use ::proptest::num::usize;
+    use ::proptest::proptest;
+    use std::cell::Cell;
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::BTreeMap;
+    use std::hash::{Hash, Hasher};
+
+    struct Rolling;
+
+    impl Digester<u16, u16> for Rolling {
+        type Digest = (u64, u64);
+
+        fn empty() -> Self::Digest {
+            (0, 1)
+        }
+
+        fn entry(key: &u16, value: &u16) -> Self::Digest {
+            let mut hasher = DefaultHasher::new();
+            (key, value).hash(&mut hasher);
+            (hasher.finish(), 0x100000001b3)
+        }
+
+        fn combine(left: &Self::Digest, right: &Self::Digest) -> Self::Digest {
+            (
+                left.0.wrapping_mul(right.1).wrapping_add(right.0),
+                left.1.wrapping_mul(right.1),
+            )
+        }
+    }
+
+    thread_local! {
+        static ENTRIES_DIGESTED: Cell<usize> = Cell::new(0);
+    }
+
+    /// Counts the entries it digests, to show when a digest is cached.
+    struct Counting;
+
+    impl Digester<u16, u16> for Counting {
+        type Digest = u64;
+
+        fn empty() -> Self::Digest {
+            0
+        }
+
+        fn entry(key: &u16, value: &u16) -> Self::Digest {
+            ENTRIES_DIGESTED.with(|count| count.set(count.get() + 1));
+            u64::from(*key) + u64::from(*value)
+        }
+
+        fn combine(left: &Self::Digest, right: &Self::Digest) -> Self::Digest {
+            left.wrapping_add(*right)
+        }
+    }
+
+    fn reference(map: &OrdMap<u16, u16>) -> (u64, u64) {
+        map.iter().fold(Rolling::empty(), |digest, (k, v)| {
+            Rolling::combine(&digest, &Rolling::entry(k, v))
+        })
+    }
+
+    #[test]
+    fn digests_follow_updates() {
+        let mut map: OrdMap<u16, u16> = (0..5000u16).map(|i| (i, i)).collect();
+        assert_eq!(reference(&map), map.root_digest::<Rolling>());
+        let snapshot = map.clone();
+        map.insert(2500, 0);
+        map.remove(&100);
+        *map.get_mut(&4000).unwrap() = 1;
+        assert_eq!(reference(&map), map.root_digest::<Rolling>());
+        assert_eq!(reference(&snapshot), snapshot.root_digest::<Rolling>());
+        let right = map.split_off(&3000);
+        assert_eq!(reference(&map), map.root_digest::<Rolling>());
+        assert_eq!(reference(&right), right.root_digest::<Rolling>());
+        map.append(right);
+        assert_eq!(reference(&map), map.root_digest::<Rolling>());
+        let mut entries: Vec<(u16, u16)> = map.iter().map(|(k, v)| (*k, *v)).collect();
+        entries.reverse();
+        let rebuilt: OrdMap<u16, u16> = entries.into_iter().collect();
+        assert_eq!(
+            rebuilt.root_digest::<Rolling>(),
+            map.root_digest::<Rolling>()
+        );
+    }
+
+    #[test]
+    fn digests_are_cached_per_digester() {
+        let map: OrdMap<u16, u16> = (0..5000u16).map(|i| (i, i)).collect();
+        let counted = map.root_digest::<Counting>();
+        let rolling = map.root_digest::<Rolling>();
+        let before = ENTRIES_DIGESTED.with(Cell::get);
+        assert_eq!(counted, map.root_digest::<Counting>());
+        assert_eq!(rolling, map.root_digest::<Rolling>());
+        assert_eq!(before, ENTRIES_DIGESTED.with(Cell::get));
+    }
+
+    proptest! {
+        #[test]
+        fn range_digests_match_their_entries(
+            input in btree_map(0u16..1000, 0u16..10, 0..500),
+            lower in 0u16..1000,
+            len in 0u16..500,
+        ) {
+            let map: OrdMap<u16, u16> = input.into_iter().collect();
+            let range = lower..lower.saturating_add(len);
+            let expected = reference(&map.range(range.clone()).map(|(k, v)| (*k, *v)).collect());
+            assert_eq!(expected, map.range_digest::<Rolling, _, _>(range.clone()));
+            let inclusive = (Bound::Excluded(range.start), Bound::Included(range.end));
+            let expected = reference(&map.range(inclusive).map(|(k, v)| (*k, *v)).collect());
+            assert_eq!(expected, map.range_digest::<Rolling, _, _>(inclusive));
+        }
+
+        #[test]
+        fn proofs_verify(input in btree_map(0u16..1000, 0u16..10, 1..500), pick in usize::ANY) {
+            let map: OrdMap<u16, u16> = input.into_iter().collect();
+            let root = map.root_digest::<Rolling>();
+            let (key, value) = map.iter().nth(pick % map.len()).unwrap();
+            let proof = map.prove::<Rolling, _>(key).unwrap();
+            assert!(proof.verify::<_, _, Rolling>(key, value, &root));
+            assert!(!proof.verify::<_, _, Rolling>(key, &(value + 1), &root));
+            assert!(map.prove::<Rolling, _>(&1000).is_none());
+        }
+
+        #[test]
+        fn reconcile_finds_every_difference(
+            ours in btree_map(0u16..1000, 0u16..10, 0..500),
+            theirs in btree_map(0u16..1000, 0u16..10, 0..500),
+        ) {
+            let mut map: OrdMap<u16, u16> = ours.into_iter().collect();
+            let other: OrdMap<u16, u16> = theirs.into_iter().collect();
+            let ranges = map.reconcile::<Rolling, _>(|range| {
+                other.range_digest::<Rolling, _, _>(range.clone())
+            });
+            if map == other {
+                assert!(ranges.is_empty());
+            }
+            for range in ranges {
+                let keys: Vec<u16> = map.range(range.clone()).map(|(k, _)| *k).collect();
+                for key in keys {
+                    map.remove(&key);
+                }
+                let theirs: BTreeMap<u16, u16> = other.range(range).map(|(k, v)| (*k, *v)).collect();
+                map.extend(theirs);
+            }
+            assert_eq!(other, map);
+        }
+    }
+}
@@ -2,23 +2,26 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+#[cfg(feature = "merkle")]
+use std::any::{Any, TypeId};
 use std::borrow::Borrow;
 use std::cmp::Ordering;
 use std::mem;
 use std::ops::{Bound, RangeBounds};
+#[cfg(feature = "merkle")]
+use std::sync::Mutex;
 
 use sized_chunks::Chunk;
-use typenum::{Add1, Unsigned};
 
-use crate::config::OrdChunkSize as NodeSize;
-use crate::util::{Pool, PoolClone, PoolDefault, PoolRef};
+use crate::config::OrdChunkSize;
+#[cfg(any(test, feature = "debug"))]
+use crate::debug::{InvariantViolation, NodeInfo, NodeKind, Walker};
+use crate::memory::{self, Visitor};
+use crate::util::{NodeType, Pool, PoolClone, PoolDefault, PoolRef};
 
 use self::Insert::*;
 use self::InsertAction::*;
 
-pub(crate) const NODE_SIZE: usize = NodeSize::USIZE;
-const MEDIAN: usize = (NODE_SIZE + 1) >> 1;
-
 pub trait BTreeValue {
     type Key;
     fn ptr_eq(&self, other: &Self) -> bool;
@@ -37,9 +40,13 @@ pub trait BTreeValue {
     fn cmp_values(&self, other: &Self) -> Ordering;
 }
 
-pub(crate) struct Node<A> {
-    keys: Chunk<A, NodeSize>,
-    children: Chunk<Option<PoolRef<Node<A>>>, Add1<NodeSize>>,
+pub struct Node<A, N: OrdChunkSize<A>> {
+    keys: Chunk<A, N>,
+    children: Chunk<Option<PoolRef<Node<A, N>>>, N::Branches>,
+    /// The number of values in this node and all its children.
+    size: usize,
+    #[cfg(feature = "merkle")]
+    digest: DigestCache,
 }
 
 #[cfg(feature = "pool")]
@@ -49,18 +56,21 @@ unsafe fn cast_uninit<A>(target: &mut A) -> &mut mem::MaybeUninit<A> {
 }
 
 #[allow(unsafe_code)]
-impl<A> PoolDefault for Node<A> {
+impl<A, N: OrdChunkSize<A>> PoolDefault for Node<A, N> {
     #[cfg(feature = "pool")]
     unsafe fn default_uninit(target: &mut mem::MaybeUninit<Self>) {
         let ptr: *mut Self = target.as_mut_ptr();
         Chunk::default_uninit(cast_uninit(&mut (*ptr).keys));
         Chunk::default_uninit(cast_uninit(&mut (*ptr).children));
         (*ptr).children.push_back(None);
+        std::ptr::write(&mut (*ptr).size, 0);
+        #[cfg(feature = "merkle")]
+        std::ptr::write(&mut (*ptr).digest, DigestCache::default());
     }
 }
 
 #[allow(unsafe_code)]
-impl<A> PoolClone for Node<A>
+impl<A, N: OrdChunkSize<A>> PoolClone for Node<A, N>
 where
     A: Clone,
 {
@@ -70,26 +80,82 @@ where
             .clone_uninit(cast_uninit(&mut (*target.as_mut_ptr()).keys));
         self.children
             .clone_uninit(cast_uninit(&mut (*target.as_mut_ptr()).children));
+        std::ptr::write(&mut (*target.as_mut_ptr()).size, self.size);
+        #[cfg(feature = "merkle")]
+        std::ptr::write(&mut (*target.as_mut_ptr()).digest, DigestCache::default());
+    }
+}
+
+/// The Merkle digests of every value below a node, once they've been
+/// computed, with the `merkle` feature, each along with the type of the
+/// digester which computed it.
+///
+/// A map is rarely digested in more than one or two ways, so the digests
+/// are kept in a list rather than a map.
+#[cfg(feature = "merkle")]
+#[derive(Default)]
+struct DigestCache(Mutex<Vec<(TypeId, Box<dyn Any + Send>)>>);
+
+#[cfg(feature = "merkle")]
+impl DigestCache {
+    fn get<T: 'static, D: Clone + 'static>(&self) -> Option<D> {
+        let cache = self.0.lock().ok()?;
+        let (_, digest) = cache
+            .iter()
+            .find(|(owner, _)| *owner == TypeId::of::<T>())?;
+        digest.downcast_ref().cloned()
+    }
+
+    fn set<T: 'static, D: Send + 'static>(&self, digest: D) {
+        if let Ok(mut cache) = self.0.lock() {
+            let digest: Box<dyn Any + Send> = Box::new(digest);
+            match cache
+                .iter_mut()
+                .find(|(owner, _)| *owner == TypeId::of::<T>())
+            {
+                Some((_, cached)) => *cached = digest,
+                None => cache.push((TypeId::of::<T>(), digest)),
+            }
+        }
     }
+
+    fn clear(&mut self) {
+        if let Ok(cache) = self.0.get_mut() {
+            cache.clear();
+        }
+    }
+}
+
+#[cfg(feature = "merkle")]
+impl Clone for DigestCache {
+    fn clone(&self) -> Self {
+        // Nodes are only ever cloned to be changed.
+        Self::default()
+    }
+}
+
+impl<A, N: OrdChunkSize<A>> NodeType for Node<A, N> {
+    #[cfg(feature = "instrument")]
+    const TREE: crate::instrument::Tree = crate::instrument::Tree::BTree;
 }
 
-pub(crate) enum Insert<A> {
+pub(crate) enum Insert<A, N: OrdChunkSize<A>> {
     Added,
     Replaced(A),
-    Split(Node<A>, A, Node<A>),
+    Split(Node<A, N>, A, Node<A, N>),
 }
 
-enum InsertAction<A> {
+enum InsertAction<A, N: OrdChunkSize<A>> {
     AddedAction,
     ReplacedAction(A),
     InsertAt,
-    InsertSplit(Node<A>, A, Node<A>),
+    InsertSplit(Node<A, N>, A, Node<A, N>),
 }
 
-pub(crate) enum Remove<A> {
+pub(crate) enum Remove<A, N: OrdChunkSize<A>> {
     NoChange,
     Removed(A),
-    Update(A, Node<A>),
+    Update(A, Node<A, N>),
 }
 
 enum Boundary {
@@ -107,7 +173,7 @@ enum RemoveAction {
     ContinueDown(usize),
 }
 
-impl<A> Clone for Node<A>
+impl<A, N: OrdChunkSize<A>> Clone for Node<A, N>
 where
     A: Clone,
 {
@@ -115,28 +181,36 @@ where
         Node {
             keys: self.keys.clone(),
             children: self.children.clone(),
+            size: self.size,
+            #[cfg(feature = "merkle")]
+            digest: DigestCache::default(),
         }
     }
 }
 
-impl<A> Default for Node<A> {
+impl<A, N: OrdChunkSize<A>> Default for Node<A, N> {
     fn default() -> Self {
         Node {
             keys: Chunk::new(),
             children: Chunk::unit(None),
+            size: 0,
+            #[cfg(feature = "merkle")]
+            digest: DigestCache::default(),
         }
     }
 }
 
-impl<A> Node<A> {
+impl<A, N: OrdChunkSize<A>> Node<A, N> {
+    const MEDIAN: usize = (N::USIZE + 1) >> 1;
+
     #[inline]
     fn has_room(&self) -> bool {
-        self.keys.len() < NODE_SIZE
+        self.keys.len() < N::USIZE
     }
 
     #[inline]
     fn too_small(&self) -> bool {
-        self.keys.len() < MEDIAN
+        self.keys.len() < Self::MEDIAN
     }
 
     #[inline]
@@ -144,25 +218,96 @@ impl<A> Node<A> {
         Node {
             keys: Chunk::unit(value),
             children: Chunk::pair(None, None),
+            size: 1,
+            #[cfg(feature = "merkle")]
+            digest: DigestCache::default(),
         }
     }
 
     #[inline]
     pub(crate) fn new_from_split(
-        pool: &Pool<Node<A>>,
-        left: Node<A>,
+        pool: &Pool<Node<A, N>>,
+        left: Node<A, N>,
         median: A,
-        right: Node<A>,
+        right: Node<A, N>,
     ) -> Self {
         Node {
+            size: left.size + 1 + right.size,
             keys: Chunk::unit(median),
             children: Chunk::pair(
                 Some(PoolRef::new(pool, left)),
                 Some(PoolRef::new(pool, right)),
             ),
+            #[cfg(feature = "merkle")]
+            digest: DigestCache::default(),
         }
     }
 
+    /// Get a mutable reference to a node, copying it first if it's shared.
+    ///
+    /// Every change to a node goes through here or through
+    /// [`unwrap`][Node::unwrap], so that its cached digest can be thrown
+    /// away.
+    #[inline]
+    pub(crate) fn make_mut<'a>(pool: &Pool<Node<A, N>>, node: &'a mut PoolRef<Self>) -> &'a mut Self
+    where
+        A: Clone,
+    {
+        #[allow(unused_mut)]
+        let mut node = PoolRef::make_mut(pool, node);
+        #[cfg(feature = "merkle")]
+        node.digest.clear();
+        node
+    }
+
+    /// Like [`make_mut`][Node::make_mut], for a root which might not have
+    /// been allocated yet.
+    #[inline]
+    pub(crate) fn make_mut_or_default<'a>(
+        pool: &Pool<Node<A, N>>,
+        node: &'a mut Option<PoolRef<Self>>,
+    ) -> &'a mut Self
+    where
+        A: Clone,
+    {
+        Self::make_mut(pool, node.get_or_insert_with(|| PoolRef::default(pool)))
+    }
+
+    /// Take a node out of its reference, copying it if it's shared.
+    #[inline]
+    pub(crate) fn unwrap(node: PoolRef<Self>) -> Self
+    where
+        A: Clone,
+    {
+        #[allow(unused_mut)]
+        let mut node = PoolRef::unwrap_or_clone(node);
+        #[cfg(feature = "merkle")]
+        node.digest.clear();
+        node
+    }
+
+    #[cfg(any(test, feature = "merkle", feature = "serde"))]
+    pub(crate) fn keys(&self) -> &[A] {
+        &self.keys
+    }
+
+    #[cfg(any(test, feature = "merkle", feature = "serde"))]
+    pub(crate) fn children(&self) -> &[Option<PoolRef<Node<A, N>>>] {
+        &self.children
+    }
+
+    /// The digest of every value below this node computed by the digester
+    /// `T`, if it's been cached.
+    #[cfg(feature = "merkle")]
+    pub(crate) fn cached_digest<T: 'static, D: Clone + 'static>(&self) -> Option<D> {
+        self.digest.get::<T, D>()
+    }
+
+    #[cfg(feature = "merkle")]
+    pub(crate) fn cache_digest<T: 'static, D: Send + 'static>(&self, digest: D) {
+        self.digest.set::<T, D>(digest)
+    }
+
     pub(crate) fn min(&self) -> Option<&A> {
         match self.children.first().unwrap() {
             None => self.keys.first(),
@@ -176,9 +321,72 @@ impl<A> Node<A> {
             Some(ref child) => child.max(),
         }
     }
+
+    /// The number of values in this node and all its children.
+    pub(crate) fn len(&self) -> usize {
+        self.size
+    }
+
+    fn child_len(child: &Option<PoolRef<Node<A, N>>>) -> usize {
+        child.as_ref().map_or(0, |child| child.size)
+    }
+
+    /// Recompute the size of a node whose keys or children have been
+    /// replaced wholesale, from the sizes of its children.
+    fn recount(&mut self) {
+        self.size = self.keys.len() + self.children.iter().map(Node::child_len).sum::<usize>();
+    }
+
+    /// The number of levels in the tree below and including this node, or 0
+    /// for an empty root.
+    pub(crate) fn height(&self) -> usize {
+        if self.keys.is_empty() {
+            return 0;
+        }
+        let mut height = 1;
+        let mut node = self;
+        while let Some(ref child) = node.children[0] {
+            height += 1;
+            node = child;
+        }
+        height
+    }
+
+    /// Walk this node and all of its children.
+    #[cfg(any(test, feature = "debug"))]
+    pub(crate) fn walk(&self, walker: &mut dyn Walker, parent: usize, depth: usize) {
+        let address = memory::address(self);
+        let info = NodeInfo {
+            kind: if self.children[0].is_none() {
+                NodeKind::Leaf
+            } else {
+                NodeKind::Branch
+            },
+            depth,
+            entries: self.keys.len(),
+            capacity: N::USIZE,
+        };
+        if walker.node(parent, address, info) {
+            for child in self.children.iter().flatten() {
+                child.walk(walker, address, depth + 1);
+            }
+        }
+    }
+
+    /// Visit this node, its values and all of its children.
+    pub(crate) fn visit(&self, visitor: &mut dyn Visitor<A>) {
+        if visitor.node(memory::address(self), memory::size_of::<Self>()) {
+            for value in self.keys.iter() {
+                visitor.value(value);
+            }
+            for child in self.children.iter().flatten() {
+                child.visit(visitor);
+            }
+        }
+    }
 }
 
-impl<A: BTreeValue> Node<A> {
+impl<A: BTreeValue, N: OrdChunkSize<A>> Node<A, N> {
     fn child_contains<BK>(&self, index: usize, key: &BK) -> bool
     where
         BK: Ord + ?Sized,
@@ -211,7 +419,7 @@ impl<A: BTreeValue> Node<A> {
         }
     }
 
-    pub(crate) fn lookup_mut<BK>(&mut self, pool: &Pool<Node<A>>, key: &BK) -> Option<&mut A>
+    pub(crate) fn lookup_mut<BK>(&mut self, pool: &Pool<Node<A, N>>, key: &BK) -> Option<&mut A>
     where
         A: Clone,
         BK: Ord + ?Sized,
@@ -228,14 +436,14 @@ impl<A: BTreeValue> Node<A> {
             Err(index) => match self.children[index] {
                 None => None,
                 Some(ref mut child_ref) => {
-                    let child = PoolRef::make_mut(pool, child_ref);
+                    let child = Node::make_mut(pool, child_ref);
                     child.lookup_mut(pool, key)
                 }
             },
         }
     }
 
-    pub(crate) fn lookup_prev<'a, BK>(&'a self, key: &BK) -> Option<&A>
+    pub(crate) fn lookup_prev<BK>(&self, key: &BK) -> Option<&A>
     where
         BK: Ord + ?Sized,
         A::Key: Borrow<BK>,
@@ -253,7 +461,7 @@ impl<A: BTreeValue> Node<A> {
         }
     }
 
-    pub(crate) fn lookup_next<'a, BK>(&'a self, key: &BK) -> Option<&A>
+    pub(crate) fn lookup_next<BK>(&self, key: &BK) -> Option<&A>
     where
         BK: Ord + ?Sized,
         A::Key: Borrow<BK>,
@@ -270,9 +478,9 @@ impl<A: BTreeValue> Node<A> {
         }
     }
 
-    pub(crate) fn lookup_prev_mut<'a, BK>(
-        &'a mut self,
-        pool: &Pool<Node<A>>,
+    pub(crate) fn lookup_prev_mut<BK>(
+        &mut self,
+        pool: &Pool<Node<A, N>>,
         key: &BK,
     ) -> Option<&mut A>
     where
@@ -291,14 +499,14 @@ impl<A: BTreeValue> Node<A> {
                     Some(_) => Some(&mut self.keys[index - 1]),
                     None => None,
                 },
-                Some(ref mut node) => PoolRef::make_mut(pool, node).lookup_prev_mut(pool, key),
+                Some(ref mut node) => Node::make_mut(pool, node).lookup_prev_mut(pool, key),
             },
         }
     }
 
-    pub(crate) fn lookup_next_mut<'a, BK>(
-        &'a mut self,
-        pool: &Pool<Node<A>>,
+    pub(crate) fn lookup_next_mut<BK>(
+        &mut self,
+        pool: &Pool<Node<A, N>>,
         key: &BK,
     ) -> Option<&mut A>
     where
@@ -316,15 +524,15 @@ impl<A: BTreeValue> Node<A> {
                     Some(_) => Some(&mut self.keys[index]),
                     None => None,
                 },
-                Some(ref mut node) => PoolRef::make_mut(pool, node).lookup_next_mut(pool, key),
+                Some(ref mut node) => Node::make_mut(pool, node).lookup_next_mut(pool, key),
             },
         }
     }
 
     pub(crate) fn path_first<'a, BK>(
         &'a self,
-        mut path: Vec<(&'a Node<A>, usize)>,
-    ) -> Vec<(&'a Node<A>, usize)>
+        mut path: Vec<(&'a Node<A, N>, usize)>,
+    ) -> Vec<(&'a Node<A, N>, usize)>
     where
         A: 'a,
         BK: Ord + ?Sized,
@@ -347,8 +555,8 @@ impl<A: BTreeValue> Node<A> {
 
     pub(crate) fn path_last<'a, BK>(
         &'a self,
-        mut path: Vec<(&'a Node<A>, usize)>,
-    ) -> Vec<(&'a Node<A>, usize)>
+        mut path: Vec<(&'a Node<A, N>, usize)>,
+    ) -> Vec<(&'a Node<A, N>, usize)>
     where
         A: 'a,
         BK: Ord + ?Sized,
@@ -373,8 +581,8 @@ impl<A: BTreeValue> Node<A> {
     pub(crate) fn path_next<'a, BK>(
         &'a self,
         key: &BK,
-        mut path: Vec<(&'a Node<A>, usize)>,
-    ) -> Vec<(&'a Node<A>, usize)>
+        mut path: Vec<(&'a Node<A, N>, usize)>,
+    ) -> Vec<(&'a Node<A, N>, usize)>
     where
         A: 'a,
         BK: Ord + ?Sized,
@@ -417,8 +625,8 @@ impl<A: BTreeValue> Node<A> {
     pub(crate) fn path_prev<'a, BK>(
         &'a self,
         key: &BK,
-        mut path: Vec<(&'a Node<A>, usize)>,
-    ) -> Vec<(&'a Node<A>, usize)>
+        mut path: Vec<(&'a Node<A, N>, usize)>,
+    ) -> Vec<(&'a Node<A, N>, usize)>
     where
         A: 'a,
         BK: Ord + ?Sized,
@@ -459,11 +667,11 @@ impl<A: BTreeValue> Node<A> {
 
     fn split(
         &mut self,
-        pool: &Pool<Node<A>>,
+        pool: &Pool<Node<A, N>>,
         value: A,
-        ins_left: Option<Node<A>>,
-        ins_right: Option<Node<A>>,
-    ) -> Insert<A> {
+        ins_left: Option<Node<A, N>>,
+        ins_right: Option<Node<A, N>>,
+    ) -> Insert<A, N> {
         let left_child = ins_left.map(|node| PoolRef::new(pool, node));
         let right_child = ins_right.map(|node| PoolRef::new(pool, node));
         let index = A::search_value(&self.keys, &value).unwrap_err();
@@ -472,17 +680,17 @@ impl<A: BTreeValue> Node<A> {
         let mut right_keys;
         let mut right_children;
         let median;
-        match index.cmp(&MEDIAN) {
+        match index.cmp(&Self::MEDIAN) {
             Ordering::Less => {
                 self.children[index] = left_child;
 
                 left_keys = Chunk::from_front(&mut self.keys, index);
                 left_keys.push_back(value);
-                left_keys.drain_from_front(&mut self.keys, MEDIAN - index - 1);
+                left_keys.drain_from_front(&mut self.keys, Self::MEDIAN - index - 1);
 
                 left_children = Chunk::from_front(&mut self.children, index + 1);
                 left_children.push_back(right_child);
-                left_children.drain_from_front(&mut self.children, MEDIAN - index - 1);
+                left_children.drain_from_front(&mut self.children, Self::MEDIAN - index - 1);
 
                 median = self.keys.pop_front();
 
@@ -492,22 +700,22 @@ impl<A: BTreeValue> Node<A> {
             Ordering::Greater => {
                 self.children[index] = left_child;
 
-                left_keys = Chunk::from_front(&mut self.keys, MEDIAN);
-                left_children = Chunk::from_front(&mut self.children, MEDIAN + 1);
+                left_keys = Chunk::from_front(&mut self.keys, Self::MEDIAN);
+                left_children = Chunk::from_front(&mut self.children, Self::MEDIAN + 1);
 
                 median = self.keys.pop_front();
 
-                right_keys = Chunk::from_front(&mut self.keys, index - MEDIAN - 1);
+                right_keys = Chunk::from_front(&mut self.keys, index - Self::MEDIAN - 1);
                 right_keys.push_back(value);
                 right_keys.append(&mut self.keys);
 
-                right_children = Chunk::from_front(&mut self.children, index - MEDIAN);
+                right_children = Chunk::from_front(&mut self.children, index - Self::MEDIAN);
                 right_children.push_back(right_child);
                 right_children.append(&mut self.children);
             }
             Ordering::Equal => {
-                left_keys = Chunk::from_front(&mut self.keys, MEDIAN);
-                left_children = Chunk::from_front(&mut self.children, MEDIAN);
+                left_keys = Chunk::from_front(&mut self.keys, Self::MEDIAN);
+                left_children = Chunk::from_front(&mut self.children, Self::MEDIAN);
                 left_children.push_back(left_child);
 
                 median = value;
@@ -518,62 +726,80 @@ impl<A: BTreeValue> Node<A> {
             }
         }
 
-        debug_assert!(left_keys.len() == MEDIAN);
-        debug_assert!(left_children.len() == MEDIAN + 1);
-        debug_assert!(right_keys.len() == MEDIAN);
-        debug_assert!(right_children.len() == MEDIAN + 1);
-
-        Split(
-            Node {
-                keys: left_keys,
-                children: left_children,
-            },
-            median,
-            Node {
-                keys: right_keys,
-                children: right_children,
-            },
-        )
+        debug_assert!(left_keys.len() == Self::MEDIAN);
+        debug_assert!(left_children.len() == Self::MEDIAN + 1);
+        debug_assert!(right_keys.len() == Self::MEDIAN);
+        debug_assert!(right_children.len() == Self::MEDIAN + 1);
+
+        let mut left = Node {
+            keys: left_keys,
+            children: left_children,
+            size: 0,
+            #[cfg(feature = "merkle")]
+            digest: DigestCache::default(),
+        };
+        left.recount();
+        let mut right = Node {
+            keys: right_keys,
+            children: right_children,
+            size: 0,
+            #[cfg(feature = "merkle")]
+            digest: DigestCache::default(),
+        };
+        right.recount();
+        Split(left, median, right)
     }
 
-    fn merge(middle: A, left: Node<A>, mut right: Node<A>) -> Node<A> {
+    fn merge(middle: A, left: Node<A, N>, mut right: Node<A, N>) -> Node<A, N> {
+        let size = left.size + 1 + right.size;
         let mut keys = left.keys;
         keys.push_back(middle);
         keys.append(&mut right.keys);
         let mut children = left.children;
         children.append(&mut right.children);
-        Node { keys, children }
+        Node {
+            keys,
+            children,
+            size,
+            #[cfg(feature = "merkle")]
+            digest: DigestCache::default(),
+        }
     }
 
-    fn pop_min(&mut self) -> (A, Option<PoolRef<Node<A>>>) {
+    fn pop_min(&mut self) -> (A, Option<PoolRef<Node<A, N>>>) {
         let value = self.keys.pop_front();
         let child = self.children.pop_front();
+        self.size -= 1 + Node::child_len(&child);
         (value, child)
     }
 
-    fn pop_max(&mut self) -> (A, Option<PoolRef<Node<A>>>) {
+    fn pop_max(&mut self) -> (A, Option<PoolRef<Node<A, N>>>) {
         let value = self.keys.pop_back();
         let child = self.children.pop_back();
+        self.size -= 1 + Node::child_len(&child);
         (value, child)
     }
 
-    fn push_min(&mut self, child: Option<PoolRef<Node<A>>>, value: A) {
+    fn push_min(&mut self, child: Option<PoolRef<Node<A, N>>>, value: A) {
+        self.size += 1 + Node::child_len(&child);
         self.keys.push_front(value);
         self.children.push_front(child);
     }
 
-    fn push_max(&mut self, child: Option<PoolRef<Node<A>>>, value: A) {
+    fn push_max(&mut self, child: Option<PoolRef<Node<A, N>>>, value: A) {
+        self.size += 1 + Node::child_len(&child);
         self.keys.push_back(value);
         self.children.push_back(child);
     }
 
-    pub(crate) fn insert(&mut self, pool: &Pool<Node<A>>, value: A) -> Insert<A>
+    pub(crate) fn insert(&mut self, pool: &Pool<Node<A, N>>, value: A) -> Insert<A, N>
     where
         A: Clone,
     {
         if self.keys.is_empty() {
             self.keys.push_back(value);
             self.children.push_back(None);
+            self.size += 1;
             return Insert::Added;
         }
         let (median, left, right) = match A::search_value(&self.keys, &value) {
@@ -589,7 +815,7 @@ impl<A: BTreeValue> Node<A> {
                     None => InsertAt,
                     // Child at location, pass it on.
                     Some(ref mut child_ref) => {
-                        let child = PoolRef::make_mut(pool, child_ref);
+                        let child = Node::make_mut(pool, child_ref);
                         match child.insert(pool, value.clone()) {
                             Insert::Added => AddedAction,
                             Insert::Replaced(value) => ReplacedAction(value),
@@ -600,12 +826,14 @@ impl<A: BTreeValue> Node<A> {
                 match action {
                     ReplacedAction(value) => return Insert::Replaced(value),
                     AddedAction => {
+                        self.size += 1;
                         return Insert::Added;
                     }
                     InsertAt => {
                         if has_room {
                             self.keys.insert(index, value);
                             self.children.insert(index + 1, None);
+                            self.size += 1;
                             return Insert::Added;
                         } else {
                             (value, None, None)
@@ -617,6 +845,7 @@ impl<A: BTreeValue> Node<A> {
                             self.keys.insert(index, median);
                             self.children
                                 .insert(index + 1, Some(PoolRef::new(pool, right)));
+                            self.size += 1;
                             return Insert::Added;
                         } else {
                             (median, Some(left), Some(right))
@@ -628,7 +857,7 @@ impl<A: BTreeValue> Node<A> {
         self.split(pool, median, left, right)
     }
 
-    pub(crate) fn remove<BK>(&mut self, pool: &Pool<Node<A>>, key: &BK) -> Remove<A>
+    pub(crate) fn remove<BK>(&mut self, pool: &Pool<Node<A, N>>, key: &BK) -> Remove<A, N>
     where
         A: Clone,
         BK: Ord + ?Sized,
@@ -640,9 +869,9 @@ impl<A: BTreeValue> Node<A> {
 
     fn remove_target<BK>(
         &mut self,
-        pool: &Pool<Node<A>>,
+        pool: &Pool<Node<A, N>>,
         target: Result<&BK, Boundary>,
-    ) -> Remove<A>
+    ) -> Remove<A, N>
     where
         A: Clone,
         BK: Ord + ?Sized,
@@ -658,10 +887,31 @@ impl<A: BTreeValue> Node<A> {
 
     fn remove_index<BK>(
         &mut self,
-        pool: &Pool<Node<A>>,
+        pool: &Pool<Node<A, N>>,
+        index: Result<usize, usize>,
+        target: Result<&BK, Boundary>,
+    ) -> Remove<A, N>
+    where
+        A: Clone,
+        BK: Ord + ?Sized,
+        A::Key: Borrow<BK>,
+    {
+        let result = self.remove_index_unsized(pool, index, target);
+        if let Remove::Removed(_) = result {
+            self.size -= 1;
+        }
+        result
+    }
+
+    /// Remove a value the way [`remove_index`][Node::remove_index]
+    /// does, leaving the size of this node, but not of its children,
+    /// to the caller.
+    fn remove_index_unsized<BK>(
+        &mut self,
+        pool: &Pool<Node<A, N>>,
         index: Result<usize, usize>,
         target: Result<&BK, Boundary>,
-    ) -> Remove<A>
+    ) -> Remove<A, N>
     where
         A: Clone,
         BK: Ord + ?Sized,
@@ -737,7 +987,7 @@ impl<A: BTreeValue> Node<A> {
                 let mut update = None;
                 let value;
                 if let Some(&mut Some(ref mut child_ref)) = children.get_mut(child_index) {
-                    let child = PoolRef::make_mut(pool, child_ref);
+                    let child = Node::make_mut(pool, child_ref);
                     match child.remove_target(pool, Err(boundary)) {
                         Remove::NoChange => unreachable!(),
                         Remove::Removed(pulled_value) => {
@@ -758,13 +1008,9 @@ impl<A: BTreeValue> Node<A> {
             }
             RemoveAction::Merge(index) => {
                 let left = self.children.remove(index).unwrap();
-                let right = mem::replace(&mut self.children[index], None).unwrap();
+                let right = self.children[index].take().unwrap();
                 let value = self.keys.remove(index);
-                let mut merged_child = Node::merge(
-                    value,
-                    PoolRef::unwrap_or_clone(left),
-                    PoolRef::unwrap_or_clone(right),
-                );
+                let mut merged_child = Node::merge(value, Node::unwrap(left), Node::unwrap(right));
                 let (removed, new_child) = match merged_child.remove_target(pool, target) {
                     Remove::NoChange => unreachable!(),
                     Remove::Removed(removed) => (removed, merged_child),
@@ -785,8 +1031,8 @@ impl<A: BTreeValue> Node<A> {
                     let mut children = self.children.as_mut_slice()[index - 1..=index]
                         .iter_mut()
                         .map(|n| n.as_mut().unwrap());
-                    let left = PoolRef::make_mut(pool, children.next().unwrap());
-                    let child = PoolRef::make_mut(pool, children.next().unwrap());
+                    let left = Node::make_mut(pool, children.next().unwrap());
+                    let child = Node::make_mut(pool, children.next().unwrap());
                     // Prepare the rebalanced node.
                     child.push_min(
                         left.children.last().unwrap().clone(),
@@ -825,8 +1071,8 @@ impl<A: BTreeValue> Node<A> {
                     let mut children = self.children.as_mut_slice()[index..index + 2]
                         .iter_mut()
                         .map(|n| n.as_mut().unwrap());
-                    let child = PoolRef::make_mut(pool, children.next().unwrap());
-                    let right = PoolRef::make_mut(pool, children.next().unwrap());
+                    let child = Node::make_mut(pool, children.next().unwrap());
+                    let right = Node::make_mut(pool, children.next().unwrap());
                     // Prepare the rebalanced node.
                     child.push_max(right.children[0].clone(), self.keys[index].clone());
                     match child.remove_target(pool, target) {
@@ -869,13 +1115,9 @@ impl<A: BTreeValue> Node<A> {
                     }
                 }
                 let left = self.children.remove(index).unwrap();
-                let right = mem::replace(&mut self.children[index], None).unwrap();
+                let right = self.children[index].take().unwrap();
                 let middle = self.keys.remove(index);
-                let mut merged = Node::merge(
-                    middle,
-                    PoolRef::unwrap_or_clone(left),
-                    PoolRef::unwrap_or_clone(right),
-                );
+                let mut merged = Node::merge(middle, Node::unwrap(left), Node::unwrap(right));
                 let update;
                 let out_value;
                 match merged.remove_target(pool, target) {
@@ -904,7 +1146,7 @@ impl<A: BTreeValue> Node<A> {
                 let mut update = None;
                 let out_value;
                 if let Some(&mut Some(ref mut child_ref)) = self.children.get_mut(index) {
-                    let child = PoolRef::make_mut(pool, child_ref);
+                    let child = Node::make_mut(pool, child_ref);
                     match child.remove_target(pool, target) {
                         Remove::NoChange => return Remove::NoChange,
                         Remove::Removed(value) => {
@@ -927,22 +1169,600 @@ impl<A: BTreeValue> Node<A> {
     }
 }
 
+// Structural split and join
+
+impl<A: BTreeValue, N: OrdChunkSize<A>> Node<A, N> {
+    /// Turn a node which may have run out of keys into a root node, along
+    /// with its new height.
+    ///
+    /// The node's keys and children may have been split off from a larger
+    /// node, so its size is recounted.
+    fn into_root(mut self, height: usize) -> (Self, usize)
+    where
+        A: Clone,
+    {
+        self.recount();
+        if !self.keys.is_empty() {
+            return (self, height);
+        }
+        match self.children.pop_front() {
+            Some(child) => (Node::unwrap(child), height - 1),
+            None => (Node::default(), 0),
+        }
+    }
+
+    fn push_max_split(
+        &mut self,
+        child: Option<PoolRef<Node<A, N>>>,
+        value: A,
+    ) -> Option<(A, Node<A, N>)> {
+        if self.has_room() {
+            self.push_max(child, value);
+            return None;
+        }
+        let mut right = Node {
+            keys: self.keys.split_off(Self::MEDIAN + 1),
+            children: self.children.split_off(Self::MEDIAN + 1),
+            size: 0,
+            #[cfg(feature = "merkle")]
+            digest: DigestCache::default(),
+        };
+        let median = self.keys.pop_back();
+        right.push_max(child, value);
+        right.recount();
+        self.recount();
+        Some((median, right))
+    }
+
+    fn push_min_split(
+        &mut self,
+        child: Option<PoolRef<Node<A, N>>>,
+        value: A,
+    ) -> Option<(Node<A, N>, A)> {
+        if self.has_room() {
+            self.push_min(child, value);
+            return None;
+        }
+        let mut left = Node {
+            keys: Chunk::from_front(&mut self.keys, Self::MEDIAN - 1),
+            children: Chunk::from_front(&mut self.children, Self::MEDIAN),
+            size: 0,
+            #[cfg(feature = "merkle")]
+            digest: DigestCache::default(),
+        };
+        let median = self.keys.pop_front();
+        left.push_min(child, value);
+        left.recount();
+        self.recount();
+        Some((left, median))
+    }
+
+    /// Join a node of the same height onto the right hand side of this one,
+    /// with a value between them. If they don't fit into a single node, the
+    /// values are shared out evenly and the new right hand node is returned
+    /// along with the value to go between them.
+    fn join_level(&mut self, mut middle: A, mut right: Node<A, N>) -> Option<(A, Node<A, N>)> {
+        let total = self.keys.len() + 1 + right.keys.len();
+        if total <= N::USIZE {
+            *self = Node::merge(middle, mem::take(self), right);
+            return None;
+        }
+        let target = total / 2;
+        while self.keys.len() < target {
+            let (value, child) = right.pop_min();
+            self.push_max(child, middle);
+            middle = value;
+        }
+        while self.keys.len() > target {
+            let (value, child) = self.pop_max();
+            right.push_min(child, middle);
+            middle = value;
+        }
+        Some((middle, right))
+    }
+
+    /// Join a shorter tree onto the right hand side of this one.
+    fn join_right(
+        &mut self,
+        pool: &Pool<Node<A, N>>,
+        height: usize,
+        middle: A,
+        right: Node<A, N>,
+        right_height: usize,
+    ) -> Option<(A, Node<A, N>)>
+    where
+        A: Clone,
+    {
+        let overflow = {
+            let child_ref = self.children.last_mut().unwrap().as_mut().unwrap();
+            let child = Node::make_mut(pool, child_ref);
+            if height - 1 == right_height {
+                child.join_level(middle, right)
+            } else {
+                child.join_right(pool, height - 1, middle, right, right_height)
+            }
+        };
+        let overflow = overflow
+            .and_then(|(median, node)| self.push_max_split(Some(PoolRef::new(pool, node)), median));
+        self.recount();
+        overflow
+    }
+
+    /// Join a shorter tree onto the left hand side of this one.
+    fn join_left(
+        &mut self,
+        pool: &Pool<Node<A, N>>,
+        height: usize,
+        left: Node<A, N>,
+        left_height: usize,
+        middle: A,
+    ) -> Option<(Node<A, N>, A)>
+    where
+        A: Clone,
+    {
+        let overflow = {
+            let child_ref = self.children.first_mut().unwrap().as_mut().unwrap();
+            let child = Node::make_mut(pool, child_ref);
+            if height - 1 == left_height {
+                let mut left = left;
+                match left.join_level(middle, mem::take(child)) {
+                    None => {
+                        *child = left;
+                        None
+                    }
+                    Some((median, right)) => {
+                        *child = right;
+                        Some((left, median))
+                    }
+                }
+            } else {
+                child.join_left(pool, height - 1, left, left_height, middle)
+            }
+        };
+        let overflow = overflow
+            .and_then(|(node, median)| self.push_min_split(Some(PoolRef::new(pool, node)), median));
+        self.recount();
+        overflow
+    }
+}
+
+impl<A: BTreeValue + Clone, N: OrdChunkSize<A>> Node<A, N> {
+    fn join_trees(
+        pool: &Pool<Node<A, N>>,
+        (mut left, left_height): (Node<A, N>, usize),
+        middle: A,
+        (mut right, right_height): (Node<A, N>, usize),
+    ) -> (Node<A, N>, usize) {
+        if left_height == 0 {
+            let height = right_height.max(1);
+            return match right.insert(pool, middle) {
+                Insert::Split(left, median, right) => {
+                    (Node::new_from_split(pool, left, median, right), height + 1)
+                }
+                _ => (right, height),
+            };
+        }
+        if right_height == 0 {
+            return match left.insert(pool, middle) {
+                Insert::Split(left, median, right) => (
+                    Node::new_from_split(pool, left, median, right),
+                    left_height + 1,
+                ),
+                _ => (left, left_height),
+            };
+        }
+        match left_height.cmp(&right_height) {
+            Ordering::Equal => match left.join_level(middle, right) {
+                None => (left, left_height),
+                Some((median, right)) => (
+                    Node::new_from_split(pool, left, median, right),
+                    left_height + 1,
+                ),
+            },
+            Ordering::Greater => {
+                match left.join_right(pool, left_height, middle, right, right_height) {
+                    None => (left, left_height),
+                    Some((median, right)) => (
+                        Node::new_from_split(pool, left, median, right),
+                        left_height + 1,
+                    ),
+                }
+            }
+            Ordering::Less => {
+                match right.join_left(pool, right_height, left, left_height, middle) {
+                    None => (right, right_height),
+                    Some((left, median)) => (
+                        Node::new_from_split(pool, left, median, right),
+                        right_height + 1,
+                    ),
+                }
+            }
+        }
+    }
+
+    fn split_tree<BK>(
+        pool: &Pool<Node<A, N>>,
+        mut node: Node<A, N>,
+        height: usize,
+        key: &BK,
+    ) -> ((Node<A, N>, usize), Option<A>, (Node<A, N>, usize))
+    where
+        BK: Ord + ?Sized,
+        A::Key: Borrow<BK>,
+    {
+        if node.keys.is_empty() {
+            return ((Node::default(), 0), None, (Node::default(), 0));
+        }
+        match A::search_key(&node.keys, key) {
+            Ok(index) => {
+                let mut right_keys = node.keys.split_off(index);
+                let right_children = node.children.split_off(index + 1);
+                let value = right_keys.pop_front();
+                let right = Node {
+                    keys: right_keys,
+                    children: right_children,
+                    size: 0,
+                    #[cfg(feature = "merkle")]
+                    digest: DigestCache::default(),
+                };
+                (node.into_root(height), Some(value), right.into_root(height))
+            }
+            Err(index) => {
+                let mut right_keys = node.keys.split_off(index);
+                let mut right_children = node.children.split_off(index + 1);
+                match node.children.pop_back() {
+                    None => {
+                        node.children.push_back(None);
+                        right_children.push_front(None);
+                        let right = Node {
+                            keys: right_keys,
+                            children: right_children,
+                            size: 0,
+                            #[cfg(feature = "merkle")]
+                            digest: DigestCache::default(),
+                        };
+                        (node.into_root(height), None, right.into_root(height))
+                    }
+                    Some(child) => {
+                        let (left_part, found, right_part) =
+                            Node::split_tree(pool, Node::unwrap(child), height - 1, key);
+                        let left = if node.keys.is_empty() {
+                            left_part
+                        } else {
+                            let middle = node.keys.pop_back();
+                            Node::join_trees(pool, node.into_root(height), middle, left_part)
+                        };
+                        let right = if right_keys.is_empty() {
+                            right_part
+                        } else {
+                            let middle = right_keys.pop_front();
+                            let right = Node {
+                                keys: right_keys,
+                                children: right_children,
+                                size: 0,
+                                #[cfg(feature = "merkle")]
+                                digest: DigestCache::default(),
+                            };
+                            Node::join_trees(pool, right_part, middle, right.into_root(height))
+                        };
+                        (left, found, right)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Split a tree into the values smaller than `key`, the value matching
+    /// `key` if any, and the values larger than `key`.
+    ///
+    /// Only the nodes along the path to `key` are rebuilt, every other
+    /// subtree is shared with the original tree.
+    pub(crate) fn split_by_key<BK>(
+        pool: &Pool<Node<A, N>>,
+        root: Node<A, N>,
+        key: &BK,
+    ) -> (Node<A, N>, Option<A>, Node<A, N>)
+    where
+        BK: Ord + ?Sized,
+        A::Key: Borrow<BK>,
+    {
+        let height = root.height();
+        let ((left, _), found, (right, _)) = Node::split_tree(pool, root, height, key);
+        (left, found, right)
+    }
+
+    /// Join two trees with `middle` between them. Every value in `left` must
+    /// be smaller than `middle`, and every value in `right` must be larger.
+    pub(crate) fn join(
+        pool: &Pool<Node<A, N>>,
+        left: Node<A, N>,
+        middle: A,
+        right: Node<A, N>,
+    ) -> Node<A, N> {
+        let left_height = left.height();
+        let right_height = right.height();
+        Node::join_trees(pool, (left, left_height), middle, (right, right_height)).0
+    }
+
+    /// Concatenate two trees. Every value in `left` must be smaller than every
+    /// value in `right`.
+    pub(crate) fn concat(
+        pool: &Pool<Node<A, N>>,
+        left: Node<A, N>,
+        mut right: Node<A, N>,
+    ) -> Node<A, N>
+    where
+        A::Key: Ord,
+    {
+        if right.keys.is_empty() {
+            return left;
+        }
+        if left.keys.is_empty() {
+            return right;
+        }
+        match right.remove_target::<A::Key>(pool, Err(Boundary::Lowest)) {
+            Remove::Removed(middle) => Node::join(pool, left, middle, right),
+            Remove::Update(middle, right) => Node::join(pool, left, middle, right),
+            Remove::NoChange => unreachable!(),
+        }
+    }
+
+    /// Apply a batch of changes, sorted by the key each one is for with no
+    /// key appearing twice, calling `f` with the value currently in the
+    /// tree for each key and its change to get the value to replace it
+    /// with, if any.
+    ///
+    /// The tree is split at the middle change, and the changes either side
+    /// of it are applied to each half of the tree before the halves are
+    /// joined back together, so every subtree between the changed keys is
+    /// shared with the original tree.
+    pub(crate) fn apply_sorted<C, F>(
+        pool: &Pool<Node<A, N>>,
+        root: Node<A, N>,
+        mut changes: Vec<C>,
+        key: &impl Fn(&C) -> &A::Key,
+        f: &mut F,
+    ) -> Node<A, N>
+    where
+        A::Key: Ord,
+        F: FnMut(Option<A>, C) -> Option<A>,
+    {
+        let right_changes = match changes.len() {
+            0 => return root,
+            len => changes.split_off(len / 2 + 1),
+        };
+        let change = changes.pop().unwrap();
+        let (left, found, right) = Node::split_by_key(pool, root, key(&change));
+        let left = Node::apply_sorted(pool, left, changes, key, f);
+        let middle = f(found, change);
+        let right = Node::apply_sorted(pool, right, right_changes, key, f);
+        match middle {
+            Some(middle) => Node::join(pool, left, middle, right),
+            None => Node::concat(pool, left, right),
+        }
+    }
+}
+
+// Building nodes from their parts
+
+#[cfg(any(test, feature = "serde"))]
+impl<A: BTreeValue, N: OrdChunkSize<A>> Node<A, N> {
+    /// Build a node out of its keys and either no children, for a leaf,
+    /// or one more child than it has keys.
+    ///
+    /// The children are checked against the keys and against each other
+    /// the same way [`check_invariants`][Node::check_invariants] would,
+    /// but only one level deep, so a tree built bottom up from checked
+    /// nodes is valid as a whole.
+    pub(crate) fn from_parts(
+        keys: Vec<A>,
+        children: Vec<PoolRef<Self>>,
+    ) -> Result<Self, &'static str> {
+        if keys.len() > N::USIZE {
+            return Err("node has too many keys");
+        }
+        if keys
+            .windows(2)
+            .any(|pair| pair[0].cmp_values(&pair[1]) != Ordering::Less)
+        {
+            return Err("node has keys out of order");
+        }
+        if !children.is_empty() {
+            if keys.is_empty() || children.len() != keys.len() + 1 {
+                return Err("node has the wrong number of children");
+            }
+            let height = children[0].height();
+            for (index, child) in children.iter().enumerate() {
+                if child.keys.is_empty() || child.keys.len() < Self::MEDIAN - 1 {
+                    return Err("node has a child with too few keys");
+                }
+                if child.height() != height {
+                    return Err("node has children of different heights");
+                }
+                let lower = index.checked_sub(1).map(|index| &keys[index]);
+                let upper = keys.get(index);
+                if lower.map_or(false, |lower| {
+                    lower.cmp_values(child.min().unwrap()) != Ordering::Less
+                }) || upper.map_or(false, |upper| {
+                    child.max().unwrap().cmp_values(upper) != Ordering::Less
+                }) {
+                    return Err("node has a child with keys out of order");
+                }
+            }
+        }
+        let children = if children.is_empty() {
+            (0..=keys.len()).map(|_| None).collect()
+        } else {
+            children.into_iter().map(Some).collect()
+        };
+        let mut node = Node {
+            keys: keys.into_iter().collect(),
+            children,
+            size: 0,
+            #[cfg(feature = "merkle")]
+            digest: DigestCache::default(),
+        };
+        node.recount();
+        Ok(node)
+    }
+}
+
+// Invariant checking
+
+#[cfg(any(test, feature = "debug"))]
+fn violation(path: &[usize], message: String) -> InvariantViolation {
+    InvariantViolation::new("B-tree", path, message)
+}
+
+#[cfg(any(test, feature = "debug"))]
+impl<A: BTreeValue, N: OrdChunkSize<A>> Node<A, N> {
+    /// Check the invariants of the tree with this node as its root,
+    /// returning the number of values in it.
+    pub(crate) fn check_invariants(&self) -> Result<usize, InvariantViolation> {
+        if self.keys.is_empty() {
+            if self.children.len() != 1 || self.children[0].is_some() {
+                return Err(violation(
+                    &[],
+                    "an empty root node must have a single empty child".to_string(),
+                ));
+            }
+            if self.size != 0 {
+                return Err(violation(
+                    &[],
+                    format!("empty root node has a recorded size of {}", self.size),
+                ));
+            }
+            return Ok(0);
+        }
+        self.check_node(&mut Vec::new(), None, None)
+            .map(|(len, _)| len)
+    }
+
+    /// Check the invariants of a non-empty node, whose keys must all fall
+    /// between `lower` and `upper`, returning the number of values below
+    /// it and its height.
+    fn check_node(
+        &self,
+        path: &mut Vec<usize>,
+        lower: Option<&A>,
+        upper: Option<&A>,
+    ) -> Result<(usize, usize), InvariantViolation> {
+        if self.children.len() != self.keys.len() + 1 {
+            return Err(violation(
+                path,
+                format!(
+                    "node has {} keys but {} children",
+                    self.keys.len(),
+                    self.children.len()
+                ),
+            ));
+        }
+        if !path.is_empty() && self.keys.len() < Self::MEDIAN - 1 {
+            return Err(violation(
+                path,
+                format!(
+                    "node has {} keys, fewer than the minimum of {}",
+                    self.keys.len(),
+                    Self::MEDIAN - 1
+                ),
+            ));
+        }
+        for (index, key) in self.keys.iter().enumerate() {
+            let previous = if index == 0 {
+                lower
+            } else {
+                Some(&self.keys[index - 1])
+            };
+            if let Some(previous) = previous {
+                if previous.cmp_values(key) != Ordering::Less {
+                    return Err(violation(path, format!("key {} is out of order", index)));
+                }
+            }
+        }
+        if let (Some(last), Some(upper)) = (self.keys.last(), upper) {
+            if last.cmp_values(upper) != Ordering::Less {
+                return Err(violation(
+                    path,
+                    format!("key {} is out of order", self.keys.len() - 1),
+                ));
+            }
+        }
+        let leaf = self.children[0].is_none();
+        let mut len = self.keys.len();
+        let mut height = None;
+        for (index, child) in self.children.iter().enumerate() {
+            match child {
+                None if leaf => {}
+                Some(child) if !leaf => {
+                    path.push(index);
+                    let lower = if index == 0 {
+                        lower
+                    } else {
+                        Some(&self.keys[index - 1])
+                    };
+                    let (child_len, child_height) =
+                        child.check_node(path, lower, self.keys.get(index).or(upper))?;
+                    path.pop();
+                    if *height.get_or_insert(child_height) != child_height {
+                        return Err(violation(
+                            path,
+                            format!(
+                                "child {} has height {}, but child 0 has height {}",
+                                index,
+                                child_height,
+                                height.unwrap()
+                            ),
+                        ));
+                    }
+                    len += child_len;
+                }
+                _ => {
+                    return Err(violation(
+                        path,
+                        "node has both empty and non-empty children".to_string(),
+                    ))
+                }
+            }
+        }
+        if len != self.size {
+            return Err(violation(
+                path,
+                format!(
+                    "node has {} values but a recorded size of {}",
+                    len, self.size
+                ),
+            ));
+        }
+        Ok((len, height.unwrap_or(0) + 1))
+    }
+}
+
 // Iterator
 
 /// An iterator over an ordered set.
-pub struct Iter<'a, A> {
-    fwd_path: Vec<(&'a Node<A>, usize)>,
-    back_path: Vec<(&'a Node<A>, usize)>,
+pub struct Iter<'a, A, N: OrdChunkSize<A>> {
+    fwd_path: Vec<(&'a Node<A, N>, usize)>,
+    back_path: Vec<(&'a Node<A, N>, usize)>,
     pub(crate) remaining: usize,
 }
 
-impl<'a, A: BTreeValue> Iter<'a, A> {
-    pub(crate) fn new<R, BK>(root: &'a Node<A>, size: usize, range: R) -> Self
+impl<'a, A: BTreeValue, N: OrdChunkSize<A>> Iter<'a, A, N> {
+    pub(crate) fn new<R, BK>(root: Option<&'a Node<A, N>>, size: usize, range: R) -> Self
     where
         R: RangeBounds<BK>,
         A::Key: Borrow<BK>,
         BK: Ord + ?Sized,
     {
+        let root = match root {
+            Some(root) => root,
+            None => {
+                return Iter {
+                    fwd_path: Vec::new(),
+                    back_path: Vec::new(),
+                    remaining: 0,
+                }
+            }
+        };
         let fwd_path = match range.start_bound() {
             Bound::Included(key) => root.path_next(key, Vec::new()),
             Bound::Excluded(key) => {
@@ -976,14 +1796,14 @@ impl<'a, A: BTreeValue> Iter<'a, A> {
         }
     }
 
-    fn get(path: &[(&'a Node<A>, usize)]) -> Option<&'a A> {
+    fn get(path: &[(&'a Node<A, N>, usize)]) -> Option<&'a A> {
         match path.last() {
             Some((node, index)) => Some(&node.keys[*index]),
             None => None,
         }
     }
 
-    fn step_forward(path: &mut Vec<(&'a Node<A>, usize)>) -> Option<&'a A> {
+    fn step_forward(path: &mut Vec<(&'a Node<A, N>, usize)>) -> Option<&'a A> {
         match path.pop() {
             Some((node, index)) => {
                 let index = index + 1;
@@ -1026,7 +1846,7 @@ impl<'a, A: BTreeValue> Iter<'a, A> {
         }
     }
 
-    fn step_back(path: &mut Vec<(&'a Node<A>, usize)>) -> Option<&'a A> {
+    fn step_back(path: &mut Vec<(&'a Node<A, N>, usize)>) -> Option<&'a A> {
         match path.pop() {
             Some((node, index)) => match node.children[index] {
                 Some(ref child) => {
@@ -1069,7 +1889,7 @@ impl<'a, A: BTreeValue> Iter<'a, A> {
     }
 }
 
-impl<'a, A: 'a + BTreeValue> Iterator for Iter<'a, A> {
+impl<'a, A: 'a + BTreeValue, N: OrdChunkSize<A>> Iterator for Iter<'a, A, N> {
     type Item = &'a A;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -1093,7 +1913,7 @@ impl<'a, A: 'a + BTreeValue> Iterator for Iter<'a, A> {
     }
 }
 
-impl<'a, A: 'a + BTreeValue> DoubleEndedIterator for Iter<'a, A> {
+impl<'a, A: 'a + BTreeValue, N: OrdChunkSize<A>> DoubleEndedIterator for Iter<'a, A, N> {
     fn next_back(&mut self) -> Option<Self::Item> {
         match Iter::get(&self.back_path) {
             None => None,
@@ -1112,38 +1932,46 @@ impl<'a, A: 'a + BTreeValue> DoubleEndedIterator for Iter<'a, A> {
 
 // Consuming iterator
 
-enum ConsumingIterItem<A> {
-    Consider(Node<A>),
+enum ConsumingIterItem<A, N: OrdChunkSize<A>> {
+    Consider(Node<A, N>),
     Yield(A),
 }
 
 /// A consuming iterator over an ordered set.
-pub struct ConsumingIter<A> {
+pub struct ConsumingIter<A, N: OrdChunkSize<A>> {
     fwd_last: Option<A>,
-    fwd_stack: Vec<ConsumingIterItem<A>>,
+    fwd_stack: Vec<ConsumingIterItem<A, N>>,
     back_last: Option<A>,
-    back_stack: Vec<ConsumingIterItem<A>>,
+    back_stack: Vec<ConsumingIterItem<A, N>>,
     remaining: usize,
 }
 
-impl<A: Clone> ConsumingIter<A> {
-    pub(crate) fn new(root: &Node<A>, total: usize) -> Self {
+impl<A: Clone, N: OrdChunkSize<A>> ConsumingIter<A, N> {
+    pub(crate) fn new(root: Option<&Node<A, N>>, total: usize) -> Self {
+        let stack = || {
+            root.into_iter()
+                .map(|root| ConsumingIterItem::Consider(root.clone()))
+                .collect()
+        };
         ConsumingIter {
             fwd_last: None,
-            fwd_stack: vec![ConsumingIterItem::Consider(root.clone())],
+            fwd_stack: stack(),
             back_last: None,
-            back_stack: vec![ConsumingIterItem::Consider(root.clone())],
+            back_stack: stack(),
             remaining: total,
         }
     }
 
-    fn push_node(stack: &mut Vec<ConsumingIterItem<A>>, maybe_node: Option<PoolRef<Node<A>>>) {
+    fn push_node(
+        stack: &mut Vec<ConsumingIterItem<A, N>>,
+        maybe_node: Option<PoolRef<Node<A, N>>>,
+    ) {
         if let Some(node) = maybe_node {
             stack.push(ConsumingIterItem::Consider(PoolRef::unwrap_or_clone(node)))
         }
     }
 
-    fn push(stack: &mut Vec<ConsumingIterItem<A>>, mut node: Node<A>) {
+    fn push(stack: &mut Vec<ConsumingIterItem<A, N>>, mut node: Node<A, N>) {
         for _n in 0..node.keys.len() {
             ConsumingIter::push_node(stack, node.children.pop_back());
             stack.push(ConsumingIterItem::Yield(node.keys.pop_back()));
@@ -1151,18 +1979,18 @@ impl<A: Clone> ConsumingIter<A> {
         ConsumingIter::push_node(stack, node.children.pop_back());
     }
 
-    fn push_fwd(&mut self, node: Node<A>) {
+    fn push_fwd(&mut self, node: Node<A, N>) {
         ConsumingIter::push(&mut self.fwd_stack, node)
     }
 
-    fn push_node_back(&mut self, maybe_node: Option<PoolRef<Node<A>>>) {
+    fn push_node_back(&mut self, maybe_node: Option<PoolRef<Node<A, N>>>) {
         if let Some(node) = maybe_node {
             self.back_stack
                 .push(ConsumingIterItem::Consider(PoolRef::unwrap_or_clone(node)))
         }
     }
 
-    fn push_back(&mut self, mut node: Node<A>) {
+    fn push_back(&mut self, mut node: Node<A, N>) {
         for _i in 0..node.keys.len() {
             self.push_node_back(node.children.pop_front());
             self.back_stack
@@ -1172,7 +2000,7 @@ impl<A: Clone> ConsumingIter<A> {
     }
 }
 
-impl<A> Iterator for ConsumingIter<A>
+impl<A, N: OrdChunkSize<A>> Iterator for ConsumingIter<A, N>
 where
     A: BTreeValue + Clone,
 {
@@ -1208,7 +2036,7 @@ where
     }
 }
 
-impl<A> DoubleEndedIterator for ConsumingIter<A>
+impl<A, N: OrdChunkSize<A>> DoubleEndedIterator for ConsumingIter<A, N>
 where
     A: BTreeValue + Clone,
 {
@@ -1238,14 +2066,14 @@ where
     }
 }
 
-impl<A: BTreeValue + Clone> ExactSizeIterator for ConsumingIter<A> {}
+impl<A: BTreeValue + Clone, N: OrdChunkSize<A>> ExactSizeIterator for ConsumingIter<A, N> {}
 
 // DiffIter
 
 /// An iterator over the differences between two ordered sets.
-pub struct DiffIter<'a, A> {
-    old_stack: Vec<IterItem<'a, A>>,
-    new_stack: Vec<IterItem<'a, A>>,
+pub struct DiffIter<'a, A, N: OrdChunkSize<A>> {
+    old_stack: Vec<IterItem<'a, A, N>>,
+    new_stack: Vec<IterItem<'a, A, N>>,
 }
 
 /// A description of a difference between two ordered sets.
@@ -1264,34 +2092,32 @@ pub enum DiffItem<'a, A> {
     Remove(&'a A),
 }
 
-enum IterItem<'a, A> {
-    Consider(&'a Node<A>),
+enum IterItem<'a, A, N: OrdChunkSize<A>> {
+    Consider(&'a Node<A, N>),
     Yield(&'a A),
 }
 
-impl<'a, A: 'a> DiffIter<'a, A> {
-    pub(crate) fn new(old: &'a Node<A>, new: &'a Node<A>) -> Self {
+impl<'a, A: 'a, N: OrdChunkSize<A>> DiffIter<'a, A, N> {
+    pub(crate) fn new(old: Option<&'a Node<A, N>>, new: Option<&'a Node<A, N>>) -> Self {
+        let stack = |root: Option<&'a Node<A, N>>| {
+            root.filter(|root| !root.keys.is_empty())
+                .map(IterItem::Consider)
+                .into_iter()
+                .collect()
+        };
         DiffIter {
-            old_stack: if old.keys.is_empty() {
-                Vec::new()
-            } else {
-                vec![IterItem::Consider(old)]
-            },
-            new_stack: if new.keys.is_empty() {
-                Vec::new()
-            } else {
-                vec![IterItem::Consider(new)]
-            },
+            old_stack: stack(old),
+            new_stack: stack(new),
         }
     }
 
-    fn push_node(stack: &mut Vec<IterItem<'a, A>>, maybe_node: &'a Option<PoolRef<Node<A>>>) {
+    fn push_node(stack: &mut Vec<IterItem<'a, A, N>>, maybe_node: &'a Option<PoolRef<Node<A, N>>>) {
         if let Some(ref node) = *maybe_node {
             stack.push(IterItem::Consider(node))
         }
     }
 
-    fn push(stack: &mut Vec<IterItem<'a, A>>, node: &'a Node<A>) {
+    fn push(stack: &mut Vec<IterItem<'a, A, N>>, node: &'a Node<A, N>) {
         for n in 0..node.keys.len() {
             let i = node.keys.len() - n;
             Self::push_node(stack, &node.children[i]);
@@ -1301,7 +2127,41 @@ impl<'a, A: 'a> DiffIter<'a, A> {
     }
 }
 
-impl<'a, A> Iterator for DiffIter<'a, A>
+impl<'a, A, N: OrdChunkSize<A>> DiffIter<'a, A, N>
+where
+    A: 'a + BTreeValue + PartialEq,
+{
+    /// Compare two trees as sorted sequences of values, by finding the
+    /// first value where they differ. Subtrees the two share are stepped
+    /// over, as they are by the diff.
+    ///
+    /// `update` compares two values with equal keys.
+    pub(crate) fn compare<F>(
+        left: Option<&'a Node<A, N>>,
+        right: Option<&'a Node<A, N>>,
+        update: F,
+    ) -> Option<Ordering>
+    where
+        F: FnOnce(&A, &A) -> Option<Ordering>,
+    {
+        // If the first difference is a value only one side has, that side
+        // is the lesser one unless the other side ends before it.
+        let continues_past = |node: Option<&Node<A, N>>, value: &A| {
+            node.and_then(Node::max)
+                .map_or(false, |max| max.cmp_values(value) == Ordering::Greater)
+        };
+        match DiffIter::new(left, right).next() {
+            None => Some(Ordering::Equal),
+            Some(DiffItem::Update { old, new }) => update(old, new),
+            Some(DiffItem::Remove(old)) if continues_past(right, old) => Some(Ordering::Less),
+            Some(DiffItem::Remove(_)) => Some(Ordering::Greater),
+            Some(DiffItem::Add(new)) if continues_past(left, new) => Some(Ordering::Greater),
+            Some(DiffItem::Add(_)) => Some(Ordering::Less),
+        }
+    }
+}
+
+impl<'a, A, N: OrdChunkSize<A>> Iterator for DiffIter<'a, A, N>
 where
     A: 'a + BTreeValue + PartialEq,
 {
@@ -3,24 +3,29 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
 use std::fmt;
 use std::hash::{BuildHasher, Hash, Hasher};
 use std::iter::FusedIterator;
+use std::ops::{Deref, DerefMut};
 use std::slice::{Iter as SliceIter, IterMut as SliceIterMut};
+#[cfg(feature = "hash-cache")]
+use std::sync::atomic::{self, AtomicBool, AtomicU64};
 use std::{mem, ptr};
 
-use bitmaps::Bits;
 use sized_chunks::sparse_chunk::{Iter as ChunkIter, IterMut as ChunkIterMut, SparseChunk};
-use typenum::{Pow, Unsigned, U2};
+use sized_chunks::Chunk;
+use typenum::Unsigned;
 
-use crate::config::HashLevelSize;
-use crate::util::{clone_ref, Pool, PoolClone, PoolDefault, PoolRef, Ref};
+use crate::config::{HashLevelSize, HashSmallSize};
+#[cfg(any(test, feature = "debug"))]
+use crate::debug::{InvariantViolation, NodeInfo, NodeKind, Walker};
+use crate::memory::{self, Visitor};
+use crate::util::{clone_ref, Counted, NodeType, Pool, PoolClone, PoolDefault, PoolRef, Ref};
 
-pub(crate) type HashWidth = <U2 as Pow<HashLevelSize>>::Output;
-pub(crate) type HashBits = <HashWidth as Bits>::Store; // a uint of HASH_SIZE bits
-pub(crate) const HASH_SHIFT: usize = HashLevelSize::USIZE;
-pub(crate) const HASH_WIDTH: usize = HashWidth::USIZE;
-pub(crate) const HASH_MASK: HashBits = (HASH_WIDTH - 1) as HashBits;
+pub(crate) type HashBits = u32;
+pub(crate) const HASH_BITS: usize = 32;
 
 pub(crate) fn hash_key<K: Hash + ?Sized, S: BuildHasher>(bh: &S, key: &K) -> HashBits {
     let mut hasher = bh.build_hasher();
@@ -29,8 +34,8 @@ pub(crate) fn hash_key<K: Hash + ?Sized, S: BuildHasher>(bh: &S, key: &K) -> Has
 }
 
 #[inline]
-fn mask(hash: HashBits, shift: usize) -> HashBits {
-    hash >> shift & HASH_MASK
+fn mask<N: Unsigned>(hash: HashBits, shift: usize) -> HashBits {
+    hash >> shift & ((1 << N::USIZE) - 1)
 }
 
 pub trait HashValue {
@@ -40,55 +45,124 @@ pub trait HashValue {
     fn ptr_eq(&self, other: &Self) -> bool;
 }
 
+impl<A, N: HashLevelSize<A>> NodeType for Node<A, N> {
+    #[cfg(feature = "instrument")]
+    const TREE: crate::instrument::Tree = crate::instrument::Tree::Hamt;
+}
+
 #[derive(Clone)]
-pub(crate) struct Node<A> {
-    data: SparseChunk<Entry<A>, HashWidth>,
+pub struct Node<A, N: HashLevelSize<A>> {
+    data: SparseChunk<Entry<A, N>, N::Width>,
+    #[cfg(feature = "hash-cache")]
+    hash: HashCache,
+}
+
+#[cfg(feature = "pool")]
+#[allow(unsafe_code)]
+unsafe fn cast_uninit<A>(target: &mut A) -> &mut mem::MaybeUninit<A> {
+    &mut *(target as *mut A as *mut mem::MaybeUninit<A>)
 }
 
 #[allow(unsafe_code)]
-impl<A> PoolDefault for Node<A> {
+impl<A, N: HashLevelSize<A>> PoolDefault for Node<A, N> {
     #[cfg(feature = "pool")]
     unsafe fn default_uninit(target: &mut mem::MaybeUninit<Self>) {
-        SparseChunk::default_uninit(
-            target
-                .as_mut_ptr()
-                .cast::<mem::MaybeUninit<SparseChunk<Entry<A>, HashWidth>>>()
-                .as_mut()
-                .unwrap(),
-        )
+        let ptr: *mut Self = target.as_mut_ptr();
+        SparseChunk::default_uninit(cast_uninit(&mut (*ptr).data));
+        #[cfg(feature = "hash-cache")]
+        ptr::write(&mut (*ptr).hash, HashCache::default());
     }
 }
 
 #[allow(unsafe_code)]
-impl<A> PoolClone for Node<A>
+impl<A, N: HashLevelSize<A>> PoolClone for Node<A, N>
 where
     A: Clone,
 {
     #[cfg(feature = "pool")]
     unsafe fn clone_uninit(&self, target: &mut mem::MaybeUninit<Self>) {
-        self.data.clone_uninit(
-            target
-                .as_mut_ptr()
-                .cast::<mem::MaybeUninit<SparseChunk<Entry<A>, HashWidth>>>()
-                .as_mut()
-                .unwrap(),
-        )
+        let ptr: *mut Self = target.as_mut_ptr();
+        self.data.clone_uninit(cast_uninit(&mut (*ptr).data));
+        #[cfg(feature = "hash-cache")]
+        ptr::write(&mut (*ptr).hash, HashCache::default());
+    }
+}
+
+/// The hash of every value below a node, once it's been computed, with
+/// the `hash-cache` feature.
+#[cfg(feature = "hash-cache")]
+#[derive(Default)]
+struct HashCache {
+    hash: AtomicU64,
+    valid: AtomicBool,
+}
+
+#[cfg(feature = "hash-cache")]
+impl HashCache {
+    fn get(&self) -> Option<u64> {
+        if self.valid.load(atomic::Ordering::Acquire) {
+            Some(self.hash.load(atomic::Ordering::Relaxed))
+        } else {
+            None
+        }
+    }
+
+    fn set(&self, hash: u64) {
+        self.hash.store(hash, atomic::Ordering::Relaxed);
+        self.valid.store(true, atomic::Ordering::Release);
+    }
+
+    fn clear(&mut self) {
+        *self.valid.get_mut() = false;
     }
 }
 
+#[cfg(feature = "hash-cache")]
+impl Clone for HashCache {
+    fn clone(&self) -> Self {
+        // Nodes are only ever cloned to be changed.
+        Self::default()
+    }
+}
+
+/// Hash a single value for a structural hash, independently of the
+/// collection's own hasher.
+fn value_hash<A: Hash>(value: &A) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Combine the hashes of some values into one which doesn't depend on
+/// their order.
+fn combine_hashes<'a, A, I>(values: I) -> u64
+where
+    A: Hash + 'a,
+    I: IntoIterator<Item = &'a A>,
+{
+    values
+        .into_iter()
+        .fold(0, |sum: u64, value| sum.wrapping_add(value_hash(value)))
+}
+
 #[derive(Clone)]
-pub(crate) struct CollisionNode<A> {
+pub struct CollisionNode<A> {
     hash: HashBits,
     data: Vec<A>,
 }
 
-pub(crate) enum Entry<A> {
+impl<A> NodeType for CollisionNode<A> {
+    #[cfg(feature = "instrument")]
+    const TREE: crate::instrument::Tree = crate::instrument::Tree::Hamt;
+}
+
+pub enum Entry<A, N: HashLevelSize<A>> {
     Value(A, HashBits),
-    Collision(Ref<CollisionNode<A>>),
-    Node(PoolRef<Node<A>>),
+    Collision(Ref<Counted<CollisionNode<A>>>),
+    Node(PoolRef<Node<A, N>>),
 }
 
-impl<A: Clone> Clone for Entry<A> {
+impl<A: Clone, N: HashLevelSize<A>> Clone for Entry<A, N> {
     fn clone(&self) -> Self {
         match self {
             Entry::Value(value, hash) => Entry::Value(value.clone(), *hash),
@@ -98,7 +172,7 @@ impl<A: Clone> Clone for Entry<A> {
     }
 }
 
-impl<A> Entry<A> {
+impl<A, N: HashLevelSize<A>> Entry<A, N> {
     fn is_value(&self) -> bool {
         matches!(self, Entry::Value(_, _))
     }
@@ -110,28 +184,30 @@ impl<A> Entry<A> {
         }
     }
 
-    fn from_node(pool: &Pool<Node<A>>, node: Node<A>) -> Self {
+    fn from_node(pool: &Pool<Node<A, N>>, node: Node<A, N>) -> Self {
         Entry::Node(PoolRef::new(pool, node))
     }
 }
 
-impl<A> From<CollisionNode<A>> for Entry<A> {
+impl<A, N: HashLevelSize<A>> From<CollisionNode<A>> for Entry<A, N> {
     fn from(node: CollisionNode<A>) -> Self {
-        Entry::Collision(Ref::new(node))
+        Entry::Collision(Ref::new(Counted::new(node)))
     }
 }
 
-impl<A> Default for Node<A> {
+impl<A, N: HashLevelSize<A>> Default for Node<A, N> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl<A> Node<A> {
+impl<A, N: HashLevelSize<A>> Node<A, N> {
     #[inline]
     pub(crate) fn new() -> Self {
         Node {
             data: SparseChunk::new(),
+            #[cfg(feature = "hash-cache")]
+            hash: HashCache::default(),
         }
     }
 
@@ -141,42 +217,158 @@ impl<A> Node<A> {
     }
 
     #[inline]
-    pub(crate) fn unit(index: usize, value: Entry<A>) -> Self {
+    pub(crate) fn unit(index: usize, value: Entry<A, N>) -> Self {
         Node {
             data: SparseChunk::unit(index, value),
+            #[cfg(feature = "hash-cache")]
+            hash: HashCache::default(),
         }
     }
 
     #[inline]
-    pub(crate) fn pair(index1: usize, value1: Entry<A>, index2: usize, value2: Entry<A>) -> Self {
+    pub(crate) fn pair(
+        index1: usize,
+        value1: Entry<A, N>,
+        index2: usize,
+        value2: Entry<A, N>,
+    ) -> Self {
         Node {
             data: SparseChunk::pair(index1, value1, index2, value2),
+            #[cfg(feature = "hash-cache")]
+            hash: HashCache::default(),
         }
     }
 
     #[inline]
-    pub(crate) fn single_child(pool: &Pool<Node<A>>, index: usize, node: Self) -> Self {
+    pub(crate) fn single_child(pool: &Pool<Node<A, N>>, index: usize, node: Self) -> Self {
         Node {
             data: SparseChunk::unit(index, Entry::from_node(pool, node)),
+            #[cfg(feature = "hash-cache")]
+            hash: HashCache::default(),
         }
     }
 
-    fn pop(&mut self) -> Entry<A> {
+    fn pop(&mut self) -> Entry<A, N> {
         self.data.pop().unwrap()
     }
+
+    /// Get a mutable reference to a node, copying it first if it's shared.
+    ///
+    /// Every change to a node goes through here, so that its cached hash
+    /// can be thrown away.
+    #[inline]
+    pub(crate) fn make_mut<'a>(pool: &Pool<Node<A, N>>, node: &'a mut PoolRef<Self>) -> &'a mut Self
+    where
+        A: Clone,
+    {
+        let node = PoolRef::make_mut(pool, node);
+        #[cfg(feature = "hash-cache")]
+        node.hash.clear();
+        node
+    }
+
+    /// Compute a hash of every value below this node which doesn't depend
+    /// on the order of the values, or the shape of the tree.
+    pub(crate) fn structural_hash(&self) -> u64
+    where
+        A: Hash,
+    {
+        #[cfg(feature = "hash-cache")]
+        {
+            if let Some(hash) = self.hash.get() {
+                return hash;
+            }
+        }
+        let hash = self.data.iter().fold(0, |sum: u64, entry| {
+            sum.wrapping_add(match entry {
+                Entry::Value(value, _) => value_hash(value),
+                Entry::Collision(coll) => combine_hashes(&coll.data),
+                Entry::Node(node) => node.structural_hash(),
+            })
+        });
+        #[cfg(feature = "hash-cache")]
+        self.hash.set(hash);
+        hash
+    }
+
+    /// The structural hash of this node, if it's been computed already.
+    #[cfg(feature = "hash-cache")]
+    pub(crate) fn cached_hash(&self) -> Option<u64> {
+        self.hash.get()
+    }
+
+    /// Walk this node and all of its children.
+    #[cfg(any(test, feature = "debug"))]
+    pub(crate) fn walk(&self, walker: &mut dyn Walker, parent: usize, depth: usize) {
+        let address = memory::address(self);
+        let branch = self
+            .data
+            .iter()
+            .any(|entry| !matches!(entry, Entry::Value(_, _)));
+        let info = NodeInfo {
+            kind: if branch {
+                NodeKind::Branch
+            } else {
+                NodeKind::Leaf
+            },
+            depth,
+            entries: self.len(),
+            capacity: N::Width::USIZE,
+        };
+        if !walker.node(parent, address, info) {
+            return;
+        }
+        for entry in self.data.iter() {
+            match entry {
+                Entry::Value(_, _) => {}
+                Entry::Collision(coll) => {
+                    let info = NodeInfo {
+                        kind: NodeKind::Collision,
+                        depth: depth + 1,
+                        entries: coll.data.len(),
+                        capacity: coll.data.capacity(),
+                    };
+                    walker.node(address, memory::address(&**coll), info);
+                }
+                Entry::Node(child) => child.walk(walker, address, depth + 1),
+            }
+        }
+    }
+
+    /// Visit this node, its values and all of its children.
+    pub(crate) fn visit(&self, visitor: &mut dyn Visitor<A>) {
+        if !visitor.node(memory::address(self), memory::size_of::<Self>()) {
+            return;
+        }
+        for entry in self.data.iter() {
+            match entry {
+                Entry::Value(value, _) => visitor.value(value),
+                Entry::Collision(coll) => {
+                    let bytes = memory::size_of::<CollisionNode<A>>()
+                        + coll.data.capacity() * memory::size_of::<A>();
+                    if visitor.node(memory::address(&**coll), bytes) {
+                        for value in &coll.data {
+                            visitor.value(value);
+                        }
+                    }
+                }
+                Entry::Node(child) => child.visit(visitor),
+            }
+        }
+    }
 }
 
-impl<A: HashValue> Node<A> {
+impl<A: HashValue, N: HashLevelSize<A>> Node<A, N> {
     fn merge_values(
-        pool: &Pool<Node<A>>,
+        pool: &Pool<Node<A, N>>,
         value1: A,
         hash1: HashBits,
         value2: A,
         hash2: HashBits,
         shift: usize,
     ) -> Self {
-        let index1 = mask(hash1, shift) as usize;
-        let index2 = mask(hash2, shift) as usize;
+        let index1 = mask::<N>(hash1, shift) as usize;
+        let index2 = mask::<N>(hash2, shift) as usize;
         if index1 != index2 {
             // Both values fit on the same level.
             Node::pair(
@@ -185,7 +377,7 @@ impl<A: HashValue> Node<A> {
                 index2,
                 Entry::Value(value2, hash2),
             )
-        } else if shift + HASH_SHIFT >= HASH_WIDTH {
+        } else if shift + N::USIZE >= HASH_BITS {
             // If we're at the bottom, we've got a collision.
             Node::unit(
                 index1,
@@ -193,7 +385,7 @@ impl<A: HashValue> Node<A> {
             )
         } else {
             // Pass the values down a level.
-            let node = Node::merge_values(pool, value1, hash1, value2, hash2, shift + HASH_SHIFT);
+            let node = Node::merge_values(pool, value1, hash1, value2, hash2, shift + N::USIZE);
             Node::single_child(pool, index1, node)
         }
     }
@@ -203,7 +395,7 @@ impl<A: HashValue> Node<A> {
         BK: Eq + ?Sized,
         A::Key: Borrow<BK>,
     {
-        let index = mask(hash, shift) as usize;
+        let index = mask::<N>(hash, shift) as usize;
         if let Some(entry) = self.data.get(index) {
             match entry {
                 Entry::Value(ref value, _) => {
@@ -214,7 +406,7 @@ impl<A: HashValue> Node<A> {
                     }
                 }
                 Entry::Collision(ref coll) => coll.get(key),
-                Entry::Node(ref child) => child.get(hash, shift + HASH_SHIFT, key),
+                Entry::Node(ref child) => child.get(hash, shift + N::USIZE, key),
             }
         } else {
             None
@@ -223,7 +415,7 @@ impl<A: HashValue> Node<A> {
 
     pub(crate) fn get_mut<BK>(
         &mut self,
-        pool: &Pool<Node<A>>,
+        pool: &Pool<Node<A, N>>,
         hash: HashBits,
         shift: usize,
         key: &BK,
@@ -233,7 +425,7 @@ impl<A: HashValue> Node<A> {
         BK: Eq + ?Sized,
         A::Key: Borrow<BK>,
     {
-        let index = mask(hash, shift) as usize;
+        let index = mask::<N>(hash, shift) as usize;
         if let Some(entry) = self.data.get_mut(index) {
             match entry {
                 Entry::Value(ref mut value, _) => {
@@ -248,8 +440,8 @@ impl<A: HashValue> Node<A> {
                     coll.get_mut(key)
                 }
                 Entry::Node(ref mut child_ref) => {
-                    let child = PoolRef::make_mut(pool, child_ref);
-                    child.get_mut(pool, hash, shift + HASH_SHIFT, key)
+                    let child = Node::make_mut(pool, child_ref);
+                    child.get_mut(pool, hash, shift + N::USIZE, key)
                 }
             }
         } else {
@@ -259,7 +451,7 @@ impl<A: HashValue> Node<A> {
 
     pub(crate) fn insert(
         &mut self,
-        pool: &Pool<Node<A>>,
+        pool: &Pool<Node<A, N>>,
         hash: HashBits,
         shift: usize,
         value: A,
@@ -267,7 +459,7 @@ impl<A: HashValue> Node<A> {
     where
         A: Clone,
     {
-        let index = mask(hash, shift) as usize;
+        let index = mask::<N>(hash, shift) as usize;
         if let Some(entry) = self.data.get_mut(index) {
             let mut fallthrough = false;
             // Value is here
@@ -289,8 +481,8 @@ impl<A: HashValue> Node<A> {
                 }
                 Entry::Node(ref mut child_ref) => {
                     // Child node
-                    let child = PoolRef::make_mut(pool, child_ref);
-                    return child.insert(pool, hash, shift + HASH_SHIFT, value);
+                    let child = Node::make_mut(pool, child_ref);
+                    return child.insert(pool, hash, shift + N::USIZE, value);
                 }
             }
             if !fallthrough {
@@ -299,7 +491,7 @@ impl<A: HashValue> Node<A> {
                 // that we overwrite it with the merged node.
                 #[allow(unsafe_code)]
                 let old_entry = unsafe { ptr::read(entry) };
-                if shift + HASH_SHIFT >= HASH_WIDTH {
+                if shift + N::USIZE >= HASH_BITS {
                     // We're at the lowest level, need to set up a collision node.
                     let coll = CollisionNode::new(hash, old_entry.unwrap_value(), value);
                     #[allow(unsafe_code)]
@@ -313,7 +505,7 @@ impl<A: HashValue> Node<A> {
                         old_hash,
                         value,
                         hash,
-                        shift + HASH_SHIFT,
+                        shift + N::USIZE,
                     );
                     #[allow(unsafe_code)]
                     unsafe {
@@ -335,7 +527,7 @@ impl<A: HashValue> Node<A> {
 
     pub(crate) fn remove<BK>(
         &mut self,
-        pool: &Pool<Node<A>>,
+        pool: &Pool<Node<A, N>>,
         hash: HashBits,
         shift: usize,
         key: &BK,
@@ -345,7 +537,7 @@ impl<A: HashValue> Node<A> {
         BK: Eq + ?Sized,
         A::Key: Borrow<BK>,
     {
-        let index = mask(hash, shift) as usize;
+        let index = mask::<N>(hash, shift) as usize;
         let mut new_node = None;
         let mut removed = None;
         if let Some(entry) = self.data.get_mut(index) {
@@ -366,8 +558,8 @@ impl<A: HashValue> Node<A> {
                     }
                 }
                 Entry::Node(ref mut child_ref) => {
-                    let child = PoolRef::make_mut(pool, child_ref);
-                    match child.remove(pool, hash, shift + HASH_SHIFT, key) {
+                    let child = Node::make_mut(pool, child_ref);
+                    match child.remove(pool, hash, shift + N::USIZE, key) {
                         None => {
                             return None;
                         }
@@ -413,12 +605,9 @@ impl<A: HashValue> CollisionNode<A> {
         BK: Eq + ?Sized,
         A::Key: Borrow<BK>,
     {
-        for entry in &self.data {
-            if key == entry.extract_key().borrow() {
-                return Some(entry);
-            }
-        }
-        None
+        self.data
+            .iter()
+            .find(|entry| key == entry.extract_key().borrow())
     }
 
     fn get_mut<BK>(&mut self, key: &BK) -> Option<&mut A>
@@ -426,12 +615,9 @@ impl<A: HashValue> CollisionNode<A> {
         BK: Eq + ?Sized,
         A::Key: Borrow<BK>,
     {
-        for entry in &mut self.data {
-            if key == entry.extract_key().borrow() {
-                return Some(entry);
-            }
-        }
-        None
+        self.data
+            .iter_mut()
+            .find(|entry| key == entry.extract_key().borrow())
     }
 
     fn insert(&mut self, value: A) -> Option<A> {
@@ -462,35 +648,423 @@ impl<A: HashValue> CollisionNode<A> {
         }
     }
 
-    fn pop(&mut self) -> Entry<A> {
+    fn pop<N: HashLevelSize<A>>(&mut self) -> Entry<A, N> {
         Entry::Value(self.data.pop().unwrap(), self.hash)
     }
 }
 
+// Small roots
+
+/// A flat array of values and their hashes.
+type SmallValues<A> = Chunk<(A, HashBits), HashSmallSize>;
+
+/// The values of a small root, kept apart from the `Chunk`s of an RRB tree
+/// so that they're counted as HAMT nodes.
+#[derive(Clone)]
+pub(crate) struct SmallNode<A>(SmallValues<A>);
+
+impl<A> NodeType for SmallNode<A> {
+    #[cfg(feature = "instrument")]
+    const TREE: crate::instrument::Tree = crate::instrument::Tree::Hamt;
+}
+
+impl<A> Deref for SmallNode<A> {
+    type Target = SmallValues<A>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<A> DerefMut for SmallNode<A> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+/// Compare two hashes in the order a HAMT keeps the values they belong
+/// to in.
+fn tree_order<N: Unsigned>(left: HashBits, right: HashBits) -> Ordering {
+    let mut shift = 0;
+    while shift < HASH_BITS {
+        match mask::<N>(left, shift).cmp(&mask::<N>(right, shift)) {
+            Ordering::Equal => shift += N::USIZE,
+            ordering => return ordering,
+        }
+    }
+    Ordering::Equal
+}
+
+/// The root of a HAMT.
+///
+/// Until it grows beyond `HashSmallSize` values, a HAMT keeps its values
+/// in a single flat array, which can be searched without hashing the key
+/// and without following pointers below the root. The array is kept in
+/// the order the tree would keep its values in, so iterating over a small
+/// root gives the same results as iterating over the equivalent tree.
+pub(crate) enum Root<A, N: HashLevelSize<A>> {
+    Empty,
+    Small(Ref<Counted<SmallNode<A>>>),
+    Tree(PoolRef<Node<A, N>>),
+}
+
+impl<A, N: HashLevelSize<A>> Default for Root<A, N> {
+    fn default() -> Self {
+        Root::Empty
+    }
+}
+
+impl<A, N: HashLevelSize<A>> Clone for Root<A, N> {
+    fn clone(&self) -> Self {
+        match self {
+            Root::Empty => Root::Empty,
+            Root::Small(values) => Root::Small(values.clone()),
+            Root::Tree(node) => Root::Tree(node.clone()),
+        }
+    }
+}
+
+impl<A, N: HashLevelSize<A>> Root<A, N> {
+    /// Compute a hash of every value in the tree which doesn't depend on
+    /// the order of the values, or the shape of the tree.
+    pub(crate) fn structural_hash(&self) -> u64
+    where
+        A: Hash,
+    {
+        match self {
+            Root::Empty => 0,
+            Root::Small(values) => combine_hashes(values.iter().map(|(value, _)| value)),
+            Root::Tree(node) => node.structural_hash(),
+        }
+    }
+
+    /// Test whether two roots are known to hold different values, from
+    /// their cached structural hashes.
+    ///
+    /// This never computes a hash, so it only returns `true` if both
+    /// hashes have been computed before.
+    pub(crate) fn known_unequal(left: &Self, right: &Self) -> bool {
+        #[cfg(feature = "hash-cache")]
+        {
+            if let (Root::Tree(left), Root::Tree(right)) = (left, right) {
+                if let (Some(left), Some(right)) = (left.cached_hash(), right.cached_hash()) {
+                    return left != right;
+                }
+            }
+        }
+        let _ = (left, right);
+        false
+    }
+
+    /// Test whether two roots are the same root, or both empty.
+    pub(crate) fn ptr_eq(left: &Self, right: &Self) -> bool {
+        match (left, right) {
+            (Root::Empty, Root::Empty) => true,
+            (Root::Small(left), Root::Small(right)) => Ref::ptr_eq(left, right),
+            (Root::Tree(left), Root::Tree(right)) => PoolRef::ptr_eq(left, right),
+            _ => false,
+        }
+    }
+
+    /// Walk the root and everything below it.
+    #[cfg(any(test, feature = "debug"))]
+    pub(crate) fn walk(&self, walker: &mut dyn Walker, parent: usize, depth: usize) {
+        match self {
+            Root::Empty => {}
+            Root::Small(values) => {
+                let info = NodeInfo {
+                    kind: NodeKind::Leaf,
+                    depth,
+                    entries: values.len(),
+                    capacity: HashSmallSize::USIZE,
+                };
+                walker.node(parent, memory::address(&**values), info);
+            }
+            Root::Tree(node) => node.walk(walker, parent, depth),
+        }
+    }
+
+    /// Visit the root, its values and everything below it.
+    pub(crate) fn visit(&self, visitor: &mut dyn Visitor<A>) {
+        match self {
+            Root::Empty => {}
+            Root::Small(values) => {
+                let address = memory::address(&**values);
+                if visitor.node(address, memory::size_of::<SmallNode<A>>()) {
+                    for (value, _) in values.iter() {
+                        visitor.value(value);
+                    }
+                }
+            }
+            Root::Tree(node) => node.visit(visitor),
+        }
+    }
+}
+
+impl<A: HashValue, N: HashLevelSize<A>> Root<A, N> {
+    /// Look up a key, calling `hash` for its hash only if the root is a
+    /// tree.
+    pub(crate) fn get<BK>(&self, hash: impl FnOnce() -> HashBits, key: &BK) -> Option<&A>
+    where
+        BK: Eq + ?Sized,
+        A::Key: Borrow<BK>,
+    {
+        match self {
+            Root::Empty => None,
+            Root::Small(values) => values
+                .iter()
+                .map(|(value, _)| value)
+                .find(|value| key == value.extract_key().borrow()),
+            Root::Tree(node) => node.get(hash(), 0, key),
+        }
+    }
+
+    /// Look up a key for modification, calling `hash` for its hash only if
+    /// the root is a tree.
+    pub(crate) fn get_mut<BK>(
+        &mut self,
+        pool: &Pool<Node<A, N>>,
+        hash: impl FnOnce() -> HashBits,
+        key: &BK,
+    ) -> Option<&mut A>
+    where
+        A: Clone,
+        BK: Eq + ?Sized,
+        A::Key: Borrow<BK>,
+    {
+        match self {
+            Root::Empty => None,
+            Root::Small(values) => Ref::make_mut(values)
+                .iter_mut()
+                .map(|(value, _)| value)
+                .find(|value| key == value.extract_key().borrow()),
+            Root::Tree(node) => Node::make_mut(pool, node).get_mut(pool, hash(), 0, key),
+        }
+    }
+
+    /// Insert a value, returning the value it replaced, if any.
+    ///
+    /// A small root which is full is turned into a tree.
+    pub(crate) fn insert(&mut self, pool: &Pool<Node<A, N>>, hash: HashBits, value: A) -> Option<A>
+    where
+        A: Clone,
+    {
+        let values = match self {
+            Root::Empty => {
+                let values = SmallNode(Chunk::unit((value, hash)));
+                *self = Root::Small(Ref::new(Counted::new(values)));
+                return None;
+            }
+            Root::Small(values) => Ref::make_mut(values),
+            Root::Tree(node) => return Node::make_mut(pool, node).insert(pool, hash, 0, value),
+        };
+        if let Some((current, _)) = values
+            .iter_mut()
+            .find(|(current, _)| current.extract_key() == value.extract_key())
+        {
+            return Some(mem::replace(current, value));
+        }
+        if !values.is_full() {
+            // Values with the same hash stay in insertion order, as they
+            // would in a collision node.
+            let index = values
+                .iter()
+                .position(|(_, other)| tree_order::<N>(hash, *other) == Ordering::Less)
+                .unwrap_or_else(|| values.len());
+            values.insert(index, (value, hash));
+            return None;
+        }
+        let mut node = Node::new();
+        for (value, hash) in values.drain() {
+            node.insert(pool, hash, 0, value);
+        }
+        node.insert(pool, hash, 0, value);
+        *self = Root::Tree(PoolRef::new(pool, node));
+        None
+    }
+
+    /// Remove a key, calling `hash` for its hash only if the root is a
+    /// tree.
+    pub(crate) fn remove<BK>(
+        &mut self,
+        pool: &Pool<Node<A, N>>,
+        hash: impl FnOnce() -> HashBits,
+        key: &BK,
+    ) -> Option<A>
+    where
+        A: Clone,
+        BK: Eq + ?Sized,
+        A::Key: Borrow<BK>,
+    {
+        match self {
+            Root::Empty => None,
+            Root::Small(values) => {
+                let index = values
+                    .iter()
+                    .position(|(value, _)| key == value.extract_key().borrow())?;
+                Some(Ref::make_mut(values).remove(index).0)
+            }
+            Root::Tree(node) => Node::make_mut(pool, node).remove(pool, hash(), 0, key),
+        }
+    }
+}
+
+// Invariant checking
+
+#[cfg(any(test, feature = "debug"))]
+fn violation(path: &[usize], message: String) -> InvariantViolation {
+    InvariantViolation::new("HAMT", path, message)
+}
+
+/// Test whether a hash leads down the given path of indices.
+#[cfg(any(test, feature = "debug"))]
+fn hash_follows<N: Unsigned>(hash: HashBits, path: &[usize]) -> bool {
+    path.iter()
+        .enumerate()
+        .all(|(level, index)| mask::<N>(hash, level * N::USIZE) as usize == *index)
+}
+
+#[cfg(any(test, feature = "debug"))]
+impl<A: HashValue, N: HashLevelSize<A>> Root<A, N> {
+    /// Check the invariants of the HAMT, returning the number of values
+    /// in it.
+    pub(crate) fn check_invariants<S>(&self, hasher: &S) -> Result<usize, InvariantViolation>
+    where
+        A::Key: Hash,
+        S: BuildHasher,
+    {
+        let values = match self {
+            Root::Empty => return Ok(0),
+            Root::Small(values) => values,
+            Root::Tree(node) => return node.check_invariants(hasher),
+        };
+        if values.is_empty() {
+            return Err(violation(&[], "small root is empty".to_string()));
+        }
+        for (i, (value, hash)) in values.iter().enumerate() {
+            if hash_key(hasher, value.extract_key()) != *hash {
+                return Err(violation(&[i], "stored hash is out of date".to_string()));
+            }
+            if i > 0 && tree_order::<N>(values[i - 1].1, *hash) == Ordering::Greater {
+                return Err(violation(&[i], "value is out of order".to_string()));
+            }
+            if values
+                .iter()
+                .take(i)
+                .any(|(other, _)| other.extract_key() == value.extract_key())
+            {
+                return Err(violation(&[i], "value is a duplicate".to_string()));
+            }
+        }
+        Ok(values.len())
+    }
+}
+
+#[cfg(any(test, feature = "debug"))]
+impl<A: HashValue, N: HashLevelSize<A>> Node<A, N> {
+    /// Check the invariants of the tree with this node as its root,
+    /// returning the number of values in it.
+    pub(crate) fn check_invariants<S>(&self, hasher: &S) -> Result<usize, InvariantViolation>
+    where
+        A::Key: Hash,
+        S: BuildHasher,
+    {
+        self.check_node(hasher, &mut Vec::new())
+    }
+
+    fn check_node<S>(&self, hasher: &S, path: &mut Vec<usize>) -> Result<usize, InvariantViolation>
+    where
+        A::Key: Hash,
+        S: BuildHasher,
+    {
+        if !path.is_empty() && self.data.is_empty() {
+            return Err(violation(path, "node below the root is empty".to_string()));
+        }
+        let mut len = 0;
+        for index in self.data.indices() {
+            path.push(index);
+            match &self.data[index] {
+                Entry::Value(value, hash) => {
+                    if hash_key(hasher, value.extract_key()) != *hash {
+                        return Err(violation(path, "stored hash is out of date".to_string()));
+                    }
+                    if !hash_follows::<N>(*hash, path) {
+                        return Err(violation(path, "value is in the wrong slot".to_string()));
+                    }
+                    len += 1;
+                }
+                Entry::Collision(coll) => {
+                    if coll.data.len() < 2 {
+                        return Err(violation(
+                            path,
+                            format!("collision node has {} values", coll.data.len()),
+                        ));
+                    }
+                    if !hash_follows::<N>(coll.hash, path) {
+                        return Err(violation(
+                            path,
+                            "collision node is in the wrong slot".to_string(),
+                        ));
+                    }
+                    for (i, value) in coll.data.iter().enumerate() {
+                        if hash_key(hasher, value.extract_key()) != coll.hash {
+                            return Err(violation(
+                                path,
+                                format!("value {} in collision node has a different hash", i),
+                            ));
+                        }
+                        if coll.data[..i]
+                            .iter()
+                            .any(|other| other.extract_key() == value.extract_key())
+                        {
+                            return Err(violation(
+                                path,
+                                format!("value {} in collision node is a duplicate", i),
+                            ));
+                        }
+                    }
+                    len += coll.data.len();
+                }
+                Entry::Node(child) => {
+                    len += child.check_node(hasher, path)?;
+                }
+            }
+            path.pop();
+        }
+        Ok(len)
+    }
+}
+
 // Ref iterator
 
-pub(crate) struct Iter<'a, A> {
+pub(crate) struct Iter<'a, A, N: HashLevelSize<A>> {
     count: usize,
-    stack: Vec<ChunkIter<'a, Entry<A>, HashWidth>>,
-    current: ChunkIter<'a, Entry<A>, HashWidth>,
+    small: SliceIter<'a, (A, HashBits)>,
+    stack: Vec<ChunkIter<'a, Entry<A, N>, N::Width>>,
+    current: Option<ChunkIter<'a, Entry<A, N>, N::Width>>,
     collision: Option<(HashBits, SliceIter<'a, A>)>,
 }
 
-impl<'a, A> Iter<'a, A>
+impl<'a, A, N: HashLevelSize<A>> Iter<'a, A, N>
 where
     A: 'a,
 {
-    pub(crate) fn new(root: &'a Node<A>, size: usize) -> Self {
+    pub(crate) fn new(root: &'a Root<A, N>, size: usize) -> Self {
+        let (small, current) = match root {
+            Root::Empty => ([].iter(), None),
+            Root::Small(values) => (values.iter(), None),
+            Root::Tree(node) => ([].iter(), Some(node.data.iter())),
+        };
         Iter {
             count: size,
-            stack: Vec::with_capacity((HASH_WIDTH / HASH_SHIFT) + 1),
-            current: root.data.iter(),
+            small,
+            stack: Vec::with_capacity((HASH_BITS / N::USIZE) + 1),
+            current,
             collision: None,
         }
     }
 }
 
-impl<'a, A> Iterator for Iter<'a, A>
+impl<'a, A, N: HashLevelSize<A>> Iterator for Iter<'a, A, N>
 where
     A: 'a,
 {
@@ -500,6 +1074,10 @@ where
         if self.count == 0 {
             return None;
         }
+        if let Some((value, hash)) = self.small.next() {
+            self.count -= 1;
+            return Some((value, *hash));
+        }
         if self.collision.is_some() {
             if let Some((hash, ref mut coll)) = self.collision {
                 match coll.next() {
@@ -513,14 +1091,13 @@ where
             self.collision = None;
             return self.next();
         }
-        match self.current.next() {
+        match self.current.as_mut()?.next() {
             Some(Entry::Value(value, hash)) => {
                 self.count -= 1;
                 Some((value, *hash))
             }
             Some(Entry::Node(child)) => {
-                let current = mem::replace(&mut self.current, child.data.iter());
-                self.stack.push(current);
+                self.stack.extend(self.current.replace(child.data.iter()));
                 self.next()
             }
             Some(Entry::Collision(coll)) => {
@@ -530,7 +1107,7 @@ where
             None => match self.stack.pop() {
                 None => None,
                 Some(iter) => {
-                    self.current = iter;
+                    self.current = Some(iter);
                     self.next()
                 }
             },
@@ -542,36 +1119,187 @@ where
     }
 }
 
-impl<'a, A> ExactSizeIterator for Iter<'a, A> where A: 'a {}
+impl<'a, A, N: HashLevelSize<A>> ExactSizeIterator for Iter<'a, A, N> where A: 'a {}
 
-impl<'a, A> FusedIterator for Iter<'a, A> where A: 'a {}
+impl<'a, A, N: HashLevelSize<A>> FusedIterator for Iter<'a, A, N> where A: 'a {}
+
+// Lockstep iterator
+
+/// A position in a walk over a tree: a value, or a node or collision
+/// node which hasn't been entered yet.
+enum Position<'a, A, N: HashLevelSize<A>> {
+    Value(&'a A),
+    Entry(&'a Entry<A, N>),
+    End,
+}
+
+impl<'a, A, N: HashLevelSize<A>> Clone for Position<'a, A, N> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, A, N: HashLevelSize<A>> Copy for Position<'a, A, N> {}
+
+impl<'a, A, N: HashLevelSize<A>> Position<'a, A, N> {
+    fn value(self) -> Option<&'a A> {
+        match self {
+            Position::Value(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// A walk over the values of a tree in the same order as `Iter`, which
+/// stops in front of each node so the caller can decide whether to enter
+/// it.
+struct Cursor<'a, A, N: HashLevelSize<A>> {
+    small: SliceIter<'a, (A, HashBits)>,
+    collision: SliceIter<'a, A>,
+    stack: Vec<ChunkIter<'a, Entry<A, N>, N::Width>>,
+}
+
+impl<'a, A, N: HashLevelSize<A>> Cursor<'a, A, N> {
+    fn new(root: &'a Root<A, N>) -> Self {
+        let mut stack = Vec::with_capacity((HASH_BITS / N::USIZE) + 1);
+        let small = match root {
+            Root::Empty => [].iter(),
+            Root::Small(values) => values.iter(),
+            Root::Tree(node) => {
+                stack.push(node.data.iter());
+                [].iter()
+            }
+        };
+        Cursor {
+            small,
+            collision: [].iter(),
+            stack,
+        }
+    }
+
+    fn next(&mut self) -> Position<'a, A, N> {
+        if let Some((value, _)) = self.small.next() {
+            return Position::Value(value);
+        }
+        if let Some(value) = self.collision.next() {
+            return Position::Value(value);
+        }
+        while let Some(iter) = self.stack.last_mut() {
+            match iter.next() {
+                Some(Entry::Value(value, _)) => return Position::Value(value),
+                Some(entry) => return Position::Entry(entry),
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+        Position::End
+    }
+
+    fn enter(&mut self, entry: &'a Entry<A, N>) {
+        match entry {
+            Entry::Node(node) => self.stack.push(node.data.iter()),
+            Entry::Collision(coll) => self.collision = coll.data.iter(),
+            Entry::Value(_, _) => unreachable!(),
+        }
+    }
+}
+
+/// An iterator over two trees at once, pairing up their values by their
+/// position in iteration order, which steps over any node the two trees
+/// share at the same position without looking inside it.
+///
+/// Once one of the trees runs out of values, the other's are paired with
+/// `None`.
+pub(crate) struct Lockstep<'a, A, N: HashLevelSize<A>> {
+    left: Cursor<'a, A, N>,
+    right: Cursor<'a, A, N>,
+    held_left: Option<Position<'a, A, N>>,
+    held_right: Option<Position<'a, A, N>>,
+}
+
+impl<'a, A, N: HashLevelSize<A>> Lockstep<'a, A, N> {
+    pub(crate) fn new(left: &'a Root<A, N>, right: &'a Root<A, N>) -> Self {
+        Lockstep {
+            left: Cursor::new(left),
+            right: Cursor::new(right),
+            held_left: None,
+            held_right: None,
+        }
+    }
+}
+
+impl<'a, A, N: HashLevelSize<A>> Iterator for Lockstep<'a, A, N> {
+    type Item = (Option<&'a A>, Option<&'a A>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let left = match self.held_left.take() {
+                Some(position) => position,
+                None => self.left.next(),
+            };
+            let right = match self.held_right.take() {
+                Some(position) => position,
+                None => self.right.next(),
+            };
+            match (left, right) {
+                (Position::Entry(Entry::Node(left)), Position::Entry(Entry::Node(right)))
+                    if PoolRef::ptr_eq(left, right) => {}
+                (
+                    Position::Entry(Entry::Collision(left)),
+                    Position::Entry(Entry::Collision(right)),
+                ) if Ref::ptr_eq(left, right) => {}
+                (Position::Entry(entry), right) => {
+                    self.left.enter(entry);
+                    self.held_right = Some(right);
+                }
+                (left, Position::Entry(entry)) => {
+                    self.right.enter(entry);
+                    self.held_left = Some(left);
+                }
+                (Position::End, Position::End) => return None,
+                (left, right) => return Some((left.value(), right.value())),
+            }
+        }
+    }
+}
 
 // Mut ref iterator
 
-pub(crate) struct IterMut<'a, A> {
+pub(crate) struct IterMut<'a, A, N: HashLevelSize<A>> {
     count: usize,
-    pool: Pool<Node<A>>,
-    stack: Vec<ChunkIterMut<'a, Entry<A>, HashWidth>>,
-    current: ChunkIterMut<'a, Entry<A>, HashWidth>,
+    pool: Pool<Node<A, N>>,
+    small: SliceIterMut<'a, (A, HashBits)>,
+    stack: Vec<ChunkIterMut<'a, Entry<A, N>, N::Width>>,
+    current: Option<ChunkIterMut<'a, Entry<A, N>, N::Width>>,
     collision: Option<(HashBits, SliceIterMut<'a, A>)>,
 }
 
-impl<'a, A> IterMut<'a, A>
+impl<'a, A, N: HashLevelSize<A>> IterMut<'a, A, N>
 where
-    A: 'a,
+    A: Clone + 'a,
 {
-    pub(crate) fn new(pool: &Pool<Node<A>>, root: &'a mut Node<A>, size: usize) -> Self {
+    pub(crate) fn new(pool: &Pool<Node<A, N>>, root: &'a mut Root<A, N>, size: usize) -> Self {
+        let (small, current) = match root {
+            Root::Empty => ([].iter_mut(), None),
+            Root::Small(values) => (Ref::make_mut(values).iter_mut(), None),
+            Root::Tree(node) => {
+                let node = Node::make_mut(pool, node);
+                ([].iter_mut(), Some(node.data.iter_mut()))
+            }
+        };
         IterMut {
             count: size,
             pool: pool.clone(),
-            stack: Vec::with_capacity((HASH_WIDTH / HASH_SHIFT) + 1),
-            current: root.data.iter_mut(),
+            small,
+            stack: Vec::with_capacity((HASH_BITS / N::USIZE) + 1),
+            current,
             collision: None,
         }
     }
 }
 
-impl<'a, A> Iterator for IterMut<'a, A>
+impl<'a, A, N: HashLevelSize<A>> Iterator for IterMut<'a, A, N>
 where
     A: Clone + 'a,
 {
@@ -581,6 +1309,10 @@ where
         if self.count == 0 {
             return None;
         }
+        if let Some((value, hash)) = self.small.next() {
+            self.count -= 1;
+            return Some((value, *hash));
+        }
         if self.collision.is_some() {
             if let Some((hash, ref mut coll)) = self.collision {
                 match coll.next() {
@@ -594,15 +1326,15 @@ where
             self.collision = None;
             return self.next();
         }
-        match self.current.next() {
+        match self.current.as_mut()?.next() {
             Some(Entry::Value(value, hash)) => {
                 self.count -= 1;
                 Some((value, *hash))
             }
             Some(Entry::Node(child_ref)) => {
-                let child = PoolRef::make_mut(&self.pool, child_ref);
-                let current = mem::replace(&mut self.current, child.data.iter_mut());
-                self.stack.push(current);
+                let child = Node::make_mut(&self.pool, child_ref);
+                self.stack
+                    .extend(self.current.replace(child.data.iter_mut()));
                 self.next()
             }
             Some(Entry::Collision(coll_ref)) => {
@@ -613,7 +1345,7 @@ where
             None => match self.stack.pop() {
                 None => None,
                 Some(iter) => {
-                    self.current = iter;
+                    self.current = Some(iter);
                     self.next()
                 }
             },
@@ -625,39 +1357,46 @@ where
     }
 }
 
-impl<'a, A> ExactSizeIterator for IterMut<'a, A> where A: Clone + 'a {}
+impl<'a, A, N: HashLevelSize<A>> ExactSizeIterator for IterMut<'a, A, N> where A: Clone + 'a {}
 
-impl<'a, A> FusedIterator for IterMut<'a, A> where A: Clone + 'a {}
+impl<'a, A, N: HashLevelSize<A>> FusedIterator for IterMut<'a, A, N> where A: Clone + 'a {}
 
 // Consuming iterator
 
-pub(crate) struct Drain<A>
+pub(crate) struct Drain<A, N: HashLevelSize<A>>
 where
     A: HashValue,
 {
     count: usize,
-    pool: Pool<Node<A>>,
-    stack: Vec<PoolRef<Node<A>>>,
-    current: PoolRef<Node<A>>,
+    pool: Pool<Node<A, N>>,
+    small: SmallValues<A>,
+    stack: Vec<PoolRef<Node<A, N>>>,
+    current: Option<PoolRef<Node<A, N>>>,
     collision: Option<CollisionNode<A>>,
 }
 
-impl<A> Drain<A>
+impl<A, N: HashLevelSize<A>> Drain<A, N>
 where
-    A: HashValue,
+    A: HashValue + Clone,
 {
-    pub(crate) fn new(pool: &Pool<Node<A>>, root: PoolRef<Node<A>>, size: usize) -> Self {
+    pub(crate) fn new(pool: &Pool<Node<A, N>>, root: Root<A, N>, size: usize) -> Self {
+        let (small, current) = match root {
+            Root::Empty => (Chunk::new(), None),
+            Root::Small(values) => (clone_ref(values).into_inner().0, None),
+            Root::Tree(node) => (Chunk::new(), Some(node)),
+        };
         Drain {
             count: size,
             pool: pool.clone(),
+            small,
             stack: vec![],
-            current: root,
+            current,
             collision: None,
         }
     }
 }
 
-impl<A> Iterator for Drain<A>
+impl<A, N: HashLevelSize<A>> Iterator for Drain<A, N>
 where
     A: HashValue + Clone,
 {
@@ -667,6 +1406,10 @@ where
         if self.count == 0 {
             return None;
         }
+        if !self.small.is_empty() {
+            self.count -= 1;
+            return Some(self.small.pop_front());
+        }
         if self.collision.is_some() {
             if let Some(ref mut coll) = self.collision {
                 if let Some(value) = coll.data.pop() {
@@ -677,24 +1420,26 @@ where
             self.collision = None;
             return self.next();
         }
-        match PoolRef::make_mut(&self.pool, &mut self.current).data.pop() {
+        match Node::make_mut(&self.pool, self.current.as_mut()?)
+            .data
+            .pop()
+        {
             Some(Entry::Value(value, hash)) => {
                 self.count -= 1;
                 Some((value, hash))
             }
             Some(Entry::Collision(coll_ref)) => {
-                self.collision = Some(clone_ref(coll_ref));
+                self.collision = Some(clone_ref(coll_ref).into_inner());
                 self.next()
             }
             Some(Entry::Node(child)) => {
-                let parent = mem::replace(&mut self.current, child);
-                self.stack.push(parent);
+                self.stack.extend(self.current.replace(child));
                 self.next()
             }
             None => match self.stack.pop() {
                 None => None,
                 Some(parent) => {
-                    self.current = parent;
+                    self.current = Some(parent);
                     self.next()
                 }
             },
@@ -706,11 +1451,11 @@ where
     }
 }
 
-impl<A: HashValue> ExactSizeIterator for Drain<A> where A: Clone {}
+impl<A: HashValue, N: HashLevelSize<A>> ExactSizeIterator for Drain<A, N> where A: Clone {}
 
-impl<A: HashValue> FusedIterator for Drain<A> where A: Clone {}
+impl<A: HashValue, N: HashLevelSize<A>> FusedIterator for Drain<A, N> where A: Clone {}
 
-impl<A: HashValue + fmt::Debug> fmt::Debug for Node<A> {
+impl<A: HashValue + fmt::Debug, N: HashLevelSize<A>> fmt::Debug for Node<A, N> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         write!(f, "Node[ ")?;
         for i in self.data.indices() {
@@ -5,12 +5,3 @@
 pub(crate) mod btree;
 pub(crate) mod hamt;
 pub(crate) mod rrb;
-
-pub(crate) mod chunk {
-    use crate::config::VectorChunkSize;
-    use sized_chunks as sc;
-    use typenum::Unsigned;
-
-    pub(crate) type Chunk<A> = sc::sized_chunk::Chunk<A, VectorChunkSize>;
-    pub(crate) const CHUNK_SIZE: usize = VectorChunkSize::USIZE;
-}
@@ -5,24 +5,27 @@
 use std::mem::replace;
 use std::ops::Range;
 
-use crate::nodes::chunk::{Chunk, CHUNK_SIZE};
+use crate::config::VectorChunkSize;
+#[cfg(any(test, feature = "debug"))]
+use crate::debug::{InvariantViolation, NodeInfo, NodeKind, Walker};
+use crate::memory::{self, Visitor};
 use crate::util::{
     Pool, PoolRef,
     Side::{self, Left, Right},
 };
 use crate::vector::RRBPool;
 
-use self::Entry::*;
+use sized_chunks::{types::ChunkLength, Chunk};
 
-pub(crate) const NODE_SIZE: usize = CHUNK_SIZE;
+use self::Entry::*;
 
 #[derive(Debug)]
-enum Size {
+enum Size<N: ChunkLength<usize>> {
     Size(usize),
-    Table(PoolRef<Chunk<usize>>),
+    Table(PoolRef<Chunk<usize, N>>),
 }
 
-impl Clone for Size {
+impl<N: ChunkLength<usize>> Clone for Size<N> {
     fn clone(&self) -> Self {
         match *self {
             Size::Size(size) => Size::Size(size),
@@ -31,7 +34,7 @@ impl Clone for Size {
     }
 }
 
-impl Size {
+impl<N: ChunkLength<usize>> Size<N> {
     fn size(&self) -> usize {
         match self {
             Size::Size(s) => *s,
@@ -46,10 +49,10 @@ impl Size {
         }
     }
 
-    fn table_from_size(pool: &Pool<Chunk<usize>>, level: usize, size: usize) -> Self {
+    fn table_from_size(pool: &Pool<Chunk<usize, N>>, level: usize, size: usize) -> Self {
         let mut chunk = Chunk::new();
         let mut remaining = size;
-        if let Some(child_size) = NODE_SIZE.checked_pow(level as u32) {
+        if let Some(child_size) = N::USIZE.checked_pow(level as u32) {
             while remaining > child_size {
                 let next_value = chunk.last().unwrap_or(&0) + child_size;
                 chunk.push_back(next_value);
@@ -63,7 +66,7 @@ impl Size {
         Size::Table(PoolRef::new(pool, chunk))
     }
 
-    fn push(&mut self, pool: &Pool<Chunk<usize>>, side: Side, level: usize, value: usize) {
+    fn push(&mut self, pool: &Pool<Chunk<usize, N>>, side: Side, level: usize, value: usize) {
         let size = match self {
             Size::Size(ref mut size) => match side {
                 Left => *size,
@@ -74,7 +77,7 @@ impl Size {
             },
             Size::Table(ref mut size_ref) => {
                 let size_table = PoolRef::make_mut(pool, size_ref);
-                debug_assert!(size_table.len() < NODE_SIZE);
+                debug_assert!(size_table.len() < N::USIZE);
                 match side {
                     Left => {
                         for entry in size_table.iter_mut() {
@@ -94,7 +97,7 @@ impl Size {
         self.push(pool, side, level, value);
     }
 
-    fn pop(&mut self, pool: &Pool<Chunk<usize>>, side: Side, level: usize, value: usize) {
+    fn pop(&mut self, pool: &Pool<Chunk<usize, N>>, side: Side, level: usize, value: usize) {
         let size = match self {
             Size::Size(ref mut size) => match side {
                 Left => *size,
@@ -126,7 +129,7 @@ impl Size {
         self.pop(pool, side, level, value);
     }
 
-    fn update(&mut self, pool: &Pool<Chunk<usize>>, index: usize, level: usize, value: isize) {
+    fn update(&mut self, pool: &Pool<Chunk<usize, N>>, index: usize, level: usize, value: isize) {
         let size = match self {
             Size::Size(ref size) => *size,
             Size::Table(ref mut size_ref) => {
@@ -159,13 +162,13 @@ pub(crate) enum SplitResult {
 }
 
 // Invariants: Nodes only at level > 0, Values/Empty only at level = 0
-enum Entry<A> {
-    Nodes(Size, PoolRef<Chunk<Node<A>>>),
-    Values(PoolRef<Chunk<A>>),
+enum Entry<A, N: VectorChunkSize<A>> {
+    Nodes(Size<N>, PoolRef<Chunk<Node<A, N>, N>>),
+    Values(PoolRef<Chunk<A, N>>),
     Empty,
 }
 
-impl<A: Clone> Clone for Entry<A> {
+impl<A: Clone, N: VectorChunkSize<A>> Clone for Entry<A, N> {
     fn clone(&self) -> Self {
         match *self {
             Nodes(ref size, ref nodes) => Nodes(size.clone(), nodes.clone()),
@@ -175,7 +178,7 @@ impl<A: Clone> Clone for Entry<A> {
     }
 }
 
-impl<A: Clone> Entry<A> {
+impl<A: Clone, N: VectorChunkSize<A>> Entry<A, N> {
     fn len(&self) -> usize {
         match self {
             Nodes(_, ref nodes) => nodes.len(),
@@ -192,42 +195,42 @@ impl<A: Clone> Entry<A> {
         }
     }
 
-    fn unwrap_values(&self) -> &Chunk<A> {
+    fn unwrap_values(&self) -> &Chunk<A, N> {
         match self {
             Values(ref values) => values,
             _ => panic!("rrb::Entry::unwrap_values: expected values, found nodes"),
         }
     }
 
-    fn unwrap_nodes(&self) -> &Chunk<Node<A>> {
+    fn unwrap_nodes(&self) -> &Chunk<Node<A, N>, N> {
         match self {
             Nodes(_, ref nodes) => nodes,
             _ => panic!("rrb::Entry::unwrap_nodes: expected nodes, found values"),
         }
     }
 
-    fn unwrap_values_mut(&mut self, pool: &RRBPool<A>) -> &mut Chunk<A> {
+    fn unwrap_values_mut(&mut self, pool: &RRBPool<A, N>) -> &mut Chunk<A, N> {
         match self {
             Values(ref mut values) => PoolRef::make_mut(&pool.value_pool, values),
             _ => panic!("rrb::Entry::unwrap_values_mut: expected values, found nodes"),
         }
     }
 
-    fn unwrap_nodes_mut(&mut self, pool: &RRBPool<A>) -> &mut Chunk<Node<A>> {
+    fn unwrap_nodes_mut(&mut self, pool: &RRBPool<A, N>) -> &mut Chunk<Node<A, N>, N> {
         match self {
             Nodes(_, ref mut nodes) => PoolRef::make_mut(&pool.node_pool, nodes),
             _ => panic!("rrb::Entry::unwrap_nodes_mut: expected nodes, found values"),
         }
     }
 
-    fn values(self) -> Chunk<A> {
+    fn values(self) -> Chunk<A, N> {
         match self {
             Values(values) => PoolRef::unwrap_or_clone(values),
             _ => panic!("rrb::Entry::values: expected values, found nodes"),
         }
     }
 
-    fn nodes(self) -> Chunk<Node<A>> {
+    fn nodes(self) -> Chunk<Node<A, N>, N> {
         match self {
             Nodes(_, nodes) => PoolRef::unwrap_or_clone(nodes),
             _ => panic!("rrb::Entry::nodes: expected nodes, found values"),
@@ -239,13 +242,55 @@ impl<A: Clone> Entry<A> {
     }
 }
 
+/// Walk a leaf chunk.
+#[cfg(any(test, feature = "debug"))]
+pub(crate) fn walk_chunk<A, N: ChunkLength<A>>(
+    chunk: &PoolRef<Chunk<A, N>>,
+    walker: &mut dyn Walker,
+    parent: usize,
+    depth: usize,
+) {
+    let info = NodeInfo {
+        kind: NodeKind::Leaf,
+        depth,
+        entries: chunk.len(),
+        capacity: N::USIZE,
+    };
+    walker.node(parent, memory::address(&**chunk), info);
+}
+
+/// Visit a leaf chunk and the values it contains.
+pub(crate) fn visit_chunk<A, N: ChunkLength<A>>(
+    chunk: &PoolRef<Chunk<A, N>>,
+    visitor: &mut dyn Visitor<A>,
+) {
+    if visitor.node(memory::address(&**chunk), memory::size_of::<Chunk<A, N>>()) {
+        for value in chunk.iter() {
+            visitor.value(value);
+        }
+    }
+}
+
+#[cfg(any(test, feature = "debug"))]
+fn violation(path: &[usize], message: String) -> InvariantViolation {
+    InvariantViolation::new("RRB tree", path, message)
+}
+
+/// What a node holds, for taking a tree apart and putting it back together.
+#[cfg(any(test, feature = "serde"))]
+pub(crate) enum Contents<'a, A, N: VectorChunkSize<A>> {
+    Empty,
+    Values(&'a Chunk<A, N>),
+    Nodes(&'a Chunk<Node<A, N>, N>),
+}
+
 // Node
 
-pub(crate) struct Node<A> {
-    children: Entry<A>,
+pub struct Node<A, N: VectorChunkSize<A>> {
+    children: Entry<A, N>,
 }
 
-impl<A: Clone> Clone for Node<A> {
+impl<A: Clone, N: VectorChunkSize<A>> Clone for Node<A, N> {
     fn clone(&self) -> Self {
         Node {
             children: self.children.clone(),
@@ -253,39 +298,73 @@ impl<A: Clone> Clone for Node<A> {
     }
 }
 
-impl<A: Clone> Default for Node<A> {
+impl<A: Clone, N: VectorChunkSize<A>> Default for Node<A, N> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl<A: Clone> Node<A> {
+impl<A: Clone, N: VectorChunkSize<A>> Node<A, N> {
     pub(crate) fn new() -> Self {
         Node { children: Empty }
     }
 
-    pub(crate) fn parent(pool: &RRBPool<A>, level: usize, children: Chunk<Self>) -> Self {
-        let size = {
-            let mut size = Size::Size(0);
-            let mut it = children.iter().peekable();
-            loop {
-                match it.next() {
-                    None => break,
-                    Some(child) => {
-                        if size.is_size()
-                            && !child.is_completely_dense(level - 1)
-                            && it.peek().is_some()
-                        {
-                            size = Size::table_from_size(&pool.size_pool, level, size.size());
-                        }
-                        size.push(&pool.size_pool, Right, level, child.len())
+    pub(crate) fn parent(pool: &RRBPool<A, N>, level: usize, children: Chunk<Self, N>) -> Self {
+        let size = Self::size_of_children(pool, level, &children);
+        Node {
+            children: Nodes(size, PoolRef::new(&pool.node_pool, children)),
+        }
+    }
+
+    fn size_of_children(pool: &RRBPool<A, N>, level: usize, children: &Chunk<Self, N>) -> Size<N> {
+        let mut size = Size::Size(0);
+        let mut it = children.iter().peekable();
+        loop {
+            match it.next() {
+                None => break,
+                Some(child) => {
+                    if size.is_size()
+                        && !child.is_completely_dense(level - 1)
+                        && it.peek().is_some()
+                    {
+                        size = Size::table_from_size(&pool.size_pool, level, size.size());
                     }
+                    size.push(&pool.size_pool, Right, level, child.len())
                 }
             }
-            size
-        };
+        }
+        size
+    }
+
+    /// Get what a node holds, without its size table.
+    #[cfg(any(test, feature = "serde"))]
+    pub(crate) fn contents(&self) -> Contents<'_, A, N> {
+        match self.children {
+            Entry::Empty => Contents::Empty,
+            Entry::Values(ref values) => Contents::Values(values),
+            Entry::Nodes(_, ref children) => Contents::Nodes(children),
+        }
+    }
+
+    /// Make a leaf node out of a chunk of values which might be shared.
+    #[cfg(any(test, feature = "serde"))]
+    pub(crate) fn from_values(values: PoolRef<Chunk<A, N>>) -> Self {
         Node {
-            children: Nodes(size, PoolRef::new(&pool.node_pool, children)),
+            children: Values(values),
+        }
+    }
+
+    /// Make a node at `level` out of a chunk of child nodes which might be
+    /// shared, building its size table if it needs one.
+    #[cfg(any(test, feature = "serde"))]
+    pub(crate) fn from_nodes(
+        pool: &RRBPool<A, N>,
+        level: usize,
+        children: PoolRef<Chunk<Self, N>>,
+    ) -> Self {
+        let size = Self::size_of_children(pool, level, &children);
+        Node {
+            children: Nodes(size, children),
         }
     }
 
@@ -293,14 +372,18 @@ impl<A: Clone> Node<A> {
         self.children = Empty;
     }
 
-    pub(crate) fn from_chunk(pool: &RRBPool<A>, level: usize, chunk: PoolRef<Chunk<A>>) -> Self {
+    pub(crate) fn from_chunk(
+        pool: &RRBPool<A, N>,
+        level: usize,
+        chunk: PoolRef<Chunk<A, N>>,
+    ) -> Self {
         let node = Node {
             children: Values(chunk),
         };
         node.elevate(pool, level)
     }
 
-    pub(crate) fn single_parent(pool: &RRBPool<A>, node: Self) -> Self {
+    pub(crate) fn single_parent(pool: &RRBPool<A, N>, node: Self) -> Self {
         let size = if node.is_dense() {
             Size::Size(node.len())
         } else {
@@ -313,7 +396,7 @@ impl<A: Clone> Node<A> {
         }
     }
 
-    pub(crate) fn join_dense(pool: &RRBPool<A>, left: Self, right: Self) -> Self {
+    pub(crate) fn join_dense(pool: &RRBPool<A, N>, left: Self, right: Self) -> Self {
         let left_len = left.len();
         let right_len = right.len();
         Node {
@@ -324,7 +407,7 @@ impl<A: Clone> Node<A> {
         }
     }
 
-    pub(crate) fn elevate(self, pool: &RRBPool<A>, level_increment: usize) -> Self {
+    pub(crate) fn elevate(self, pool: &RRBPool<A, N>, level_increment: usize) -> Self {
         if level_increment > 0 {
             Self::single_parent(pool, self.elevate(pool, level_increment - 1))
         } else {
@@ -332,7 +415,7 @@ impl<A: Clone> Node<A> {
         }
     }
 
-    pub(crate) fn join_branches(self, pool: &RRBPool<A>, right: Self, level: usize) -> Self {
+    pub(crate) fn join_branches(self, pool: &RRBPool<A, N>, right: Self, level: usize) -> Self {
         let left_len = self.len();
         let right_len = right.len();
         let size = if self.is_completely_dense(level) && right.is_dense() {
@@ -379,6 +462,82 @@ impl<A: Clone> Node<A> {
         self.children.unwrap_nodes().first().unwrap()
     }
 
+    /// Count the leaf chunks, the branch nodes and the relaxed (size table)
+    /// branch nodes below and including this node, without visiting the
+    /// leaves themselves.
+    pub(crate) fn count_nodes(&self, level: usize) -> (usize, usize, usize) {
+        match self.children {
+            Entry::Empty => (0, 0, 0),
+            Entry::Values(_) => (1, 0, 0),
+            Entry::Nodes(ref size, ref children) => {
+                let relaxed = if size.is_size() { 0 } else { 1 };
+                if level == 1 {
+                    (children.len(), 1, relaxed)
+                } else {
+                    children
+                        .iter()
+                        .map(|child| child.count_nodes(level - 1))
+                        .fold((0, 1, relaxed), |acc, counts| {
+                            (acc.0 + counts.0, acc.1 + counts.1, acc.2 + counts.2)
+                        })
+                }
+            }
+        }
+    }
+
+    /// Walk the nodes below and including this one.
+    #[cfg(any(test, feature = "debug"))]
+    pub(crate) fn walk(&self, walker: &mut dyn Walker, parent: usize, depth: usize) {
+        match self.children {
+            Entry::Empty => {}
+            Entry::Values(ref values) => walk_chunk(values, walker, parent, depth),
+            Entry::Nodes(ref size, ref children) => {
+                let address = memory::address(&**children);
+                let info = NodeInfo {
+                    kind: if size.is_size() {
+                        NodeKind::Branch
+                    } else {
+                        NodeKind::Relaxed
+                    },
+                    depth,
+                    entries: children.len(),
+                    capacity: N::USIZE,
+                };
+                if walker.node(parent, address, info) {
+                    for child in children.iter() {
+                        child.walk(walker, address, depth + 1);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Visit the allocations below this node and the values they contain.
+    ///
+    /// The node itself lives inline in its parent, so it isn't visited.
+    pub(crate) fn visit(&self, visitor: &mut dyn Visitor<A>) {
+        match self.children {
+            Entry::Empty => {}
+            Entry::Values(ref values) => visit_chunk(values, visitor),
+            Entry::Nodes(ref size, ref children) => {
+                if let Size::Table(ref table) = size {
+                    visitor.node(
+                        memory::address(&**table),
+                        memory::size_of::<Chunk<usize, N>>(),
+                    );
+                }
+                if visitor.node(
+                    memory::address(&**children),
+                    memory::size_of::<Chunk<Node<A, N>, N>>(),
+                ) {
+                    for child in children.iter() {
+                        child.visit(visitor);
+                    }
+                }
+            }
+        }
+    }
+
     /// True if the node is dense and so doesn't have a size table
     fn is_dense(&self) -> bool {
         !matches!(self.children, Entry::Nodes(Size::Table(_), _))
@@ -388,9 +547,9 @@ impl<A: Clone> Node<A> {
     // TODO can use this technique to quickly test if a Size::Table
     // should be converted back to a Size::Size
     fn is_completely_dense(&self, level: usize) -> bool {
-        // Size of a full node is NODE_SIZE at level 0, NODE_SIZE² at
+        // Size of a full node is N::USIZE at level 0, N::USIZE² at
         // level 1, etc.
-        if let Some(expected_size) = NODE_SIZE.checked_pow(level as u32 + 1) {
+        if let Some(expected_size) = N::USIZE.checked_pow(level as u32 + 1) {
             self.size() == expected_size
         } else {
             // We overflowed a usize, there's no way we can be completely dense as we know the size
@@ -409,21 +568,21 @@ impl<A: Clone> Node<A> {
     }
 
     #[inline]
-    fn push_size(&mut self, pool: &RRBPool<A>, side: Side, level: usize, value: usize) {
+    fn push_size(&mut self, pool: &RRBPool<A, N>, side: Side, level: usize, value: usize) {
         if let Entry::Nodes(ref mut size, _) = self.children {
             size.push(&pool.size_pool, side, level, value)
         }
     }
 
     #[inline]
-    fn pop_size(&mut self, pool: &RRBPool<A>, side: Side, level: usize, value: usize) {
+    fn pop_size(&mut self, pool: &RRBPool<A, N>, side: Side, level: usize, value: usize) {
         if let Entry::Nodes(ref mut size, _) = self.children {
             size.pop(&pool.size_pool, side, level, value)
         }
     }
 
     #[inline]
-    fn update_size(&mut self, pool: &RRBPool<A>, index: usize, level: usize, value: isize) {
+    fn update_size(&mut self, pool: &RRBPool<A, N>, index: usize, level: usize, value: isize) {
         if let Entry::Nodes(ref mut size, _) = self.children {
             size.update(&pool.size_pool, index, level, value)
         }
@@ -436,7 +595,7 @@ impl<A: Clone> Node<A> {
             } else {
                 match size {
                     Size::Table(ref size_table) => size_table[index - 1],
-                    Size::Size(_) => index * NODE_SIZE.pow(level as u32),
+                    Size::Size(_) => index * N::USIZE.pow(level as u32),
                 }
             }
         } else {
@@ -445,7 +604,7 @@ impl<A: Clone> Node<A> {
     }
 
     fn index_in(&self, level: usize, index: usize) -> Option<usize> {
-        let mut target_idx = if let Some(child_size) = NODE_SIZE.checked_pow(level as u32) {
+        let mut target_idx = if let Some(child_size) = N::USIZE.checked_pow(level as u32) {
             index / child_size
         } else {
             0
@@ -474,7 +633,7 @@ impl<A: Clone> Node<A> {
         }
     }
 
-    pub(crate) fn index_mut(&mut self, pool: &RRBPool<A>, level: usize, index: usize) -> &mut A {
+    pub(crate) fn index_mut(&mut self, pool: &RRBPool<A, N>, level: usize, index: usize) -> &mut A {
         if level == 0 {
             &mut self.children.unwrap_values_mut(pool)[index]
         } else {
@@ -490,11 +649,11 @@ impl<A: Clone> Node<A> {
         level: usize,
         base: usize,
         index: usize,
-    ) -> (Range<usize>, *const Chunk<A>) {
+    ) -> (Range<usize>, *const Chunk<A, N>) {
         if level == 0 {
             (
                 base..(base + self.children.len()),
-                self.children.unwrap_values() as *const Chunk<A>,
+                self.children.unwrap_values() as *const Chunk<A, N>,
             )
         } else {
             let target_idx = self.index_in(level, index).unwrap();
@@ -508,15 +667,15 @@ impl<A: Clone> Node<A> {
 
     pub(crate) fn lookup_chunk_mut(
         &mut self,
-        pool: &RRBPool<A>,
+        pool: &RRBPool<A, N>,
         level: usize,
         base: usize,
         index: usize,
-    ) -> (Range<usize>, *mut Chunk<A>) {
+    ) -> (Range<usize>, *mut Chunk<A, N>) {
         if level == 0 {
             (
                 base..(base + self.children.len()),
-                self.children.unwrap_values_mut(pool) as *mut Chunk<A>,
+                self.children.unwrap_values_mut(pool) as *mut Chunk<A, N>,
             )
         } else {
             let target_idx = self.index_in(level, index).unwrap();
@@ -528,7 +687,7 @@ impl<A: Clone> Node<A> {
         }
     }
 
-    fn push_child_node(&mut self, pool: &RRBPool<A>, side: Side, child: Node<A>) {
+    fn push_child_node(&mut self, pool: &RRBPool<A, N>, side: Side, child: Node<A, N>) {
         let children = self.children.unwrap_nodes_mut(pool);
         match side {
             Left => children.push_front(child),
@@ -536,7 +695,7 @@ impl<A: Clone> Node<A> {
         }
     }
 
-    fn pop_child_node(&mut self, pool: &RRBPool<A>, side: Side) -> Node<A> {
+    fn pop_child_node(&mut self, pool: &RRBPool<A, N>, side: Side) -> Node<A, N> {
         let children = self.children.unwrap_nodes_mut(pool);
         match side {
             Left => children.pop_front(),
@@ -546,11 +705,11 @@ impl<A: Clone> Node<A> {
 
     pub(crate) fn push_chunk(
         &mut self,
-        pool: &RRBPool<A>,
+        pool: &RRBPool<A, N>,
         level: usize,
         side: Side,
-        mut chunk: PoolRef<Chunk<A>>,
-    ) -> PushResult<PoolRef<Chunk<A>>> {
+        mut chunk: PoolRef<Chunk<A, N>>,
+    ) -> PushResult<PoolRef<Chunk<A, N>>> {
         if chunk.is_empty() {
             return PushResult::Done;
         }
@@ -562,7 +721,7 @@ impl<A: Clone> Node<A> {
                 PushResult::Done
             } else {
                 let values = self.children.unwrap_values_mut(pool);
-                if values.len() + chunk.len() <= NODE_SIZE {
+                if values.len() + chunk.len() <= N::USIZE {
                     let chunk = PoolRef::make_mut(&pool.value_pool, &mut chunk);
                     match side {
                         Side::Left => {
@@ -588,7 +747,7 @@ impl<A: Clone> Node<A> {
                         let old_size = rightmost.len();
                         let chunk = PoolRef::make_mut(&pool.value_pool, &mut chunk);
                         let values = rightmost.children.unwrap_values_mut(pool);
-                        let to_drain = chunk.len().min(NODE_SIZE - values.len());
+                        let to_drain = chunk.len().min(N::USIZE - values.len());
                         values.drain_from_front(chunk, to_drain);
                         size.pop(&pool.size_pool, Side::Right, level, old_size);
                         size.push(&pool.size_pool, Side::Right, level, values.len());
@@ -605,7 +764,7 @@ impl<A: Clone> Node<A> {
                         let old_size = leftmost.len();
                         let chunk = PoolRef::make_mut(&pool.value_pool, &mut chunk);
                         let values = leftmost.children.unwrap_values_mut(pool);
-                        let to_drain = chunk.len().min(NODE_SIZE - values.len());
+                        let to_drain = chunk.len().min(N::USIZE - values.len());
                         values.drain_from_back(chunk, to_drain);
                         size.pop(&pool.size_pool, Side::Left, level, old_size);
                         size.push(&pool.size_pool, Side::Left, level, values.len());
@@ -622,7 +781,7 @@ impl<A: Clone> Node<A> {
                 // more space in existing chunks. To keep the middle dense, we
                 // do not add it here.
                 if !chunk.is_empty() {
-                    if side == Left && chunk.len() < NODE_SIZE {
+                    if side == Left && chunk.len() < N::USIZE {
                         if let Entry::Nodes(ref mut size, _) = self.children {
                             if let Size::Size(value) = *size {
                                 *size = Size::table_from_size(&pool.size_pool, level, value);
@@ -679,7 +838,7 @@ impl<A: Clone> Node<A> {
                     PushResult::Done
                 }
                 Some(child) => {
-                    if side == Left && chunk_size < NODE_SIZE {
+                    if side == Left && chunk_size < N::USIZE {
                         if let Entry::Nodes(ref mut size, _) = self.children {
                             if let Size::Size(value) = *size {
                                 *size = Size::table_from_size(&pool.size_pool, level, value);
@@ -696,10 +855,10 @@ impl<A: Clone> Node<A> {
 
     pub(crate) fn pop_chunk(
         &mut self,
-        pool: &RRBPool<A>,
+        pool: &RRBPool<A, N>,
         level: usize,
         side: Side,
-    ) -> PopResult<PoolRef<Chunk<A>>> {
+    ) -> PopResult<PoolRef<Chunk<A, N>>> {
         if self.is_empty() {
             return PopResult::Empty;
         }
@@ -758,7 +917,7 @@ impl<A: Clone> Node<A> {
 
     pub(crate) fn split(
         &mut self,
-        pool: &RRBPool<A>,
+        pool: &RRBPool<A, N>,
         level: usize,
         drop_side: Side,
         index: usize,
@@ -848,7 +1007,7 @@ impl<A: Clone> Node<A> {
                             *size -= dropped;
                         }
                         Size::Size(ref mut size) => {
-                            let size_per_child = NODE_SIZE.pow(level as u32);
+                            let size_per_child = N::USIZE.pow(level as u32);
                             let remainder = (target_idx + 1) * size_per_child;
                             let new_size = remainder - dropped;
                             if new_size < *size {
@@ -881,7 +1040,7 @@ impl<A: Clone> Node<A> {
         }
     }
 
-    fn merge_leaves(pool: &RRBPool<A>, mut left: Self, mut right: Self) -> Self {
+    fn merge_leaves(pool: &RRBPool<A, N>, mut left: Self, mut right: Self) -> Self {
         if left.children.is_empty_node() {
             // Left is empty, just use right
             Self::single_parent(pool, right)
@@ -894,10 +1053,10 @@ impl<A: Clone> Node<A> {
                 let left_len = left_vals.len();
                 let right_vals = right.children.unwrap_values_mut(pool);
                 let right_len = right_vals.len();
-                if left_len + right_len <= NODE_SIZE {
+                if left_len + right_len <= N::USIZE {
                     left_vals.append(right_vals);
                 } else {
-                    let count = right_len.min(NODE_SIZE - left_len);
+                    let count = right_len.min(N::USIZE - left_len);
                     left_vals.drain_from_front(right_vals, count);
                 }
             }
@@ -910,7 +1069,7 @@ impl<A: Clone> Node<A> {
     }
 
     fn merge_rebalance(
-        pool: &RRBPool<A>,
+        pool: &RRBPool<A, N>,
         level: usize,
         left: Self,
         middle: Self,
@@ -981,7 +1140,12 @@ impl<A: Clone> Node<A> {
         Node::parent(pool, level + 1, root)
     }
 
-    pub(crate) fn merge(pool: &RRBPool<A>, mut left: Self, mut right: Self, level: usize) -> Self {
+    pub(crate) fn merge(
+        pool: &RRBPool<A, N>,
+        mut left: Self,
+        mut right: Self,
+        level: usize,
+    ) -> Self {
         if level == 0 {
             Self::merge_leaves(pool, left, right)
         } else {
@@ -1018,47 +1182,99 @@ impl<A: Clone> Node<A> {
         }
     }
 
+    /// Check the invariants of the tree with this node as its root,
+    /// returning the number of values in it.
     #[cfg(any(test, feature = "debug"))]
-    pub(crate) fn assert_invariants(&self, level: usize) -> usize {
-        // Verifies that the size table matches reality.
+    pub(crate) fn check_invariants(
+        &self,
+        level: usize,
+        path: &mut Vec<usize>,
+    ) -> Result<usize, InvariantViolation> {
         match self.children {
-            Entry::Empty => 0,
+            Entry::Empty => Ok(0),
             Entry::Values(ref values) => {
                 // An empty value node is pointless and should never occur.
-                assert_ne!(0, values.len());
+                if values.is_empty() {
+                    return Err(violation(path, "leaf node is empty".to_string()));
+                }
                 // Value nodes should only occur at level 0.
-                assert_eq!(0, level);
-                values.len()
+                if level != 0 {
+                    return Err(violation(
+                        path,
+                        format!("leaf node found at level {}", level),
+                    ));
+                }
+                Ok(values.len())
             }
             Entry::Nodes(ref size, ref children) => {
                 // A parent node with no children should never occur.
-                assert_ne!(0, children.len());
+                if children.is_empty() {
+                    return Err(violation(path, "branch node is empty".to_string()));
+                }
                 // Parent nodes should never occur at level 0.
-                assert_ne!(0, level);
+                if level == 0 {
+                    return Err(violation(path, "branch node found at level 0".to_string()));
+                }
                 let mut lengths = Vec::new();
                 let should_be_dense = matches!(size, Size::Size(_));
                 for (index, child) in children.iter().enumerate() {
-                    let len = child.assert_invariants(level - 1);
-                    if should_be_dense && index < children.len() - 1 {
-                        // Assert that non-end nodes without size tables are full.
-                        assert_eq!(len, NODE_SIZE.pow(level as u32));
+                    path.push(index);
+                    let len = child.check_invariants(level - 1, path)?;
+                    path.pop();
+                    // Non-end nodes without size tables must be full.
+                    if should_be_dense
+                        && index < children.len() - 1
+                        && len != N::USIZE.pow(level as u32)
+                    {
+                        return Err(violation(
+                            path,
+                            format!(
+                                "child {} of a dense node has {} values, expected {}",
+                                index,
+                                len,
+                                N::USIZE.pow(level as u32)
+                            ),
+                        ));
                     }
                     lengths.push(len);
                 }
+                let total: usize = lengths.iter().sum();
                 match size {
                     Size::Size(size) => {
-                        let total: usize = lengths.iter().sum();
-                        assert_eq!(*size, total);
+                        if *size != total {
+                            return Err(violation(
+                                path,
+                                format!("node size is {}, but it has {} values", size, total),
+                            ));
+                        }
                     }
                     Size::Table(ref table) => {
-                        assert_eq!(table.iter().len(), children.len());
-                        for (index, current) in table.iter().enumerate() {
-                            let expected: usize = lengths.iter().take(index + 1).sum();
-                            assert_eq!(expected, *current);
+                        if table.len() != children.len() {
+                            return Err(violation(
+                                path,
+                                format!(
+                                    "size table has {} entries for {} children",
+                                    table.len(),
+                                    children.len()
+                                ),
+                            ));
+                        }
+                        let mut expected = 0;
+                        for (index, (current, len)) in table.iter().zip(&lengths).enumerate() {
+                            expected += len;
+                            if *current != expected {
+                                return Err(violation(
+                                    path,
+                                    format!(
+                                        "size table entry {} is {}, expected {}",
+                                        index, current, expected
+                                    ),
+                                ));
+                            }
                         }
                     }
                 }
-                lengths.iter().sum()
+                Ok(total)
             }
         }
     }
@@ -24,13 +24,18 @@ use std::fmt::{Debug, Error, Formatter};
 use std::hash::{BuildHasher, Hash, Hasher};
 use std::iter::{FromIterator, Iterator, Sum};
 use std::mem;
-use std::ops::{Add, Index, IndexMut, RangeBounds};
+use std::ops::{Add, Bound, Index, IndexMut, RangeBounds};
 
+use crate::config::{DefaultOrdChunkSize, OrdChunkSize, POOL_SIZE};
+#[cfg(any(test, feature = "debug"))]
+use crate::debug::{InvariantViolation, Stats, Walk, Walker};
 use crate::hashmap::HashMap;
+use crate::memory::{self, HeapNodes, HeapSize, MemoryReport};
 use crate::nodes::btree::{BTreeValue, Insert, Node, Remove};
+use crate::patch::Patch;
 #[cfg(has_specialisation)]
 use crate::util::linear_search_by;
-use crate::util::{Pool, PoolRef};
+use crate::util::{option_ptr_eq, transaction, Pool, PoolRef};
 
 pub use crate::nodes::btree::{
     ConsumingIter, DiffItem as NodeDiffItem, DiffIter as NodeDiffIter, Iter as RangedIter,
@@ -148,7 +153,7 @@ impl<K: Ord + Copy, V> BTreeValue for (K, V) {
     }
 }
 
-def_pool!(OrdMapPool<K, V>, Node<(K, V)>);
+def_pool!(OrdMapPool<K, V; N: OrdChunkSize<(K, V)> = DefaultOrdChunkSize>, Node<(K, V), N>);
 
 /// An ordered map.
 ///
@@ -163,22 +168,29 @@ def_pool!(OrdMapPool<K, V>, Node<(K, V)>);
 ///
 /// [hashmap::HashMap]: ../hashmap/struct.HashMap.html
 /// [std::cmp::Ord]: https://doc.rust-lang.org/std/cmp/trait.Ord.html
-pub struct OrdMap<K, V> {
+pub struct OrdMap<K, V, N: OrdChunkSize<(K, V)> = DefaultOrdChunkSize> {
     size: usize,
-    pool: OrdMapPool<K, V>,
-    root: PoolRef<Node<(K, V)>>,
+    pool: OrdMapPool<K, V, N>,
+    /// The root node, which isn't allocated until the first insert.
+    root: Option<PoolRef<Node<(K, V), N>>>,
 }
 
 impl<K, V> OrdMap<K, V> {
-    /// Construct an empty map.
-    #[must_use]
-    pub fn new() -> Self {
-        let pool = OrdMapPool::default();
-        let root = PoolRef::default(&pool.0);
-        OrdMap {
-            size: 0,
-            pool,
-            root,
+    def_const_fn! {
+        #[cfg(not(feature = "pool"))]
+        /// Construct an empty map.
+        ///
+        /// An empty map doesn't allocate any memory until something is
+        /// inserted into it. This is a `const fn` unless the `pool`
+        /// feature is on, because memory pools can't be created in a
+        /// constant.
+        #[must_use]
+        pub fn new() -> Self {
+            OrdMap {
+                size: 0,
+                pool: OrdMapPool(Pool::new(POOL_SIZE)),
+                root: None,
+            }
         }
     }
 
@@ -186,12 +198,7 @@ impl<K, V> OrdMap<K, V> {
     #[cfg(feature = "pool")]
     #[must_use]
     pub fn with_pool(pool: &OrdMapPool<K, V>) -> Self {
-        let root = PoolRef::default(&pool.0);
-        OrdMap {
-            size: 0,
-            pool: pool.clone(),
-            root,
-        }
+        Self::with_pool_and_node_size(pool)
     }
 
     /// Construct a map with a single mapping.
@@ -215,6 +222,87 @@ impl<K, V> OrdMap<K, V> {
         OrdMap {
             size: 1,
             pool,
+            root: Some(root),
+        }
+    }
+}
+
+impl<K, V, N: OrdChunkSize<(K, V)>> OrdMap<K, V, N> {
+    /// Construct an empty map with a node size other than the default.
+    ///
+    /// [`new`][new], [`Default`][Default] and [`FromIterator`][FromIterator]
+    /// always build maps of the default node size, so that the type of
+    /// a map can be inferred from its keys and values alone.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use im::ordmap::OrdMap;
+    /// use im::config::U16;
+    ///
+    /// let mut map = OrdMap::<i32, [u8; 256], U16>::with_node_size();
+    /// map.insert(1, [0; 256]);
+    /// assert_eq!(1, map.len());
+    /// ```
+    ///
+    /// [new]: #method.new
+    /// [Default]: https://doc.rust-lang.org/std/default/trait.Default.html
+    /// [FromIterator]: https://doc.rust-lang.org/std/iter/trait.FromIterator.html
+    #[must_use]
+    pub fn with_node_size() -> Self {
+        OrdMap {
+            size: 0,
+            pool: OrdMapPool::default(),
+            root: None,
+        }
+    }
+
+    /// Construct a map with a node size other than the default from an
+    /// iterator.
+    ///
+    /// Time: O(n log n)
+    #[must_use]
+    pub fn from_iter_with_node_size<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Ord + Clone,
+        V: Clone,
+    {
+        let mut map = Self::with_node_size();
+        for (key, value) in iter {
+            map.insert(key, value);
+        }
+        map
+    }
+
+    /// Construct an empty map with a node size other than the default,
+    /// using a specific memory pool.
+    #[cfg(feature = "pool")]
+    #[must_use]
+    pub fn with_pool_and_node_size(pool: &OrdMapPool<K, V, N>) -> Self {
+        OrdMap {
+            size: 0,
+            pool: pool.clone(),
+            root: None,
+        }
+    }
+
+    /// Get the root node, if the map has one.
+    pub(crate) fn root(&self) -> Option<&Node<(K, V), N>> {
+        self.root.as_deref()
+    }
+
+    /// Construct a map around a root node which might be shared with other
+    /// maps, and which has `size` values below it.
+    #[cfg(any(test, feature = "serde"))]
+    pub(crate) fn from_shared_root(
+        pool: &OrdMapPool<K, V, N>,
+        root: Option<PoolRef<Node<(K, V), N>>>,
+        size: usize,
+    ) -> Self {
+        OrdMap {
+            size,
+            pool: pool.clone(),
             root,
         }
     }
@@ -251,7 +339,7 @@ impl<K, V> OrdMap<K, V> {
     ///
     /// Time: O(1)
     pub fn ptr_eq(&self, other: &Self) -> bool {
-        std::ptr::eq(self, other) || PoolRef::ptr_eq(&self.root, &other.root)
+        std::ptr::eq(self, other) || option_ptr_eq(&self.root, &other.root)
     }
 
     /// Get the size of a map.
@@ -280,7 +368,7 @@ impl<K, V> OrdMap<K, V> {
     /// Note that if you didn't specifically construct it with a pool, you'll
     /// get back a reference to a pool of size 0.
     #[cfg(feature = "pool")]
-    pub fn pool(&self) -> &OrdMapPool<K, V> {
+    pub fn pool(&self) -> &OrdMapPool<K, V, N> {
         &self.pool
     }
 
@@ -302,16 +390,147 @@ impl<K, V> OrdMap<K, V> {
     /// ```
     pub fn clear(&mut self) {
         if !self.is_empty() {
-            self.root = PoolRef::default(&self.pool.0);
+            self.root = None;
             self.size = 0;
         }
     }
+
+    /// Get the number of bytes of heap memory used by the map's nodes.
+    ///
+    /// Nodes shared with other maps are counted in full. Heap memory
+    /// owned by the keys and values themselves isn't counted: use
+    /// [`heap_size_with`][heap_size_with] to include it.
+    ///
+    /// Time: O(n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::ordmap::OrdMap;
+    /// let map: OrdMap<i64, i64> = (0..1000).map(|i| (i, i)).collect();
+    /// assert!(map.heap_size() >= 1000 * std::mem::size_of::<(i64, i64)>());
+    /// ```
+    ///
+    /// [heap_size_with]: #method.heap_size_with
+    #[must_use]
+    pub fn heap_size(&self) -> usize {
+        self.heap_size_with(|_, _| 0)
+    }
+
+    /// Get the number of bytes of heap memory used by the map's nodes,
+    /// plus the number of bytes `value_size` reports for each key/value
+    /// pair.
+    ///
+    /// Time: O(n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::ordmap::OrdMap;
+    /// let map = ordmap!{1 => String::from("foo"), 2 => String::from("bar")};
+    /// assert_eq!(
+    ///     map.heap_size() + 6,
+    ///     map.heap_size_with(|_, value| value.capacity())
+    /// );
+    /// ```
+    pub fn heap_size_with<F>(&self, mut value_size: F) -> usize
+    where
+        F: FnMut(&K, &V) -> usize,
+    {
+        let mut visitor = HeapSize::new(|(key, value): &(K, V)| value_size(key, value));
+        if let Some(ref root) = self.root {
+            root.visit(&mut visitor);
+        }
+        visitor.bytes()
+    }
+
+    /// Get the number of bytes of heap memory used by nodes which this
+    /// map shares with `other`.
+    ///
+    /// Time: O(n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::ordmap::OrdMap;
+    /// let map: OrdMap<i64, i64> = (0..1000).map(|i| (i, i)).collect();
+    /// let mut other = map.clone();
+    /// assert_eq!(map.heap_size(), map.shared_with(&other));
+    /// other.insert(1000, 1000);
+    /// assert!(map.shared_with(&other) < map.heap_size());
+    /// ```
+    #[must_use]
+    pub fn shared_with(&self, other: &Self) -> usize {
+        let mut report = MemoryReport::new();
+        report.add(self);
+        report.add(other);
+        report.shared_bytes()
+    }
+
+    /// Get a summary of the shape of the map's tree.
+    ///
+    /// This method requires the `debug` feature flag.
+    ///
+    /// Time: O(n / 64)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::ordmap::OrdMap;
+    /// let map: OrdMap<i64, i64> = (0..1000).map(|i| (i, i)).collect();
+    /// let stats = map.stats();
+    /// assert_eq!(1000, stats.entries);
+    /// assert!(stats.depth >= 2);
+    /// ```
+    #[cfg(any(test, feature = "debug"))]
+    #[must_use]
+    pub fn stats(&self) -> Stats {
+        Stats::collect(self.len(), self)
+    }
 }
 
-impl<K, V> OrdMap<K, V>
+impl<K, V, N: OrdChunkSize<(K, V)>> OrdMap<K, V, N>
 where
     K: Ord,
 {
+    /// Verify the internal consistency of a map, returning a
+    /// description of the first problem found, if any.
+    ///
+    /// This method walks the B-tree making up the map and verifies
+    /// that all of its invariants hold.
+    ///
+    /// This method requires the `debug` feature flag.
+    ///
+    /// Time: O(n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::ordmap::OrdMap;
+    /// let map: OrdMap<i64, i64> = (0..1000).map(|i| (i, i)).collect();
+    /// assert_eq!(Ok(()), map.check_invariants());
+    /// ```
+    #[cfg(any(test, feature = "debug"))]
+    pub fn check_invariants(&self) -> Result<(), InvariantViolation> {
+        let len = match self.root {
+            Some(ref root) => root.check_invariants()?,
+            None => 0,
+        };
+        if self.size != len {
+            return Err(InvariantViolation::new(
+                "B-tree",
+                &[],
+                format!("map size is {}, but it has {} values", self.size, len),
+            ));
+        }
+        Ok(())
+    }
+
     /// Get the largest key in a map, along with its value. If the map
     /// is empty, return `None`.
     ///
@@ -330,7 +549,7 @@ where
     /// ```
     #[must_use]
     pub fn get_max(&self) -> Option<&(K, V)> {
-        self.root.max()
+        self.root()?.max()
     }
 
     /// Get the smallest key in a map, along with its value. If the
@@ -351,39 +570,39 @@ where
     /// ```
     #[must_use]
     pub fn get_min(&self) -> Option<&(K, V)> {
-        self.root.min()
+        self.root()?.min()
     }
 
     /// Get an iterator over the key/value pairs of a map.
     #[must_use]
-    pub fn iter(&self) -> Iter<'_, K, V> {
+    pub fn iter(&self) -> Iter<'_, K, V, N> {
         Iter {
-            it: RangedIter::new(&self.root, self.size, ..),
+            it: RangedIter::new(self.root(), self.size, ..),
         }
     }
 
     /// Create an iterator over a range of key/value pairs.
     #[must_use]
-    pub fn range<R, BK>(&self, range: R) -> Iter<'_, K, V>
+    pub fn range<R, BK>(&self, range: R) -> Iter<'_, K, V, N>
     where
         R: RangeBounds<BK>,
         K: Borrow<BK>,
         BK: Ord + ?Sized,
     {
         Iter {
-            it: RangedIter::new(&self.root, self.size, range),
+            it: RangedIter::new(self.root(), self.size, range),
         }
     }
 
     /// Get an iterator over a map's keys.
     #[must_use]
-    pub fn keys(&self) -> Keys<'_, K, V> {
+    pub fn keys(&self) -> Keys<'_, K, V, N> {
         Keys { it: self.iter() }
     }
 
     /// Get an iterator over a map's values.
     #[must_use]
-    pub fn values(&self) -> Values<'_, K, V> {
+    pub fn values(&self) -> Values<'_, K, V, N> {
         Values { it: self.iter() }
     }
 
@@ -399,9 +618,9 @@ where
     /// the two maps, minus the number of elements belonging to nodes
     /// shared between them)
     #[must_use]
-    pub fn diff<'a>(&'a self, other: &'a Self) -> DiffIter<'a, K, V> {
+    pub fn diff<'a>(&'a self, other: &'a Self) -> DiffIter<'a, K, V, N> {
         DiffIter {
-            it: NodeDiffIter::new(&self.root, &other.root),
+            it: NodeDiffIter::new(self.root(), other.root()),
         }
     }
 
@@ -426,7 +645,7 @@ where
         BK: Ord + ?Sized,
         K: Borrow<BK>,
     {
-        self.root.lookup(key).map(|(_, v)| v)
+        self.root()?.lookup(key).map(|(_, v)| v)
     }
 
     /// Get the key/value pair for a key from a map.
@@ -450,7 +669,7 @@ where
         BK: Ord + ?Sized,
         K: Borrow<BK>,
     {
-        self.root.lookup(key).map(|&(ref k, ref v)| (k, v))
+        self.root()?.lookup(key).map(|&(ref k, ref v)| (k, v))
     }
 
     /// Get the closest smaller entry in a map to a given key
@@ -475,7 +694,7 @@ where
         BK: Ord + ?Sized,
         K: Borrow<BK>,
     {
-        self.root.lookup_prev(key).map(|(k, v)| (k, v))
+        self.root()?.lookup_prev(key).map(|(k, v)| (k, v))
     }
 
     /// Get the closest larger entry in a map to a given key
@@ -500,7 +719,7 @@ where
         BK: Ord + ?Sized,
         K: Borrow<BK>,
     {
-        self.root.lookup_next(key).map(|(k, v)| (k, v))
+        self.root()?.lookup_next(key).map(|(k, v)| (k, v))
     }
 
     /// Test for the presence of a key in a map.
@@ -540,7 +759,8 @@ where
     pub fn is_submap_by<B, RM, F>(&self, other: RM, mut cmp: F) -> bool
     where
         F: FnMut(&V, &B) -> bool,
-        RM: Borrow<OrdMap<K, B>>,
+        RM: Borrow<OrdMap<K, B, N>>,
+        N: OrdChunkSize<(K, B)>,
     {
         self.iter()
             .all(|(k, v)| other.borrow().get(k).map(|ov| cmp(v, ov)).unwrap_or(false))
@@ -558,7 +778,8 @@ where
     pub fn is_proper_submap_by<B, RM, F>(&self, other: RM, cmp: F) -> bool
     where
         F: FnMut(&V, &B) -> bool,
-        RM: Borrow<OrdMap<K, B>>,
+        RM: Borrow<OrdMap<K, B, N>>,
+        N: OrdChunkSize<(K, B)>,
     {
         self.len() != other.borrow().len() && self.is_submap_by(other, cmp)
     }
@@ -617,7 +838,7 @@ where
     }
 }
 
-impl<K, V> OrdMap<K, V>
+impl<K, V, N: OrdChunkSize<(K, V)>> OrdMap<K, V, N>
 where
     K: Ord + Clone,
     V: Clone,
@@ -646,8 +867,9 @@ where
         BK: Ord + ?Sized,
         K: Borrow<BK>,
     {
-        let root = PoolRef::make_mut(&self.pool.0, &mut self.root);
-        root.lookup_mut(&self.pool.0, key).map(|(_, v)| v)
+        let pool = &self.pool.0;
+        let root = Node::make_mut(pool, self.root.as_mut()?);
+        root.lookup_mut(pool, key).map(|(_, v)| v)
     }
 
     /// Get the closest smaller entry in a map to a given key
@@ -676,7 +898,7 @@ where
         K: Borrow<BK>,
     {
         let pool = &self.pool.0;
-        PoolRef::make_mut(pool, &mut self.root)
+        Node::make_mut(pool, self.root.as_mut()?)
             .lookup_prev_mut(pool, key)
             .map(|(ref k, ref mut v)| (k, v))
     }
@@ -707,7 +929,7 @@ where
         K: Borrow<BK>,
     {
         let pool = &self.pool.0;
-        PoolRef::make_mut(pool, &mut self.root)
+        Node::make_mut(pool, self.root.as_mut()?)
             .lookup_next_mut(pool, key)
             .map(|(ref k, ref mut v)| (k, v))
     }
@@ -740,22 +962,22 @@ where
     /// [insert]: #method.insert
     #[inline]
     pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let pool = &self.pool.0;
         let new_root = {
-            let root = PoolRef::make_mut(&self.pool.0, &mut self.root);
-            match root.insert(&self.pool.0, (key, value)) {
+            let root = Node::make_mut_or_default(pool, &mut self.root);
+            match root.insert(pool, (key, value)) {
                 Insert::Replaced((_, old_value)) => return Some(old_value),
                 Insert::Added => {
                     self.size += 1;
                     return None;
                 }
-                Insert::Split(left, median, right) => PoolRef::new(
-                    &self.pool.0,
-                    Node::new_from_split(&self.pool.0, left, median, right),
-                ),
+                Insert::Split(left, median, right) => {
+                    PoolRef::new(pool, Node::new_from_split(pool, left, median, right))
+                }
             }
         };
         self.size += 1;
-        self.root = new_root;
+        self.root = Some(new_root);
         None
     }
 
@@ -793,20 +1015,21 @@ where
         BK: Ord + ?Sized,
         K: Borrow<BK>,
     {
-        let (new_root, removed_value) = {
-            let root = PoolRef::make_mut(&self.pool.0, &mut self.root);
-            match root.remove(&self.pool.0, k) {
-                Remove::NoChange => return None,
-                Remove::Removed(pair) => {
-                    self.size -= 1;
-                    return Some(pair);
-                }
-                Remove::Update(pair, root) => (PoolRef::new(&self.pool.0, root), Some(pair)),
+        let pool = &self.pool.0;
+        let root = Node::make_mut(pool, self.root.as_mut()?);
+        let removed = match root.remove(pool, k) {
+            Remove::NoChange => return None,
+            Remove::Removed(pair) => pair,
+            Remove::Update(pair, root) => {
+                self.root = Some(PoolRef::new(pool, root));
+                pair
             }
         };
         self.size -= 1;
-        self.root = new_root;
-        removed_value
+        if self.size == 0 {
+            self.root = None;
+        }
+        Some(removed)
     }
 
     /// Construct a new map by inserting a key/value mapping into a
@@ -1087,7 +1310,7 @@ where
     where
         I: IntoIterator<Item = Self>,
     {
-        i.into_iter().fold(Self::default(), Self::union)
+        i.into_iter().fold(Self::with_node_size(), Self::union)
     }
 
     /// Construct the union of a sequence of maps, using a function to
@@ -1107,7 +1330,7 @@ where
         F: Fn(V, V) -> V,
     {
         i.into_iter()
-            .fold(Self::default(), |a, b| a.union_with(b, &f))
+            .fold(Self::with_node_size(), |a, b| a.union_with(b, &f))
     }
 
     /// Construct the union of a sequence of maps, using a function to
@@ -1128,7 +1351,7 @@ where
         F: Fn(&K, V, V) -> V,
     {
         i.into_iter()
-            .fold(Self::default(), |a, b| a.union_with_key(b, &f))
+            .fold(Self::with_node_size(), |a, b| a.union_with_key(b, &f))
     }
 
     /// Construct the symmetric difference between two maps by discarding keys
@@ -1265,7 +1488,7 @@ where
     where
         F: FnMut(&K, V, V) -> Option<V>,
     {
-        let mut out = Self::default();
+        let mut out = Self::with_node_size();
         for (key, right_value) in other {
             match self.remove(&key) {
                 None => {
@@ -1333,11 +1556,12 @@ where
     /// Time: O(n log n)
     #[inline]
     #[must_use]
-    pub fn intersection_with<B, C, F>(self, other: OrdMap<K, B>, mut f: F) -> OrdMap<K, C>
+    pub fn intersection_with<B, C, F>(self, other: OrdMap<K, B, N>, mut f: F) -> OrdMap<K, C, N>
     where
         B: Clone,
         C: Clone,
         F: FnMut(V, B) -> C,
+        N: OrdChunkSize<(K, B)> + OrdChunkSize<(K, C)>,
     {
         self.intersection_with_key(other, |_, v1, v2| f(v1, v2))
     }
@@ -1362,13 +1586,18 @@ where
     /// ));
     /// ```
     #[must_use]
-    pub fn intersection_with_key<B, C, F>(mut self, other: OrdMap<K, B>, mut f: F) -> OrdMap<K, C>
+    pub fn intersection_with_key<B, C, F>(
+        mut self,
+        other: OrdMap<K, B, N>,
+        mut f: F,
+    ) -> OrdMap<K, C, N>
     where
         B: Clone,
         C: Clone,
         F: FnMut(&K, V, B) -> C,
+        N: OrdChunkSize<(K, B)> + OrdChunkSize<(K, C)>,
     {
-        let mut out = OrdMap::<K, C>::default();
+        let mut out = OrdMap::<K, C, N>::with_node_size();
         for (key, right_value) in other {
             match self.remove(&key) {
                 None => (),
@@ -1381,11 +1610,40 @@ where
         out
     }
 
+    fn from_root(pool: OrdMapPool<K, V, N>, root: Node<(K, V), N>, size: usize) -> Self {
+        let mut map = OrdMap {
+            size: 0,
+            pool,
+            root: None,
+        };
+        map.set_root(root, size);
+        map
+    }
+
+    fn set_root(&mut self, root: Node<(K, V), N>, size: usize) {
+        self.size = size;
+        self.root = if size == 0 {
+            None
+        } else {
+            Some(PoolRef::new(&self.pool.0, root))
+        };
+    }
+
+    fn take_root(&mut self) -> Node<(K, V), N> {
+        self.size = 0;
+        self.root.take().map(Node::unwrap).unwrap_or_default()
+    }
+
     /// Split a map into two, with the left hand map containing keys
     /// which are smaller than `split`, and the right hand map
     /// containing keys which are larger than `split`.
     ///
     /// The `split` mapping is discarded.
+    ///
+    /// Only the nodes along the path to `split` are rebuilt, the
+    /// rest of the tree is shared with the original map.
+    ///
+    /// Time: O(log n)
     #[must_use]
     pub fn split<BK>(&self, split: &BK) -> (Self, Self)
     where
@@ -1401,41 +1659,322 @@ where
     /// containing keys which are larger than `split`.
     ///
     /// Returns both the two maps and the value of `split`.
+    ///
+    /// Time: O(log n)
     #[must_use]
     pub fn split_lookup<BK>(&self, split: &BK) -> (Self, Option<V>, Self)
     where
         BK: Ord + ?Sized,
         K: Borrow<BK>,
     {
-        // TODO this is atrociously slow, got to be a better way
-        self.iter()
-            .fold((ordmap![], None, ordmap![]), |(l, m, r), (k, v)| {
-                match k.borrow().cmp(split) {
-                    Ordering::Less => (l.update(k.clone(), v.clone()), m, r),
-                    Ordering::Equal => (l, Some(v.clone()), r),
-                    Ordering::Greater => (l, m, r.update(k.clone(), v.clone())),
+        let size = self.size;
+        let root = self.root().cloned().unwrap_or_default();
+        let (left, found, right) = Node::split_by_key(&self.pool.0, root, split);
+        let left_size = left.len();
+        let right_size = size - left_size - if found.is_some() { 1 } else { 0 };
+        (
+            OrdMap::from_root(self.pool.clone(), left, left_size),
+            found.map(|(_, value)| value),
+            OrdMap::from_root(self.pool.clone(), right, right_size),
+        )
+    }
+
+    /// Split a map in place, leaving the keys which are smaller than
+    /// `split` in the current map and returning a new map containing
+    /// `split` and every key larger than it.
+    ///
+    /// Only the nodes along the path to `split` are rebuilt, the
+    /// rest of the tree is shared between the two maps.
+    ///
+    /// Time: O(log n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::ordmap::OrdMap;
+    /// let mut map = ordmap!{1 => 11, 2 => 22, 3 => 33, 4 => 44};
+    /// let right = map.split_off(&3);
+    /// assert_eq!(ordmap!{1 => 11, 2 => 22}, map);
+    /// assert_eq!(ordmap!{3 => 33, 4 => 44}, right);
+    /// ```
+    pub fn split_off<BK>(&mut self, split: &BK) -> Self
+    where
+        BK: Ord + ?Sized,
+        K: Borrow<BK>,
+    {
+        let size = self.size;
+        let root = self.take_root();
+        let pool = &self.pool.0;
+        let (left, found, mut right) = Node::split_by_key(pool, root, split);
+        if let Some(pair) = found {
+            right = Node::join(pool, Node::default(), pair, right);
+        }
+        let right_size = right.len();
+        self.set_root(left, size - right_size);
+        OrdMap::from_root(self.pool.clone(), right, right_size)
+    }
+
+    /// Move every mapping from `other` into the current map.
+    ///
+    /// If every key in `other` is larger than every key in the
+    /// current map, the two trees are joined structurally, sharing
+    /// all but the nodes along the seam between them. Otherwise,
+    /// each mapping is inserted in turn, and values from `other`
+    /// overwrite existing values for the same key.
+    ///
+    /// Time: O(log n) if the keys of `other` are all larger, O(m log
+    /// n) otherwise
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::ordmap::OrdMap;
+    /// let mut map = ordmap!{1 => 11, 2 => 22};
+    /// map.append(ordmap!{3 => 33, 4 => 44});
+    /// assert_eq!(ordmap!{1 => 11, 2 => 22, 3 => 33, 4 => 44}, map);
+    /// ```
+    pub fn append(&mut self, other: Self) {
+        if other.is_empty() {
+            return;
+        }
+        if self.is_empty() {
+            *self = other;
+            return;
+        }
+        let disjoint = match (self.get_max(), other.get_min()) {
+            (Some((max, _)), Some((min, _))) => max < min,
+            _ => false,
+        };
+        if !disjoint {
+            self.extend(other);
+            return;
+        }
+        let size = self.size + other.size;
+        let left = self.take_root();
+        let right = other.root.map(Node::unwrap).unwrap_or_default();
+        let root = Node::concat(&self.pool.0, left, right);
+        self.set_root(root, size);
+    }
+
+    /// Apply a patch to the map, setting each key it changes to the
+    /// value it has after the change, or removing it.
+    ///
+    /// The map is split at the middle change, and each half of the
+    /// patch is applied to each half of the map before they're joined
+    /// back together, so the map is walked once, and every subtree
+    /// between the changed keys is shared with the original map. A patch
+    /// which isn't sorted by key, such as one made from a
+    /// [`HashMap`][HashMap] diff, is sorted first, and if it changes a
+    /// key more than once, the last change wins.
+    ///
+    /// Time: O(k log n) for a patch of k changes
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::ordmap::OrdMap;
+    /// # use im::patch::Patch;
+    /// let old = ordmap!{1 => 11, 2 => 22};
+    /// let new = ordmap!{2 => 20, 3 => 33};
+    /// let patch: Patch<_, _> = old.diff(&new).collect();
+    /// let mut map = old.clone();
+    /// map.apply_patch(&patch);
+    /// assert_eq!(new, map);
+    /// ```
+    ///
+    /// [HashMap]: ../hashmap/struct.HashMap.html
+    pub fn apply_patch(&mut self, patch: &Patch<K, V>) {
+        let mut changes: Vec<_> = patch.changes().iter().collect();
+        if !changes.windows(2).all(|pair| pair[0].0 < pair[1].0) {
+            // A stable sort keeps the last change to each key first once
+            // the list is reversed, which is the one `dedup_by` keeps.
+            changes.reverse();
+            changes.sort_by(|left, right| left.0.cmp(&right.0));
+            changes.dedup_by(|later, earlier| later.0 == earlier.0);
+        }
+        let mut size = self.size;
+        let root = self.take_root();
+        let root = Node::apply_sorted(
+            &self.pool.0,
+            root,
+            changes,
+            &|change| &change.0,
+            &mut |found, (key, change)| {
+                let value = change.new_value().map(|value| (key.clone(), value.clone()));
+                match (&found, &value) {
+                    (Some(_), None) => size -= 1,
+                    (None, Some(_)) => size += 1,
+                    _ => {}
                 }
-            })
+                value
+            },
+        );
+        self.set_root(root, size);
+    }
+
+    /// Edit the map in a [transaction][transactions]: `f` edits a
+    /// copy of the map, which replaces it only if `f` returns `Ok`.
+    ///
+    /// Time: O(1), plus the edits
+    ///
+    /// [transactions]: crate#transactions
+    pub fn transaction<F, R, E>(&mut self, f: F) -> Result<R, E>
+    where
+        F: FnOnce(&mut Self) -> Result<R, E>,
+    {
+        transaction(self, f)
+    }
+
+    /// Merge the changes two maps have each made to a common ancestor.
+    ///
+    /// The result is `ours` with every change `theirs` has made to
+    /// `base` applied to it. When both maps have changed a key, and
+    /// haven't changed it to the same thing, the `resolve` function is
+    /// called with the key and its values in `base`, `ours` and
+    /// `theirs`, where `None` means the key isn't in that map, and
+    /// returns the merged value, or `None` to leave the key out.
+    ///
+    /// Both maps are compared to `base` with [`diff`][diff], which
+    /// skips every node they still share with it, so the cost depends
+    /// on how much each side has changed rather than on the size of
+    /// the maps, and the result shares every subtree of `ours` which
+    /// `theirs` hasn't changed.
+    ///
+    /// Time: O(d log n) where d is the number of keys changed on either
+    /// side
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::ordmap::OrdMap;
+    /// let base = ordmap!{1 => 1, 2 => 2, 3 => 3};
+    /// let ours = base.update(1, 10).update(3, 30);
+    /// let theirs = base.without(&2).update(3, 300);
+    /// let merged = OrdMap::merge3(&base, &ours, &theirs, |_key, _base, ours, theirs| {
+    ///     Some(ours.unwrap_or(&0) + theirs.unwrap_or(&0))
+    /// });
+    /// assert_eq!(ordmap!{1 => 10, 3 => 330}, merged);
+    /// ```
+    ///
+    /// [diff]: #method.diff
+    #[must_use]
+    pub fn merge3<F>(base: &Self, ours: &Self, theirs: &Self, mut resolve: F) -> Self
+    where
+        V: PartialEq,
+        F: FnMut(&K, Option<&V>, Option<&V>, Option<&V>) -> Option<V>,
+    {
+        let mut changes = Vec::new();
+        let mut ours_changes = base.diff(ours).map(|item| item.parts()).peekable();
+        for (key, old, new) in base.diff(theirs).map(|item| item.parts()) {
+            while ours_changes.peek().map_or(false, |change| change.0 < key) {
+                ours_changes.next();
+            }
+            match ours_changes.peek() {
+                Some(&(ours_key, _, ours_new)) if ours_key == key => {
+                    if ours_new != new {
+                        changes.push((key.clone(), resolve(key, old, ours_new, new)));
+                    }
+                }
+                _ => changes.push((key.clone(), new.cloned())),
+            }
+        }
+        let mut merged = ours.clone();
+        let mut size = merged.size;
+        let root = merged.take_root();
+        let root = Node::apply_sorted(
+            &merged.pool.0,
+            root,
+            changes,
+            &|change| &change.0,
+            &mut |found, (key, value)| {
+                match (&found, &value) {
+                    (Some(_), None) => size -= 1,
+                    (None, Some(_)) => size += 1,
+                    _ => {}
+                }
+                value.map(|value| (key, value))
+            },
+        );
+        merged.set_root(root, size);
+        merged
+    }
+
+    /// Remove every mapping with a key inside `range` from the map,
+    /// and return them as a new map.
+    ///
+    /// The map is split at each end of the range and the remaining
+    /// two parts are joined back together, so only the nodes along
+    /// the edges of the range are rebuilt.
+    ///
+    /// Time: O(log n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::ordmap::OrdMap;
+    /// let mut map = ordmap!{1 => 11, 2 => 22, 3 => 33, 4 => 44, 5 => 55};
+    /// let removed = map.remove_range(2..4);
+    /// assert_eq!(ordmap!{1 => 11, 4 => 44, 5 => 55}, map);
+    /// assert_eq!(ordmap!{2 => 22, 3 => 33}, removed);
+    /// ```
+    pub fn remove_range<R, BK>(&mut self, range: R) -> Self
+    where
+        R: RangeBounds<BK>,
+        K: Borrow<BK>,
+        BK: Ord + ?Sized,
+    {
+        let size = self.size;
+        let root = self.take_root();
+        let pool = &self.pool.0;
+        let (left, rest) = match range.start_bound() {
+            Bound::Unbounded => (Node::default(), root),
+            Bound::Included(key) => match Node::split_by_key(pool, root, key) {
+                (left, Some(pair), right) => (left, Node::join(pool, Node::default(), pair, right)),
+                (left, None, right) => (left, right),
+            },
+            Bound::Excluded(key) => match Node::split_by_key(pool, root, key) {
+                (left, Some(pair), right) => (Node::join(pool, left, pair, Node::default()), right),
+                (left, None, right) => (left, right),
+            },
+        };
+        let (removed, right) = match range.end_bound() {
+            Bound::Unbounded => (rest, Node::default()),
+            Bound::Included(key) => match Node::split_by_key(pool, rest, key) {
+                (removed, Some(pair), right) => {
+                    (Node::join(pool, removed, pair, Node::default()), right)
+                }
+                (removed, None, right) => (removed, right),
+            },
+            Bound::Excluded(key) => match Node::split_by_key(pool, rest, key) {
+                (removed, Some(pair), right) => {
+                    (removed, Node::join(pool, Node::default(), pair, right))
+                }
+                (removed, None, right) => (removed, right),
+            },
+        };
+        let removed_size = removed.len();
+        let root = Node::concat(pool, left, right);
+        self.set_root(root, size - removed_size);
+        OrdMap::from_root(self.pool.clone(), removed, removed_size)
     }
 
     /// Construct a map with only the `n` smallest keys from a given
     /// map.
     #[must_use]
     pub fn take(&self, n: usize) -> Self {
-        self.iter()
-            .take(n)
-            .map(|(k, v)| (k.clone(), v.clone()))
-            .collect()
+        Self::from_iter_with_node_size(self.iter().take(n).map(|(k, v)| (k.clone(), v.clone())))
     }
 
     /// Construct a map with the `n` smallest keys removed from a
     /// given map.
     #[must_use]
     pub fn skip(&self, n: usize) -> Self {
-        self.iter()
-            .skip(n)
-            .map(|(k, v)| (k.clone(), v.clone()))
-            .collect()
+        Self::from_iter_with_node_size(self.iter().skip(n).map(|(k, v)| (k.clone(), v.clone())))
     }
 
     /// Remove the smallest key from a map, and return its value as
@@ -1486,7 +2025,7 @@ where
     ///
     /// [Entry]: enum.Entry.html
     #[must_use]
-    pub fn entry(&mut self, key: K) -> Entry<'_, K, V> {
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, N> {
         if self.contains_key(&key) {
             Entry::Occupied(OccupiedEntry { map: self, key })
         } else {
@@ -1498,18 +2037,18 @@ where
 // Entries
 
 /// A handle for a key and its associated value.
-pub enum Entry<'a, K, V>
+pub enum Entry<'a, K, V, N: OrdChunkSize<(K, V)> = DefaultOrdChunkSize>
 where
     K: Ord + Clone,
     V: Clone,
 {
     /// An entry which exists in the map.
-    Occupied(OccupiedEntry<'a, K, V>),
+    Occupied(OccupiedEntry<'a, K, V, N>),
     /// An entry which doesn't exist in the map.
-    Vacant(VacantEntry<'a, K, V>),
+    Vacant(VacantEntry<'a, K, V, N>),
 }
 
-impl<'a, K, V> Entry<'a, K, V>
+impl<'a, K, V, N: OrdChunkSize<(K, V)>> Entry<'a, K, V, N>
 where
     K: Ord + Clone,
     V: Clone,
@@ -1566,16 +2105,16 @@ where
 }
 
 /// An entry for a mapping that already exists in the map.
-pub struct OccupiedEntry<'a, K, V>
+pub struct OccupiedEntry<'a, K, V, N: OrdChunkSize<(K, V)> = DefaultOrdChunkSize>
 where
     K: Ord + Clone,
     V: Clone,
 {
-    map: &'a mut OrdMap<K, V>,
+    map: &'a mut OrdMap<K, V, N>,
     key: K,
 }
 
-impl<'a, K, V> OccupiedEntry<'a, K, V>
+impl<'a, K, V, N: OrdChunkSize<(K, V)>> OccupiedEntry<'a, K, V, N>
 where
     K: 'a + Ord + Clone,
     V: 'a + Clone,
@@ -1623,16 +2162,16 @@ where
 }
 
 /// An entry for a mapping that does not already exist in the map.
-pub struct VacantEntry<'a, K, V>
+pub struct VacantEntry<'a, K, V, N: OrdChunkSize<(K, V)> = DefaultOrdChunkSize>
 where
     K: Ord + Clone,
     V: Clone,
 {
-    map: &'a mut OrdMap<K, V>,
+    map: &'a mut OrdMap<K, V, N>,
     key: K,
 }
 
-impl<'a, K, V> VacantEntry<'a, K, V>
+impl<'a, K, V, N: OrdChunkSize<(K, V)>> VacantEntry<'a, K, V, N>
 where
     K: 'a + Ord + Clone,
     V: 'a + Clone,
@@ -1659,7 +2198,29 @@ where
 
 // Core traits
 
-impl<K, V> Clone for OrdMap<K, V> {
+#[cfg(any(test, feature = "debug"))]
+impl<K, V, N: OrdChunkSize<(K, V)>> Walk for OrdMap<K, V, N> {
+    fn walk(&self, walker: &mut dyn Walker) {
+        if let Some(ref root) = self.root {
+            root.walk(walker, 0, 1)
+        }
+    }
+}
+
+impl<K, V, N: OrdChunkSize<(K, V)>> HeapNodes for OrdMap<K, V, N> {
+    fn visit_heap_nodes(&self, node: &mut dyn FnMut(usize, usize) -> bool) {
+        memory::visit_nodes(
+            |visitor| {
+                if let Some(ref root) = self.root {
+                    root.visit(visitor)
+                }
+            },
+            node,
+        )
+    }
+}
+
+impl<K, V, N: OrdChunkSize<(K, V)>> Clone for OrdMap<K, V, N> {
     /// Clone a map.
     ///
     /// Time: O(1)
@@ -1674,42 +2235,44 @@ impl<K, V> Clone for OrdMap<K, V> {
 }
 
 #[cfg(not(has_specialisation))]
-impl<K, V> PartialEq for OrdMap<K, V>
+impl<K, V, N: OrdChunkSize<(K, V)>> PartialEq for OrdMap<K, V, N>
 where
     K: Ord + PartialEq,
     V: PartialEq,
 {
     fn eq(&self, other: &Self) -> bool {
-        self.len() == other.len() && self.diff(other).next().is_none()
+        self.len() == other.len() && self.iter().eq(other.iter())
     }
 }
 
 #[cfg(has_specialisation)]
-impl<K, V> PartialEq for OrdMap<K, V>
+impl<K, V, N: OrdChunkSize<(K, V)>> PartialEq for OrdMap<K, V, N>
 where
     K: Ord + PartialEq,
     V: PartialEq,
 {
     default fn eq(&self, other: &Self) -> bool {
-        self.len() == other.len() && self.diff(other).next().is_none()
+        self.len() == other.len() && self.iter().eq(other.iter())
     }
 }
 
+/// Subtrees which the two maps share aren't compared, as every value in
+/// them is equal to itself.
 #[cfg(has_specialisation)]
-impl<K, V> PartialEq for OrdMap<K, V>
+impl<K, V, N: OrdChunkSize<(K, V)>> PartialEq for OrdMap<K, V, N>
 where
     K: Ord + Eq,
     V: Eq,
 {
     fn eq(&self, other: &Self) -> bool {
-        PoolRef::ptr_eq(&self.root, &other.root)
+        option_ptr_eq(&self.root, &other.root)
             || (self.len() == other.len() && self.diff(other).next().is_none())
     }
 }
 
-impl<K: Ord + Eq, V: Eq> Eq for OrdMap<K, V> {}
+impl<K: Ord + Eq, V: Eq, N: OrdChunkSize<(K, V)>> Eq for OrdMap<K, V, N> {}
 
-impl<K, V> PartialOrd for OrdMap<K, V>
+impl<K, V, N: OrdChunkSize<(K, V)>> PartialOrd for OrdMap<K, V, N>
 where
     K: Ord,
     V: PartialOrd,
@@ -1719,17 +2282,23 @@ where
     }
 }
 
-impl<K, V> Ord for OrdMap<K, V>
+/// Subtrees which the two maps share aren't compared, as every value in
+/// them is equal to itself.
+impl<K, V, N: OrdChunkSize<(K, V)>> Ord for OrdMap<K, V, N>
 where
     K: Ord,
     V: Ord,
 {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.iter().cmp(other.iter())
+        if option_ptr_eq(&self.root, &other.root) {
+            return Ordering::Equal;
+        }
+        NodeDiffIter::compare(self.root(), other.root(), |old, new| Some(old.cmp(new)))
+            .unwrap_or(Ordering::Equal)
     }
 }
 
-impl<K, V> Hash for OrdMap<K, V>
+impl<K, V, N: OrdChunkSize<(K, V)>> Hash for OrdMap<K, V, N>
 where
     K: Ord + Hash,
     V: Hash,
@@ -1750,31 +2319,31 @@ impl<K, V> Default for OrdMap<K, V> {
     }
 }
 
-impl<'a, K, V> Add for &'a OrdMap<K, V>
+impl<'a, K, V, N: OrdChunkSize<(K, V)>> Add for &'a OrdMap<K, V, N>
 where
     K: Ord + Clone,
     V: Clone,
 {
-    type Output = OrdMap<K, V>;
+    type Output = OrdMap<K, V, N>;
 
     fn add(self, other: Self) -> Self::Output {
         self.clone().union(other.clone())
     }
 }
 
-impl<K, V> Add for OrdMap<K, V>
+impl<K, V, N: OrdChunkSize<(K, V)>> Add for OrdMap<K, V, N>
 where
     K: Ord + Clone,
     V: Clone,
 {
-    type Output = OrdMap<K, V>;
+    type Output = OrdMap<K, V, N>;
 
     fn add(self, other: Self) -> Self::Output {
         self.union(other)
     }
 }
 
-impl<K, V> Sum for OrdMap<K, V>
+impl<K, V, N: OrdChunkSize<(K, V)>> Sum for OrdMap<K, V, N>
 where
     K: Ord + Clone,
     V: Clone,
@@ -1783,11 +2352,11 @@ where
     where
         I: Iterator<Item = Self>,
     {
-        it.fold(Self::default(), |a, b| a + b)
+        it.fold(Self::with_node_size(), |a, b| a + b)
     }
 }
 
-impl<K, V, RK, RV> Extend<(RK, RV)> for OrdMap<K, V>
+impl<K, V, RK, RV, N: OrdChunkSize<(K, V)>> Extend<(RK, RV)> for OrdMap<K, V, N>
 where
     K: Ord + Clone + From<RK>,
     V: Clone + From<RV>,
@@ -1802,7 +2371,7 @@ where
     }
 }
 
-impl<'a, BK, K, V> Index<&'a BK> for OrdMap<K, V>
+impl<'a, BK, K, V, N: OrdChunkSize<(K, V)>> Index<&'a BK> for OrdMap<K, V, N>
 where
     BK: Ord + ?Sized,
     K: Ord + Borrow<BK>,
@@ -1810,29 +2379,28 @@ where
     type Output = V;
 
     fn index(&self, key: &BK) -> &Self::Output {
-        match self.root.lookup(key) {
+        match self.get_key_value(key) {
             None => panic!("OrdMap::index: invalid key"),
-            Some(&(_, ref value)) => value,
+            Some((_, value)) => value,
         }
     }
 }
 
-impl<'a, BK, K, V> IndexMut<&'a BK> for OrdMap<K, V>
+impl<'a, BK, K, V, N: OrdChunkSize<(K, V)>> IndexMut<&'a BK> for OrdMap<K, V, N>
 where
     BK: Ord + ?Sized,
     K: Ord + Clone + Borrow<BK>,
     V: Clone,
 {
     fn index_mut(&mut self, key: &BK) -> &mut Self::Output {
-        let root = PoolRef::make_mut(&self.pool.0, &mut self.root);
-        match root.lookup_mut(&self.pool.0, key) {
+        match self.get_mut(key) {
             None => panic!("OrdMap::index: invalid key"),
-            Some(&mut (_, ref mut value)) => value,
+            Some(value) => value,
         }
     }
 }
 
-impl<K, V> Debug for OrdMap<K, V>
+impl<K, V, N: OrdChunkSize<(K, V)>> Debug for OrdMap<K, V, N>
 where
     K: Ord + Debug,
     V: Debug,
@@ -1849,11 +2417,11 @@ where
 // Iterators
 
 /// An iterator over the key/value pairs of a map.
-pub struct Iter<'a, K, V> {
-    it: RangedIter<'a, (K, V)>,
+pub struct Iter<'a, K, V, N: OrdChunkSize<(K, V)> = DefaultOrdChunkSize> {
+    it: RangedIter<'a, (K, V), N>,
 }
 
-impl<'a, K, V> Iterator for Iter<'a, K, V>
+impl<'a, K, V, N: OrdChunkSize<(K, V)>> Iterator for Iter<'a, K, V, N>
 where
     (K, V): 'a + BTreeValue,
 {
@@ -1868,7 +2436,7 @@ where
     }
 }
 
-impl<'a, K, V> DoubleEndedIterator for Iter<'a, K, V>
+impl<'a, K, V, N: OrdChunkSize<(K, V)>> DoubleEndedIterator for Iter<'a, K, V, N>
 where
     (K, V): 'a + BTreeValue,
 {
@@ -1877,14 +2445,23 @@ where
     }
 }
 
-impl<'a, K, V> ExactSizeIterator for Iter<'a, K, V> where (K, V): 'a + BTreeValue {}
+impl<'a, K, V, N: OrdChunkSize<(K, V)>> ExactSizeIterator for Iter<'a, K, V, N> where
+    (K, V): 'a + BTreeValue
+{
+}
 
 /// An iterator over the differences between two maps.
-pub struct DiffIter<'a, K, V> {
-    it: NodeDiffIter<'a, (K, V)>,
+pub struct DiffIter<'a, K, V, N: OrdChunkSize<(K, V)> = DefaultOrdChunkSize> {
+    it: NodeDiffIter<'a, (K, V), N>,
 }
 
-/// A description of a difference between two ordered maps.
+/// A description of a difference between two maps.
+///
+/// This is the item type of both [`OrdMap::diff`][OrdMap::diff] and
+/// [`HashMap::diff`][HashMap::diff].
+///
+/// [OrdMap::diff]: ./struct.OrdMap.html#method.diff
+/// [HashMap::diff]: ../hashmap/struct.HashMap.html#method.diff
 #[derive(PartialEq, Eq, Debug)]
 pub enum DiffItem<'a, K, V> {
     /// This value has been added to the new map.
@@ -1900,7 +2477,18 @@ pub enum DiffItem<'a, K, V> {
     Remove(&'a K, &'a V),
 }
 
-impl<'a, K, V> Iterator for DiffIter<'a, K, V>
+impl<'a, K, V> DiffItem<'a, K, V> {
+    /// Get the key, the old value and the new value.
+    pub(crate) fn parts(&self) -> (&'a K, Option<&'a V>, Option<&'a V>) {
+        match *self {
+            DiffItem::Add(key, value) => (key, None, Some(value)),
+            DiffItem::Update { old, new } => (new.0, Some(old.1), Some(new.1)),
+            DiffItem::Remove(key, value) => (key, Some(value), None),
+        }
+    }
+}
+
+impl<'a, K, V, N: OrdChunkSize<(K, V)>> Iterator for DiffIter<'a, K, V, N>
 where
     (K, V): 'a + BTreeValue + PartialEq,
 {
@@ -1922,11 +2510,11 @@ where
 }
 
 /// An iterator ove the keys of a map.
-pub struct Keys<'a, K, V> {
-    it: Iter<'a, K, V>,
+pub struct Keys<'a, K, V, N: OrdChunkSize<(K, V)> = DefaultOrdChunkSize> {
+    it: Iter<'a, K, V, N>,
 }
 
-impl<'a, K, V> Iterator for Keys<'a, K, V>
+impl<'a, K, V, N: OrdChunkSize<(K, V)>> Iterator for Keys<'a, K, V, N>
 where
     K: 'a + Ord,
     V: 'a,
@@ -1942,7 +2530,7 @@ where
     }
 }
 
-impl<'a, K, V> DoubleEndedIterator for Keys<'a, K, V>
+impl<'a, K, V, N: OrdChunkSize<(K, V)>> DoubleEndedIterator for Keys<'a, K, V, N>
 where
     K: 'a + Ord,
     V: 'a,
@@ -1952,7 +2540,7 @@ where
     }
 }
 
-impl<'a, K, V> ExactSizeIterator for Keys<'a, K, V>
+impl<'a, K, V, N: OrdChunkSize<(K, V)>> ExactSizeIterator for Keys<'a, K, V, N>
 where
     K: 'a + Ord,
     V: 'a,
@@ -1960,11 +2548,11 @@ where
 }
 
 /// An iterator over the values of a map.
-pub struct Values<'a, K, V> {
-    it: Iter<'a, K, V>,
+pub struct Values<'a, K, V, N: OrdChunkSize<(K, V)> = DefaultOrdChunkSize> {
+    it: Iter<'a, K, V, N>,
 }
 
-impl<'a, K, V> Iterator for Values<'a, K, V>
+impl<'a, K, V, N: OrdChunkSize<(K, V)>> Iterator for Values<'a, K, V, N>
 where
     K: 'a + Ord,
     V: 'a,
@@ -1980,7 +2568,7 @@ where
     }
 }
 
-impl<'a, K, V> DoubleEndedIterator for Values<'a, K, V>
+impl<'a, K, V, N: OrdChunkSize<(K, V)>> DoubleEndedIterator for Values<'a, K, V, N>
 where
     K: 'a + Ord,
     V: 'a,
@@ -1990,7 +2578,7 @@ where
     }
 }
 
-impl<'a, K, V> ExactSizeIterator for Values<'a, K, V>
+impl<'a, K, V, N: OrdChunkSize<(K, V)>> ExactSizeIterator for Values<'a, K, V, N>
 where
     K: 'a + Ord,
     V: 'a,
@@ -2006,58 +2594,53 @@ where
     where
         T: IntoIterator<Item = (RK, RV)>,
     {
-        let mut m = OrdMap::default();
-        for (k, v) in i {
-            m.insert(From::from(k), From::from(v));
-        }
-        m
+        Self::from_iter_with_node_size(i.into_iter().map(|(k, v)| (From::from(k), From::from(v))))
     }
 }
 
-impl<'a, K, V> IntoIterator for &'a OrdMap<K, V>
+impl<'a, K, V, N: OrdChunkSize<(K, V)>> IntoIterator for &'a OrdMap<K, V, N>
 where
     K: Ord,
 {
     type Item = (&'a K, &'a V);
-    type IntoIter = Iter<'a, K, V>;
+    type IntoIter = Iter<'a, K, V, N>;
 
     fn into_iter(self) -> Self::IntoIter {
         self.iter()
     }
 }
 
-impl<K, V> IntoIterator for OrdMap<K, V>
+impl<K, V, N: OrdChunkSize<(K, V)>> IntoIterator for OrdMap<K, V, N>
 where
     K: Ord + Clone,
     V: Clone,
 {
     type Item = (K, V);
-    type IntoIter = ConsumingIter<(K, V)>;
+    type IntoIter = ConsumingIter<(K, V), N>;
 
     fn into_iter(self) -> Self::IntoIter {
-        ConsumingIter::new(&self.root, self.size)
+        ConsumingIter::new(self.root(), self.size)
     }
 }
 
 // Conversions
 
-impl<K, V> AsRef<OrdMap<K, V>> for OrdMap<K, V> {
+impl<K, V, N: OrdChunkSize<(K, V)>> AsRef<OrdMap<K, V, N>> for OrdMap<K, V, N> {
     fn as_ref(&self) -> &Self {
         self
     }
 }
 
-impl<'m, 'k, 'v, K, V, OK, OV> From<&'m OrdMap<&'k K, &'v V>> for OrdMap<OK, OV>
+impl<'m, 'k, 'v, K, V, OK, OV, N> From<&'m OrdMap<&'k K, &'v V, N>> for OrdMap<OK, OV, N>
 where
     K: Ord + ToOwned<Owned = OK> + ?Sized,
     V: ToOwned<Owned = OV> + ?Sized,
     OK: Ord + Clone + Borrow<K>,
     OV: Clone + Borrow<V>,
+    N: OrdChunkSize<(&'k K, &'v V)> + OrdChunkSize<(OK, OV)>,
 {
-    fn from(m: &OrdMap<&K, &V>) -> Self {
-        m.iter()
-            .map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
-            .collect()
+    fn from(m: &'m OrdMap<&'k K, &'v V, N>) -> Self {
+        Self::from_iter_with_node_size(m.iter().map(|(k, v)| ((*k).to_owned(), (*v).to_owned())))
     }
 }
 
@@ -2186,10 +2769,64 @@ pub mod proptest {
 mod test {
     use super::*;
     use crate::proptest::*;
-    use crate::test::is_sorted;
+    use crate::test::{check_merge3, is_sorted};
     use ::proptest::num::{i16, usize};
     use ::proptest::{bool, collection, proptest};
 
+    #[test]
+    fn transaction_rolls_back_on_panic() {
+        let mut map: OrdMap<i32, i32> = (0..1000).map(|i| (i, i)).collect();
+        let before = map.clone();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            map.transaction(|map| -> Result<(), ()> {
+                map.remove_range(100..900);
+                panic!("half way through")
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(before, map);
+        assert!(map.ptr_eq(&before));
+    }
+
+    #[test]
+    fn comparisons_skip_shared_subtrees() {
+        let mut map: OrdMap<i32, f64> = (0..10_000).map(|i| (i, i as f64)).collect();
+        map.insert(0, f64::NAN);
+        let mut other = map.clone();
+        // A NaN isn't equal to itself, so only `Eq` values can skip the
+        // subtrees two maps share.
+        assert_ne!(map, map.clone());
+        assert_eq!(None, map.partial_cmp(&map.clone()));
+        other.insert(5000, 5000.0);
+        assert_ne!(map, other);
+        assert_eq!(None, map.partial_cmp(&other));
+        map.insert(0, 0.0);
+        other.insert(0, 0.0);
+        assert_eq!(map, other);
+        other.insert(5000, 0.0);
+        assert_ne!(map, other);
+        assert_eq!(Some(Ordering::Greater), map.partial_cmp(&other));
+
+        let ints: OrdMap<i32, i32> = (0..10_000).map(|i| (i, i)).collect();
+        let versions = vec![
+            ints.without(&9999),
+            ints.without(&5000),
+            ints.without(&0),
+            ints.update(10_000, 0),
+            ints.update(-1, 0),
+            ints.update(5000, -1),
+            ints.without(&5000).update(5001, 5002),
+            OrdMap::new(),
+            ints.clone(),
+        ];
+        for left in &versions {
+            for right in &versions {
+                assert_eq!(left.iter().cmp(right.iter()), left.cmp(right));
+                assert_eq!(left.iter().eq(right.iter()), left == right);
+            }
+        }
+    }
+
     #[test]
     fn iterates_in_order() {
         let map = ordmap! {
@@ -2216,6 +2853,17 @@ mod test {
         assert_eq!(it.next(), None);
     }
 
+    #[cfg(all(threadsafe, not(feature = "pool")))]
+    #[test]
+    fn new_in_static() {
+        static EMPTY: OrdMap<i32, i32> = OrdMap::new();
+        assert_eq!(None, EMPTY.get(&1));
+        let mut map = EMPTY.clone();
+        map.insert(1, 2);
+        assert_eq!(Some(&2), map.get(&1));
+        assert!(EMPTY.is_empty());
+    }
+
     #[test]
     fn into_iter() {
         let map = ordmap! {
@@ -2326,6 +2974,83 @@ mod test {
         assert_eq!(Some(&131_000), v1.get(&131_000));
     }
 
+    #[test]
+    fn structural_split_and_join() {
+        let map = (0..10_000).map(|i| (i, i)).collect::<OrdMap<i32, i32>>();
+        for split in &[0, 1, 63, 64, 2000, 5000, 9999, 10_000] {
+            let mut left = map.clone();
+            let mut right = left.split_off(split);
+            assert_eq!(Ok(()), left.check_invariants());
+            assert_eq!(Ok(()), right.check_invariants());
+            assert_eq!(*split as usize, left.len());
+            assert_eq!(10_000 - *split as usize, right.len());
+            assert!(left.keys().cloned().eq(0..*split));
+            assert!(right.keys().cloned().eq(*split..10_000));
+            for i in (0..10_000).step_by(7) {
+                left.remove(&i);
+                right.remove(&i);
+            }
+            let mut joined = left.clone();
+            joined.append(right.clone());
+            assert_eq!(Ok(()), joined.check_invariants());
+            assert_eq!(left.len() + right.len(), joined.len());
+            assert!(joined
+                .keys()
+                .cloned()
+                .eq((0..10_000).filter(|i| i % 7 != 0)));
+        }
+        assert_eq!(Some(&5000), map.get(&5000));
+        assert_eq!(10_000, map.len());
+    }
+
+    #[test]
+    fn remove_range_retention_window() {
+        let mut map = OrdMap::<i32, i32>::new();
+        for epoch in 0..20 {
+            for i in 0..1000 {
+                map.insert(epoch * 1000 + i, epoch);
+            }
+            if epoch >= 5 {
+                let removed = map.remove_range(..(epoch - 4) * 1000);
+                assert_eq!(Ok(()), removed.check_invariants());
+                assert_eq!(Ok(()), map.check_invariants());
+                assert_eq!(1000, removed.len());
+            }
+            assert_eq!(1000 * (epoch.min(4) + 1) as usize, map.len());
+        }
+        assert!(map.keys().cloned().eq(15_000..20_000));
+    }
+
+    #[test]
+    fn check_invariants_detects_wrong_size() {
+        let mut map = (0..1000).map(|i| (i, i)).collect::<OrdMap<i32, i32>>();
+        assert_eq!(Ok(()), map.check_invariants());
+        map.size = 999;
+        let error = map.check_invariants().unwrap_err();
+        assert_eq!("B-tree", error.structure());
+        assert_eq!(
+            "B-tree invariant violated at []: map size is 999, but it has 1000 values",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn small_nodes() {
+        let mut map: OrdMap<i32, i32, typenum::U4> = OrdMap::with_node_size();
+        for i in (0..1000).rev() {
+            map.insert(i, i * 2);
+        }
+        assert_eq!(Ok(()), map.check_invariants());
+        let snapshot = map.clone();
+        for i in (0..1000).step_by(3) {
+            assert_eq!(Some(i * 2), map.remove(&i));
+        }
+        assert_eq!(Ok(()), map.check_invariants());
+        assert_eq!(1000, snapshot.len());
+        assert!(map.keys().cloned().eq((0..1000).filter(|i| i % 3 != 0)));
+        assert_eq!(67, map.range(100..200).count());
+    }
+
     #[test]
     fn index_operator() {
         let mut map = ordmap! {1 => 2, 3 => 4, 5 => 6};
@@ -2395,7 +3120,9 @@ mod test {
 
     #[test]
     fn range_iter_big() {
-        use crate::nodes::btree::NODE_SIZE;
+        use crate::config::DefaultOrdChunkSize;
+        use typenum::Unsigned;
+        const NODE_SIZE: usize = DefaultOrdChunkSize::USIZE;
         use std::ops::Bound::Included;
         const N: usize = NODE_SIZE * NODE_SIZE * 5; // enough for a sizeable 3 level tree
 
@@ -2446,7 +3173,7 @@ mod test {
         #[test]
         fn overwrite_values(ref vec in collection::vec((i16::ANY, i16::ANY), 1..1000), index_rand in usize::ANY, new_val in i16::ANY) {
             let index = vec[index_rand % vec.len()].0;
-            let map1 = OrdMap::from_iter(vec.clone());
+            let map1: OrdMap<_, _> = OrdMap::from_iter(vec.clone());
             let map2 = map1.update(index, new_val);
             for (k, v) in map2 {
                 if k == index {
@@ -2473,6 +3200,79 @@ mod test {
             }
         }
 
+        #[test]
+        fn split_off(ref input in collection::btree_map(i16::ANY, i16::ANY, 0..5000), split in i16::ANY) {
+            let mut map: OrdMap<i16, i16> = OrdMap::from(input.clone());
+            let mut tree = input.clone();
+            let right = map.split_off(&split);
+            let tree_right = tree.split_off(&split);
+            assert_eq!(Ok(()), map.check_invariants());
+            assert_eq!(Ok(()), right.check_invariants());
+            assert_eq!(tree.len(), map.len());
+            assert_eq!(tree_right.len(), right.len());
+            assert!(map.iter().map(|(k, v)| (*k, *v)).eq(tree.into_iter()));
+            assert!(right.iter().map(|(k, v)| (*k, *v)).eq(tree_right.into_iter()));
+        }
+
+        #[test]
+        fn split_lookup(ref input in collection::btree_map(i16::ANY, i16::ANY, 0..5000), split in i16::ANY) {
+            let map: OrdMap<i16, i16> = OrdMap::from(input.clone());
+            let (left, found, right) = map.split_lookup(&split);
+            assert_eq!(Ok(()), left.check_invariants());
+            assert_eq!(Ok(()), right.check_invariants());
+            assert_eq!(input.get(&split).cloned(), found);
+            assert!(left.iter().map(|(k, v)| (*k, *v)).eq(input.range(..split).map(|(k, v)| (*k, *v))));
+            assert!(right.iter().map(|(k, v)| (*k, *v)).eq(input.range(split..).filter(|(k, _)| **k != split).map(|(k, v)| (*k, *v))));
+            assert_eq!(input.len(), left.len() + right.len() + found.iter().count());
+        }
+
+        #[test]
+        fn append(ref left in collection::btree_map(i16::ANY, i16::ANY, 0..5000), ref right in collection::btree_map(i16::ANY, i16::ANY, 0..5000), split in i16::ANY) {
+            let left: collections::BTreeMap<i16, i16> = left.range(..split).map(|(k, v)| (*k, *v)).collect();
+            let right: collections::BTreeMap<i16, i16> = right.range(split..).map(|(k, v)| (*k, *v)).collect();
+            let mut map = OrdMap::from(left.clone());
+            map.append(OrdMap::from(right.clone()));
+            let mut tree = left;
+            assert_eq!(Ok(()), map.check_invariants());
+            tree.extend(right);
+            assert_eq!(tree.len(), map.len());
+            assert!(map.iter().map(|(k, v)| (*k, *v)).eq(tree.iter().map(|(k, v)| (*k, *v))));
+            for key in tree.keys().step_by(3) {
+                map.remove(key);
+            }
+            assert_eq!(tree.len() - (tree.len() + 2) / 3, map.len());
+            assert_eq!(Ok(()), map.check_invariants());
+        }
+
+        #[test]
+        fn append_overlapping(ref left in collection::btree_map(i16::ANY, i16::ANY, 0..1000), ref right in collection::btree_map(i16::ANY, i16::ANY, 0..1000)) {
+            let mut map = OrdMap::from(left.clone());
+            map.append(OrdMap::from(right.clone()));
+            let mut tree = left.clone();
+            assert_eq!(Ok(()), map.check_invariants());
+            tree.extend(right.clone());
+            assert_eq!(tree.len(), map.len());
+            assert!(map.iter().map(|(k, v)| (*k, *v)).eq(tree.iter().map(|(k, v)| (*k, *v))));
+        }
+
+        #[test]
+        fn remove_range(ref input in collection::btree_map(i16::ANY, i16::ANY, 0..5000), start in i16::ANY, end in i16::ANY, inclusive in bool::ANY) {
+            let (start, end) = (start.min(end), start.max(end));
+            let mut map: OrdMap<i16, i16> = OrdMap::from(input.clone());
+            let (removed, expected_removed): (OrdMap<i16, i16>, Vec<(i16, i16)>) = if inclusive {
+                (map.remove_range(start..=end), input.range(start..=end).map(|(k, v)| (*k, *v)).collect())
+            } else {
+                (map.remove_range(start..end), input.range(start..end).map(|(k, v)| (*k, *v)).collect())
+            };
+            let expected: Vec<(i16, i16)> = input.iter().map(|(k, v)| (*k, *v)).filter(|(k, _)| *k < start || *k > end || (*k == end && !inclusive)).collect();
+            assert_eq!(expected_removed.len(), removed.len());
+            assert_eq!(expected.len(), map.len());
+            assert_eq!(Ok(()), removed.check_invariants());
+            assert_eq!(Ok(()), map.check_invariants());
+            assert!(removed.iter().map(|(k, v)| (*k, *v)).eq(expected_removed.into_iter()));
+            assert!(map.iter().map(|(k, v)| (*k, *v)).eq(expected.into_iter()));
+        }
+
         #[test]
         fn insert_and_delete_values(
             ref input in ord_map(0usize..64, 0usize..64, 1..1000),
@@ -2561,6 +3361,7 @@ mod test {
                 mut_map.insert(*k, *v);
             }
             assert_eq!(map, mut_map);
+            assert_eq!(Ok(()), mut_map.check_invariants());
         }
 
         #[test]
@@ -2570,6 +3371,7 @@ mod test {
                 let len = map.len();
                 assert_eq!(orig.get(key), map.get(key));
                 assert_eq!(orig.get(key).cloned(), map.remove(key));
+                assert_eq!(Ok(()), map.check_invariants());
                 assert_eq!(None, map.get(key));
                 assert_eq!(len - 1, map.len());
             }
@@ -2582,6 +3384,7 @@ mod test {
                 let len = map.len();
                 assert_eq!(orig.get(key), map.get(key));
                 assert_eq!(orig.get(key).cloned(), map.remove(key));
+                assert_eq!(Ok(()), map.check_invariants());
                 assert_eq!(None, map.get(key));
                 assert_eq!(len - 1, map.len());
             }
@@ -2593,7 +3396,7 @@ mod test {
             index_rand in usize::ANY
         ) {
             let index = *input.keys().nth(index_rand % input.len()).unwrap();
-            let map1 = OrdMap::from_iter(input.clone());
+            let map1: OrdMap<_, _> = OrdMap::from_iter(input.clone());
             let (val, map2): (i16, _) = map1.extract(&index).unwrap();
             let map3 = map2.update(index, val);
             for key in map2.keys() {
@@ -2645,5 +3448,14 @@ mod test {
             }).collect();
             assert_eq!(expected, diff);
         }
+
+        #[test]
+        fn merge3_matches_model(
+            base in collection::vec((0..2000u16, i16::ANY), 0..2000),
+            ours_edits in collection::vec((0..2000u16, ::proptest::option::of(i16::ANY)), 0..100),
+            theirs_edits in collection::vec((0..2000u16, ::proptest::option::of(i16::ANY)), 0..100)
+        ) {
+            check_merge3::<OrdMap<u16, i16>>(base, &ours_edits, &theirs_edits);
+        }
     }
 }
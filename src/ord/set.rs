@@ -23,16 +23,21 @@ use std::collections;
 use std::fmt::{Debug, Error, Formatter};
 use std::hash::{BuildHasher, Hash, Hasher};
 use std::iter::{FromIterator, IntoIterator, Sum};
-use std::ops::{Add, Deref, Mul, RangeBounds};
+use std::ops::{Add, Bound, Deref, Mul, RangeBounds};
 
+use crate::config::{DefaultOrdChunkSize, OrdChunkSize, POOL_SIZE};
+#[cfg(any(test, feature = "debug"))]
+use crate::debug::{InvariantViolation, Stats, Walk, Walker};
 use crate::hashset::HashSet;
+use crate::memory::{self, HeapNodes, HeapSize, MemoryReport};
 use crate::nodes::btree::{
     BTreeValue, ConsumingIter as ConsumingNodeIter, DiffIter as NodeDiffIter, Insert,
     Iter as NodeIter, Node, Remove,
 };
+use crate::patch::Patch;
 #[cfg(has_specialisation)]
 use crate::util::linear_search_by;
-use crate::util::{Pool, PoolRef};
+use crate::util::{option_ptr_eq, transaction, Pool, PoolRef};
 
 pub use crate::nodes::btree::DiffItem;
 
@@ -63,8 +68,12 @@ macro_rules! ordset {
     }};
 }
 
+/// A value as an [`OrdSet`][OrdSet] stores it in its B-tree.
+///
+/// This type only shows up in the bound on a set's node size, which is
+/// written `N: OrdChunkSize<Value<A>>`.
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
-struct Value<A>(A);
+pub struct Value<A>(A);
 
 impl<A> Deref for Value<A> {
     type Target = A;
@@ -156,7 +165,7 @@ impl<A: Ord + Copy> BTreeValue for Value<A> {
     }
 }
 
-def_pool!(OrdSetPool<A>, Node<Value<A>>);
+def_pool!(OrdSetPool<A; N: OrdChunkSize<Value<A>> = DefaultOrdChunkSize>, Node<Value<A>, N>);
 
 /// An ordered set.
 ///
@@ -172,22 +181,29 @@ def_pool!(OrdSetPool<A>, Node<Value<A>>);
 /// [1]: https://en.wikipedia.org/wiki/B-tree
 /// [hashset::HashSet]: ./struct.HashSet.html
 /// [std::cmp::Ord]: https://doc.rust-lang.org/std/cmp/trait.Ord.html
-pub struct OrdSet<A> {
+pub struct OrdSet<A, N: OrdChunkSize<Value<A>> = DefaultOrdChunkSize> {
     size: usize,
-    pool: OrdSetPool<A>,
-    root: PoolRef<Node<Value<A>>>,
+    pool: OrdSetPool<A, N>,
+    /// The root node, which isn't allocated until the first insert.
+    root: Option<PoolRef<Node<Value<A>, N>>>,
 }
 
 impl<A> OrdSet<A> {
-    /// Construct an empty set.
-    #[must_use]
-    pub fn new() -> Self {
-        let pool = OrdSetPool::default();
-        let root = PoolRef::default(&pool.0);
-        OrdSet {
-            size: 0,
-            pool,
-            root,
+    def_const_fn! {
+        #[cfg(not(feature = "pool"))]
+        /// Construct an empty set.
+        ///
+        /// An empty set doesn't allocate any memory until something is
+        /// inserted into it. This is a `const fn` unless the `pool`
+        /// feature is on, because memory pools can't be created in a
+        /// constant.
+        #[must_use]
+        pub fn new() -> Self {
+            OrdSet {
+                size: 0,
+                pool: OrdSetPool(Pool::new(POOL_SIZE)),
+                root: None,
+            }
         }
     }
 
@@ -195,12 +211,7 @@ impl<A> OrdSet<A> {
     #[cfg(feature = "pool")]
     #[must_use]
     pub fn with_pool(pool: &OrdSetPool<A>) -> Self {
-        let root = PoolRef::default(&pool.0);
-        OrdSet {
-            size: 0,
-            pool: pool.clone(),
-            root,
-        }
+        Self::with_pool_and_node_size(pool)
     }
 
     /// Construct a set with a single value.
@@ -221,10 +232,75 @@ impl<A> OrdSet<A> {
         OrdSet {
             size: 1,
             pool,
-            root,
+            root: Some(root),
+        }
+    }
+}
+
+impl<A, N: OrdChunkSize<Value<A>>> OrdSet<A, N> {
+    /// Construct an empty set with a node size other than the default.
+    ///
+    /// [`new`][new], [`Default`][Default] and [`FromIterator`][FromIterator]
+    /// always build sets of the default node size, so that the type of
+    /// a set can be inferred from its values alone.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use im::ordset::OrdSet;
+    /// use im::config::U16;
+    ///
+    /// let mut set = OrdSet::<i32, U16>::with_node_size();
+    /// set.insert(1);
+    /// assert!(set.contains(&1));
+    /// ```
+    ///
+    /// [new]: #method.new
+    /// [Default]: https://doc.rust-lang.org/std/default/trait.Default.html
+    /// [FromIterator]: https://doc.rust-lang.org/std/iter/trait.FromIterator.html
+    #[must_use]
+    pub fn with_node_size() -> Self {
+        OrdSet {
+            size: 0,
+            pool: OrdSetPool::default(),
+            root: None,
         }
     }
 
+    /// Construct a set with a node size other than the default from an
+    /// iterator.
+    ///
+    /// Time: O(n log n)
+    #[must_use]
+    pub fn from_iter_with_node_size<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = A>,
+        A: Ord + Clone,
+    {
+        let mut set = Self::with_node_size();
+        for value in iter {
+            set.insert(value);
+        }
+        set
+    }
+
+    /// Construct an empty set with a node size other than the default,
+    /// using a specific memory pool.
+    #[cfg(feature = "pool")]
+    #[must_use]
+    pub fn with_pool_and_node_size(pool: &OrdSetPool<A, N>) -> Self {
+        OrdSet {
+            size: 0,
+            pool: pool.clone(),
+            root: None,
+        }
+    }
+
+    /// Get the root node, if the set has one.
+    fn root(&self) -> Option<&Node<Value<A>, N>> {
+        self.root.as_deref()
+    }
+
     /// Test whether a set is empty.
     ///
     /// Time: O(1)
@@ -274,7 +350,7 @@ impl<A> OrdSet<A> {
     ///
     /// Time: O(1)
     pub fn ptr_eq(&self, other: &Self) -> bool {
-        std::ptr::eq(self, other) || PoolRef::ptr_eq(&self.root, &other.root)
+        std::ptr::eq(self, other) || option_ptr_eq(&self.root, &other.root)
     }
 
     /// Get a reference to the memory pool used by this set.
@@ -282,7 +358,7 @@ impl<A> OrdSet<A> {
     /// Note that if you didn't specifically construct it with a pool, you'll
     /// get back a reference to a pool of size 0.
     #[cfg(feature = "pool")]
-    pub fn pool(&self) -> &OrdSetPool<A> {
+    pub fn pool(&self) -> &OrdSetPool<A, N> {
         &self.pool
     }
 
@@ -304,16 +380,146 @@ impl<A> OrdSet<A> {
     /// ```
     pub fn clear(&mut self) {
         if !self.is_empty() {
-            self.root = PoolRef::default(&self.pool.0);
+            self.root = None;
             self.size = 0;
         }
     }
+
+    /// Get the number of bytes of heap memory used by the set's nodes.
+    ///
+    /// Nodes shared with other sets are counted in full. Heap memory
+    /// owned by the values themselves isn't counted: use
+    /// [`heap_size_with`][heap_size_with] to include it.
+    ///
+    /// Time: O(n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::ordset::OrdSet;
+    /// let set: OrdSet<i64> = (0..1000).collect();
+    /// assert!(set.heap_size() >= 1000 * std::mem::size_of::<i64>());
+    /// ```
+    ///
+    /// [heap_size_with]: #method.heap_size_with
+    #[must_use]
+    pub fn heap_size(&self) -> usize {
+        self.heap_size_with(|_| 0)
+    }
+
+    /// Get the number of bytes of heap memory used by the set's nodes,
+    /// plus the number of bytes `value_size` reports for each value.
+    ///
+    /// Time: O(n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::ordset::OrdSet;
+    /// let set = ordset![String::from("foo"), String::from("bar")];
+    /// assert_eq!(
+    ///     set.heap_size() + 6,
+    ///     set.heap_size_with(|string| string.capacity())
+    /// );
+    /// ```
+    pub fn heap_size_with<F>(&self, mut value_size: F) -> usize
+    where
+        F: FnMut(&A) -> usize,
+    {
+        let mut visitor = HeapSize::new(|value: &Value<A>| value_size(value));
+        if let Some(ref root) = self.root {
+            root.visit(&mut visitor);
+        }
+        visitor.bytes()
+    }
+
+    /// Get the number of bytes of heap memory used by nodes which this
+    /// set shares with `other`.
+    ///
+    /// Time: O(n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::ordset::OrdSet;
+    /// let set: OrdSet<i64> = (0..1000).collect();
+    /// let mut other = set.clone();
+    /// assert_eq!(set.heap_size(), set.shared_with(&other));
+    /// other.insert(1000);
+    /// assert!(set.shared_with(&other) < set.heap_size());
+    /// ```
+    #[must_use]
+    pub fn shared_with(&self, other: &Self) -> usize {
+        let mut report = MemoryReport::new();
+        report.add(self);
+        report.add(other);
+        report.shared_bytes()
+    }
+
+    /// Get a summary of the shape of the set's tree.
+    ///
+    /// This method requires the `debug` feature flag.
+    ///
+    /// Time: O(n / 64)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::ordset::OrdSet;
+    /// let set: OrdSet<i64> = (0..1000).collect();
+    /// let stats = set.stats();
+    /// assert_eq!(1000, stats.entries);
+    /// assert!(stats.depth >= 2);
+    /// ```
+    #[cfg(any(test, feature = "debug"))]
+    #[must_use]
+    pub fn stats(&self) -> Stats {
+        Stats::collect(self.len(), self)
+    }
 }
 
-impl<A> OrdSet<A>
+impl<A, N: OrdChunkSize<Value<A>>> OrdSet<A, N>
 where
     A: Ord,
 {
+    /// Verify the internal consistency of a set, returning a
+    /// description of the first problem found, if any.
+    ///
+    /// This method walks the B-tree making up the set and verifies
+    /// that all of its invariants hold.
+    ///
+    /// This method requires the `debug` feature flag.
+    ///
+    /// Time: O(n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::ordset::OrdSet;
+    /// let set: OrdSet<i64> = (0..1000).collect();
+    /// assert_eq!(Ok(()), set.check_invariants());
+    /// ```
+    #[cfg(any(test, feature = "debug"))]
+    pub fn check_invariants(&self) -> Result<(), InvariantViolation> {
+        let len = match self.root {
+            Some(ref root) => root.check_invariants()?,
+            None => 0,
+        };
+        if self.size != len {
+            return Err(InvariantViolation::new(
+                "B-tree",
+                &[],
+                format!("set size is {}, but it has {} values", self.size, len),
+            ));
+        }
+        Ok(())
+    }
+
     /// Get the smallest value in a set.
     ///
     /// If the set is empty, returns `None`.
@@ -321,7 +527,7 @@ where
     /// Time: O(log n)
     #[must_use]
     pub fn get_min(&self) -> Option<&A> {
-        self.root.min().map(Deref::deref)
+        self.root()?.min().map(Deref::deref)
     }
 
     /// Get the largest value in a set.
@@ -331,27 +537,27 @@ where
     /// Time: O(log n)
     #[must_use]
     pub fn get_max(&self) -> Option<&A> {
-        self.root.max().map(Deref::deref)
+        self.root()?.max().map(Deref::deref)
     }
 
     /// Create an iterator over the contents of the set.
     #[must_use]
-    pub fn iter(&self) -> Iter<'_, A> {
+    pub fn iter(&self) -> Iter<'_, A, N> {
         Iter {
-            it: NodeIter::new(&self.root, self.size, ..),
+            it: NodeIter::new(self.root(), self.size, ..),
         }
     }
 
     /// Create an iterator over a range inside the set.
     #[must_use]
-    pub fn range<R, BA>(&self, range: R) -> RangedIter<'_, A>
+    pub fn range<R, BA>(&self, range: R) -> RangedIter<'_, A, N>
     where
         R: RangeBounds<BA>,
         A: Borrow<BA>,
         BA: Ord + ?Sized,
     {
         RangedIter {
-            it: NodeIter::new(&self.root, self.size, range),
+            it: NodeIter::new(self.root(), self.size, range),
         }
     }
 
@@ -367,9 +573,9 @@ where
     /// the two sets, minus the number of elements belonging to nodes
     /// shared between them)
     #[must_use]
-    pub fn diff<'a>(&'a self, other: &'a Self) -> DiffIter<'_, A> {
+    pub fn diff<'a>(&'a self, other: &'a Self) -> DiffIter<'a, A, N> {
         DiffIter {
-            it: NodeDiffIter::new(&self.root, &other.root),
+            it: NodeDiffIter::new(self.root(), other.root()),
         }
     }
 
@@ -393,7 +599,7 @@ where
         BA: Ord + ?Sized,
         A: Borrow<BA>,
     {
-        self.root.lookup(a).is_some()
+        self.root().and_then(|root| root.lookup(a)).is_some()
     }
 
     /// Get the closest smaller value in a set to a given value.
@@ -413,7 +619,7 @@ where
     /// ```
     #[must_use]
     pub fn get_prev(&self, key: &A) -> Option<&A> {
-        self.root.lookup_prev(key).map(|v| &v.0)
+        self.root()?.lookup_prev(key).map(|v| &v.0)
     }
 
     /// Get the closest larger value in a set to a given value.
@@ -433,7 +639,7 @@ where
     /// ```
     #[must_use]
     pub fn get_next(&self, key: &A) -> Option<&A> {
-        self.root.lookup_next(key).map(|v| &v.0)
+        self.root()?.lookup_next(key).map(|v| &v.0)
     }
 
     /// Test whether a set is a subset of another set, meaning that
@@ -466,7 +672,7 @@ where
     }
 }
 
-impl<A> OrdSet<A>
+impl<A, N: OrdChunkSize<Value<A>>> OrdSet<A, N>
 where
     A: Ord + Clone,
 {
@@ -489,22 +695,22 @@ where
     /// ```
     #[inline]
     pub fn insert(&mut self, a: A) -> Option<A> {
+        let pool = &self.pool.0;
         let new_root = {
-            let root = PoolRef::make_mut(&self.pool.0, &mut self.root);
-            match root.insert(&self.pool.0, Value(a)) {
+            let root = Node::make_mut_or_default(pool, &mut self.root);
+            match root.insert(pool, Value(a)) {
                 Insert::Replaced(Value(old_value)) => return Some(old_value),
                 Insert::Added => {
                     self.size += 1;
                     return None;
                 }
-                Insert::Split(left, median, right) => PoolRef::new(
-                    &self.pool.0,
-                    Node::new_from_split(&self.pool.0, left, median, right),
-                ),
+                Insert::Split(left, median, right) => {
+                    PoolRef::new(pool, Node::new_from_split(pool, left, median, right))
+                }
             }
         };
         self.size += 1;
-        self.root = new_root;
+        self.root = Some(new_root);
         None
     }
 
@@ -517,20 +723,21 @@ where
         BA: Ord + ?Sized,
         A: Borrow<BA>,
     {
-        let (new_root, removed_value) = {
-            let root = PoolRef::make_mut(&self.pool.0, &mut self.root);
-            match root.remove(&self.pool.0, a) {
-                Remove::Update(value, root) => (PoolRef::new(&self.pool.0, root), Some(value.0)),
-                Remove::Removed(value) => {
-                    self.size -= 1;
-                    return Some(value.0);
-                }
-                Remove::NoChange => return None,
+        let pool = &self.pool.0;
+        let root = Node::make_mut(pool, self.root.as_mut()?);
+        let removed = match root.remove(pool, a) {
+            Remove::Update(value, root) => {
+                self.root = Some(PoolRef::new(pool, root));
+                value
             }
+            Remove::Removed(value) => value,
+            Remove::NoChange => return None,
         };
         self.size -= 1;
-        self.root = new_root;
-        removed_value
+        if self.size == 0 {
+            self.root = None;
+        }
+        Some(removed.0)
     }
 
     /// Remove the smallest value from a set.
@@ -656,7 +863,7 @@ where
     where
         I: IntoIterator<Item = Self>,
     {
-        i.into_iter().fold(Self::default(), Self::union)
+        i.into_iter().fold(Self::with_node_size(), Self::union)
     }
 
     /// Construct the symmetric difference between two sets.
@@ -746,7 +953,7 @@ where
     /// ```
     #[must_use]
     pub fn intersection(self, other: Self) -> Self {
-        let mut out = Self::default();
+        let mut out = Self::with_node_size();
         for value in other {
             if self.contains(&value) {
                 out.insert(value);
@@ -755,13 +962,37 @@ where
         out
     }
 
+    fn from_root(pool: OrdSetPool<A, N>, root: Node<Value<A>, N>, size: usize) -> Self {
+        let mut set = OrdSet {
+            size: 0,
+            pool,
+            root: None,
+        };
+        set.set_root(root, size);
+        set
+    }
+
+    fn set_root(&mut self, root: Node<Value<A>, N>, size: usize) {
+        self.size = size;
+        self.root = if size == 0 {
+            None
+        } else {
+            Some(PoolRef::new(&self.pool.0, root))
+        };
+    }
+
+    fn take_root(&mut self) -> Node<Value<A>, N> {
+        self.size = 0;
+        self.root.take().map(Node::unwrap).unwrap_or_default()
+    }
+
     /// Split a set into two, with the left hand set containing values
     /// which are smaller than `split`, and the right hand set
     /// containing values which are larger than `split`.
     ///
     /// The `split` value itself is discarded.
     ///
-    /// Time: O(n)
+    /// Time: O(log n)
     #[must_use]
     pub fn split<BA>(self, split: &BA) -> (Self, Self)
     where
@@ -780,30 +1011,235 @@ where
     /// the `split` value existed in the original set, and false
     /// otherwise.
     ///
-    /// Time: O(n)
+    /// Time: O(log n)
     #[must_use]
-    pub fn split_member<BA>(self, split: &BA) -> (Self, bool, Self)
+    pub fn split_member<BA>(mut self, split: &BA) -> (Self, bool, Self)
     where
         BA: Ord + ?Sized,
         A: Borrow<BA>,
     {
-        let mut left = Self::default();
-        let mut right = Self::default();
-        let mut present = false;
-        for value in self {
-            match value.borrow().cmp(split) {
-                Ordering::Less => {
-                    left.insert(value);
+        let size = self.size;
+        let root = self.take_root();
+        let (left, found, right) = Node::split_by_key(&self.pool.0, root, split);
+        let left_size = left.len();
+        let right_size = size - left_size - if found.is_some() { 1 } else { 0 };
+        (
+            OrdSet::from_root(self.pool.clone(), left, left_size),
+            found.is_some(),
+            OrdSet::from_root(self.pool.clone(), right, right_size),
+        )
+    }
+
+    /// Split a set in place, leaving the values which are smaller
+    /// than `split` in the current set and returning a new set
+    /// containing `split` and every value larger than it.
+    ///
+    /// Only the nodes along the path to `split` are rebuilt, the
+    /// rest of the tree is shared between the two sets.
+    ///
+    /// Time: O(log n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::ordset::OrdSet;
+    /// let mut set = ordset![1, 2, 3, 4];
+    /// let right = set.split_off(&3);
+    /// assert_eq!(ordset![1, 2], set);
+    /// assert_eq!(ordset![3, 4], right);
+    /// ```
+    pub fn split_off<BA>(&mut self, split: &BA) -> Self
+    where
+        BA: Ord + ?Sized,
+        A: Borrow<BA>,
+    {
+        let size = self.size;
+        let root = self.take_root();
+        let pool = &self.pool.0;
+        let (left, found, mut right) = Node::split_by_key(pool, root, split);
+        if let Some(value) = found {
+            right = Node::join(pool, Node::default(), value, right);
+        }
+        let right_size = right.len();
+        self.set_root(left, size - right_size);
+        OrdSet::from_root(self.pool.clone(), right, right_size)
+    }
+
+    /// Move every value from `other` into the current set.
+    ///
+    /// If every value in `other` is larger than every value in the
+    /// current set, the two trees are joined structurally, sharing
+    /// all but the nodes along the seam between them. Otherwise, each
+    /// value is inserted in turn.
+    ///
+    /// Time: O(log n) if the values of `other` are all larger, O(m
+    /// log n) otherwise
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::ordset::OrdSet;
+    /// let mut set = ordset![1, 2];
+    /// set.append(ordset![3, 4]);
+    /// assert_eq!(ordset![1, 2, 3, 4], set);
+    /// ```
+    pub fn append(&mut self, other: Self) {
+        if other.is_empty() {
+            return;
+        }
+        if self.is_empty() {
+            *self = other;
+            return;
+        }
+        let disjoint = match (self.get_max(), other.get_min()) {
+            (Some(max), Some(min)) => max < min,
+            _ => false,
+        };
+        if !disjoint {
+            self.extend(other);
+            return;
+        }
+        let size = self.size + other.size;
+        let left = self.take_root();
+        let right = other.root.map(Node::unwrap).unwrap_or_default();
+        let root = Node::concat(&self.pool.0, left, right);
+        self.set_root(root, size);
+    }
+
+    /// Apply a patch to the set, adding or removing each value it
+    /// changes.
+    ///
+    /// The set is split at the middle change, and each half of the
+    /// patch is applied to each half of the set before they're joined
+    /// back together, so the set is walked once, and every subtree
+    /// between the changed values is shared with the original set. A
+    /// patch which isn't sorted, such as one made from a
+    /// [`HashSet`][HashSet] diff, is sorted first, and if it changes a
+    /// value more than once, the last change wins.
+    ///
+    /// Time: O(k log n) for a patch of k changes
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::ordset::OrdSet;
+    /// # use im::patch::Patch;
+    /// let old = ordset![1, 2, 3];
+    /// let new = ordset![2, 3, 4];
+    /// let patch: Patch<_, _> = old.diff(&new).collect();
+    /// let mut set = old.clone();
+    /// set.apply_patch(&patch);
+    /// assert_eq!(new, set);
+    /// ```
+    ///
+    /// [HashSet]: ../hashset/struct.HashSet.html
+    pub fn apply_patch(&mut self, patch: &Patch<A, ()>) {
+        let mut changes: Vec<_> = patch.changes().iter().collect();
+        if !changes.windows(2).all(|pair| pair[0].0 < pair[1].0) {
+            // A stable sort keeps the last change to each value first once
+            // the list is reversed, which is the one `dedup_by` keeps.
+            changes.reverse();
+            changes.sort_by(|left, right| left.0.cmp(&right.0));
+            changes.dedup_by(|later, earlier| later.0 == earlier.0);
+        }
+        let mut size = self.size;
+        let root = self.take_root();
+        let root = Node::apply_sorted(
+            &self.pool.0,
+            root,
+            changes,
+            &|change| &change.0,
+            &mut |found, (value, change)| {
+                let value = change.new_value().map(|_| Value(value.clone()));
+                match (&found, &value) {
+                    (Some(_), None) => size -= 1,
+                    (None, Some(_)) => size += 1,
+                    _ => {}
                 }
-                Ordering::Equal => {
-                    present = true;
+                value
+            },
+        );
+        self.set_root(root, size);
+    }
+
+    /// Edit the set in a [transaction][transactions]: `f` edits a
+    /// copy of the set, which replaces it only if `f` returns `Ok`.
+    ///
+    /// Time: O(1), plus the edits
+    ///
+    /// [transactions]: crate#transactions
+    pub fn transaction<F, R, E>(&mut self, f: F) -> Result<R, E>
+    where
+        F: FnOnce(&mut Self) -> Result<R, E>,
+    {
+        transaction(self, f)
+    }
+
+    /// Remove every value inside `range` from the set, and return
+    /// them as a new set.
+    ///
+    /// The set is split at each end of the range and the remaining
+    /// two parts are joined back together, so only the nodes along
+    /// the edges of the range are rebuilt.
+    ///
+    /// Time: O(log n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::ordset::OrdSet;
+    /// let mut set = ordset![1, 2, 3, 4, 5];
+    /// let removed = set.remove_range(2..4);
+    /// assert_eq!(ordset![1, 4, 5], set);
+    /// assert_eq!(ordset![2, 3], removed);
+    /// ```
+    pub fn remove_range<R, BA>(&mut self, range: R) -> Self
+    where
+        R: RangeBounds<BA>,
+        A: Borrow<BA>,
+        BA: Ord + ?Sized,
+    {
+        let size = self.size;
+        let root = self.take_root();
+        let pool = &self.pool.0;
+        let (left, rest) = match range.start_bound() {
+            Bound::Unbounded => (Node::default(), root),
+            Bound::Included(key) => match Node::split_by_key(pool, root, key) {
+                (left, Some(value), right) => {
+                    (left, Node::join(pool, Node::default(), value, right))
                 }
-                Ordering::Greater => {
-                    right.insert(value);
+                (left, None, right) => (left, right),
+            },
+            Bound::Excluded(key) => match Node::split_by_key(pool, root, key) {
+                (left, Some(value), right) => {
+                    (Node::join(pool, left, value, Node::default()), right)
                 }
-            }
-        }
-        (left, present, right)
+                (left, None, right) => (left, right),
+            },
+        };
+        let (removed, right) = match range.end_bound() {
+            Bound::Unbounded => (rest, Node::default()),
+            Bound::Included(key) => match Node::split_by_key(pool, rest, key) {
+                (removed, Some(value), right) => {
+                    (Node::join(pool, removed, value, Node::default()), right)
+                }
+                (removed, None, right) => (removed, right),
+            },
+            Bound::Excluded(key) => match Node::split_by_key(pool, rest, key) {
+                (removed, Some(value), right) => {
+                    (removed, Node::join(pool, Node::default(), value, right))
+                }
+                (removed, None, right) => (removed, right),
+            },
+        };
+        let removed_size = removed.len();
+        let root = Node::concat(pool, left, right);
+        self.set_root(root, size - removed_size);
+        OrdSet::from_root(self.pool.clone(), removed, removed_size)
     }
 
     /// Construct a set with only the `n` smallest values from a given
@@ -812,7 +1248,7 @@ where
     /// Time: O(n)
     #[must_use]
     pub fn take(&self, n: usize) -> Self {
-        self.iter().take(n).cloned().collect()
+        Self::from_iter_with_node_size(self.iter().take(n).cloned())
     }
 
     /// Construct a set with the `n` smallest values removed from a
@@ -821,13 +1257,35 @@ where
     /// Time: O(n)
     #[must_use]
     pub fn skip(&self, n: usize) -> Self {
-        self.iter().skip(n).cloned().collect()
+        Self::from_iter_with_node_size(self.iter().skip(n).cloned())
     }
 }
 
 // Core traits
 
-impl<A> Clone for OrdSet<A> {
+#[cfg(any(test, feature = "debug"))]
+impl<A, N: OrdChunkSize<Value<A>>> Walk for OrdSet<A, N> {
+    fn walk(&self, walker: &mut dyn Walker) {
+        if let Some(ref root) = self.root {
+            root.walk(walker, 0, 1)
+        }
+    }
+}
+
+impl<A, N: OrdChunkSize<Value<A>>> HeapNodes for OrdSet<A, N> {
+    fn visit_heap_nodes(&self, node: &mut dyn FnMut(usize, usize) -> bool) {
+        memory::visit_nodes(
+            |visitor| {
+                if let Some(ref root) = self.root {
+                    root.visit(visitor)
+                }
+            },
+            node,
+        )
+    }
+}
+
+impl<A, N: OrdChunkSize<Value<A>>> Clone for OrdSet<A, N> {
     /// Clone a set.
     ///
     /// Time: O(1)
@@ -841,28 +1299,33 @@ impl<A> Clone for OrdSet<A> {
     }
 }
 
-impl<A: Ord> PartialEq for OrdSet<A> {
+/// Subtrees which the two sets share aren't compared.
+impl<A: Ord, N: OrdChunkSize<Value<A>>> PartialEq for OrdSet<A, N> {
     fn eq(&self, other: &Self) -> bool {
-        PoolRef::ptr_eq(&self.root, &other.root)
+        option_ptr_eq(&self.root, &other.root)
             || (self.len() == other.len() && self.diff(other).next().is_none())
     }
 }
 
-impl<A: Ord + Eq> Eq for OrdSet<A> {}
+impl<A: Ord + Eq, N: OrdChunkSize<Value<A>>> Eq for OrdSet<A, N> {}
 
-impl<A: Ord> PartialOrd for OrdSet<A> {
+impl<A: Ord, N: OrdChunkSize<Value<A>>> PartialOrd for OrdSet<A, N> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.iter().partial_cmp(other.iter())
+        Some(self.cmp(other))
     }
 }
 
-impl<A: Ord> Ord for OrdSet<A> {
+impl<A: Ord, N: OrdChunkSize<Value<A>>> Ord for OrdSet<A, N> {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.iter().cmp(other.iter())
+        if option_ptr_eq(&self.root, &other.root) {
+            return Ordering::Equal;
+        }
+        NodeDiffIter::compare(self.root(), other.root(), |old, new| Some(old.cmp(new)))
+            .unwrap_or(Ordering::Equal)
     }
 }
 
-impl<A: Ord + Hash> Hash for OrdSet<A> {
+impl<A: Ord + Hash, N: OrdChunkSize<Value<A>>> Hash for OrdSet<A, N> {
     fn hash<H>(&self, state: &mut H)
     where
         H: Hasher,
@@ -875,52 +1338,52 @@ impl<A: Ord + Hash> Hash for OrdSet<A> {
 
 impl<A> Default for OrdSet<A> {
     fn default() -> Self {
-        OrdSet::new()
+        Self::new()
     }
 }
 
-impl<A: Ord + Clone> Add for OrdSet<A> {
-    type Output = OrdSet<A>;
+impl<A: Ord + Clone, N: OrdChunkSize<Value<A>>> Add for OrdSet<A, N> {
+    type Output = OrdSet<A, N>;
 
     fn add(self, other: Self) -> Self::Output {
         self.union(other)
     }
 }
 
-impl<'a, A: Ord + Clone> Add for &'a OrdSet<A> {
-    type Output = OrdSet<A>;
+impl<'a, A: Ord + Clone, N: OrdChunkSize<Value<A>>> Add for &'a OrdSet<A, N> {
+    type Output = OrdSet<A, N>;
 
     fn add(self, other: Self) -> Self::Output {
         self.clone().union(other.clone())
     }
 }
 
-impl<A: Ord + Clone> Mul for OrdSet<A> {
-    type Output = OrdSet<A>;
+impl<A: Ord + Clone, N: OrdChunkSize<Value<A>>> Mul for OrdSet<A, N> {
+    type Output = OrdSet<A, N>;
 
     fn mul(self, other: Self) -> Self::Output {
         self.intersection(other)
     }
 }
 
-impl<'a, A: Ord + Clone> Mul for &'a OrdSet<A> {
-    type Output = OrdSet<A>;
+impl<'a, A: Ord + Clone, N: OrdChunkSize<Value<A>>> Mul for &'a OrdSet<A, N> {
+    type Output = OrdSet<A, N>;
 
     fn mul(self, other: Self) -> Self::Output {
         self.clone().intersection(other.clone())
     }
 }
 
-impl<A: Ord + Clone> Sum for OrdSet<A> {
+impl<A: Ord + Clone, N: OrdChunkSize<Value<A>>> Sum for OrdSet<A, N> {
     fn sum<I>(it: I) -> Self
     where
         I: Iterator<Item = Self>,
     {
-        it.fold(Self::new(), |a, b| a + b)
+        it.fold(Self::with_node_size(), |a, b| a + b)
     }
 }
 
-impl<A, R> Extend<R> for OrdSet<A>
+impl<A, R, N: OrdChunkSize<Value<A>>> Extend<R> for OrdSet<A, N>
 where
     A: Ord + Clone + From<R>,
 {
@@ -934,7 +1397,7 @@ where
     }
 }
 
-impl<A: Ord + Debug> Debug for OrdSet<A> {
+impl<A: Ord + Debug, N: OrdChunkSize<Value<A>>> Debug for OrdSet<A, N> {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
         f.debug_set().entries(self.iter()).finish()
     }
@@ -943,11 +1406,11 @@ impl<A: Ord + Debug> Debug for OrdSet<A> {
 // Iterators
 
 /// An iterator over the elements of a set.
-pub struct Iter<'a, A> {
-    it: NodeIter<'a, Value<A>>,
+pub struct Iter<'a, A, N: OrdChunkSize<Value<A>> = DefaultOrdChunkSize> {
+    it: NodeIter<'a, Value<A>, N>,
 }
 
-impl<'a, A> Iterator for Iter<'a, A>
+impl<'a, A, N: OrdChunkSize<Value<A>>> Iterator for Iter<'a, A, N>
 where
     A: 'a + Ord,
 {
@@ -965,7 +1428,7 @@ where
     }
 }
 
-impl<'a, A> DoubleEndedIterator for Iter<'a, A>
+impl<'a, A, N: OrdChunkSize<Value<A>>> DoubleEndedIterator for Iter<'a, A, N>
 where
     A: 'a + Ord,
 {
@@ -974,18 +1437,18 @@ where
     }
 }
 
-impl<'a, A> ExactSizeIterator for Iter<'a, A> where A: 'a + Ord {}
+impl<'a, A, N: OrdChunkSize<Value<A>>> ExactSizeIterator for Iter<'a, A, N> where A: 'a + Ord {}
 
 /// A ranged iterator over the elements of a set.
 ///
 /// The only difference from `Iter` is that this one doesn't implement
 /// `ExactSizeIterator` because we can't know the size of the range without first
 /// iterating over it to count.
-pub struct RangedIter<'a, A> {
-    it: NodeIter<'a, Value<A>>,
+pub struct RangedIter<'a, A, N: OrdChunkSize<Value<A>> = DefaultOrdChunkSize> {
+    it: NodeIter<'a, Value<A>, N>,
 }
 
-impl<'a, A> Iterator for RangedIter<'a, A>
+impl<'a, A, N: OrdChunkSize<Value<A>>> Iterator for RangedIter<'a, A, N>
 where
     A: 'a + Ord,
 {
@@ -1003,7 +1466,7 @@ where
     }
 }
 
-impl<'a, A> DoubleEndedIterator for RangedIter<'a, A>
+impl<'a, A, N: OrdChunkSize<Value<A>>> DoubleEndedIterator for RangedIter<'a, A, N>
 where
     A: 'a + Ord,
 {
@@ -1013,11 +1476,11 @@ where
 }
 
 /// A consuming iterator over the elements of a set.
-pub struct ConsumingIter<A> {
-    it: ConsumingNodeIter<Value<A>>,
+pub struct ConsumingIter<A, N: OrdChunkSize<Value<A>> = DefaultOrdChunkSize> {
+    it: ConsumingNodeIter<Value<A>, N>,
 }
 
-impl<A> Iterator for ConsumingIter<A>
+impl<A, N: OrdChunkSize<Value<A>>> Iterator for ConsumingIter<A, N>
 where
     A: Ord + Clone,
 {
@@ -1032,11 +1495,11 @@ where
 }
 
 /// An iterator over the difference between two sets.
-pub struct DiffIter<'a, A> {
-    it: NodeDiffIter<'a, Value<A>>,
+pub struct DiffIter<'a, A, N: OrdChunkSize<Value<A>> = DefaultOrdChunkSize> {
+    it: NodeDiffIter<'a, Value<A>, N>,
 }
 
-impl<'a, A> Iterator for DiffIter<'a, A>
+impl<'a, A, N: OrdChunkSize<Value<A>>> Iterator for DiffIter<'a, A, N>
 where
     A: Ord + PartialEq,
 {
@@ -1065,49 +1528,46 @@ where
     where
         T: IntoIterator<Item = R>,
     {
-        let mut out = Self::new();
-        for item in i {
-            out.insert(From::from(item));
-        }
-        out
+        Self::from_iter_with_node_size(i.into_iter().map(From::from))
     }
 }
 
-impl<'a, A> IntoIterator for &'a OrdSet<A>
+impl<'a, A, N: OrdChunkSize<Value<A>>> IntoIterator for &'a OrdSet<A, N>
 where
     A: 'a + Ord,
 {
     type Item = &'a A;
-    type IntoIter = Iter<'a, A>;
+    type IntoIter = Iter<'a, A, N>;
 
     fn into_iter(self) -> Self::IntoIter {
         self.iter()
     }
 }
 
-impl<A> IntoIterator for OrdSet<A>
+impl<A, N: OrdChunkSize<Value<A>>> IntoIterator for OrdSet<A, N>
 where
     A: Ord + Clone,
 {
     type Item = A;
-    type IntoIter = ConsumingIter<A>;
+    type IntoIter = ConsumingIter<A, N>;
 
     fn into_iter(self) -> Self::IntoIter {
         ConsumingIter {
-            it: ConsumingNodeIter::new(&self.root, self.size),
+            it: ConsumingNodeIter::new(self.root(), self.size),
         }
     }
 }
 
 // Conversions
 
-impl<'s, 'a, A, OA> From<&'s OrdSet<&'a A>> for OrdSet<OA>
+impl<'s, 'a, A, OA, N> From<&'s OrdSet<&'a A, N>> for OrdSet<OA, N>
 where
     A: ToOwned<Owned = OA> + Ord + ?Sized,
     OA: Borrow<A> + Ord + Clone,
+    N: OrdChunkSize<Value<&'a A>> + OrdChunkSize<Value<OA>>,
 {
-    fn from(set: &OrdSet<&A>) -> Self {
-        set.iter().map(|a| (*a).to_owned()).collect()
+    fn from(set: &'s OrdSet<&'a A, N>) -> Self {
+        Self::from_iter_with_node_size(set.iter().map(|a| (*a).to_owned()))
     }
 }
 
@@ -1194,6 +1654,20 @@ mod test {
         assert!(!set.contains("foo"));
     }
 
+    #[test]
+    fn small_nodes() {
+        let mut set: OrdSet<i32, typenum::U8> = OrdSet::from_iter_with_node_size(0..1000);
+        assert_eq!(Ok(()), set.check_invariants());
+        let (left, right) = set.clone().split(&500);
+        assert!(left.iter().cloned().eq(0..500));
+        assert!(right.iter().cloned().eq(501..1000));
+        for i in (0..1000).step_by(2) {
+            assert_eq!(Some(i), set.remove(&i));
+        }
+        assert_eq!(Ok(()), set.check_invariants());
+        assert_eq!(500, set.len());
+    }
+
     #[test]
     fn ranged_iter() {
         let set: OrdSet<i32> = ordset![1, 2, 3, 4, 5];
@@ -1226,6 +1700,43 @@ mod test {
             assert!(s.len() >= 10);
         }
 
+        #[test]
+        fn split_member(ref input in ::proptest::collection::btree_set(0i32..10_000, 0..5000), split in 0i32..10_000) {
+            let set: OrdSet<i32> = input.iter().cloned().collect();
+            let (left, found, right) = set.split_member(&split);
+            assert_eq!(Ok(()), left.check_invariants());
+            assert_eq!(Ok(()), right.check_invariants());
+            assert_eq!(input.contains(&split), found);
+            assert!(left.iter().eq(input.range(..split)));
+            assert!(right.iter().eq(input.range(split..).filter(|v| **v != split)));
+        }
+
+        #[test]
+        fn split_off_and_append(ref input in ::proptest::collection::btree_set(0i32..10_000, 0..5000), split in 0i32..10_000) {
+            let mut left: OrdSet<i32> = input.iter().cloned().collect();
+            let right = left.split_off(&split);
+            assert_eq!(Ok(()), left.check_invariants());
+            assert_eq!(Ok(()), right.check_invariants());
+            assert!(left.iter().eq(input.range(..split)));
+            assert!(right.iter().eq(input.range(split..)));
+            left.append(right);
+            assert_eq!(Ok(()), left.check_invariants());
+            assert_eq!(input.len(), left.len());
+            assert!(left.iter().eq(input.iter()));
+        }
+
+        #[test]
+        fn remove_range(ref input in ::proptest::collection::btree_set(0i32..10_000, 0..5000), start in 0i32..10_000, end in 0i32..10_000) {
+            let (start, end) = (start.min(end), start.max(end));
+            let mut set: OrdSet<i32> = input.iter().cloned().collect();
+            let removed = set.remove_range(start..end);
+            assert_eq!(Ok(()), removed.check_invariants());
+            assert_eq!(Ok(()), set.check_invariants());
+            assert!(removed.iter().eq(input.range(start..end)));
+            assert!(set.iter().eq(input.iter().filter(|v| **v < start || **v >= end)));
+            assert_eq!(input.len(), set.len() + removed.len());
+        }
+
         #[test]
         fn long_ranged_iter(max in 1..1000) {
             let range = 0..max;
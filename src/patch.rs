@@ -0,0 +1,543 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Owned descriptions of the differences between two collections.
+//!
+//! The `diff` methods on the collections borrow from both sides, so
+//! what they return can't be stored or sent anywhere. Collecting a diff
+//! into a [`Patch`][Patch] takes a copy of each changed key and value,
+//! including the old ones, so the patch can be kept around, sent to
+//! another copy of the collection, applied with the collection's
+//! `apply_patch` method, or [inverted][Patch::invert] to undo it. With
+//! the `serde` feature, patches can be serialised.
+//!
+//! Maps use a `Patch<K, V>`, and sets a `Patch<A, ()>`. A
+//! [`VectorPatch`][VectorPatch] describes the changes to a vector by
+//! index instead, as a list of [`Edit`][Edit]s.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[macro_use] extern crate im;
+//! # use im::patch::Patch;
+//! let old = ordmap!{1 => "one", 2 => "two", 3 => "three"};
+//! let new = old.update(2, "deux").without(&3);
+//! let patch: Patch<_, _> = old.diff(&new).collect();
+//!
+//! let mut map = old.clone();
+//! map.apply_patch(&patch);
+//! assert_eq!(new, map);
+//!
+//! map.apply_patch(&patch.invert());
+//! assert_eq!(old, map);
+//! ```
+//!
+//! [Patch]: ./struct.Patch.html
+//! [Patch::invert]: ./struct.Patch.html#method.invert
+//! [VectorPatch]: ./struct.VectorPatch.html
+//! [Edit]: ./struct.Edit.html
+
+use std::iter::FromIterator;
+
+use crate::hashset::DiffItem as HashSetDiffItem;
+use crate::ordmap::DiffItem as MapDiffItem;
+use crate::ordset::DiffItem as OrdSetDiffItem;
+use crate::vector::DiffItem as VectorDiffItem;
+
+/// A change to the value for a single key.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Change<V> {
+    /// The key was added with this value.
+    Add(V),
+    /// The key's value was changed.
+    Update {
+        /// The old value.
+        old: V,
+        /// The new value.
+        new: V,
+    },
+    /// The key was removed, and had this value.
+    Remove(V),
+}
+
+impl<V> Change<V> {
+    /// Get the value before the change, if there was one.
+    #[must_use]
+    pub fn old_value(&self) -> Option<&V> {
+        match self {
+            Change::Add(_) => None,
+            Change::Update { old, .. } => Some(old),
+            Change::Remove(old) => Some(old),
+        }
+    }
+
+    /// Get the value after the change, if there is one.
+    #[must_use]
+    pub fn new_value(&self) -> Option<&V> {
+        match self {
+            Change::Add(new) => Some(new),
+            Change::Update { new, .. } => Some(new),
+            Change::Remove(_) => None,
+        }
+    }
+
+    /// Construct the change which undoes this one.
+    #[must_use]
+    pub fn invert(self) -> Self {
+        match self {
+            Change::Add(value) => Change::Remove(value),
+            Change::Update { old, new } => Change::Update { old: new, new: old },
+            Change::Remove(value) => Change::Add(value),
+        }
+    }
+}
+
+/// A list of changes to the keys of a map or set.
+///
+/// Each key appears at most once. A patch collected from the `diff` of
+/// an [`OrdMap`][OrdMap] or [`OrdSet`][OrdSet] is sorted by key, which
+/// lets `apply_patch` apply it to an ordered collection without sorting
+/// it first.
+///
+/// Sets use `()` for their values. An [`OrdSet`][OrdSet] can hold a
+/// value which is equal to another under `Ord` but not under
+/// `PartialEq`, and reports replacing one with the other as an update;
+/// its patch keeps only the new value, so inverting it doesn't bring
+/// back the old one.
+///
+/// [OrdMap]: ../ordmap/struct.OrdMap.html
+/// [OrdSet]: ../ordset/struct.OrdSet.html
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Patch<K, V> {
+    changes: Vec<(K, Change<V>)>,
+}
+
+impl<K, V> Patch<K, V> {
+    /// Construct an empty patch.
+    #[must_use]
+    pub fn new() -> Self {
+        Patch {
+            changes: Vec::new(),
+        }
+    }
+
+    /// Get the number of changes in the patch.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    /// Test whether the patch changes nothing.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Get the changes in the patch.
+    #[must_use]
+    pub fn changes(&self) -> &[(K, Change<V>)] {
+        &self.changes
+    }
+
+    /// Take the changes out of the patch.
+    #[must_use]
+    pub fn into_changes(self) -> Vec<(K, Change<V>)> {
+        self.changes
+    }
+
+    /// Construct the patch which undoes this one.
+    #[must_use]
+    pub fn invert(self) -> Self {
+        self.changes
+            .into_iter()
+            .map(|(key, change)| (key, change.invert()))
+            .collect()
+    }
+}
+
+impl<K, V> Default for Patch<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> FromIterator<(K, Change<V>)> for Patch<K, V> {
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = (K, Change<V>)>,
+    {
+        Patch {
+            changes: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl<'a, K: Clone, V: Clone> FromIterator<MapDiffItem<'a, K, V>> for Patch<K, V> {
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = MapDiffItem<'a, K, V>>,
+    {
+        iter.into_iter()
+            .map(|item| match item {
+                MapDiffItem::Add(key, value) => (key.clone(), Change::Add(value.clone())),
+                MapDiffItem::Update { old, new } => (
+                    new.0.clone(),
+                    Change::Update {
+                        old: old.1.clone(),
+                        new: new.1.clone(),
+                    },
+                ),
+                MapDiffItem::Remove(key, value) => (key.clone(), Change::Remove(value.clone())),
+            })
+            .collect()
+    }
+}
+
+impl<'a, A: Clone> FromIterator<OrdSetDiffItem<'a, A>> for Patch<A, ()> {
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = OrdSetDiffItem<'a, A>>,
+    {
+        iter.into_iter()
+            .map(|item| match item {
+                OrdSetDiffItem::Add(value) => (value.clone(), Change::Add(())),
+                OrdSetDiffItem::Update { new, .. } => {
+                    (new.clone(), Change::Update { old: (), new: () })
+                }
+                OrdSetDiffItem::Remove(value) => (value.clone(), Change::Remove(())),
+            })
+            .collect()
+    }
+}
+
+impl<'a, A: Clone> FromIterator<HashSetDiffItem<'a, A>> for Patch<A, ()> {
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = HashSetDiffItem<'a, A>>,
+    {
+        iter.into_iter()
+            .map(|item| match item {
+                HashSetDiffItem::Add(value) => (value.clone(), Change::Add(())),
+                HashSetDiffItem::Remove(value) => (value.clone(), Change::Remove(())),
+            })
+            .collect()
+    }
+}
+
+/// A change to a run of values in a vector.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Edit<A> {
+    /// The index of the first value changed, counted in the vector before
+    /// any of the patch's edits.
+    pub index: usize,
+    /// The values removed from that index.
+    pub removed: Vec<A>,
+    /// The values inserted at that index in their place.
+    pub inserted: Vec<A>,
+}
+
+/// A list of changes to a vector, by index.
+///
+/// The edits are sorted by index and don't overlap, and each edit's index
+/// is a position in the vector before the patch, so a patch reads the
+/// same way as a diff of the two vectors would.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct VectorPatch<A> {
+    edits: Vec<Edit<A>>,
+}
+
+impl<A> VectorPatch<A> {
+    /// Construct an empty patch.
+    #[must_use]
+    pub fn new() -> Self {
+        VectorPatch { edits: Vec::new() }
+    }
+
+    /// Get the number of edits in the patch.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.edits.len()
+    }
+
+    /// Test whether the patch changes nothing.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    /// Get the edits in the patch.
+    #[must_use]
+    pub fn edits(&self) -> &[Edit<A>] {
+        &self.edits
+    }
+
+    /// Take the edits out of the patch.
+    #[must_use]
+    pub fn into_edits(self) -> Vec<Edit<A>> {
+        self.edits
+    }
+
+    /// Construct the patch which undoes this one.
+    ///
+    /// Each edit swaps its removed and inserted values, and its index
+    /// moves by however many values the edits before it inserted or
+    /// removed.
+    #[must_use]
+    pub fn invert(self) -> Self {
+        let mut shift = 0isize;
+        self.edits
+            .into_iter()
+            .map(|edit| {
+                let index = (edit.index as isize + shift) as usize;
+                shift += edit.inserted.len() as isize - edit.removed.len() as isize;
+                Edit {
+                    index,
+                    removed: edit.inserted,
+                    inserted: edit.removed,
+                }
+            })
+            .collect()
+    }
+}
+
+impl<A> Default for VectorPatch<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A> FromIterator<Edit<A>> for VectorPatch<A> {
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = Edit<A>>,
+    {
+        VectorPatch {
+            edits: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl<'a, A: Clone> From<VectorDiffItem<'a, A>> for Edit<A> {
+    fn from(item: VectorDiffItem<'a, A>) -> Self {
+        let owned = |values: Vec<&A>| values.into_iter().cloned().collect();
+        match item {
+            VectorDiffItem::Insert { index, values } => Edit {
+                index,
+                removed: Vec::new(),
+                inserted: owned(values),
+            },
+            VectorDiffItem::Delete { index, values } => Edit {
+                index,
+                removed: owned(values),
+                inserted: Vec::new(),
+            },
+            VectorDiffItem::Replace { index, old, new } => Edit {
+                index,
+                removed: owned(old),
+                inserted: owned(new),
+            },
+        }
+    }
+}
+
+impl<'a, A: Clone> FromIterator<VectorDiffItem<'a, A>> for VectorPatch<A> {
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = VectorDiffItem<'a, A>>,
+    {
+        iter.into_iter().map(Edit::from).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory::MemoryReport;
+    use crate::proptest::{hash_map, hash_set, ord_map, ord_set, vector};
+    use crate::{HashMap, HashSet, OrdMap};
+    use ::proptest::collection;
+    use ::proptest::num::{i16, usize};
+    use ::proptest::proptest;
+
+    // Turn arbitrary gaps and lengths into sorted edits which fit inside a
+    // vector of `values`.
+    fn edits(values: &[i16], raw: Vec<(usize, usize, Vec<i16>)>) -> Vec<Edit<i16>> {
+        let mut cursor = 0;
+        raw.into_iter()
+            .map(|(gap, remove, inserted)| {
+                let index = (cursor + gap).min(values.len());
+                let remove = remove.min(values.len() - index);
+                cursor = index + remove;
+                Edit {
+                    index,
+                    removed: values[index..cursor].to_vec(),
+                    inserted,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unsorted_patch_applies_last_change() {
+        let patch: Patch<_, _> = vec![
+            (3, Change::Add("c")),
+            (1, Change::Add("a")),
+            (3, Change::Remove("c")),
+            (2, Change::Add("b")),
+        ]
+        .into_iter()
+        .collect();
+        let mut map = ordmap! {3 => "c"};
+        map.apply_patch(&patch);
+        assert_eq!(ordmap! {1 => "a", 2 => "b"}, map);
+        let mut map = hashmap! {3 => "c"};
+        map.apply_patch(&patch);
+        assert_eq!(hashmap! {1 => "a", 2 => "b"}, map);
+    }
+
+    #[test]
+    fn vector_patch_inverts() {
+        let patch: VectorPatch<_> = vec![
+            Edit {
+                index: 0,
+                removed: vec![1],
+                inserted: vec![],
+            },
+            Edit {
+                index: 2,
+                removed: vec![],
+                inserted: vec![7, 8],
+            },
+        ]
+        .into_iter()
+        .collect();
+        let mut vec = vector![1, 2, 3];
+        vec.apply_patch(&patch);
+        assert_eq!(vector![2, 7, 8, 3], vec);
+        vec.apply_patch(&patch.invert());
+        assert_eq!(vector![1, 2, 3], vec);
+    }
+
+    #[test]
+    #[should_panic]
+    fn vector_patch_rejects_overlapping_edits() {
+        let patch: VectorPatch<_> = vec![
+            Edit {
+                index: 1,
+                removed: vec![2, 3],
+                inserted: vec![],
+            },
+            Edit {
+                index: 2,
+                removed: vec![],
+                inserted: vec![7],
+            },
+        ]
+        .into_iter()
+        .collect();
+        vector![1, 2, 3].apply_patch(&patch);
+    }
+
+    proptest! {
+        #[test]
+        fn ordmap_patch(
+            ref old in ord_map(i16::ANY, i16::ANY, 0..1000),
+            ref new in ord_map(i16::ANY, i16::ANY, 0..1000)
+        ) {
+            let patch: Patch<_, _> = old.diff(new).collect();
+            let mut map = old.clone();
+            map.apply_patch(&patch);
+            assert_eq!(Ok(()), map.check_invariants());
+            assert_eq!(new, &map);
+            map.apply_patch(&patch.invert());
+            assert_eq!(Ok(()), map.check_invariants());
+            assert_eq!(old, &map);
+        }
+
+        #[test]
+        fn ordmap_patch_shares_unchanged_nodes(
+            ref edits in collection::vec((0..20_000u16, i16::ANY), 0..20)
+        ) {
+            let old: OrdMap<u16, i16> = (0..20_000u16).map(|key| (key, 0i16)).collect();
+            let mut new = old.clone();
+            for (key, value) in edits {
+                new.insert(*key, *value);
+            }
+            let patch: Patch<_, _> = old.diff(&new).collect();
+            let mut map = old.clone();
+            map.apply_patch(&patch);
+            assert_eq!(new, map);
+            let mut report = MemoryReport::new();
+            report.add(&old);
+            let before = report.total_nodes();
+            report.add(&map);
+            assert!((report.total_nodes() - before) * 3 < before);
+        }
+
+        #[test]
+        fn ordset_patch(
+            ref old in ord_set(i16::ANY, 0..1000),
+            ref new in ord_set(i16::ANY, 0..1000)
+        ) {
+            let patch: Patch<_, _> = old.diff(new).collect();
+            let mut set = old.clone();
+            set.apply_patch(&patch);
+            assert_eq!(Ok(()), set.check_invariants());
+            assert_eq!(new, &set);
+            set.apply_patch(&patch.invert());
+            assert_eq!(old, &set);
+        }
+
+        #[test]
+        fn hashmap_patch(
+            ref old in hash_map(i16::ANY, i16::ANY, 0..1000),
+            ref new in hash_map(i16::ANY, i16::ANY, 0..1000)
+        ) {
+            let patch: Patch<_, _> = old.diff(new).collect();
+            let mut map: HashMap<i16, i16> = old.clone();
+            map.apply_patch(&patch);
+            assert_eq!(new, &map);
+            map.apply_patch(&patch.invert());
+            assert_eq!(old, &map);
+        }
+
+        #[test]
+        fn hashset_patch(
+            ref old in hash_set(i16::ANY, 0..1000),
+            ref new in hash_set(i16::ANY, 0..1000)
+        ) {
+            let patch: Patch<_, _> = old.diff(new).collect();
+            let mut set: HashSet<i16> = old.clone();
+            set.apply_patch(&patch);
+            assert_eq!(new, &set);
+            set.apply_patch(&patch.invert());
+            assert_eq!(old, &set);
+        }
+
+        #[test]
+        fn vector_patch(
+            ref old in vector(i16::ANY, 0..2000),
+            ref raw in collection::vec(
+                (0..300usize, 0..50usize, collection::vec(i16::ANY, 0..50)),
+                0..20
+            )
+        ) {
+            let values: Vec<i16> = old.iter().cloned().collect();
+            let edits = edits(&values, raw.clone());
+            let mut expected = values.clone();
+            for edit in edits.iter().rev() {
+                expected.splice(
+                    edit.index..edit.index + edit.removed.len(),
+                    edit.inserted.iter().cloned(),
+                );
+            }
+            let patch: VectorPatch<_> = edits.into_iter().collect();
+            let mut vec = old.clone();
+            vec.apply_patch(&patch);
+            assert_eq!(expected, vec.iter().cloned().collect::<Vec<_>>());
+            vec.apply_patch(&patch.invert());
+            assert_eq!(old, &vec);
+        }
+    }
+}
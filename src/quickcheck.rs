@@ -1,43 +1,60 @@
+use crate::config::{HashLevelSize, OrdChunkSize, VectorChunkSize};
+use crate::hashset::Value as HashValue;
+use crate::ordset::Value as OrdValue;
 use crate::{HashMap, HashSet, OrdMap, OrdSet, Vector};
 use ::quickcheck::{Arbitrary, Gen};
 use std::hash::{BuildHasher, Hash};
-use std::iter::FromIterator;
 
-impl<A: Arbitrary + Sync + Clone> Arbitrary for Vector<A> {
+impl<A, N> Arbitrary for Vector<A, N>
+where
+    A: Arbitrary + Sync + Clone,
+    N: VectorChunkSize<A> + 'static,
+{
     fn arbitrary(g: &mut Gen) -> Self {
-        Vector::from_iter(Vec::<A>::arbitrary(g))
+        Self::from_iter_with_node_size(Vec::<A>::arbitrary(g))
     }
 }
 
-impl<K: Ord + Clone + Arbitrary + Sync, V: Clone + Arbitrary + Sync> Arbitrary for OrdMap<K, V> {
+impl<K, V, N> Arbitrary for OrdMap<K, V, N>
+where
+    K: Ord + Clone + Arbitrary + Sync,
+    V: Clone + Arbitrary + Sync,
+    N: OrdChunkSize<(K, V)> + 'static,
+{
     fn arbitrary(g: &mut Gen) -> Self {
-        OrdMap::from_iter(Vec::<(K, V)>::arbitrary(g))
+        Self::from_iter_with_node_size(Vec::<(K, V)>::arbitrary(g))
     }
 }
 
-impl<A: Ord + Clone + Arbitrary + Sync> Arbitrary for OrdSet<A> {
+impl<A, N> Arbitrary for OrdSet<A, N>
+where
+    A: Ord + Clone + Arbitrary + Sync,
+    N: OrdChunkSize<OrdValue<A>> + 'static,
+{
     fn arbitrary(g: &mut Gen) -> Self {
-        OrdSet::from_iter(Vec::<A>::arbitrary(g))
+        Self::from_iter_with_node_size(Vec::<A>::arbitrary(g))
     }
 }
 
-impl<A, S> Arbitrary for HashSet<A, S>
+impl<A, S, N> Arbitrary for HashSet<A, S, N>
 where
     A: Hash + Eq + Arbitrary + Sync,
     S: BuildHasher + Default + Send + Sync + 'static,
+    N: HashLevelSize<HashValue<A>> + 'static,
 {
     fn arbitrary(g: &mut Gen) -> Self {
-        HashSet::from_iter(Vec::<A>::arbitrary(g))
+        Self::from_iter_with_node_size(Vec::<A>::arbitrary(g))
     }
 }
 
-impl<K, V, S> Arbitrary for HashMap<K, V, S>
+impl<K, V, S, N> Arbitrary for HashMap<K, V, S, N>
 where
     K: Hash + Eq + Arbitrary + Sync,
     V: Arbitrary + Sync,
     S: BuildHasher + Default + Send + Sync + 'static,
+    N: HashLevelSize<(K, V)> + 'static,
 {
     fn arbitrary(g: &mut Gen) -> Self {
-        HashMap::from(Vec::<(K, V)>::arbitrary(g))
+        Self::from_iter_with_node_size(Vec::<(K, V)>::arbitrary(g))
     }
 }
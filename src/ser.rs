@@ -2,22 +2,28 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use serde::de::{Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::de::{
+    Deserialize, Deserializer, EnumAccess, Error as DeError, MapAccess, SeqAccess, VariantAccess,
+    Visitor,
+};
 use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
 use std::fmt;
 use std::hash::{BuildHasher, Hash};
+use std::iter::FromIterator;
 use std::marker::PhantomData;
 use std::ops::Deref;
 
+use crate::config::{HashLevelSize, OrdChunkSize, VectorChunkSize};
 use crate::hashmap::HashMap;
-use crate::hashset::HashSet;
+use crate::hashset::{HashSet, Value as HashValue};
 use crate::ordmap::OrdMap;
-use crate::ordset::OrdSet;
+use crate::ordset::{OrdSet, Value as OrdValue};
+use crate::patch::{Change, Edit, Patch, VectorPatch};
 use crate::vector::Vector;
 
 struct SeqVisitor<'de, S, A>
 where
-    S: From<Vec<A>>,
+    S: FromIterator<A>,
     A: Deserialize<'de>,
 {
     phantom_s: PhantomData<S>,
@@ -27,7 +33,7 @@ where
 
 impl<'de, S, A> SeqVisitor<'de, S, A>
 where
-    S: From<Vec<A>>,
+    S: FromIterator<A>,
     A: Deserialize<'de>,
 {
     pub(crate) fn new() -> SeqVisitor<'de, S, A> {
@@ -41,7 +47,7 @@ where
 
 impl<'de, S, A> Visitor<'de> for SeqVisitor<'de, S, A>
 where
-    S: From<Vec<A>>,
+    S: FromIterator<A>,
     A: Deserialize<'de>,
 {
     type Value = S;
@@ -61,13 +67,13 @@ where
         while let Some(i) = access.next_element()? {
             v.push(i)
         }
-        Ok(From::from(v))
+        Ok(v.into_iter().collect())
     }
 }
 
 struct MapVisitor<'de, S, K, V>
 where
-    S: From<Vec<(K, V)>>,
+    S: FromIterator<(K, V)>,
     K: Deserialize<'de>,
     V: Deserialize<'de>,
 {
@@ -79,7 +85,7 @@ where
 
 impl<'de, S, K, V> MapVisitor<'de, S, K, V>
 where
-    S: From<Vec<(K, V)>>,
+    S: FromIterator<(K, V)>,
     K: Deserialize<'de>,
     V: Deserialize<'de>,
 {
@@ -95,7 +101,7 @@ where
 
 impl<'de, S, K, V> Visitor<'de> for MapVisitor<'de, S, K, V>
 where
-    S: From<Vec<(K, V)>>,
+    S: FromIterator<(K, V)>,
     K: Deserialize<'de>,
     V: Deserialize<'de>,
 {
@@ -116,22 +122,31 @@ where
         while let Some(i) = access.next_entry()? {
             v.push(i)
         }
-        Ok(From::from(v))
+        Ok(v.into_iter().collect())
     }
 }
 
 // Set
 
-impl<'de, A: Deserialize<'de> + Ord + Clone> Deserialize<'de> for OrdSet<A> {
+impl<'de, A, N> Deserialize<'de> for OrdSet<A, N>
+where
+    A: Deserialize<'de> + Ord + Clone,
+    N: OrdChunkSize<OrdValue<A>>,
+{
     fn deserialize<D>(des: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
-        des.deserialize_seq(SeqVisitor::new())
+        des.deserialize_seq(SeqVisitor::<'de, Vec<A>, A>::new())
+            .map(Self::from_iter_with_node_size)
     }
 }
 
-impl<A: Ord + Clone + Serialize> Serialize for OrdSet<A> {
+impl<A, N> Serialize for OrdSet<A, N>
+where
+    A: Ord + Clone + Serialize,
+    N: OrdChunkSize<OrdValue<A>>,
+{
     fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
@@ -146,18 +161,27 @@ impl<A: Ord + Clone + Serialize> Serialize for OrdSet<A> {
 
 // Map
 
-impl<'de, K: Deserialize<'de> + Ord + Clone, V: Deserialize<'de> + Clone> Deserialize<'de>
-    for OrdMap<K, V>
+impl<'de, K, V, N> Deserialize<'de> for OrdMap<K, V, N>
+where
+    K: Deserialize<'de> + Ord + Clone,
+    V: Deserialize<'de> + Clone,
+    N: OrdChunkSize<(K, V)>,
 {
     fn deserialize<D>(des: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
-        des.deserialize_map(MapVisitor::<'de, OrdMap<K, V>, K, V>::new())
+        des.deserialize_map(MapVisitor::<'de, Vec<(K, V)>, K, V>::new())
+            .map(Self::from_iter_with_node_size)
     }
 }
 
-impl<K: Serialize + Ord + Clone, V: Serialize + Clone> Serialize for OrdMap<K, V> {
+impl<K, V, N> Serialize for OrdMap<K, V, N>
+where
+    K: Serialize + Ord + Clone,
+    V: Serialize + Clone,
+    N: OrdChunkSize<(K, V)>,
+{
     fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
@@ -172,25 +196,28 @@ impl<K: Serialize + Ord + Clone, V: Serialize + Clone> Serialize for OrdMap<K, V
 
 // HashMap
 
-impl<'de, K, V, S> Deserialize<'de> for HashMap<K, V, S>
+impl<'de, K, V, S, N> Deserialize<'de> for HashMap<K, V, S, N>
 where
     K: Deserialize<'de> + Hash + Eq + Clone,
     V: Deserialize<'de> + Clone,
     S: BuildHasher + Default,
+    N: HashLevelSize<(K, V)>,
 {
     fn deserialize<D>(des: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
-        des.deserialize_map(MapVisitor::<'de, HashMap<K, V, S>, K, V>::new())
+        des.deserialize_map(MapVisitor::<'de, Vec<(K, V)>, K, V>::new())
+            .map(Self::from_iter_with_node_size)
     }
 }
 
-impl<K, V, S> Serialize for HashMap<K, V, S>
+impl<K, V, S, N> Serialize for HashMap<K, V, S, N>
 where
     K: Serialize + Hash + Eq + Clone,
     V: Serialize + Clone,
     S: BuildHasher + Default,
+    N: HashLevelSize<(K, V)>,
 {
     fn serialize<Ser>(&self, ser: Ser) -> Result<Ser::Ok, Ser::Error>
     where
@@ -206,18 +233,27 @@ where
 
 // HashSet
 
-impl<'de, A: Deserialize<'de> + Hash + Eq + Clone, S: BuildHasher + Default> Deserialize<'de>
-    for HashSet<A, S>
+impl<'de, A, S, N> Deserialize<'de> for HashSet<A, S, N>
+where
+    A: Deserialize<'de> + Hash + Eq + Clone,
+    S: BuildHasher + Default,
+    N: HashLevelSize<HashValue<A>>,
 {
     fn deserialize<D>(des: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
-        des.deserialize_seq(SeqVisitor::new())
+        des.deserialize_seq(SeqVisitor::<'de, Vec<A>, A>::new())
+            .map(Self::from_iter_with_node_size)
     }
 }
 
-impl<A: Serialize + Hash + Eq + Clone, S: BuildHasher + Default> Serialize for HashSet<A, S> {
+impl<A, S, N> Serialize for HashSet<A, S, N>
+where
+    A: Serialize + Hash + Eq + Clone,
+    S: BuildHasher + Default,
+    N: HashLevelSize<HashValue<A>>,
+{
     fn serialize<Ser>(&self, ser: Ser) -> Result<Ser::Ok, Ser::Error>
     where
         Ser: Serializer,
@@ -232,16 +268,25 @@ impl<A: Serialize + Hash + Eq + Clone, S: BuildHasher + Default> Serialize for H
 
 // Vector
 
-impl<'de, A: Clone + Deserialize<'de>> Deserialize<'de> for Vector<A> {
+impl<'de, A, N> Deserialize<'de> for Vector<A, N>
+where
+    A: Clone + Deserialize<'de>,
+    N: VectorChunkSize<A>,
+{
     fn deserialize<D>(des: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
-        des.deserialize_seq(SeqVisitor::<'de, Vector<A>, A>::new())
+        des.deserialize_seq(SeqVisitor::<'de, Vec<A>, A>::new())
+            .map(Self::from_iter_with_node_size)
     }
 }
 
-impl<A: Clone + Serialize> Serialize for Vector<A> {
+impl<A, N> Serialize for Vector<A, N>
+where
+    A: Clone + Serialize,
+    N: VectorChunkSize<A>,
+{
     fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
@@ -254,6 +299,210 @@ impl<A: Clone + Serialize> Serialize for Vector<A> {
     }
 }
 
+// Patch
+
+const CHANGE_VARIANTS: &[&str] = &["Add", "Update", "Remove"];
+
+enum ChangeTag {
+    Add,
+    Update,
+    Remove,
+}
+
+struct ChangeTagVisitor;
+
+impl<'de> Visitor<'de> for ChangeTagVisitor {
+    type Value = ChangeTag;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("a change variant")
+    }
+
+    fn visit_u64<E: DeError>(self, value: u64) -> Result<Self::Value, E> {
+        match value {
+            0 => Ok(ChangeTag::Add),
+            1 => Ok(ChangeTag::Update),
+            2 => Ok(ChangeTag::Remove),
+            _ => Err(E::invalid_value(
+                serde::de::Unexpected::Unsigned(value),
+                &"a variant index 0 <= i < 3",
+            )),
+        }
+    }
+
+    fn visit_str<E: DeError>(self, value: &str) -> Result<Self::Value, E> {
+        match value {
+            "Add" => Ok(ChangeTag::Add),
+            "Update" => Ok(ChangeTag::Update),
+            "Remove" => Ok(ChangeTag::Remove),
+            _ => Err(E::unknown_variant(value, CHANGE_VARIANTS)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ChangeTag {
+    fn deserialize<D>(des: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        des.deserialize_identifier(ChangeTagVisitor)
+    }
+}
+
+struct ChangeVisitor<V> {
+    phantom_v: PhantomData<V>,
+}
+
+impl<'de, V> Visitor<'de> for ChangeVisitor<V>
+where
+    V: Deserialize<'de>,
+{
+    type Value = Change<V>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("a change")
+    }
+
+    fn visit_enum<Access>(self, access: Access) -> Result<Self::Value, Access::Error>
+    where
+        Access: EnumAccess<'de>,
+    {
+        let (tag, variant) = access.variant()?;
+        match tag {
+            ChangeTag::Add => variant.newtype_variant().map(Change::Add),
+            ChangeTag::Update => variant
+                .newtype_variant()
+                .map(|(old, new)| Change::Update { old, new }),
+            ChangeTag::Remove => variant.newtype_variant().map(Change::Remove),
+        }
+    }
+}
+
+impl<'de, V> Deserialize<'de> for Change<V>
+where
+    V: Deserialize<'de>,
+{
+    fn deserialize<D>(des: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        des.deserialize_enum(
+            "Change",
+            CHANGE_VARIANTS,
+            ChangeVisitor {
+                phantom_v: PhantomData,
+            },
+        )
+    }
+}
+
+impl<V> Serialize for Change<V>
+where
+    V: Serialize,
+{
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Change::Add(new) => ser.serialize_newtype_variant("Change", 0, "Add", new),
+            Change::Update { old, new } => {
+                ser.serialize_newtype_variant("Change", 1, "Update", &(old, new))
+            }
+            Change::Remove(old) => ser.serialize_newtype_variant("Change", 2, "Remove", old),
+        }
+    }
+}
+
+impl<'de, K, V> Deserialize<'de> for Patch<K, V>
+where
+    K: Deserialize<'de>,
+    V: Deserialize<'de>,
+{
+    fn deserialize<D>(des: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        des.deserialize_seq(SeqVisitor::<'de, Patch<K, V>, (K, Change<V>)>::new())
+    }
+}
+
+impl<K, V> Serialize for Patch<K, V>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut s = ser.serialize_seq(Some(self.len()))?;
+        for i in self.changes() {
+            s.serialize_element(i)?;
+        }
+        s.end()
+    }
+}
+
+// Vector patch
+
+impl<'de, A> Deserialize<'de> for Edit<A>
+where
+    A: Deserialize<'de>,
+{
+    fn deserialize<D>(des: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let (index, removed, inserted) = Deserialize::deserialize(des)?;
+        Ok(Edit {
+            index,
+            removed,
+            inserted,
+        })
+    }
+}
+
+impl<A> Serialize for Edit<A>
+where
+    A: Serialize,
+{
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        (self.index, &self.removed, &self.inserted).serialize(ser)
+    }
+}
+
+impl<'de, A> Deserialize<'de> for VectorPatch<A>
+where
+    A: Deserialize<'de>,
+{
+    fn deserialize<D>(des: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        des.deserialize_seq(SeqVisitor::<'de, VectorPatch<A>, Edit<A>>::new())
+    }
+}
+
+impl<A> Serialize for VectorPatch<A>
+where
+    A: Serialize,
+{
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut s = ser.serialize_seq(Some(self.len()))?;
+        for i in self.edits() {
+            s.serialize_element(i)?;
+        }
+        s.end()
+    }
+}
+
 // Tests
 
 #[cfg(test)]
@@ -264,6 +513,51 @@ mod test {
     use ::proptest::proptest;
     use serde_json::{from_str, to_string};
 
+    #[test]
+    fn ser_vector_patch() {
+        let patch: VectorPatch<i32> = vec![
+            Edit {
+                index: 1,
+                removed: vec![2],
+                inserted: vec![7, 8],
+            },
+            Edit {
+                index: 4,
+                removed: vec![5, 6],
+                inserted: vec![],
+            },
+        ]
+        .into_iter()
+        .collect();
+        let json = to_string(&patch).unwrap();
+        assert_eq!("[[1,[2],[7,8]],[4,[5,6],[]]]", json);
+        assert_eq!(patch, from_str::<VectorPatch<i32>>(&json).unwrap());
+    }
+
+    #[test]
+    fn ser_change() {
+        let patch: Patch<i32, &str> = vec![
+            (1, Change::Add("one")),
+            (
+                2,
+                Change::Update {
+                    old: "two",
+                    new: "deux",
+                },
+            ),
+            (3, Change::Remove("three")),
+        ]
+        .into_iter()
+        .collect();
+        let json = to_string(&patch).unwrap();
+        assert_eq!(
+            r#"[[1,{"Add":"one"}],[2,{"Update":["two","deux"]}],[3,{"Remove":"three"}]]"#,
+            json
+        );
+        assert_eq!(patch, from_str::<Patch<i32, &str>>(&json).unwrap());
+        assert!(from_str::<Patch<i32, &str>>(r#"[[1,{"Replace":"one"}]]"#).is_err());
+    }
+
     proptest! {
         #[test]
         fn ser_ordset(ref v in ord_set(i32::ANY, 0..100)) {
@@ -289,5 +583,20 @@ mod test {
         fn ser_vector(ref v in vector(i32::ANY, 0..100)) {
             assert_eq!(v, &from_str::<Vector<i32>>(&to_string(&v).unwrap()).unwrap());
         }
+
+        #[test]
+        fn ser_patch(
+            ref a in ord_map(i32::ANY, i32::ANY, 0..100),
+            ref b in ord_map(i32::ANY, i32::ANY, 0..100)
+        ) {
+            let patch: Patch<i32, i32> = a.diff(b).collect();
+            assert_eq!(patch, from_str::<Patch<i32, i32>>(&to_string(&patch).unwrap()).unwrap());
+        }
+
+        #[test]
+        fn ser_set_patch(ref a in ord_set(i32::ANY, 0..100), ref b in ord_set(i32::ANY, 0..100)) {
+            let patch: Patch<i32, ()> = a.diff(b).collect();
+            assert_eq!(patch, from_str::<Patch<i32, ()>>(&to_string(&patch).unwrap()).unwrap());
+        }
     }
 }
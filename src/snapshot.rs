@@ -0,0 +1,732 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Serialising many versions of a collection at once.
+//!
+//! Serialising a collection writes out every value in it, so writing
+//! out a hundred snapshots of a collection which only differ by a few
+//! values each writes out a hundred copies of it. A
+//! [`Snapshots`][Snapshots] holds a list of versions of a collection and
+//! serialises them together, writing each node they share only once.
+//! Deserialising it puts the nodes back together, so the versions share
+//! their memory again the way they did when they were written.
+//!
+//! This works for [`Vector`][Vector], [`OrdMap`][OrdMap] and
+//! [`HashMap`][HashMap]:
+//!
+//! * A `Vector` or an `OrdMap` is written as a table of its nodes,
+//!   children before their parents, each referring to its children by
+//!   their position in the table, followed by the root of each version.
+//!   Nodes are identified by their address, so any node reachable from
+//!   more than one version is written once.
+//! * Where a node goes in a `HashMap` depends on its hasher, which
+//!   usually can't be written out, so its nodes can't be put back where
+//!   they were. Instead, the first version is written in full, and each
+//!   following version as the entries in nodes it doesn't share with the
+//!   version before it, plus the keys it no longer has. Reading replays
+//!   these changes on top of a clone of the previous version, which
+//!   shares its nodes the same way.
+//!
+//! Reading checks that the nodes fit together into valid trees, so a
+//! corrupt or hostile input gives an error rather than a broken
+//! collection.
+//!
+//! # Examples
+//!
+//! ```
+//! # use im::snapshot::Snapshots;
+//! # use im::memory::MemoryReport;
+//! # use im::OrdMap;
+//! let mut map: OrdMap<i32, i32> = (0..10_000).map(|i| (i, i)).collect();
+//! let mut snapshots = Snapshots::new();
+//! for i in 0..10 {
+//!     map.insert(i * 1000, -i);
+//!     snapshots.push(map.clone());
+//! }
+//!
+//! let json = serde_json::to_string(&snapshots).unwrap();
+//! let flat = serde_json::to_string(snapshots.versions()).unwrap();
+//! assert!(json.len() * 5 < flat.len());
+//!
+//! let loaded: Snapshots<OrdMap<i32, i32>> = serde_json::from_str(&json).unwrap();
+//! assert_eq!(snapshots, loaded);
+//!
+//! let mut report = MemoryReport::new();
+//! for version in loaded.versions() {
+//!     report.add(version);
+//! }
+//! assert!(report.total_bytes() < map.heap_size() * 2);
+//! ```
+//!
+//! [Snapshots]: ./struct.Snapshots.html
+//! [Vector]: ../vector/struct.Vector.html
+//! [OrdMap]: ../ordmap/struct.OrdMap.html
+//! [HashMap]: ../hashmap/struct.HashMap.html
+
+use std::collections::HashMap as StdHashMap;
+use std::hash::{BuildHasher, Hash};
+use std::iter::FromIterator;
+
+use serde::de::{Deserialize, Deserializer, Error};
+use serde::ser::{Serialize, Serializer};
+use sized_chunks::{Chunk, InlineArray};
+
+use crate::config::{HashLevelSize, OrdChunkSize, VectorChunkSize};
+use crate::hashmap::HashMap;
+use crate::memory;
+use crate::nodes::btree::Node as BTreeNode;
+use crate::nodes::rrb::{Contents, Node as RrbNode};
+use crate::ordmap::{OrdMap, OrdMapPool};
+use crate::util::{PoolRef, Ref};
+use crate::vector::{Parts, RRBPool, Rrb, Vector};
+
+/// A list of versions of a collection, serialised so that the nodes they
+/// share are only written once.
+///
+/// See the [module level documentation](./index.html) for details.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshots<C> {
+    versions: Vec<C>,
+}
+
+impl<C> Snapshots<C> {
+    /// Construct an empty list of versions.
+    #[must_use]
+    pub fn new() -> Self {
+        Snapshots {
+            versions: Vec::new(),
+        }
+    }
+
+    /// Add a version to the end of the list.
+    pub fn push(&mut self, version: C) {
+        self.versions.push(version)
+    }
+
+    /// Get the versions in the list, oldest first.
+    #[must_use]
+    pub fn versions(&self) -> &[C] {
+        &self.versions
+    }
+
+    /// Take the versions out of the list, oldest first.
+    #[must_use]
+    pub fn into_versions(self) -> Vec<C> {
+        self.versions
+    }
+}
+
+impl<C> Default for Snapshots<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C> From<Vec<C>> for Snapshots<C> {
+    fn from(versions: Vec<C>) -> Self {
+        Snapshots { versions }
+    }
+}
+
+impl<C> FromIterator<C> for Snapshots<C> {
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = C>,
+    {
+        Snapshots {
+            versions: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// Nodes numbered in the order they were added, looked up by their
+/// address.
+struct Table<T> {
+    ids: StdHashMap<usize, usize>,
+    entries: Vec<T>,
+}
+
+impl<T> Table<T> {
+    fn new() -> Self {
+        Table {
+            ids: StdHashMap::new(),
+            entries: Vec::new(),
+        }
+    }
+
+    fn get(&self, address: usize) -> Option<usize> {
+        self.ids.get(&address).cloned()
+    }
+
+    fn push(&mut self, address: usize, entry: T) -> usize {
+        let id = self.push_unshared(entry);
+        self.ids.insert(address, id);
+        id
+    }
+
+    fn push_unshared(&mut self, entry: T) -> usize {
+        self.entries.push(entry);
+        self.entries.len() - 1
+    }
+}
+
+/// Look up an entry we've already read, which a later one refers to.
+fn earlier<T, E: Error>(entries: &[T], id: usize) -> Result<&T, E> {
+    entries
+        .get(id)
+        .ok_or_else(|| E::custom(format!("reference to node {} before it's defined", id)))
+}
+
+// OrdMap
+
+/// A B-tree node: its keys and values, and the ids of its children.
+type OrdEntry<K, V> = (Vec<(K, V)>, Vec<usize>);
+
+fn write_ord_node<'a, A, N: OrdChunkSize<A>>(
+    node: &'a BTreeNode<A, N>,
+    table: &mut Table<(&'a [A], Vec<usize>)>,
+) -> usize {
+    let address = memory::address(node);
+    if let Some(id) = table.get(address) {
+        return id;
+    }
+    let children = node
+        .children()
+        .iter()
+        .flatten()
+        .map(|child| write_ord_node(child, table))
+        .collect();
+    table.push(address, (node.keys(), children))
+}
+
+impl<K, V, N> Serialize for Snapshots<OrdMap<K, V, N>>
+where
+    K: Serialize,
+    V: Serialize,
+    N: OrdChunkSize<(K, V)>,
+{
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut table = Table::new();
+        let roots: Vec<Option<usize>> = self
+            .versions
+            .iter()
+            .map(|map| map.root().map(|root| write_ord_node(root, &mut table)))
+            .collect();
+        (&table.entries, &roots).serialize(ser)
+    }
+}
+
+impl<'de, K, V, N> Deserialize<'de> for Snapshots<OrdMap<K, V, N>>
+where
+    K: Deserialize<'de> + Ord + Clone,
+    V: Deserialize<'de> + Clone,
+    N: OrdChunkSize<(K, V)>,
+{
+    fn deserialize<D>(des: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let (entries, roots): (Vec<OrdEntry<K, V>>, Vec<Option<usize>>) =
+            Deserialize::deserialize(des)?;
+        let pool = OrdMapPool::default();
+        // Each node along with the number of values below it.
+        let mut nodes: Vec<(PoolRef<BTreeNode<(K, V), N>>, usize)> =
+            Vec::with_capacity(entries.len());
+        for (keys, children) in entries {
+            let mut size = keys.len();
+            let children = children
+                .into_iter()
+                .map(|id| {
+                    let (child, child_size) = earlier(&nodes, id)?;
+                    size += child_size;
+                    Ok(child.clone())
+                })
+                .collect::<Result<_, D::Error>>()?;
+            let node = BTreeNode::from_parts(keys, children).map_err(D::Error::custom)?;
+            nodes.push((PoolRef::new(&pool.0, node), size));
+        }
+        roots
+            .into_iter()
+            .map(|root| match root {
+                None => Ok(OrdMap::from_shared_root(&pool, None, 0)),
+                Some(id) => {
+                    let (root, size) = earlier(&nodes, id)?;
+                    Ok(OrdMap::from_shared_root(&pool, Some(root.clone()), *size))
+                }
+            })
+            .collect()
+    }
+}
+
+// Vector
+
+/// A chunk of RRB tree nodes: the level of the nodes in it, and for each
+/// of them the id of the chunk it holds, a leaf if they're at level 0 and
+/// a branch otherwise, or nothing for an empty node.
+type VectorBranch = (usize, Vec<Option<usize>>);
+
+/// The root of a vector's middle tree: its level and what it holds, as
+/// for a node in a [`VectorBranch`].
+type VectorMiddle = (usize, Option<usize>);
+
+/// A version of a vector: the ids of its leaves, which are its outer
+/// front, inner front, inner back and outer back chunks if it has a
+/// middle tree, or its only chunk if it doesn't, and its middle tree.
+type VectorVersion = (Vec<usize>, Option<usize>);
+
+struct VectorWriter<'a, A, N: VectorChunkSize<A>> {
+    leaves: Table<&'a [A]>,
+    branches: Table<VectorBranch>,
+    middles: Table<VectorMiddle>,
+    phantom: std::marker::PhantomData<N>,
+}
+
+impl<'a, A: Clone, N: VectorChunkSize<A>> VectorWriter<'a, A, N> {
+    fn leaf(&mut self, chunk: &'a Chunk<A, N>) -> usize {
+        let address = memory::address(chunk);
+        match self.leaves.get(address) {
+            Some(id) => id,
+            None => self.leaves.push(address, chunk),
+        }
+    }
+
+    /// Write out what a node at `level` holds.
+    fn node(&mut self, node: &'a RrbNode<A, N>, level: usize) -> Option<usize> {
+        match node.contents() {
+            Contents::Empty => None,
+            Contents::Values(values) => Some(self.leaf(values)),
+            Contents::Nodes(children) => {
+                let address = memory::address(children);
+                if let Some(id) = self.branches.get(address) {
+                    return Some(id);
+                }
+                let ids = children
+                    .iter()
+                    .map(|child| self.node(child, level - 1))
+                    .collect();
+                Some(self.branches.push(address, (level - 1, ids)))
+            }
+        }
+    }
+
+    fn version(&mut self, vector: &'a Vector<A, N>) -> VectorVersion {
+        match vector.parts() {
+            Parts::Inline(values) => (vec![self.leaves.push_unshared(values)], None),
+            Parts::Single(chunk) => (vec![self.leaf(chunk)], None),
+            Parts::Full(level, middle, chunks) => {
+                let leaves = chunks.iter().map(|chunk| self.leaf(chunk)).collect();
+                let address = memory::address(middle);
+                let middle = match self.middles.get(address) {
+                    Some(id) => id,
+                    None => {
+                        let entry = (level, self.node(middle, level));
+                        self.middles.push(address, entry)
+                    }
+                };
+                (leaves, Some(middle))
+            }
+        }
+    }
+}
+
+impl<A, N> Serialize for Snapshots<Vector<A, N>>
+where
+    A: Serialize + Clone,
+    N: VectorChunkSize<A>,
+{
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut writer = VectorWriter {
+            leaves: Table::new(),
+            branches: Table::new(),
+            middles: Table::new(),
+            phantom: std::marker::PhantomData,
+        };
+        let versions: Vec<VectorVersion> = self
+            .versions
+            .iter()
+            .map(|vector| writer.version(vector))
+            .collect();
+        (
+            &writer.leaves.entries,
+            &writer.branches.entries,
+            &writer.middles.entries,
+            &versions,
+        )
+            .serialize(ser)
+    }
+}
+
+struct VectorReader<A, N: VectorChunkSize<A>> {
+    pool: RRBPool<A, N>,
+    leaves: Vec<Vec<A>>,
+    /// The leaves which have been made into chunks so far. A small
+    /// vector's values are stored inline rather than in a chunk, and
+    /// there might be more of them than fit in one.
+    chunks: Vec<Option<PoolRef<Chunk<A, N>>>>,
+    /// The level of the nodes in each branch, and the branch itself.
+    branches: Vec<(usize, PoolRef<Chunk<RrbNode<A, N>, N>>)>,
+    /// The level of each middle tree, and the tree itself.
+    middles: Vec<(usize, Ref<RrbNode<A, N>>)>,
+}
+
+impl<A: Clone, N: VectorChunkSize<A>> VectorReader<A, N> {
+    fn chunk<E: Error>(&mut self, id: usize) -> Result<PoolRef<Chunk<A, N>>, E> {
+        if let Some(chunk) = earlier(&self.chunks, id)? {
+            return Ok(chunk.clone());
+        }
+        let values = &self.leaves[id];
+        if values.len() > N::USIZE {
+            return Err(E::custom("leaf has too many values"));
+        }
+        let chunk = PoolRef::new(&self.pool.value_pool, values.iter().cloned().collect());
+        self.chunks[id] = Some(chunk.clone());
+        Ok(chunk)
+    }
+
+    /// Read a node at `level`.
+    fn node<E: Error>(&mut self, level: usize, id: Option<usize>) -> Result<RrbNode<A, N>, E> {
+        match id {
+            None if level == 0 => Ok(RrbNode::new()),
+            None => Err(E::custom("empty node above the bottom of the tree")),
+            Some(id) if level == 0 => {
+                let chunk = self.chunk(id)?;
+                if chunk.is_empty() {
+                    return Err(E::custom("empty leaf in the middle of the tree"));
+                }
+                Ok(RrbNode::from_values(chunk))
+            }
+            Some(id) => {
+                let (child_level, children) = earlier(&self.branches, id)?;
+                if *child_level != level - 1 {
+                    return Err(E::custom("branch is at the wrong level"));
+                }
+                Ok(RrbNode::from_nodes(&self.pool, level, children.clone()))
+            }
+        }
+    }
+
+    fn branch<E: Error>(&mut self, (level, ids): VectorBranch) -> Result<(), E> {
+        if ids.is_empty() {
+            return Err(E::custom("branch is empty"));
+        }
+        if ids.len() > N::USIZE {
+            return Err(E::custom("branch has too many nodes"));
+        }
+        let children = ids
+            .into_iter()
+            .map(|id| match id {
+                Some(id) => self.node(level, Some(id)),
+                None => Err(E::custom("branch has an empty child")),
+            })
+            .collect::<Result<Vec<_>, E>>()?;
+        let children = PoolRef::new(&self.pool.node_pool, children.into_iter().collect());
+        self.branches.push((level, children));
+        Ok(())
+    }
+
+    fn version<E: Error>(&mut self, (leaves, middle): VectorVersion) -> Result<Vector<A, N>, E> {
+        match (leaves.as_slice(), middle) {
+            (&[id], None) => {
+                let values = earlier(&self.leaves, id)?;
+                if values.len() <= InlineArray::<A, Rrb<A, N>>::CAPACITY {
+                    Ok(Vector::from_iter_with_node_size(values.iter().cloned()))
+                } else {
+                    let chunk = self.chunk(id)?;
+                    Ok(Vector::from_chunk(&self.pool, chunk))
+                }
+            }
+            (&[outer_f, inner_f, inner_b, outer_b], Some(middle)) => {
+                let (level, middle) = earlier(&self.middles, middle)?.clone();
+                let chunks = [
+                    self.chunk(outer_f)?,
+                    self.chunk(inner_f)?,
+                    self.chunk(inner_b)?,
+                    self.chunk(outer_b)?,
+                ];
+                Ok(Vector::from_tree(&self.pool, level, middle, chunks))
+            }
+            _ => Err(E::custom("vector has the wrong number of leaves")),
+        }
+    }
+}
+
+impl<'de, A, N> Deserialize<'de> for Snapshots<Vector<A, N>>
+where
+    A: Deserialize<'de> + Clone,
+    N: VectorChunkSize<A>,
+{
+    fn deserialize<D>(des: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let (leaves, branches, middles, versions): (
+            Vec<Vec<A>>,
+            Vec<VectorBranch>,
+            Vec<VectorMiddle>,
+            Vec<VectorVersion>,
+        ) = Deserialize::deserialize(des)?;
+        let mut reader = VectorReader {
+            pool: RRBPool::default(),
+            chunks: leaves.iter().map(|_| None).collect(),
+            leaves,
+            branches: Vec::with_capacity(branches.len()),
+            middles: Vec::with_capacity(middles.len()),
+        };
+        for branch in branches {
+            reader.branch(branch)?;
+        }
+        for (level, id) in middles {
+            let middle = reader.node(level, id)?;
+            reader.middles.push((level, Ref::new(middle)));
+        }
+        versions
+            .into_iter()
+            .map(|version| reader.version(version))
+            .collect()
+    }
+}
+
+// HashMap
+
+/// A version of a hash map: the keys removed since the previous version,
+/// and the entries added or changed, along with any others in the nodes
+/// which changed.
+type HashDelta<K, V> = (Vec<K>, Vec<(K, V)>);
+
+impl<K, V, S, N> Serialize for Snapshots<HashMap<K, V, S, N>>
+where
+    K: Serialize + Hash + Eq,
+    V: Serialize,
+    S: BuildHasher,
+    N: HashLevelSize<(K, V)>,
+{
+    fn serialize<Ser>(&self, ser: Ser) -> Result<Ser::Ok, Ser::Error>
+    where
+        Ser: Serializer,
+    {
+        let mut deltas: Vec<HashDelta<&K, &V>> = Vec::with_capacity(self.versions.len());
+        let mut previous = None;
+        for map in &self.versions {
+            deltas.push(match previous {
+                None => (Vec::new(), map.iter().collect()),
+                Some(previous) => {
+                    let mut removed = Vec::new();
+                    let mut changed = Vec::new();
+                    for (old, new) in HashMap::unshared(previous, map) {
+                        if let Some((key, _)) = old {
+                            if !map.contains_key(key) {
+                                removed.push(key);
+                            }
+                        }
+                        if let Some((key, value)) = new {
+                            changed.push((key, value));
+                        }
+                    }
+                    (removed, changed)
+                }
+            });
+            previous = Some(map);
+        }
+        deltas.serialize(ser)
+    }
+}
+
+impl<'de, K, V, S, N> Deserialize<'de> for Snapshots<HashMap<K, V, S, N>>
+where
+    K: Deserialize<'de> + Hash + Eq + Clone,
+    V: Deserialize<'de> + Clone,
+    S: BuildHasher + Default,
+    N: HashLevelSize<(K, V)>,
+{
+    fn deserialize<D>(des: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let deltas: Vec<HashDelta<K, V>> = Deserialize::deserialize(des)?;
+        let mut map = HashMap::with_node_size();
+        Ok(deltas
+            .into_iter()
+            .map(|(removed, changed)| {
+                for key in removed {
+                    map.remove(&key);
+                }
+                map.extend(changed);
+                map.clone()
+            })
+            .collect())
+    }
+}
+
+// Tests
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory::MemoryReport;
+    use ::proptest::collection;
+    use ::proptest::num::{i32, u16, usize};
+    use ::proptest::proptest;
+    use serde_json::{from_str, to_string};
+
+    fn report<C: crate::memory::HeapNodes>(versions: &[C]) -> usize {
+        let mut report = MemoryReport::new();
+        for version in versions {
+            report.add(version);
+        }
+        report.total_bytes()
+    }
+
+    #[test]
+    fn versions_share_nodes_once_loaded() {
+        let mut map: OrdMap<u32, u32> = (0..20_000u32).map(|i| (i, i)).collect();
+        let mut vector: Vector<u32> = (0..20_000).collect();
+        let mut hash_map: HashMap<u32, u32> = (0..20_000u32).map(|i| (i, i)).collect();
+        let mut maps = Snapshots::new();
+        let mut vectors = Snapshots::new();
+        let mut hash_maps = Snapshots::new();
+        for i in 0..20 {
+            map.insert(i * 997, 0);
+            vector.set(i as usize * 997, 0);
+            vector.push_front(i);
+            hash_map.insert(i * 997, 0);
+            maps.push(map.clone());
+            vectors.push(vector.clone());
+            hash_maps.push(hash_map.clone());
+        }
+
+        let json = to_string(&maps).unwrap();
+        assert!(json.len() * 10 < to_string(maps.versions()).unwrap().len());
+        let loaded: Snapshots<OrdMap<u32, u32>> = from_str(&json).unwrap();
+        assert_eq!(maps, loaded);
+        assert_eq!(report(maps.versions()), report(loaded.versions()));
+
+        let json = to_string(&vectors).unwrap();
+        assert!(json.len() * 10 < to_string(vectors.versions()).unwrap().len());
+        let loaded: Snapshots<Vector<u32>> = from_str(&json).unwrap();
+        assert_eq!(vectors, loaded);
+        // Size tables in the middle tree are rebuilt, and left out when they
+        // aren't needed.
+        assert!(report(loaded.versions()) <= report(vectors.versions()));
+
+        let json = to_string(&hash_maps).unwrap();
+        assert!(json.len() * 10 < to_string(hash_maps.versions()).unwrap().len());
+        let loaded: Snapshots<HashMap<u32, u32>> = from_str(&json).unwrap();
+        assert_eq!(hash_maps, loaded);
+        assert!(report(loaded.versions()) < hash_map.heap_size() * 2);
+    }
+
+    #[test]
+    fn invalid_trees_are_rejected() {
+        // Keys out of order.
+        assert!(from_str::<Snapshots<OrdMap<i32, i32>>>("[[[[[2,0],[1,0]],[]]],[0]]").is_err());
+        // A child which hasn't been defined yet.
+        assert!(from_str::<Snapshots<OrdMap<i32, i32>>>("[[[[[1,0]],[1,2]]],[0]]").is_err());
+        // A vector's middle tree holding a leaf as a branch.
+        assert!(from_str::<Snapshots<Vector<i32>>>(
+            "[[[1],[],[],[]],[[0,[0]]],[[2,0]],[[[1,1,1,1],0]]]"
+        )
+        .is_err());
+        assert!(from_str::<Snapshots<Vector<i32>>>(
+            "[[[1],[],[],[]],[[0,[0]]],[[1,0]],[[[1,1,1,1],0]]]"
+        )
+        .is_ok());
+        // An empty branch as the middle tree.
+        assert!(
+            from_str::<Snapshots<Vector<i32>>>("[[[1]],[[5,[]]],[[6,0]],[[[0,0,0,0],0]]]").is_err()
+        );
+        // Empty leaves inside a branch.
+        assert!(from_str::<Snapshots<Vector<i32>>>(
+            "[[[],[1]],[[0,[0,1,null]]],[[1,0]],[[[1,1,1,1],0]]]"
+        )
+        .is_err());
+        assert!(from_str::<Snapshots<Vector<i32>>>(
+            "[[[],[1]],[[0,[1,null]]],[[1,0]],[[[1,1,1,1],0]]]"
+        )
+        .is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn ordmap_versions_round_trip(
+            ref edits in collection::vec((u16::ANY, i32::ANY), 0..200),
+            initial in 0..2000u16,
+        ) {
+            let mut map: OrdMap<u16, i32> = (0..initial).map(|i| (i, 0)).collect();
+            let mut snapshots = Snapshots::new();
+            for (key, value) in edits {
+                if *value < 0 {
+                    map.remove(key);
+                } else {
+                    map.insert(*key, *value);
+                }
+                snapshots.push(map.clone());
+            }
+            let loaded: Snapshots<OrdMap<u16, i32>> =
+                from_str(&to_string(&snapshots).unwrap()).unwrap();
+            for version in loaded.versions() {
+                version.check_invariants().unwrap();
+            }
+            assert_eq!(snapshots, loaded);
+        }
+
+        #[test]
+        fn vector_versions_round_trip(
+            ref edits in collection::vec((usize::ANY, i32::ANY), 0..50),
+            initial in 0..2000usize,
+        ) {
+            let mut vector: Vector<i32> = (0..initial as i32).collect();
+            let mut snapshots = Snapshots::new();
+            for (index, value) in edits {
+                let index = index % (vector.len() + 1);
+                match value.rem_euclid(4) {
+                    0 => vector.insert(index, *value),
+                    1 if index < vector.len() => {
+                        vector.set(index, *value);
+                    }
+                    2 if index < vector.len() => {
+                        vector.remove(index);
+                    }
+                    _ => {
+                        let back = vector.split_off(index);
+                        vector.append(back);
+                        vector.push_front(*value);
+                    }
+                }
+                snapshots.push(vector.clone());
+            }
+            let loaded: Snapshots<Vector<i32>> =
+                from_str(&to_string(&snapshots).unwrap()).unwrap();
+            for version in loaded.versions() {
+                version.check_invariants().unwrap();
+            }
+            assert_eq!(snapshots, loaded);
+        }
+
+        #[test]
+        fn hashmap_versions_round_trip(
+            ref edits in collection::vec((u16::ANY, i32::ANY), 0..100),
+            initial in 0..500u16,
+        ) {
+            let mut map: HashMap<u16, i32> = (0..initial).map(|i| (i, 0)).collect();
+            let mut snapshots = Snapshots::new();
+            for (key, value) in edits {
+                if *value < 0 {
+                    map.remove(key);
+                } else {
+                    map.insert(*key, *value);
+                }
+                snapshots.push(map.clone());
+            }
+            let loaded: Snapshots<HashMap<u16, i32>> =
+                from_str(&to_string(&snapshots).unwrap()).unwrap();
+            assert_eq!(snapshots, loaded);
+        }
+    }
+}
@@ -2,6 +2,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use crate::config::VectorChunkSize;
 use crate::vector::FocusMut;
 use rand_core::{RngCore, SeedableRng};
 use std::cmp::Ordering;
@@ -19,9 +20,10 @@ fn gen_range<R: RngCore>(rng: &mut R, min: usize, max: usize) -> usize {
 // additional passes to find the exact partition places. This allows us to split the focus into
 // three correctly sized parts for less than, equal to and greater than items. As a bonus this
 // doesn't need to reorder the equal items to the center of the vector.
-fn do_quicksort<A, F, R>(vector: FocusMut<'_, A>, cmp: &F, rng: &mut R)
+fn do_quicksort<A, N, F, R>(vector: FocusMut<'_, A, N>, cmp: &F, rng: &mut R)
 where
     A: Clone,
+    N: VectorChunkSize<A>,
     F: Fn(&A, &A) -> Ordering,
     R: RngCore,
 {
@@ -172,9 +174,10 @@ where
     }
 }
 
-pub(crate) fn quicksort<A, F>(vector: FocusMut<'_, A>, cmp: &F)
+pub(crate) fn quicksort<A, N, F>(vector: FocusMut<'_, A, N>, cmp: &F)
 where
     A: Clone,
+    N: VectorChunkSize<A>,
     F: Fn(&A, &A) -> Ordering,
 {
     let mut rng = rand_xoshiro::Xoshiro256Plus::seed_from_u64(0);
@@ -2,8 +2,28 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+//! Sharing collections between threads.
+//!
+//! The collections are cheap to clone and never change once another
+//! thread can see them, so the usual way to share one is to keep it in
+//! an [`Arc`][std::sync::Arc] and swap in a new version when it's
+//! updated. An [`Atom`][Atom] does exactly that: readers take a snapshot
+//! with [`load`][Atom::load], which never takes a lock, and can then
+//! read it for as long as they like, while writers build a new version
+//! from the old one and put it in place with
+//! [`compare_and_set`][Atom::compare_and_set], or let
+//! [`update`][Atom::update] retry that for them.
+//!
+//! [Atom]: ./struct.Atom.html
+//! [Atom::load]: ./struct.Atom.html#method.load
+//! [Atom::compare_and_set]: ./struct.Atom.html#method.compare_and_set
+//! [Atom::update]: ./struct.Atom.html#method.update
+
 pub(crate) use self::lock::Lock;
 
+#[cfg(threadsafe)]
+pub use self::atom::Atom;
+
 #[cfg(threadsafe)]
 mod lock {
     use std::sync::{Arc, Mutex, MutexGuard};
@@ -67,3 +87,355 @@ mod lock {
         }
     }
 }
+
+#[cfg(threadsafe)]
+mod atom {
+    use std::fmt::{Debug, Error, Formatter};
+    use std::marker::PhantomData;
+    use std::mem::ManuallyDrop;
+    use std::ptr;
+    use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering::SeqCst};
+    use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+    use std::thread;
+
+    /// A shared cell holding the current version of a value.
+    ///
+    /// The value is kept in an [`Arc`][Arc], and [`load`][load] hands out
+    /// a clone of that `Arc`, a snapshot which stays the same however
+    /// many times the atom is changed afterwards. Changing the atom
+    /// replaces its `Arc` with a new one, leaving every snapshot alone,
+    /// which together with a persistent collection means writers copy
+    /// only the nodes they change, and build the new version without
+    /// holding any lock.
+    ///
+    /// Readers never take a lock: `load` swaps nothing in and out, it
+    /// just counts itself as a reader, clones the `Arc` the atom points
+    /// to, and counts itself out again. Writers are put in line by a
+    /// mutex among themselves, and each one, having swapped in its new
+    /// value, waits for the readers which might still be cloning the old
+    /// one before letting go of it. Readers arriving after the swap are
+    /// counted separately, so a stream of them can't keep a writer
+    /// waiting, and no one ever waits for a writer building a new
+    /// version.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::sync::Atom;
+    /// # use std::sync::Arc;
+    /// # use std::thread;
+    /// let atom = Arc::new(Atom::new(ordmap!{}));
+    /// let threads: Vec<_> = (0..4)
+    ///     .map(|i| {
+    ///         let atom = atom.clone();
+    ///         thread::spawn(move || {
+    ///             atom.update(|map| map.update(i, i * 10));
+    ///         })
+    ///     })
+    ///     .collect();
+    /// for thread in threads {
+    ///     thread.join().unwrap();
+    /// }
+    /// assert_eq!(ordmap!{0 => 0, 1 => 10, 2 => 20, 3 => 30}, *atom.load());
+    /// ```
+    ///
+    /// [Arc]: https://doc.rust-lang.org/std/sync/struct.Arc.html
+    /// [load]: #method.load
+    pub struct Atom<T> {
+        /// The current value, from `Arc::into_raw`. The atom owns one
+        /// strong count of it.
+        current: AtomicPtr<T>,
+        /// Which of `readers` a reader should count itself in, by its
+        /// lowest bit. Each write flips it.
+        epoch: AtomicUsize,
+        /// The number of readers between starting to read `current` and
+        /// having cloned it, for each side of `epoch`.
+        readers: [AtomicUsize; 2],
+        writer: Mutex<()>,
+        // The atom shares its value between threads the way an `Arc`
+        // does, so it's `Send` and `Sync` when an `Arc<T>` would be.
+        owns: PhantomData<Arc<T>>,
+    }
+
+    impl<T> Atom<T> {
+        /// Construct an atom holding a value.
+        #[must_use]
+        pub fn new(value: T) -> Self {
+            Atom::from(Arc::new(value))
+        }
+
+        /// Take a snapshot of the current value.
+        ///
+        /// This never takes a lock or waits for a writer.
+        #[must_use]
+        #[allow(unsafe_code)]
+        pub fn load(&self) -> Arc<T> {
+            let side = self.enter();
+            let current = self.current.load(SeqCst);
+            // The atom's own strong count on `current` is only given up
+            // by a writer which has swapped it out, and which then waits
+            // for every reader counted on our side to leave.
+            let snapshot = Arc::clone(&ManuallyDrop::new(unsafe { Arc::from_raw(current) }));
+            self.readers[side].fetch_sub(1, SeqCst);
+            snapshot
+        }
+
+        /// Count a reader in on the current side of the epoch, and
+        /// return that side.
+        fn enter(&self) -> usize {
+            loop {
+                let side = self.epoch.load(SeqCst) & 1;
+                self.readers[side].fetch_add(1, SeqCst);
+                // If a writer flipped the epoch before we were counted,
+                // it may not wait for us, so count ourselves in on the
+                // new side instead.
+                if self.epoch.load(SeqCst) & 1 == side {
+                    return side;
+                }
+                self.readers[side].fetch_sub(1, SeqCst);
+            }
+        }
+
+        fn lock(&self) -> MutexGuard<'_, ()> {
+            // The mutex guards no data, so a poisoned one is still fine.
+            self.writer.lock().unwrap_or_else(PoisonError::into_inner)
+        }
+
+        /// Put `value` in place of the current value and return the one
+        /// it replaced, once no reader can still be cloning it. The
+        /// writer lock must be held.
+        #[allow(unsafe_code)]
+        fn replace(&self, value: Arc<T>) -> Arc<T> {
+            let old = self.current.swap(Arc::into_raw(value) as *mut T, SeqCst);
+            let side = self.epoch.fetch_add(1, SeqCst) & 1;
+            while self.readers[side].load(SeqCst) != 0 {
+                thread::yield_now();
+            }
+            // Every reader which could have seen `old` has now cloned it,
+            // so the atom's count on it can be handed over.
+            unsafe { Arc::from_raw(old) }
+        }
+
+        /// Replace the current value.
+        pub fn store<A>(&self, value: A)
+        where
+            A: Into<Arc<T>>,
+        {
+            // The old value is dropped here, after the lock is released.
+            self.swap(value);
+        }
+
+        /// Replace the current value, returning the one it replaced.
+        ///
+        /// This waits for other writers, and for readers in the middle of
+        /// taking a snapshot of the value it replaces.
+        pub fn swap<A>(&self, value: A) -> Arc<T>
+        where
+            A: Into<Arc<T>>,
+        {
+            let value = value.into();
+            let _writer = self.lock();
+            self.replace(value)
+        }
+
+        /// Replace the current value with `new` if it's still the snapshot
+        /// `current`.
+        ///
+        /// Snapshots are compared by address, not by value, so this fails
+        /// if the atom has been changed at all since `current` was taken,
+        /// even if it was changed back to an equal value. If it fails, it
+        /// returns the atom's current value, to try again with.
+        pub fn compare_and_set<A>(&self, current: &Arc<T>, new: A) -> Result<(), Arc<T>>
+        where
+            A: Into<Arc<T>>,
+        {
+            let new = new.into();
+            let writer = self.lock();
+            // `current` keeps its value alive, so no other value can have
+            // been given the same address since.
+            if ptr::eq(self.current.load(SeqCst), Arc::as_ptr(current)) {
+                let old = self.replace(new);
+                drop(writer);
+                drop(old);
+                Ok(())
+            } else {
+                Err(self.load())
+            }
+        }
+
+        /// Replace the current value with the result of calling `f` on it,
+        /// and return the new value.
+        ///
+        /// `f` is called without holding any lock, and if another thread
+        /// changes the atom in the meantime, it's called again on the
+        /// newer value, so it may be called more than once and shouldn't
+        /// have side effects.
+        pub fn update<F>(&self, mut f: F) -> Arc<T>
+        where
+            F: FnMut(&T) -> T,
+        {
+            let mut current = self.load();
+            loop {
+                let new = Arc::new(f(&current));
+                match self.compare_and_set(&current, new.clone()) {
+                    Ok(()) => return new,
+                    Err(actual) => current = actual,
+                }
+            }
+        }
+
+        /// Take the current value out of the atom.
+        #[must_use]
+        #[allow(unsafe_code)]
+        pub fn into_inner(mut self) -> Arc<T> {
+            let current = std::mem::replace(self.current.get_mut(), ptr::null_mut());
+            // No one else can be reading an atom we own, and the atom's
+            // count on its value is ours to take now that it holds null.
+            unsafe { Arc::from_raw(current) }
+        }
+    }
+
+    impl<T> Drop for Atom<T> {
+        #[allow(unsafe_code)]
+        fn drop(&mut self) {
+            let current = *self.current.get_mut();
+            if !current.is_null() {
+                // The atom owns a strong count on its value, and no one
+                // else can be reading it.
+                drop(unsafe { Arc::from_raw(current) });
+            }
+        }
+    }
+
+    impl<T: Default> Default for Atom<T> {
+        fn default() -> Self {
+            Atom::new(T::default())
+        }
+    }
+
+    impl<T> From<T> for Atom<T> {
+        fn from(value: T) -> Self {
+            Atom::new(value)
+        }
+    }
+
+    impl<T> From<Arc<T>> for Atom<T> {
+        fn from(value: Arc<T>) -> Self {
+            Atom {
+                current: AtomicPtr::new(Arc::into_raw(value) as *mut T),
+                epoch: AtomicUsize::new(0),
+                readers: [AtomicUsize::new(0), AtomicUsize::new(0)],
+                writer: Mutex::new(()),
+                owns: PhantomData,
+            }
+        }
+    }
+
+    impl<T: Debug> Debug for Atom<T> {
+        fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+            f.debug_tuple("Atom").field(&self.load()).finish()
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+        use crate::{HashMap, OrdMap};
+        use std::thread;
+
+        #[test]
+        fn snapshots_outlive_changes() {
+            let atom = Atom::new(hashmap! {1 => "one"});
+            let before = atom.load();
+            let old = atom.swap(before.update(2, "two"));
+            assert!(Arc::ptr_eq(&before, &old));
+            assert_eq!(hashmap! {1 => "one"}, *before);
+            assert_eq!(hashmap! {1 => "one", 2 => "two"}, *atom.load());
+        }
+
+        #[test]
+        fn compare_and_set_fails_on_stale_snapshot() {
+            let atom: Atom<HashMap<i32, i32>> = Atom::default();
+            let stale = atom.load();
+            atom.store(HashMap::unit(1, 1));
+            let actual = atom.compare_and_set(&stale, HashMap::new()).unwrap_err();
+            assert_eq!(HashMap::unit(1, 1), *actual);
+            assert_eq!(Ok(()), atom.compare_and_set(&actual, HashMap::new()));
+            assert!(atom.into_inner().is_empty());
+        }
+
+        #[test]
+        fn concurrent_updates_are_not_lost() {
+            let atom = Arc::new(Atom::new(OrdMap::new()));
+            let threads: Vec<_> = (0..8)
+                .map(|thread| {
+                    let atom = atom.clone();
+                    thread::spawn(move || {
+                        for i in 0..200 {
+                            atom.update(|map| map.update(thread * 1000 + i, i));
+                        }
+                    })
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            let map = atom.load();
+            assert_eq!(1600, map.len());
+            assert_eq!(Ok(()), map.check_invariants());
+        }
+
+        #[test]
+        fn readers_never_see_a_dropped_value() {
+            static MADE: AtomicUsize = AtomicUsize::new(0);
+            static DROPPED: AtomicUsize = AtomicUsize::new(0);
+            struct Counted(usize);
+            impl Counted {
+                fn new(value: usize) -> Self {
+                    MADE.fetch_add(1, SeqCst);
+                    Counted(value)
+                }
+            }
+            impl Drop for Counted {
+                fn drop(&mut self) {
+                    DROPPED.fetch_add(1, SeqCst);
+                    // A reader holding on to a dropped value would see this.
+                    self.0 = usize::MAX;
+                }
+            }
+
+            let atom = Arc::new(Atom::new(Counted::new(0)));
+            let readers: Vec<_> = (0..4)
+                .map(|_| {
+                    let atom = atom.clone();
+                    thread::spawn(move || {
+                        let mut last = 0;
+                        for _ in 0..20_000 {
+                            let seen = atom.load().0;
+                            assert!(seen >= last && seen != usize::MAX);
+                            last = seen;
+                        }
+                    })
+                })
+                .collect();
+            let writers: Vec<_> = (0..2)
+                .map(|_| {
+                    let atom = atom.clone();
+                    thread::spawn(move || {
+                        for _ in 0..2000 {
+                            atom.update(|old| Counted::new(old.0 + 1));
+                        }
+                    })
+                })
+                .collect();
+            for thread in readers.into_iter().chain(writers) {
+                thread.join().unwrap();
+            }
+            let atom = Arc::try_unwrap(atom).ok().unwrap();
+            assert_eq!(4000, atom.load().0);
+            drop(atom);
+            assert_eq!(MADE.load(SeqCst), DROPPED.load(SeqCst));
+        }
+    }
+}
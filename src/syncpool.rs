@@ -0,0 +1,454 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A thread safe memory pool for reference counted nodes.
+//!
+//! This provides the same interface as `refpool`, which only works with
+//! `Rc` style reference counting, for the thread safe build of the crate.
+//!
+//! A pool keeps a number of free lists, each behind its own lock, and
+//! each thread sticks to one of them. A thread never waits for a lock:
+//! if its free list is in use by another thread, it just goes to the
+//! allocator instead, as if the pool was empty (or full, when freeing).
+//! Freed nodes go back to the pool they came from, whichever thread
+//! they're dropped on.
+
+#![allow(unsafe_code)]
+
+use std::fmt::{Debug, Error, Formatter};
+use std::mem::{self, MaybeUninit};
+use std::ops::Deref;
+use std::ptr::{self, NonNull};
+use std::sync::atomic::{self, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::util::{NodeType, PoolClone, PoolDefault, Token};
+
+/// The number of free lists in a pool.
+const SHARDS: usize = 8;
+
+/// Reference counts above this are assumed to come from leaked
+/// references, as with `std::sync::Arc`.
+const MAX_REFCOUNT: usize = isize::MAX as usize;
+
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static SHARD: usize = NEXT_SHARD.fetch_add(1, Ordering::Relaxed) % SHARDS;
+}
+
+fn current_shard() -> usize {
+    SHARD.try_with(|shard| *shard).unwrap_or(0)
+}
+
+struct PoolBox<A> {
+    count: AtomicUsize,
+    /// The pool this box returns to when it's dropped, or `None` if it
+    /// goes straight back to the allocator, or while it's sitting in a
+    /// free list.
+    pool: Option<Arc<PoolInner<A>>>,
+    /// The token counting this box's value, while it has one.
+    #[cfg(feature = "instrument")]
+    token: Option<Token>,
+    value: MaybeUninit<A>,
+}
+
+/// A pointer to a free box, which holds no value and no pool handle.
+struct FreeBox<A>(NonNull<PoolBox<A>>);
+
+// A free box holds nothing but its own memory, so it can be moved
+// between threads freely.
+unsafe impl<A> Send for FreeBox<A> {}
+
+struct PoolInner<A> {
+    shard_size: usize,
+    shards: [Mutex<Vec<FreeBox<A>>>; SHARDS],
+}
+
+impl<A> PoolInner<A> {
+    fn new(max_size: usize) -> Self {
+        let shard_size = (max_size + SHARDS - 1) / SHARDS;
+        PoolInner {
+            shard_size,
+            shards: Default::default(),
+        }
+    }
+
+    /// Take a box out of the current thread's free list, if there's one
+    /// and nobody else is using it.
+    fn pop(&self) -> Option<NonNull<PoolBox<A>>> {
+        let mut free = self.shards[current_shard()].try_lock().ok()?;
+        free.pop().map(|free| free.0)
+    }
+
+    /// Put a box back in the current thread's free list, returning it if
+    /// the free list is full or in use.
+    fn push(&self, ptr: NonNull<PoolBox<A>>) -> Option<NonNull<PoolBox<A>>> {
+        match self.shards[current_shard()].try_lock() {
+            Ok(ref mut free) if free.len() < self.shard_size => {
+                free.push(FreeBox(ptr));
+                None
+            }
+            _ => Some(ptr),
+        }
+    }
+}
+
+impl<A> Drop for PoolInner<A> {
+    fn drop(&mut self) {
+        for shard in &mut self.shards {
+            let free = match shard.get_mut() {
+                Ok(free) => free,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            for free in free.drain(..) {
+                unsafe { drop(Box::from_raw(free.0.as_ptr())) }
+            }
+        }
+    }
+}
+
+/// Allocate a box with a reference count of 1 and an uninitialised value.
+fn new_box<A>() -> NonNull<PoolBox<A>> {
+    let boxed = Box::new(PoolBox {
+        count: AtomicUsize::new(1),
+        pool: None,
+        #[cfg(feature = "instrument")]
+        token: None,
+        value: MaybeUninit::uninit(),
+    });
+    unsafe { NonNull::new_unchecked(Box::into_raw(boxed)) }
+}
+
+/// A thread safe pool of memory for reference counted values of type `A`.
+///
+/// Cloning a pool gives you another handle to the same pool. A pool of
+/// size 0 doesn't allocate anything, and just hands out boxes from the
+/// allocator.
+pub(crate) struct Pool<A> {
+    inner: Option<Arc<PoolInner<A>>>,
+}
+
+impl<A> Pool<A> {
+    pub(crate) fn new(max_size: usize) -> Self {
+        Pool {
+            inner: if max_size == 0 {
+                None
+            } else {
+                Some(Arc::new(PoolInner::new(max_size)))
+            },
+        }
+    }
+
+    /// The number of free boxes currently in the pool.
+    pub(crate) fn get_pool_size(&self) -> usize {
+        self.inner
+            .iter()
+            .flat_map(|inner| inner.shards.iter())
+            .map(|shard| shard.lock().map(|free| free.len()).unwrap_or(0))
+            .sum()
+    }
+
+    /// Fill the pool with newly allocated boxes.
+    pub(crate) fn fill(&self) {
+        if let Some(ref inner) = self.inner {
+            for shard in &inner.shards {
+                if let Ok(mut free) = shard.lock() {
+                    while free.len() < inner.shard_size {
+                        free.push(FreeBox(new_box()));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Get a box with a reference count of 1 and an uninitialised value,
+    /// to be counted by `token`.
+    #[allow(unused_variables)]
+    fn alloc(&self, token: Token) -> NonNull<PoolBox<A>> {
+        let ptr = match self.inner {
+            None => new_box(),
+            Some(ref inner) => {
+                let ptr = inner.pop().unwrap_or_else(new_box);
+                unsafe { (*ptr.as_ptr()).pool = Some(inner.clone()) };
+                ptr
+            }
+        };
+        #[cfg(feature = "instrument")]
+        unsafe {
+            (*ptr.as_ptr()).token = Some(token)
+        };
+        ptr
+    }
+}
+
+impl<A> Clone for Pool<A> {
+    fn clone(&self) -> Self {
+        Pool {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+/// Return a box whose value has been dropped or moved out to its pool,
+/// or to the allocator if the pool has no room for it.
+unsafe fn release<A>(ptr: NonNull<PoolBox<A>>) {
+    #[cfg(feature = "instrument")]
+    drop((*ptr.as_ptr()).token.take());
+    match (*ptr.as_ptr()).pool.take() {
+        None => drop(Box::from_raw(ptr.as_ptr())),
+        Some(pool) => {
+            (*ptr.as_ptr()).count.store(1, Ordering::Relaxed);
+            if let Some(ptr) = pool.push(ptr) {
+                drop(Box::from_raw(ptr.as_ptr()));
+            }
+            // The pool handle must only be dropped once we're done with
+            // its free lists, as it might be the last one.
+            drop(pool);
+        }
+    }
+}
+
+/// A thread safe reference counted pointer to a value allocated from a
+/// [`Pool`][Pool].
+///
+/// This is public, though unreachable, because it shows up in the bounds of
+/// [`OrdChunkSize`][crate::config::OrdChunkSize].
+pub struct PoolRef<A> {
+    ptr: NonNull<PoolBox<A>>,
+}
+
+unsafe impl<A: Send + Sync> Send for PoolRef<A> {}
+unsafe impl<A: Send + Sync> Sync for PoolRef<A> {}
+
+impl<A> PoolRef<A> {
+    fn boxed(&self) -> &PoolBox<A> {
+        unsafe { self.ptr.as_ref() }
+    }
+
+    pub(crate) fn default(pool: &Pool<A>) -> Self
+    where
+        A: PoolDefault + NodeType,
+    {
+        let ptr = pool.alloc(Token::allocated::<A>());
+        unsafe { A::default_uninit(&mut (*ptr.as_ptr()).value) };
+        PoolRef { ptr }
+    }
+
+    pub(crate) fn new(pool: &Pool<A>, value: A) -> Self
+    where
+        A: NodeType,
+    {
+        let ptr = pool.alloc(Token::allocated::<A>());
+        unsafe { (*ptr.as_ptr()).value.as_mut_ptr().write(value) };
+        PoolRef { ptr }
+    }
+
+    pub(crate) fn clone_from(pool: &Pool<A>, value: &A) -> Self
+    where
+        A: PoolClone + NodeType,
+    {
+        let ptr = pool.alloc(Token::cloned::<A>());
+        unsafe { value.clone_uninit(&mut (*ptr.as_ptr()).value) };
+        PoolRef { ptr }
+    }
+
+    fn is_unique(&self) -> bool {
+        self.boxed().count.load(Ordering::Acquire) == 1
+    }
+
+    pub(crate) fn make_mut<'a>(pool: &Pool<A>, this: &'a mut Self) -> &'a mut A
+    where
+        A: PoolClone + NodeType,
+    {
+        if !this.is_unique() {
+            *this = PoolRef::clone_from(pool, this);
+        }
+        unsafe { &mut *(*this.ptr.as_ptr()).value.as_mut_ptr() }
+    }
+
+    pub(crate) fn ptr_eq(left: &Self, right: &Self) -> bool {
+        left.ptr == right.ptr
+    }
+
+    pub(crate) fn unwrap_or_clone(this: Self) -> A
+    where
+        A: PoolClone + NodeType,
+    {
+        if !this.is_unique() {
+            return (*this).clone();
+        }
+        let ptr = this.ptr;
+        mem::forget(this);
+        unsafe {
+            let value = ptr::read((*ptr.as_ptr()).value.as_ptr());
+            release(ptr);
+            value
+        }
+    }
+}
+
+impl<A> Clone for PoolRef<A> {
+    fn clone(&self) -> Self {
+        let count = self.boxed().count.fetch_add(1, Ordering::Relaxed);
+        if count > MAX_REFCOUNT {
+            std::process::abort();
+        }
+        PoolRef { ptr: self.ptr }
+    }
+}
+
+impl<A> Drop for PoolRef<A> {
+    fn drop(&mut self) {
+        if self.boxed().count.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        atomic::fence(Ordering::Acquire);
+        unsafe {
+            ptr::drop_in_place((*self.ptr.as_ptr()).value.as_mut_ptr());
+            release(self.ptr);
+        }
+    }
+}
+
+impl<A> Deref for PoolRef<A> {
+    type Target = A;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.boxed().value.as_ptr() }
+    }
+}
+
+impl<A> PartialEq for PoolRef<A>
+where
+    A: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<A> Eq for PoolRef<A> where A: Eq {}
+
+impl<A> Debug for PoolRef<A>
+where
+    A: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        (**self).fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+
+    #[derive(Clone, Default)]
+    struct Counted(Option<Arc<AtomicUsize>>);
+
+    impl NodeType for Counted {
+        #[cfg(feature = "instrument")]
+        const TREE: crate::instrument::Tree = crate::instrument::Tree::Rrb;
+    }
+
+    impl NodeType for usize {
+        #[cfg(feature = "instrument")]
+        const TREE: crate::instrument::Tree = crate::instrument::Tree::Rrb;
+    }
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            if let Some(ref drops) = self.0 {
+                drops.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    #[test]
+    fn reuses_freed_boxes() {
+        let pool: Pool<usize> = Pool::new(16);
+        assert_eq!(0, pool.get_pool_size());
+        let first = PoolRef::new(&pool, 1);
+        let address = &*first as *const usize;
+        drop(first);
+        assert_eq!(1, pool.get_pool_size());
+        let second = PoolRef::new(&pool, 2);
+        assert_eq!(address, &*second as *const usize);
+        assert_eq!(0, pool.get_pool_size());
+        assert_eq!(2, PoolRef::unwrap_or_clone(second));
+        assert_eq!(1, pool.get_pool_size());
+    }
+
+    #[test]
+    fn fill_and_overflow() {
+        let pool: Pool<usize> = Pool::new(16);
+        pool.fill();
+        assert_eq!(16, pool.get_pool_size());
+        let refs: Vec<_> = (0..32).map(|i| PoolRef::new(&pool, i)).collect();
+        drop(refs);
+        assert!(pool.get_pool_size() <= 16);
+        let empty: Pool<usize> = Pool::new(0);
+        drop(PoolRef::new(&empty, 1));
+        assert_eq!(0, empty.get_pool_size());
+    }
+
+    #[test]
+    fn make_mut_copies_shared_values() {
+        let pool: Pool<usize> = Pool::new(16);
+        let mut first = PoolRef::new(&pool, 1);
+        let second = first.clone();
+        *PoolRef::make_mut(&pool, &mut first) += 1;
+        assert!(!PoolRef::ptr_eq(&first, &second));
+        assert_eq!((2, 1), (*first, *second));
+        let address = &*first as *const usize;
+        *PoolRef::make_mut(&pool, &mut first) += 1;
+        assert_eq!(address, &*first as *const usize);
+    }
+
+    #[test]
+    fn drops_every_value_once() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let pool: Pool<Counted> = Pool::new(64);
+        let refs: Vec<_> = (0..100)
+            .map(|_| PoolRef::new(&pool, Counted(Some(drops.clone()))))
+            .collect();
+        let clones = refs.clone();
+        drop(refs);
+        assert_eq!(0, drops.load(Ordering::SeqCst));
+        drop(clones);
+        assert_eq!(100, drops.load(Ordering::SeqCst));
+        // Boxes outliving every handle to their pool keep it alive.
+        let value = PoolRef::new(&pool, Counted(Some(drops.clone())));
+        drop(pool);
+        drop(value);
+        assert_eq!(101, drops.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let pool: Pool<usize> = Pool::new(1024);
+        let values: Vec<_> = (0..1000).map(|i| PoolRef::new(&pool, i)).collect();
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let pool = pool.clone();
+                let values = values.clone();
+                thread::spawn(move || {
+                    let mut total = 0;
+                    for value in values {
+                        let mut value = value;
+                        *PoolRef::make_mut(&pool, &mut value) += 1;
+                        total += *value;
+                    }
+                    total
+                })
+            })
+            .collect();
+        drop(values);
+        for thread in threads {
+            assert_eq!((1..=1000).sum::<usize>(), thread.join().unwrap());
+        }
+        assert!(pool.get_pool_size() <= 1024);
+    }
+}
@@ -3,10 +3,14 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use metrohash::MetroHash64;
+use std::fmt::Debug;
 use std::hash::{BuildHasher, Hasher};
+use std::iter::FromIterator;
 use std::marker::PhantomData;
 use typenum::{Unsigned, U64};
 
+use crate::{HashMap, OrdMap};
+
 pub(crate) fn is_sorted<A, I>(l: I) -> bool
 where
     I: IntoIterator<Item = A>,
@@ -84,3 +88,106 @@ impl BuildHasher for MetroHashBuilder {
         MetroHash64::with_seed(self.seed)
     }
 }
+
+/// A map with a `merge3`, for checking it against a model with
+/// [`check_merge3`][check_merge3].
+pub(crate) trait Merge3Map: Clone + PartialEq + Debug + FromIterator<(u16, i16)> {
+    fn get(&self, key: &u16) -> Option<&i16>;
+    fn insert(&mut self, key: u16, value: i16);
+    fn remove(&mut self, key: &u16);
+    fn merge3(base: &Self, ours: &Self, theirs: &Self, resolve: &mut Resolve<'_>) -> Self;
+}
+
+pub(crate) type Resolve<'a> =
+    dyn FnMut(&u16, Option<&i16>, Option<&i16>, Option<&i16>) -> Option<i16> + 'a;
+
+impl Merge3Map for OrdMap<u16, i16> {
+    fn get(&self, key: &u16) -> Option<&i16> {
+        OrdMap::get(self, key)
+    }
+
+    fn insert(&mut self, key: u16, value: i16) {
+        OrdMap::insert(self, key, value);
+    }
+
+    fn remove(&mut self, key: &u16) {
+        OrdMap::remove(self, key);
+    }
+
+    fn merge3(base: &Self, ours: &Self, theirs: &Self, resolve: &mut Resolve<'_>) -> Self {
+        OrdMap::merge3(base, ours, theirs, resolve)
+    }
+}
+
+impl Merge3Map for HashMap<u16, i16> {
+    fn get(&self, key: &u16) -> Option<&i16> {
+        HashMap::get(self, key)
+    }
+
+    fn insert(&mut self, key: u16, value: i16) {
+        HashMap::insert(self, key, value);
+    }
+
+    fn remove(&mut self, key: &u16) {
+        HashMap::remove(self, key);
+    }
+
+    fn merge3(base: &Self, ours: &Self, theirs: &Self, resolve: &mut Resolve<'_>) -> Self {
+        HashMap::merge3(base, ours, theirs, resolve)
+    }
+}
+
+/// Check that `merge3` of two edited copies of `base` gives the same
+/// result as merging every key by hand, and calls its resolver for just
+/// the keys both sides changed differently.
+pub(crate) fn check_merge3<M: Merge3Map>(
+    base: Vec<(u16, i16)>,
+    ours_edits: &[(u16, Option<i16>)],
+    theirs_edits: &[(u16, Option<i16>)],
+) {
+    let edit = |map: &M, edits: &[(u16, Option<i16>)]| {
+        let mut map = map.clone();
+        for (key, value) in edits {
+            match value {
+                Some(value) => map.insert(*key, *value),
+                None => map.remove(key),
+            };
+        }
+        map
+    };
+    let resolved = |base: Option<&i16>, ours: Option<&i16>, theirs: Option<&i16>| {
+        base.cloned().unwrap_or(0) ^ ours.cloned().unwrap_or(1) ^ theirs.cloned().unwrap_or(2)
+    };
+    let mut keys: Vec<u16> = base.iter().map(|(key, _)| *key).collect();
+    keys.extend(ours_edits.iter().chain(theirs_edits).map(|(key, _)| *key));
+    keys.sort_unstable();
+    keys.dedup();
+    let base: M = base.into_iter().collect();
+    let ours = edit(&base, ours_edits);
+    let theirs = edit(&base, theirs_edits);
+    let mut conflicts = Vec::new();
+    let merged = M::merge3(&base, &ours, &theirs, &mut |key, base, ours, theirs| {
+        conflicts.push(*key);
+        assert_ne!(ours, theirs);
+        Some(resolved(base, ours, theirs))
+    });
+    let mut expected = Vec::new();
+    let mut expected_conflicts = Vec::new();
+    for key in keys {
+        let (b, o, t) = (base.get(&key), ours.get(&key), theirs.get(&key));
+        let value = if o == b {
+            t.cloned()
+        } else if t == b || o == t {
+            o.cloned()
+        } else {
+            expected_conflicts.push(key);
+            Some(resolved(b, o, t))
+        };
+        if let Some(value) = value {
+            expected.push((key, value));
+        }
+    }
+    conflicts.sort_unstable();
+    assert_eq!(expected_conflicts, conflicts);
+    assert_eq!(expected.into_iter().collect::<M>(), merged);
+}
@@ -5,18 +5,24 @@
 // Every codebase needs a `util` module.
 
 use std::cmp::Ordering;
-use std::ops::{Bound, IndexMut, Range, RangeBounds};
+use std::ops::{Bound, Deref, DerefMut, IndexMut, Range, RangeBounds};
 use std::ptr;
 
+use sized_chunks::{types::ChunkLength, Chunk};
+
 #[cfg(feature = "pool")]
 pub(crate) use refpool::{PoolClone, PoolDefault};
 
 // The `Ref` type is an alias for either `Rc` or `Arc`, user's choice.
 
-// `Arc` without refpool
-#[cfg(all(threadsafe))]
+// `Arc` without a pool
+#[cfg(all(threadsafe, not(feature = "pool")))]
 pub(crate) use crate::fakepool::{Arc as PoolRef, Pool, PoolClone, PoolDefault};
 
+// `Arc` with the thread safe pool
+#[cfg(all(threadsafe, feature = "pool"))]
+pub(crate) use crate::syncpool::{Pool, PoolRef};
+
 // `Ref` == `Arc` when threadsafe
 #[cfg(threadsafe)]
 pub(crate) type Ref<A> = std::sync::Arc<A>;
@@ -35,6 +41,125 @@ pub(crate) type Pool<A> = refpool::Pool<A>;
 #[cfg(not(threadsafe))]
 pub(crate) type Ref<A> = std::rc::Rc<A>;
 
+/// A type of node allocated through a `PoolRef` or kept in a
+/// [`Counted`][Counted], labelled with the kind of tree it belongs to
+/// when the `instrument` feature is on.
+pub(crate) trait NodeType {
+    #[cfg(feature = "instrument")]
+    const TREE: crate::instrument::Tree;
+}
+
+// Every chunk allocated through a `PoolRef` belongs to an RRB tree.
+impl<A, N: ChunkLength<A>> NodeType for Chunk<A, N> {
+    #[cfg(feature = "instrument")]
+    const TREE: crate::instrument::Tree = crate::instrument::Tree::Rrb;
+}
+
+/// A marker kept next to each counted node, which counts the node as freed
+/// when it's dropped if the `instrument` feature is on, and takes up no
+/// space otherwise.
+#[allow(dead_code)]
+pub(crate) struct Token {
+    #[cfg(feature = "instrument")]
+    tree: crate::instrument::Tree,
+}
+
+#[allow(dead_code)]
+impl Token {
+    /// Count a newly allocated node of type `A`.
+    #[inline(always)]
+    pub(crate) fn allocated<A: NodeType>() -> Self {
+        Self::new::<A>(
+            #[cfg(feature = "instrument")]
+            crate::instrument::Operation::Allocate,
+        )
+    }
+
+    /// Count a node of type `A` allocated as a copy of a shared one.
+    #[inline(always)]
+    pub(crate) fn cloned<A: NodeType>() -> Self {
+        Self::new::<A>(
+            #[cfg(feature = "instrument")]
+            crate::instrument::Operation::Clone,
+        )
+    }
+
+    #[inline(always)]
+    fn new<A: NodeType>(
+        #[cfg(feature = "instrument")] operation: crate::instrument::Operation,
+    ) -> Self {
+        #[cfg(feature = "instrument")]
+        crate::instrument::record(A::TREE, operation);
+        Token {
+            #[cfg(feature = "instrument")]
+            tree: A::TREE,
+        }
+    }
+}
+
+#[cfg(feature = "instrument")]
+impl Drop for Token {
+    fn drop(&mut self) {
+        crate::instrument::record(self.tree, crate::instrument::Operation::Free);
+    }
+}
+
+/// A node's value, along with the token which counts its allocation.
+///
+/// A `PoolRef` without a pool keeps its node in one of these, and so does
+/// a node which is only ever kept behind a `Ref`.
+// Public, though unreachable, because it shows up in `nodes::hamt::Entry`.
+#[allow(unreachable_pub)]
+pub struct Counted<A> {
+    pub(crate) value: A,
+    // Only here to be dropped along with the value.
+    #[allow(dead_code)]
+    pub(crate) token: Token,
+}
+
+impl<A> Counted<A> {
+    #[inline(always)]
+    pub(crate) fn new(value: A) -> Self
+    where
+        A: NodeType,
+    {
+        Counted {
+            value,
+            token: Token::allocated::<A>(),
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn into_inner(self) -> A {
+        self.value
+    }
+}
+
+impl<A: NodeType + Clone> Clone for Counted<A> {
+    #[inline(always)]
+    fn clone(&self) -> Self {
+        Counted {
+            value: self.value.clone(),
+            token: Token::cloned::<A>(),
+        }
+    }
+}
+
+impl<A> Deref for Counted<A> {
+    type Target = A;
+    #[inline(always)]
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl<A> DerefMut for Counted<A> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.value
+    }
+}
+
 pub(crate) fn clone_ref<A>(r: Ref<A>) -> A
 where
     A: Clone,
@@ -42,6 +167,32 @@ where
     Ref::try_unwrap(r).unwrap_or_else(|r| (*r).clone())
 }
 
+/// Run `f` on a copy of `collection`, and replace `collection` with the
+/// copy only if `f` returns `Ok`. This is every collection's
+/// `transaction` method; the crate docs describe what it promises.
+///
+/// If `f` returns `Err` or panics, the copy is dropped and `collection`
+/// is never touched, so there's nothing to roll back.
+pub(crate) fn transaction<C, F, R, E>(collection: &mut C, f: F) -> Result<R, E>
+where
+    C: Clone,
+    F: FnOnce(&mut C) -> Result<R, E>,
+{
+    let mut working = collection.clone();
+    let result = f(&mut working)?;
+    *collection = working;
+    Ok(result)
+}
+
+/// Test whether two optional nodes are the same node, or both absent.
+pub(crate) fn option_ptr_eq<A>(left: &Option<PoolRef<A>>, right: &Option<PoolRef<A>>) -> bool {
+    match (left, right) {
+        (Some(left), Some(right)) => PoolRef::ptr_eq(left, right),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub(crate) enum Side {
     Left,
@@ -105,12 +256,31 @@ where
     start_index..end_index
 }
 
+// Define a function as a `const fn` when the `cfg` condition holds, and
+// as a plain `fn` otherwise, for constructors which can only be `const`
+// on newer compilers or without the `pool` feature.
+macro_rules! def_const_fn {
+    (
+        #[cfg($($cond:tt)*)]
+        $(#[$attr:meta])*
+        $vis:vis fn $name:ident($($arg:ident: $argty:ty),*) -> $ret:ty $body:block
+    ) => {
+        #[cfg($($cond)*)]
+        $(#[$attr])*
+        $vis const fn $name($($arg: $argty),*) -> $ret $body
+
+        #[cfg(not($($cond)*))]
+        $(#[$attr])*
+        $vis fn $name($($arg: $argty),*) -> $ret $body
+    };
+}
+
 macro_rules! def_pool {
-    ($name:ident<$($arg:tt),*>, $pooltype:ty) => {
+    ($name:ident<$($arg:ident),*; $size:ident: $bound:path = $default:ty>, $pooltype:ty) => {
         /// A memory pool for the appropriate node type.
-        pub struct $name<$($arg,)*>(Pool<$pooltype>);
+        pub struct $name<$($arg,)* $size: $bound = $default>(pub(crate) Pool<$pooltype>);
 
-        impl<$($arg,)*> $name<$($arg,)*> {
+        impl<$($arg,)* $size: $bound> $name<$($arg,)* $size> {
             /// Create a new pool with the given size.
             pub fn new(size: usize) -> Self {
                 Self(Pool::new(size))
@@ -127,13 +297,13 @@ macro_rules! def_pool {
             }
         }
 
-        impl<$($arg,)*> Default for $name<$($arg,)*> {
+        impl<$($arg,)* $size: $bound> Default for $name<$($arg,)* $size> {
             fn default() -> Self {
                 Self::new($crate::config::POOL_SIZE)
             }
         }
 
-        impl<$($arg,)*> Clone for $name<$($arg,)*> {
+        impl<$($arg,)* $size: $bound> Clone for $name<$($arg,)* $size> {
             fn clone(&self) -> Self {
                 Self(self.0.clone())
             }
@@ -0,0 +1,387 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// A sequence diff for vectors.
+//
+// Two versions of a vector made from one another share most of their
+// leaf chunks, so the diff first lines up the leaves of both vectors,
+// matching leaves by address, and only compares values inside the gaps
+// between the leaves they share. Both steps use Myers' O(ND) algorithm,
+// in its linear space form, which splits each problem at the middle snake
+// of an optimal edit path and recurses on the two halves.
+
+use std::iter::FusedIterator;
+use std::ptr;
+
+use crate::config::VectorChunkSize;
+use crate::vector::Vector;
+
+/// A description of a difference between two vectors.
+///
+/// Each `index` is the index in the old vector where the change
+/// happens, so every hunk of a diff can be applied to the old vector
+/// as it is.
+#[derive(PartialEq, Eq, Debug)]
+pub enum DiffItem<'a, A> {
+    /// These values have been inserted before `index`.
+    Insert {
+        /// The index of the value they were inserted before.
+        index: usize,
+        /// The inserted values.
+        values: Vec<&'a A>,
+    },
+    /// These values, starting at `index`, have been removed.
+    Delete {
+        /// The index of the first removed value.
+        index: usize,
+        /// The removed values.
+        values: Vec<&'a A>,
+    },
+    /// These values, starting at `index`, have been replaced with
+    /// others.
+    Replace {
+        /// The index of the first replaced value.
+        index: usize,
+        /// The values which were removed.
+        old: Vec<&'a A>,
+        /// The values which took their place.
+        new: Vec<&'a A>,
+    },
+}
+
+impl<'a, A> DiffItem<'a, A> {
+    fn new(index: usize, old: &[&'a A], new: &[&'a A]) -> Self {
+        if old.is_empty() {
+            DiffItem::Insert {
+                index,
+                values: new.to_vec(),
+            }
+        } else if new.is_empty() {
+            DiffItem::Delete {
+                index,
+                values: old.to_vec(),
+            }
+        } else {
+            DiffItem::Replace {
+                index,
+                old: old.to_vec(),
+                new: new.to_vec(),
+            }
+        }
+    }
+}
+
+/// An iterator over the differences between two vectors.
+pub struct DiffIter<'a, A> {
+    items: std::vec::IntoIter<DiffItem<'a, A>>,
+}
+
+impl<'a, A> DiffIter<'a, A> {
+    pub(crate) fn new<N>(old: &'a Vector<A, N>, new: &'a Vector<A, N>) -> Self
+    where
+        A: Clone + PartialEq,
+        N: VectorChunkSize<A>,
+    {
+        let old_leaves: Vec<&'a [A]> = old.leaves().collect();
+        let new_leaves: Vec<&'a [A]> = new.leaves().collect();
+        let mut shared = Vec::new();
+        myers(
+            &old_leaves,
+            &new_leaves,
+            &|left: &&[A], right: &&[A]| {
+                ptr::eq(left.as_ptr(), right.as_ptr()) && left.len() == right.len()
+            },
+            (0, 0),
+            &mut shared,
+        );
+        shared.push((old_leaves.len(), new_leaves.len(), 0));
+
+        let mut items = Vec::new();
+        let (mut old_leaf, mut new_leaf, mut index) = (0, 0, 0);
+        for (old_end, new_end, count) in shared {
+            let old_values: Vec<&'a A> = old_leaves[old_leaf..old_end]
+                .iter()
+                .flat_map(|leaf| leaf.iter())
+                .collect();
+            let new_values: Vec<&'a A> = new_leaves[new_leaf..new_end]
+                .iter()
+                .flat_map(|leaf| leaf.iter())
+                .collect();
+            diff_values(index, &old_values, &new_values, &mut items);
+            index += old_values.len();
+            index += old_leaves[old_end..old_end + count]
+                .iter()
+                .map(|leaf| leaf.len())
+                .sum::<usize>();
+            old_leaf = old_end + count;
+            new_leaf = new_end + count;
+        }
+        DiffIter {
+            items: items.into_iter(),
+        }
+    }
+}
+
+impl<'a, A> Iterator for DiffIter<'a, A> {
+    type Item = DiffItem<'a, A>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.items.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.items.size_hint()
+    }
+}
+
+impl<'a, A> ExactSizeIterator for DiffIter<'a, A> {}
+
+impl<'a, A> FusedIterator for DiffIter<'a, A> {}
+
+// Diff the values in a gap between shared leaves, starting at `index` in
+// the old vector.
+fn diff_values<'a, A: PartialEq>(
+    index: usize,
+    old: &[&'a A],
+    new: &[&'a A],
+    items: &mut Vec<DiffItem<'a, A>>,
+) {
+    let mut same = Vec::new();
+    myers(old, new, &|left, right| left == right, (0, 0), &mut same);
+    same.push((old.len(), new.len(), 0));
+    let (mut old_start, mut new_start) = (0, 0);
+    for (old_end, new_end, len) in same {
+        if old_start < old_end || new_start < new_end {
+            items.push(DiffItem::new(
+                index + old_start,
+                &old[old_start..old_end],
+                &new[new_start..new_end],
+            ));
+        }
+        old_start = old_end + len;
+        new_start = new_end + len;
+    }
+}
+
+// Find a shortest edit script between `old` and `new`, and push each run
+// of values they have in common onto `same`, as `(old index, new index,
+// length)`, in order. `offset` is where the slices start in the sequences
+// being compared.
+fn myers<T, F>(
+    old: &[T],
+    new: &[T],
+    eq: &F,
+    offset: (usize, usize),
+    same: &mut Vec<(usize, usize, usize)>,
+) where
+    F: Fn(&T, &T) -> bool,
+{
+    let prefix = old
+        .iter()
+        .zip(new)
+        .take_while(|(left, right)| eq(left, right))
+        .count();
+    if prefix > 0 {
+        push_run(same, (offset.0, offset.1, prefix));
+    }
+    let (old, new) = (&old[prefix..], &new[prefix..]);
+    let offset = (offset.0 + prefix, offset.1 + prefix);
+    let suffix = old
+        .iter()
+        .rev()
+        .zip(new.iter().rev())
+        .take_while(|(left, right)| eq(left, right))
+        .count();
+    let (old, new) = (&old[..old.len() - suffix], &new[..new.len() - suffix]);
+    if !old.is_empty() && !new.is_empty() {
+        let (x, y) = middle_snake(old, new, eq);
+        myers(&old[..x], &new[..y], eq, offset, same);
+        myers(&old[x..], &new[y..], eq, (offset.0 + x, offset.1 + y), same);
+    }
+    if suffix > 0 {
+        push_run(same, (offset.0 + old.len(), offset.1 + new.len(), suffix));
+    }
+}
+
+// Push a run of common values, joining it to the one before it if they
+// touch.
+fn push_run(same: &mut Vec<(usize, usize, usize)>, run: (usize, usize, usize)) {
+    if let Some(last) = same.last_mut() {
+        if last.0 + last.2 == run.0 && last.1 + last.2 == run.1 {
+            last.2 += run.2;
+            return;
+        }
+    }
+    same.push(run);
+}
+
+// Find the start of the middle snake of a shortest edit path from the
+// start of `old` and `new` to their ends, by searching forwards from the
+// start and backwards from the end at the same time until the two
+// searches meet. The slices mustn't be empty, and mustn't start or end
+// with the same value.
+fn middle_snake<T, F>(old: &[T], new: &[T], eq: &F) -> (usize, usize)
+where
+    F: Fn(&T, &T) -> bool,
+{
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let delta = n - m;
+    let odd = delta & 1 == 1;
+    let max = (n + m + 1) / 2 + 1;
+    // The furthest x reached on each diagonal k = x - y, searching
+    // forwards, and backwards with x and y counted from the ends.
+    let mut forward = vec![0; (2 * max + 1) as usize];
+    let mut backward = vec![0; (2 * max + 1) as usize];
+    let at = |k: isize| (k + max) as usize;
+    for d in 0..max {
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = if k == -d || (k != d && forward[at(k - 1)] < forward[at(k + 1)]) {
+                forward[at(k + 1)]
+            } else {
+                forward[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            let start = (x, y);
+            while x < n && y < m && eq(&old[x as usize], &new[y as usize]) {
+                x += 1;
+                y += 1;
+            }
+            forward[at(k)] = x;
+            if odd && (k - delta).abs() < d && x + backward[at(delta - k)] >= n {
+                return (start.0 as usize, start.1 as usize);
+            }
+        }
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = if k == -d || (k != d && backward[at(k - 1)] < backward[at(k + 1)]) {
+                backward[at(k + 1)]
+            } else {
+                backward[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && eq(&old[(n - x - 1) as usize], &new[(m - y - 1) as usize]) {
+                x += 1;
+                y += 1;
+            }
+            backward[at(k)] = x;
+            if !odd && (k - delta).abs() <= d && x + forward[at(delta - k)] >= n {
+                return ((n - x) as usize, (m - y) as usize);
+            }
+        }
+    }
+    unreachable!("the searches always meet")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::patch::VectorPatch;
+    use crate::proptest::vector;
+    use ::proptest::collection;
+    use ::proptest::num::{i16, usize};
+    use ::proptest::proptest;
+
+    #[test]
+    fn edit_to_large_vector_is_one_hunk() {
+        let old: Vector<u32> = (0..100_000).collect();
+        let mut new = old.clone();
+        new.insert(50_000, 7);
+        new.insert(50_001, 8);
+        assert_eq!(
+            vec![DiffItem::Insert {
+                index: 50_000,
+                values: vec![&7, &8]
+            }],
+            old.diff(&new).collect::<Vec<_>>()
+        );
+        new.remove(70_000);
+        new.set(80_000, 9);
+        assert_eq!(
+            vec![
+                DiffItem::Insert {
+                    index: 50_000,
+                    values: vec![&7, &8]
+                },
+                DiffItem::Delete {
+                    index: 69_998,
+                    values: vec![&69_998]
+                },
+                DiffItem::Replace {
+                    index: 79_999,
+                    old: vec![&79_999],
+                    new: vec![&9]
+                },
+            ],
+            old.diff(&new).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn equal_vectors_have_no_diff() {
+        let old: Vector<u32> = (0..10_000).collect();
+        let new: Vector<u32> = (0..10_000).collect();
+        assert_eq!(0, old.diff(&new).count());
+        assert_eq!(0, old.diff(&old.clone()).count());
+    }
+
+    // The length of the longest common subsequence, the slow way.
+    fn lcs(old: &[u8], new: &[u8]) -> usize {
+        let mut table = vec![vec![0; new.len() + 1]; old.len() + 1];
+        for i in (0..old.len()).rev() {
+            for j in (0..new.len()).rev() {
+                table[i][j] = if old[i] == new[j] {
+                    table[i + 1][j + 1] + 1
+                } else {
+                    table[i + 1][j].max(table[i][j + 1])
+                };
+            }
+        }
+        table[0][0]
+    }
+
+    proptest! {
+        #[test]
+        fn myers_is_minimal(
+            ref old in collection::vec(0..4u8, 0..40),
+            ref new in collection::vec(0..4u8, 0..40)
+        ) {
+            let mut same = Vec::new();
+            myers(old, new, &|left, right| left == right, (0, 0), &mut same);
+            let (mut old_end, mut new_end) = (0, 0);
+            for &(old_index, new_index, len) in &same {
+                assert!(len > 0);
+                assert!(old_index >= old_end && new_index >= new_end);
+                assert_eq!(old[old_index..old_index + len], new[new_index..new_index + len]);
+                old_end = old_index + len;
+                new_end = new_index + len;
+            }
+            assert_eq!(lcs(old, new), same.iter().map(|run| run.2).sum::<usize>());
+        }
+
+        #[test]
+        fn diff_patches_old_into_new(
+            ref old in vector(i16::ANY, 0..3000),
+            ref edits in collection::vec((usize::ANY, 0..3usize, i16::ANY), 0..20)
+        ) {
+            let mut new = old.clone();
+            for &(index, kind, value) in edits {
+                match kind {
+                    0 => new.insert(index % (new.len() + 1), value),
+                    1 if !new.is_empty() => {
+                        new.remove(index % new.len());
+                    }
+                    _ if !new.is_empty() => {
+                        new.set(index % new.len(), value);
+                    }
+                    _ => {}
+                }
+            }
+            let patch: VectorPatch<_> = old.diff(&new).collect();
+            let mut patched = old.clone();
+            patched.apply_patch(&patch);
+            assert_eq!(&new, &patched);
+            patched.apply_patch(&patch.invert());
+            assert_eq!(old, &patched);
+        }
+    }
+}
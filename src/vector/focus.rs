@@ -7,7 +7,10 @@ use std::ops::{Range, RangeBounds};
 use std::ptr::null;
 use std::sync::atomic::{AtomicPtr, Ordering};
 
-use crate::nodes::chunk::Chunk;
+use sized_chunks::Chunk;
+
+use crate::config::{DefaultVectorChunkSize, VectorChunkSize};
+use crate::error::Error;
 use crate::sync::Lock;
 use crate::util::{to_range, PoolRef, Ref};
 use crate::vector::{
@@ -82,21 +85,21 @@ use crate::vector::{
 /// [Iter]: struct.Iter.html
 /// [narrow]: #method.narrow
 /// [split_at]: #method.split_at
-pub enum Focus<'a, A> {
+pub enum Focus<'a, A, N: VectorChunkSize<A> = DefaultVectorChunkSize> {
     #[doc(hidden)]
     Single(&'a [A]),
     #[doc(hidden)]
-    Full(TreeFocus<A>),
+    Full(TreeFocus<A, N>),
 }
 
-impl<'a, A> Focus<'a, A>
+impl<'a, A, N: VectorChunkSize<A>> Focus<'a, A, N>
 where
     A: Clone + 'a,
 {
     /// Construct a `Focus` for a [`Vector`][Vector].
     ///
     /// [Vector]: enum.Vector.html
-    pub fn new(vector: &'a Vector<A>) -> Self {
+    pub fn new(vector: &'a Vector<A, N>) -> Self {
         match &vector.vector {
             Inline(_, chunk) => Focus::Single(chunk),
             Single(_, chunk) => Focus::Single(chunk),
@@ -136,6 +139,14 @@ where
         self.get(index).expect("index out of bounds")
     }
 
+    /// Get a reference to the value at a given index, or return an error
+    /// if the index is out of bounds.
+    pub fn try_index(&mut self, index: usize) -> Result<&A, Error> {
+        let len = self.len();
+        self.get(index)
+            .ok_or(Error::IndexOutOfBounds { index, len })
+    }
+
     /// Get the chunk for the given index.
     ///
     /// This gives you a reference to the leaf node that contains the index,
@@ -151,6 +162,13 @@ where
         }
     }
 
+    /// Get the chunk for the given index, or return an error if the index
+    /// is out of bounds.
+    pub fn try_chunk_at(&mut self, index: usize) -> Result<(Range<usize>, &[A]), Error> {
+        Error::check_index(index, self.len())?;
+        Ok(self.chunk_at(index))
+    }
+
     /// Narrow the focus onto a subslice of the vector.
     ///
     /// `Focus::narrow(range)` has the same effect as `&slice[range]`, without
@@ -164,9 +182,9 @@ where
     /// # #[macro_use] extern crate im;
     /// # use im::vector::Vector;
     /// # use std::iter::FromIterator;
-    /// let vec = Vector::from_iter(0..1000);
+    /// let vec: Vector<_> = Vector::from_iter(0..1000);
     /// let narrowed = vec.focus().narrow(100..200);
-    /// let narrowed_vec = narrowed.into_iter().cloned().collect();
+    /// let narrowed_vec: Vector<_> = narrowed.into_iter().cloned().collect();
     /// assert_eq!(Vector::from_iter(100..200), narrowed_vec);
     /// ```
     ///
@@ -186,6 +204,21 @@ where
         }
     }
 
+    /// Narrow the focus onto a subslice of the vector, or return an error
+    /// if the range isn't fully inside the current focus.
+    ///
+    /// Unlike [`narrow`][Focus::narrow], this accepts an empty range.
+    pub fn try_narrow<R>(self, range: R) -> Result<Self, Error>
+    where
+        R: RangeBounds<usize>,
+    {
+        let r = Error::check_range(&range, self.len())?;
+        Ok(match self {
+            Focus::Single(chunk) => Focus::Single(&chunk[r]),
+            Focus::Full(tree) => Focus::Full(tree.narrow(r)),
+        })
+    }
+
     /// Split the focus into two.
     ///
     /// Given an index `index`, consume the focus and produce two new foci, the
@@ -204,10 +237,10 @@ where
     /// # #[macro_use] extern crate im;
     /// # use im::vector::Vector;
     /// # use std::iter::FromIterator;
-    /// let vec = Vector::from_iter(0..1000);
+    /// let vec: Vector<_> = Vector::from_iter(0..1000);
     /// let (left, right) = vec.focus().split_at(500);
-    /// let left_vec = left.into_iter().cloned().collect();
-    /// let right_vec = right.into_iter().cloned().collect();
+    /// let left_vec: Vector<_> = left.into_iter().cloned().collect();
+    /// let right_vec: Vector<_> = right.into_iter().cloned().collect();
     /// assert_eq!(Vector::from_iter(0..500), left_vec);
     /// assert_eq!(Vector::from_iter(500..1000), right_vec);
     /// ```
@@ -229,21 +262,28 @@ where
             }
         }
     }
+
+    /// Split the focus into two, or return an error if the index is out of
+    /// bounds.
+    pub fn try_split_at(self, index: usize) -> Result<(Self, Self), Error> {
+        Error::check_index(index, self.len())?;
+        Ok(self.split_at(index))
+    }
 }
 
-impl<'a, A> IntoIterator for Focus<'a, A>
+impl<'a, A, N: VectorChunkSize<A>> IntoIterator for Focus<'a, A, N>
 where
     A: Clone + 'a,
 {
     type Item = &'a A;
-    type IntoIter = Iter<'a, A>;
+    type IntoIter = Iter<'a, A, N>;
 
     fn into_iter(self) -> Self::IntoIter {
         Iter::from_focus(self)
     }
 }
 
-impl<'a, A> Clone for Focus<'a, A>
+impl<'a, A, N: VectorChunkSize<A>> Clone for Focus<'a, A, N>
 where
     A: Clone + 'a,
 {
@@ -255,15 +295,15 @@ where
     }
 }
 
-pub struct TreeFocus<A> {
-    tree: Rrb<A>,
+pub struct TreeFocus<A, N: VectorChunkSize<A> = DefaultVectorChunkSize> {
+    tree: Rrb<A, N>,
     view: Range<usize>,
     middle_range: Range<usize>,
     target_range: Range<usize>,
-    target_ptr: *const Chunk<A>,
+    target_ptr: *const Chunk<A, N>,
 }
 
-impl<A> Clone for TreeFocus<A> {
+impl<A, N: VectorChunkSize<A>> Clone for TreeFocus<A, N> {
     fn clone(&self) -> Self {
         let tree = self.tree.clone();
         TreeFocus {
@@ -278,21 +318,21 @@ impl<A> Clone for TreeFocus<A> {
 
 #[allow(unsafe_code)]
 #[cfg(threadsafe)]
-unsafe impl<A: Send> Send for TreeFocus<A> {}
+unsafe impl<A: Send, N: VectorChunkSize<A>> Send for TreeFocus<A, N> {}
 #[allow(unsafe_code)]
 #[cfg(threadsafe)]
-unsafe impl<A: Sync> Sync for TreeFocus<A> {}
+unsafe impl<A: Sync, N: VectorChunkSize<A>> Sync for TreeFocus<A, N> {}
 
 #[inline]
 fn contains<A: Ord>(range: &Range<A>, index: &A) -> bool {
     *index >= range.start && *index < range.end
 }
 
-impl<A> TreeFocus<A>
+impl<A, N: VectorChunkSize<A>> TreeFocus<A, N>
 where
     A: Clone,
 {
-    fn new(tree: &Rrb<A>) -> Self {
+    fn new(tree: &Rrb<A, N>) -> Self {
         let middle_start = tree.outer_f.len() + tree.inner_f.len();
         let middle_end = middle_start + tree.middle.len();
         TreeFocus {
@@ -368,7 +408,7 @@ where
     }
 
     #[allow(unsafe_code)]
-    fn get_focus(&self) -> &Chunk<A> {
+    fn get_focus(&self) -> &Chunk<A, N> {
         unsafe { &*self.target_ptr }
     }
 
@@ -416,7 +456,7 @@ where
 /// # #[macro_use] extern crate im;
 /// # use im::vector::Vector;
 /// # use std::iter::FromIterator;
-/// let mut vec = Vector::from_iter(0..1000);
+/// let mut vec: Vector<_> = Vector::from_iter(0..1000);
 /// let focus1 = vec.focus_mut();
 /// // Fails here in 2015 edition because you're creating
 /// // two mutable references to the same thing.
@@ -433,7 +473,7 @@ where
 /// # #[macro_use] extern crate im;
 /// # use im::vector::Vector;
 /// # use std::iter::FromIterator;
-/// let mut vec = Vector::from_iter(0..1000);
+/// let mut vec: Vector<_> = Vector::from_iter(0..1000);
 /// let focus = vec.focus_mut();
 /// let (mut left, mut right) = focus.split_at(500);
 /// assert_eq!(Some(&0), left.get(0));
@@ -447,7 +487,7 @@ where
 /// # #[macro_use] extern crate im;
 /// # use im::vector::Vector;
 /// # use std::iter::FromIterator;
-/// let mut vec = Vector::from_iter(0..1000);
+/// let mut vec: Vector<_> = Vector::from_iter(0..1000);
 /// let (left, right) = {
 ///     let focus = vec.focus_mut();
 ///     focus.split_at(500)
@@ -459,19 +499,19 @@ where
 /// ```
 ///
 /// [Focus]: enum.Focus.html
-pub enum FocusMut<'a, A> {
+pub enum FocusMut<'a, A, N: VectorChunkSize<A> = DefaultVectorChunkSize> {
     #[doc(hidden)]
-    Single(RRBPool<A>, &'a mut [A]),
+    Single(RRBPool<A, N>, &'a mut [A]),
     #[doc(hidden)]
-    Full(RRBPool<A>, TreeFocusMut<'a, A>),
+    Full(RRBPool<A, N>, TreeFocusMut<'a, A, N>),
 }
 
-impl<'a, A> FocusMut<'a, A>
+impl<'a, A, N: VectorChunkSize<A>> FocusMut<'a, A, N>
 where
     A: Clone + 'a,
 {
     /// Construct a `FocusMut` for a `Vector`.
-    pub fn new(vector: &'a mut Vector<A>) -> Self {
+    pub fn new(vector: &'a mut Vector<A, N>) -> Self {
         match &mut vector.vector {
             Inline(pool, chunk) => FocusMut::Single(pool.clone(), chunk),
             Single(pool, chunk) => FocusMut::Single(
@@ -523,6 +563,20 @@ where
         self.get_mut(index).expect("index out of bounds")
     }
 
+    /// Get a reference to the value at a given index, or return an error
+    /// if the index is out of bounds.
+    pub fn try_index(&mut self, index: usize) -> Result<&A, Error> {
+        self.try_index_mut(index).map(|value| &*value)
+    }
+
+    /// Get a mutable reference to the value at a given index, or return an
+    /// error if the index is out of bounds.
+    pub fn try_index_mut(&mut self, index: usize) -> Result<&mut A, Error> {
+        let len = self.len();
+        self.get_mut(index)
+            .ok_or(Error::IndexOutOfBounds { index, len })
+    }
+
     /// Update the value at a given index.
     ///
     /// Returns `None` if the index is out of bounds, or the replaced value
@@ -543,6 +597,17 @@ where
         self.pair(a, b, |left, right| swap(left, right));
     }
 
+    /// Swap the values at two given indices, or return an error if either
+    /// index is out of bounds.
+    ///
+    /// If the indices are equal, this function returns without doing anything.
+    pub fn checked_swap(&mut self, a: usize, b: usize) -> Result<(), Error> {
+        Error::check_index(a, self.len())?;
+        Error::check_index(b, self.len())?;
+        self.swap(a, b);
+        Ok(())
+    }
+
     /// Lookup two indices simultaneously and run a function over them.
     ///
     /// Useful because the borrow checker won't let you have more than one
@@ -573,6 +638,21 @@ where
         unsafe { f(&mut *pa, &mut *pb) }
     }
 
+    /// Lookup two indices simultaneously and run a function over them, or
+    /// return an error if either index is out of bounds or if they are the
+    /// same index.
+    pub fn try_pair<F, B>(&mut self, a: usize, b: usize, f: F) -> Result<B, Error>
+    where
+        F: FnMut(&mut A, &mut A) -> B,
+    {
+        Error::check_index(a, self.len())?;
+        Error::check_index(b, self.len())?;
+        if a == b {
+            return Err(Error::DuplicateIndex { index: a });
+        }
+        Ok(self.pair(a, b, f))
+    }
+
     /// Lookup three indices simultaneously and run a function over them.
     ///
     /// Useful because the borrow checker won't let you have more than one
@@ -604,6 +684,25 @@ where
         unsafe { f(&mut *pa, &mut *pb, &mut *pc) }
     }
 
+    /// Lookup three indices simultaneously and run a function over them, or
+    /// return an error if any index is out of bounds or if any indices are
+    /// equal.
+    pub fn try_triplet<F, B>(&mut self, a: usize, b: usize, c: usize, f: F) -> Result<B, Error>
+    where
+        F: FnMut(&mut A, &mut A, &mut A) -> B,
+    {
+        Error::check_index(a, self.len())?;
+        Error::check_index(b, self.len())?;
+        Error::check_index(c, self.len())?;
+        if a == b || a == c {
+            return Err(Error::DuplicateIndex { index: a });
+        }
+        if b == c {
+            return Err(Error::DuplicateIndex { index: b });
+        }
+        Ok(self.triplet(a, b, c, f))
+    }
+
     /// Get the chunk for the given index.
     ///
     /// This gives you a reference to the leaf node that contains the index,
@@ -622,6 +721,13 @@ where
         }
     }
 
+    /// Get the chunk for the given index, or return an error if the index
+    /// is out of bounds.
+    pub fn try_chunk_at(&mut self, index: usize) -> Result<(Range<usize>, &mut [A]), Error> {
+        Error::check_index(index, self.len())?;
+        Ok(self.chunk_at(index))
+    }
+
     /// Narrow the focus onto a subslice of the vector.
     ///
     /// `FocusMut::narrow(range)` has the same effect as `&slice[range]`, without
@@ -635,9 +741,9 @@ where
     /// # #[macro_use] extern crate im;
     /// # use im::vector::Vector;
     /// # use std::iter::FromIterator;
-    /// let mut vec = Vector::from_iter(0..1000);
+    /// let mut vec: Vector<_> = Vector::from_iter(0..1000);
     /// let narrowed = vec.focus_mut().narrow(100..200);
-    /// let narrowed_vec = narrowed.unmut().into_iter().cloned().collect();
+    /// let narrowed_vec: Vector<_> = narrowed.unmut().into_iter().cloned().collect();
     /// assert_eq!(Vector::from_iter(100..200), narrowed_vec);
     /// ```
     ///
@@ -657,6 +763,16 @@ where
         }
     }
 
+    /// Narrow the focus onto a subslice of the vector, or return an error
+    /// if the range isn't fully inside the current focus.
+    pub fn try_narrow<R>(self, range: R) -> Result<Self, Error>
+    where
+        R: RangeBounds<usize>,
+    {
+        let r = Error::check_range(&range, self.len())?;
+        Ok(self.narrow(r))
+    }
+
     /// Split the focus into two.
     ///
     /// Given an index `index`, consume the focus and produce two new foci, the
@@ -675,7 +791,7 @@ where
     /// # #[macro_use] extern crate im;
     /// # use im::vector::Vector;
     /// # use std::iter::FromIterator;
-    /// let mut vec = Vector::from_iter(0..1000);
+    /// let mut vec: Vector<_> = Vector::from_iter(0..1000);
     /// {
     ///     let (left, right) = vec.focus_mut().split_at(500);
     ///     for ptr in left {
@@ -685,7 +801,7 @@ where
     ///         *ptr -= 100;
     ///     }
     /// }
-    /// let expected = Vector::from_iter(100..600)
+    /// let expected: Vector<_> = Vector::from_iter(100..600)
     ///              + Vector::from_iter(400..900);
     /// assert_eq!(expected, vec);
     /// ```
@@ -715,8 +831,15 @@ where
         }
     }
 
+    /// Split the focus into two, or return an error if the index is past
+    /// the end of the focus.
+    pub fn try_split_at(self, index: usize) -> Result<(Self, Self), Error> {
+        Error::check_position(index, self.len())?;
+        Ok(self.split_at(index))
+    }
+
     /// Convert a `FocusMut` into a `Focus`.
-    pub fn unmut(self) -> Focus<'a, A> {
+    pub fn unmut(self) -> Focus<'a, A, N> {
         match self {
             FocusMut::Single(_, chunk) => Focus::Single(chunk),
             FocusMut::Full(_, mut tree) => Focus::Full(TreeFocus {
@@ -733,40 +856,40 @@ where
     }
 }
 
-impl<'a, A> IntoIterator for FocusMut<'a, A>
+impl<'a, A, N: VectorChunkSize<A>> IntoIterator for FocusMut<'a, A, N>
 where
     A: Clone + 'a,
 {
     type Item = &'a mut A;
-    type IntoIter = IterMut<'a, A>;
+    type IntoIter = IterMut<'a, A, N>;
 
     fn into_iter(self) -> Self::IntoIter {
         IterMut::from_focus(self)
     }
 }
 
-impl<'a, A> From<FocusMut<'a, A>> for Focus<'a, A>
+impl<'a, A, N: VectorChunkSize<A>> From<FocusMut<'a, A, N>> for Focus<'a, A, N>
 where
     A: Clone + 'a,
 {
-    fn from(f: FocusMut<'a, A>) -> Self {
+    fn from(f: FocusMut<'a, A, N>) -> Self {
         f.unmut()
     }
 }
 
-pub struct TreeFocusMut<'a, A> {
-    tree: Lock<&'a mut Rrb<A>>,
+pub struct TreeFocusMut<'a, A, N: VectorChunkSize<A> = DefaultVectorChunkSize> {
+    tree: Lock<&'a mut Rrb<A, N>>,
     view: Range<usize>,
     middle_range: Range<usize>,
     target_range: Range<usize>,
-    target_ptr: AtomicPtr<Chunk<A>>,
+    target_ptr: AtomicPtr<Chunk<A, N>>,
 }
 
-impl<'a, A> TreeFocusMut<'a, A>
+impl<'a, A, N: VectorChunkSize<A>> TreeFocusMut<'a, A, N>
 where
     A: Clone + 'a,
 {
-    fn new(tree: &'a mut Rrb<A>) -> Self {
+    fn new(tree: &'a mut Rrb<A, N>) -> Self {
         let middle_start = tree.outer_f.len() + tree.inner_f.len();
         let middle_end = middle_start + tree.middle.len();
         TreeFocusMut {
@@ -824,7 +947,7 @@ where
         (range.start - self.view.start)..(range.end - self.view.start)
     }
 
-    fn set_focus(&mut self, pool: &RRBPool<A>, index: usize) {
+    fn set_focus(&mut self, pool: &RRBPool<A, N>, index: usize) {
         let mut tree = self
             .tree
             .lock()
@@ -871,11 +994,11 @@ where
     }
 
     #[allow(unsafe_code)]
-    fn get_focus(&mut self) -> &mut Chunk<A> {
+    fn get_focus(&mut self) -> &mut Chunk<A, N> {
         unsafe { &mut *self.target_ptr.load(Ordering::Relaxed) }
     }
 
-    pub fn get(&mut self, pool: &RRBPool<A>, index: usize) -> Option<&mut A> {
+    pub fn get(&mut self, pool: &RRBPool<A, N>, index: usize) -> Option<&mut A> {
         if index >= self.len() {
             return None;
         }
@@ -887,7 +1010,7 @@ where
         Some(&mut self.get_focus()[target_phys_index])
     }
 
-    pub fn get_chunk(&mut self, pool: &RRBPool<A>, index: usize) -> (Range<usize>, &mut [A]) {
+    pub fn get_chunk(&mut self, pool: &RRBPool<A, N>, index: usize) -> (Range<usize>, &mut [A]) {
         let phys_index = self.physical_index(index);
         if !contains(&self.target_range, &phys_index) {
             self.set_focus(pool, phys_index);
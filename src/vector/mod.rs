@@ -45,22 +45,34 @@
 
 use std::borrow::Borrow;
 use std::cmp::Ordering;
-use std::fmt::{Debug, Error, Formatter};
+use std::fmt::{self, Debug, Formatter};
 use std::hash::{Hash, Hasher};
 use std::iter::Sum;
 use std::iter::{FromIterator, FusedIterator};
 use std::mem::{replace, swap};
 use std::ops::{Add, Index, IndexMut, RangeBounds};
 
-use sized_chunks::InlineArray;
-
-use crate::nodes::chunk::{Chunk, CHUNK_SIZE};
-use crate::nodes::rrb::{Node, PopResult, PushResult, SplitResult};
+use sized_chunks::{types::ChunkLength, Chunk, InlineArray};
+
+use crate::config::{DefaultVectorChunkSize, VectorChunkSize};
+#[cfg(any(test, feature = "debug"))]
+use crate::debug::{InvariantViolation, Stats, Walk, Walker};
+use crate::error::Error;
+use crate::memory::{self, HeapNodes, HeapSize, MemoryReport, Visitor};
+#[cfg(any(test, feature = "debug"))]
+use crate::nodes::rrb::walk_chunk;
+use crate::nodes::rrb::{visit_chunk, Node, PopResult, PushResult, SplitResult};
+use crate::patch::VectorPatch;
 use crate::sort;
-use crate::util::{clone_ref, swap_indices, to_range, Pool, PoolDefault, PoolRef, Ref, Side};
+use crate::util::{
+    clone_ref, swap_indices, to_range, transaction, NodeType, Pool, PoolDefault, PoolRef, Ref, Side,
+};
 
 use self::VectorInner::{Full, Inline, Single};
 
+mod diff;
+pub use self::diff::{DiffItem, DiffIter};
+
 mod focus;
 
 pub use self::focus::{Focus, FocusMut};
@@ -142,28 +154,28 @@ macro_rules! vector {
 /// [chunkedseq]: http://deepsea.inria.fr/pasl/chunkedseq.pdf
 /// [Vec]: https://doc.rust-lang.org/std/vec/struct.Vec.html
 /// [VecDeque]: https://doc.rust-lang.org/std/collections/struct.VecDeque.html
-pub struct Vector<A> {
-    vector: VectorInner<A>,
+pub struct Vector<A, N: VectorChunkSize<A> = DefaultVectorChunkSize> {
+    vector: VectorInner<A, N>,
 }
 
-enum VectorInner<A> {
-    Inline(RRBPool<A>, InlineArray<A, Rrb<A>>),
-    Single(RRBPool<A>, PoolRef<Chunk<A>>),
-    Full(RRBPool<A>, Rrb<A>),
+enum VectorInner<A, N: VectorChunkSize<A>> {
+    Inline(RRBPool<A, N>, InlineArray<A, Rrb<A, N>>),
+    Single(RRBPool<A, N>, PoolRef<Chunk<A, N>>),
+    Full(RRBPool<A, N>, Rrb<A, N>),
 }
 
 #[doc(hidden)]
-pub struct Rrb<A> {
+pub struct Rrb<A, N: VectorChunkSize<A> = DefaultVectorChunkSize> {
     length: usize,
     middle_level: usize,
-    outer_f: PoolRef<Chunk<A>>,
-    inner_f: PoolRef<Chunk<A>>,
-    middle: Ref<Node<A>>,
-    inner_b: PoolRef<Chunk<A>>,
-    outer_b: PoolRef<Chunk<A>>,
+    outer_f: PoolRef<Chunk<A, N>>,
+    inner_f: PoolRef<Chunk<A, N>>,
+    middle: Ref<Node<A, N>>,
+    inner_b: PoolRef<Chunk<A, N>>,
+    outer_b: PoolRef<Chunk<A, N>>,
 }
 
-impl<A> Clone for Rrb<A> {
+impl<A, N: VectorChunkSize<A>> Clone for Rrb<A, N> {
     fn clone(&self) -> Self {
         Rrb {
             length: self.length,
@@ -178,12 +190,61 @@ impl<A> Clone for Rrb<A> {
 }
 
 impl<A: Clone> Vector<A> {
+    /// Construct an empty vector.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            vector: Inline(RRBPool::default(), InlineArray::new()),
+        }
+    }
+
+    /// Construct an empty vector using a specific memory pool.
+    #[cfg(feature = "pool")]
+    #[must_use]
+    pub fn with_pool(pool: &RRBPool<A>) -> Self {
+        Self::with_pool_and_node_size(pool)
+    }
+
+    /// Construct a vector with a single value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::vector::Vector;
+    /// let vec = Vector::unit(1337);
+    /// assert_eq!(1, vec.len());
+    /// assert_eq!(
+    ///   vec.get(0),
+    ///   Some(&1337)
+    /// );
+    /// ```
+    #[inline]
+    #[must_use]
+    pub fn unit(a: A) -> Self {
+        let pool = RRBPool::default();
+        if InlineArray::<A, Rrb<A>>::CAPACITY > 0 {
+            let mut array = InlineArray::new();
+            array.push(a);
+            Self {
+                vector: Inline(pool, array),
+            }
+        } else {
+            let chunk = PoolRef::new(&pool.value_pool, Chunk::unit(a));
+            Self {
+                vector: Single(pool, chunk),
+            }
+        }
+    }
+}
+
+impl<A: Clone, N: VectorChunkSize<A>> Vector<A, N> {
     /// Get a reference to the memory pool this `Vector` is using.
     ///
     /// Note that if you didn't specifically construct it with a pool, you'll
     /// get back a reference to a pool of size 0.
     #[cfg_attr(not(feature = "pool"), doc(hidden))]
-    pub fn pool(&self) -> &RRBPool<A> {
+    pub fn pool(&self) -> &RRBPool<A, N> {
         match self.vector {
             Inline(ref pool, _) => pool,
             Single(ref pool, _) => pool,
@@ -260,18 +321,65 @@ impl<A: Clone> Vector<A> {
         }
     }
 
-    /// Construct an empty vector.
+    /// Construct an empty vector with a node size other than the
+    /// default.
+    ///
+    /// [`new`][new], [`Default`][Default] and [`FromIterator`][FromIterator]
+    /// always build vectors of the default node size, so that the type
+    /// of a vector can be inferred from its elements alone.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use im::vector::Vector;
+    /// use im::config::U128;
+    ///
+    /// let mut bytes = Vector::<u8, U128>::with_node_size();
+    /// bytes.extend(0..=255);
+    /// assert_eq!(256, bytes.len());
+    /// ```
+    ///
+    /// [new]: #method.new
+    /// [Default]: https://doc.rust-lang.org/std/default/trait.Default.html
+    /// [FromIterator]: https://doc.rust-lang.org/std/iter/trait.FromIterator.html
     #[must_use]
-    pub fn new() -> Self {
+    pub fn with_node_size() -> Self {
         Self {
             vector: Inline(RRBPool::default(), InlineArray::new()),
         }
     }
 
-    /// Construct an empty vector using a specific memory pool.
+    /// Construct a vector with a node size other than the default from
+    /// an iterator.
+    ///
+    /// Time: O(n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use im::vector::Vector;
+    /// use im::config::U128;
+    ///
+    /// let bytes = Vector::<u8, U128>::from_iter_with_node_size(0..=255);
+    /// assert_eq!(Some(&255), bytes.last());
+    /// ```
+    #[must_use]
+    pub fn from_iter_with_node_size<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = A>,
+    {
+        let mut seq = Self::with_node_size();
+        for item in iter {
+            seq.push_back(item)
+        }
+        seq
+    }
+
+    /// Construct an empty vector with a node size other than the
+    /// default, using a specific memory pool.
     #[cfg(feature = "pool")]
     #[must_use]
-    pub fn with_pool(pool: &RRBPool<A>) -> Self {
+    pub fn with_pool_and_node_size(pool: &RRBPool<A, N>) -> Self {
         Self {
             vector: Inline(pool.clone(), InlineArray::new()),
         }
@@ -353,7 +461,10 @@ impl<A: Clone> Vector<A> {
     /// Time: O(1)
     #[must_use]
     pub fn ptr_eq(&self, other: &Self) -> bool {
-        fn cmp_chunk<A>(left: &PoolRef<Chunk<A>>, right: &PoolRef<Chunk<A>>) -> bool {
+        fn cmp_chunk<A, N: ChunkLength<A>>(
+            left: &PoolRef<Chunk<A, N>>,
+            right: &PoolRef<Chunk<A, N>>,
+        ) -> bool {
             (left.is_empty() && right.is_empty()) || PoolRef::ptr_eq(left, right)
         }
 
@@ -380,7 +491,7 @@ impl<A: Clone> Vector<A> {
     /// Time: O(1)
     #[inline]
     #[must_use]
-    pub fn iter(&self) -> Iter<'_, A> {
+    pub fn iter(&self) -> Iter<'_, A, N> {
         Iter::new(self)
     }
 
@@ -389,7 +500,7 @@ impl<A: Clone> Vector<A> {
     /// Time: O(1)
     #[inline]
     #[must_use]
-    pub fn iter_mut(&mut self) -> IterMut<'_, A> {
+    pub fn iter_mut(&mut self) -> IterMut<'_, A, N> {
         IterMut::new(self)
     }
 
@@ -404,7 +515,7 @@ impl<A: Clone> Vector<A> {
     /// [Chunk]: ../chunk/struct.Chunk.html
     #[inline]
     #[must_use]
-    pub fn leaves(&self) -> Chunks<'_, A> {
+    pub fn leaves(&self) -> Chunks<'_, A, N> {
         Chunks::new(self)
     }
 
@@ -419,10 +530,87 @@ impl<A: Clone> Vector<A> {
     /// [Chunk]: ../chunk/struct.Chunk.html
     #[inline]
     #[must_use]
-    pub fn leaves_mut(&mut self) -> ChunksMut<'_, A> {
+    pub fn leaves_mut(&mut self) -> ChunksMut<'_, A, N> {
         ChunksMut::new(self)
     }
 
+    /// Get an iterator over the differences between this vector and
+    /// another, as hunks of values to insert, delete or replace to turn
+    /// this vector into the other one.
+    ///
+    /// The leaves of the two vectors are lined up first, and leaves
+    /// which are shared between them are skipped without looking at
+    /// their values, so comparing two versions of a large vector made
+    /// from one another is quick. The values between the shared leaves
+    /// are compared using Myers' diff algorithm, so the hunks are as
+    /// small as they can be around the leaves the vectors share.
+    ///
+    /// The hunks can be collected into a [`VectorPatch`][VectorPatch]
+    /// and applied to another copy of this vector.
+    ///
+    /// Time: O(n/k + (m + d) d) where n is the length of the vectors, k is
+    /// their chunk size, m is the number of values in leaves they don't
+    /// share, and d is the number of values inserted or removed
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::vector::{DiffItem, Vector};
+    /// let old = vector![1, 2, 3, 4];
+    /// let new = vector![1, 5, 3];
+    /// assert_eq!(
+    ///     vec![
+    ///         DiffItem::Replace { index: 1, old: vec![&2], new: vec![&5] },
+    ///         DiffItem::Delete { index: 3, values: vec![&4] },
+    ///     ],
+    ///     old.diff(&new).collect::<Vec<_>>()
+    /// );
+    /// ```
+    ///
+    /// [VectorPatch]: ../patch/struct.VectorPatch.html
+    #[must_use]
+    pub fn diff<'a>(&'a self, other: &'a Self) -> DiffIter<'a, A>
+    where
+        A: PartialEq,
+    {
+        DiffIter::new(self, other)
+    }
+
+    /// Walk two vectors side by side, calling `f` with the parts of their
+    /// common length where they don't share a leaf, until it returns a
+    /// result.
+    ///
+    /// The slices given to `f` have equal lengths and start at the same
+    /// index in both vectors.
+    fn find_unshared<R, F>(&self, other: &Self, mut f: F) -> Option<R>
+    where
+        F: FnMut(&[A], &[A]) -> Option<R>,
+    {
+        let mut left_leaves = self.leaves();
+        let mut right_leaves = other.leaves();
+        let mut left: &[A] = &[];
+        let mut right: &[A] = &[];
+        loop {
+            if left.is_empty() {
+                left = left_leaves.next()?;
+                continue;
+            }
+            if right.is_empty() {
+                right = right_leaves.next()?;
+                continue;
+            }
+            let len = left.len().min(right.len());
+            if !std::ptr::eq(left.as_ptr(), right.as_ptr()) {
+                if let Some(result) = f(&left[..len], &right[..len]) {
+                    return Some(result);
+                }
+            }
+            left = &left[len..];
+            right = &right[len..];
+        }
+    }
+
     /// Construct a [`Focus`][Focus] for a vector.
     ///
     /// Time: O(1)
@@ -430,7 +618,7 @@ impl<A: Clone> Vector<A> {
     /// [Focus]: enum.Focus.html
     #[inline]
     #[must_use]
-    pub fn focus(&self) -> Focus<'_, A> {
+    pub fn focus(&self) -> Focus<'_, A, N> {
         Focus::new(self)
     }
 
@@ -441,7 +629,7 @@ impl<A: Clone> Vector<A> {
     /// [FocusMut]: enum.FocusMut.html
     #[inline]
     #[must_use]
-    pub fn focus_mut(&mut self) -> FocusMut<'_, A> {
+    pub fn focus_mut(&mut self) -> FocusMut<'_, A, N> {
         FocusMut::new(self)
     }
 
@@ -785,39 +973,7 @@ impl<A: Clone> Vector<A> {
     }
 }
 
-impl<A: Clone> Vector<A> {
-    /// Construct a vector with a single value.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// # #[macro_use] extern crate im;
-    /// # use im::vector::Vector;
-    /// let vec = Vector::unit(1337);
-    /// assert_eq!(1, vec.len());
-    /// assert_eq!(
-    ///   vec.get(0),
-    ///   Some(&1337)
-    /// );
-    /// ```
-    #[inline]
-    #[must_use]
-    pub fn unit(a: A) -> Self {
-        let pool = RRBPool::default();
-        if InlineArray::<A, Rrb<A>>::CAPACITY > 0 {
-            let mut array = InlineArray::new();
-            array.push(a);
-            Self {
-                vector: Inline(pool, array),
-            }
-        } else {
-            let chunk = PoolRef::new(&pool.value_pool, Chunk::unit(a));
-            Self {
-                vector: Single(pool, chunk),
-            }
-        }
-    }
-
+impl<A: Clone, N: VectorChunkSize<A>> Vector<A, N> {
     /// Create a new vector with the value at index `index` updated.
     ///
     /// Panics if the index is out of bounds.
@@ -839,6 +995,25 @@ impl<A: Clone> Vector<A> {
         out
     }
 
+    /// Create a new vector with the value at index `index` updated, or
+    /// return an error if the index is out of bounds.
+    ///
+    /// Time: O(log n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::error::Error;
+    /// let vec = vector![1, 2, 3];
+    /// assert_eq!(Ok(vector![1, 5, 3]), vec.try_update(1, 5));
+    /// assert_eq!(Err(Error::IndexOutOfBounds { index: 3, len: 3 }), vec.try_update(3, 5));
+    /// ```
+    pub fn try_update(&self, index: usize, value: A) -> Result<Self, Error> {
+        Error::check_index(index, self.len())?;
+        Ok(self.update(index, value))
+    }
+
     /// Update the value at index `index` in a vector.
     ///
     /// Returns the previous value at the index.
@@ -851,6 +1026,20 @@ impl<A: Clone> Vector<A> {
         replace(&mut self[index], value)
     }
 
+    /// Update the value at index `index` in a vector, or return an error
+    /// if the index is out of bounds.
+    ///
+    /// Returns the previous value at the index.
+    ///
+    /// Time: O(log n)
+    pub fn try_set(&mut self, index: usize, value: A) -> Result<A, Error> {
+        let len = self.len();
+        match self.get_mut(index) {
+            Some(slot) => Ok(replace(slot, value)),
+            None => Err(Error::IndexOutOfBounds { index, len }),
+        }
+    }
+
     /// Swap the elements at indices `i` and `j`.
     ///
     /// Time: O(log n)
@@ -858,6 +1047,17 @@ impl<A: Clone> Vector<A> {
         swap_indices(self, i, j)
     }
 
+    /// Swap the elements at indices `i` and `j`, or return an error if
+    /// either index is out of bounds.
+    ///
+    /// Time: O(log n)
+    pub fn checked_swap(&mut self, i: usize, j: usize) -> Result<(), Error> {
+        Error::check_index(i, self.len())?;
+        Error::check_index(j, self.len())?;
+        self.swap(i, j);
+        Ok(())
+    }
+
     /// Push a value to the front of a vector.
     ///
     /// Time: O(1)*
@@ -998,14 +1198,14 @@ impl<A: Clone> Vector<A> {
                     Inline(_, _) => unreachable!("inline vecs should have been promoted"),
                     // If both are single chunks and left has room for right: directly
                     // memcpy right into left
-                    Single(_, ref mut right) if total_length <= CHUNK_SIZE => {
+                    Single(_, ref mut right) if total_length <= N::USIZE => {
                         PoolRef::make_mut(&pool.value_pool, left)
                             .append(PoolRef::make_mut(&pool.value_pool, right));
                         return;
                     }
                     // If only left is a single chunk and has room for right: push
                     // right's elements into left
-                    _ if total_length <= CHUNK_SIZE => {
+                    _ if total_length <= N::USIZE => {
                         while let Some(value) = other.pop_front() {
                             PoolRef::make_mut(&pool.value_pool, left).push_back(value);
                         }
@@ -1035,7 +1235,7 @@ impl<A: Clone> Vector<A> {
                     // can fit right's buffers: push right's elements onto left
                     if left.middle.is_empty()
                         && right.middle.is_empty()
-                        && total_length <= CHUNK_SIZE * 4
+                        && total_length <= N::USIZE * 4
                     {
                         while let Some(value) = right.pop_front(pool) {
                             left.push_back(pool, value);
@@ -1133,6 +1333,30 @@ impl<A: Clone> Vector<A> {
         (self, right)
     }
 
+    /// Split a vector at a given index, or return an error if the index
+    /// is past the end of the vector.
+    ///
+    /// The vector is consumed either way; use
+    /// [`try_split_off`][Vector::try_split_off] to keep it on failure.
+    ///
+    /// Time: O(log n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::error::Error;
+    /// let vec = vector![1, 2, 3, 7, 8, 9];
+    /// let (left, right) = vec.clone().try_split_at(3).unwrap();
+    /// assert_eq!(vector![1, 2, 3], left);
+    /// assert_eq!(vector![7, 8, 9], right);
+    /// assert_eq!(Err(Error::IndexOutOfBounds { index: 7, len: 6 }), vec.try_split_at(7));
+    /// ```
+    pub fn try_split_at(mut self, index: usize) -> Result<(Self, Self), Error> {
+        let right = self.try_split_off(index)?;
+        Ok((self, right))
+    }
+
     /// Split a vector at a given index.
     ///
     /// Split a vector at a given index, leaving the left hand side in
@@ -1198,7 +1422,7 @@ impl<A: Clone> Vector<A> {
                         length: tree.length - index,
                         middle_level: tree.middle_level,
                         outer_f: PoolRef::new(&pool.value_pool, if2),
-                        inner_f: PoolRef::<Chunk<A>>::default(&pool.value_pool),
+                        inner_f: PoolRef::<Chunk<A, N>>::default(&pool.value_pool),
                         middle: std::mem::take(&mut tree.middle),
                         inner_b: replace_pool_def(&pool.value_pool, &mut tree.inner_b),
                         outer_b: replace_pool_def(&pool.value_pool, &mut tree.outer_b),
@@ -1248,7 +1472,7 @@ impl<A: Clone> Vector<A> {
                         length: tree.length - index,
                         middle_level: tree.middle_level,
                         outer_f: c2,
-                        inner_f: PoolRef::<Chunk<A>>::default(&pool.value_pool),
+                        inner_f: PoolRef::<Chunk<A, N>>::default(&pool.value_pool),
                         middle: right_middle,
                         inner_b: replace_pool_def(&pool.value_pool, &mut tree.inner_b),
                         outer_b: replace(&mut tree.outer_b, c1),
@@ -1291,6 +1515,83 @@ impl<A: Clone> Vector<A> {
         }
     }
 
+    /// Split a vector at a given index, leaving the left hand side in the
+    /// current vector and returning a new vector containing the right hand
+    /// side, or return an error if the index is past the end of the vector.
+    ///
+    /// Time: O(log n)
+    pub fn try_split_off(&mut self, index: usize) -> Result<Self, Error> {
+        Error::check_position(index, self.len())?;
+        Ok(self.split_off(index))
+    }
+
+    /// Apply a patch to the vector, replacing the values each of its
+    /// edits removes with the values it inserts.
+    ///
+    /// The vector is split at each edit and joined back together, so
+    /// the runs of values between the edits are shared with the
+    /// original vector rather than copied.
+    ///
+    /// Time: O(k log n + m) for a patch of k edits inserting m values
+    ///
+    /// # Panics
+    ///
+    /// Panics if the edits aren't sorted by index, overlap, or reach
+    /// past the end of the vector.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::vector::Vector;
+    /// # use im::patch::{Edit, VectorPatch};
+    /// let mut vec = vector![1, 2, 3, 4, 5];
+    /// let patch: VectorPatch<_> = vec![
+    ///     Edit { index: 1, removed: vec![2], inserted: vec![7, 8] },
+    ///     Edit { index: 4, removed: vec![5], inserted: vec![] },
+    /// ].into_iter().collect();
+    /// vec.apply_patch(&patch);
+    /// assert_eq!(vector![1, 7, 8, 3, 4], vec);
+    /// ```
+    pub fn apply_patch(&mut self, patch: &VectorPatch<A>) {
+        let empty = Self {
+            vector: Inline(self.pool().clone(), InlineArray::new()),
+        };
+        let mut rest = replace(self, empty);
+        // The index in the original vector where `rest` starts.
+        let mut offset = 0;
+        for edit in patch.edits() {
+            assert!(
+                edit.index >= offset,
+                "Vector::apply_patch: edits must be sorted and must not overlap"
+            );
+            let tail = rest.split_off(edit.index - offset);
+            self.append(rest);
+            rest = tail;
+            assert!(
+                edit.removed.len() <= rest.len(),
+                "Vector::apply_patch: edit removes past the end of the vector"
+            );
+            rest = rest.split_off(edit.removed.len());
+            offset = edit.index + edit.removed.len();
+            self.extend(edit.inserted.iter().cloned());
+        }
+        self.append(rest);
+    }
+
+    /// Edit the vector in a [transaction][transactions]: `f` edits a
+    /// copy of the vector, which replaces it only if `f` returns `Ok`.
+    ///
+    /// Time: O(1), plus the edits
+    ///
+    /// [transactions]: crate#transactions
+    pub fn transaction<F, R, E>(&mut self, f: F) -> Result<R, E>
+    where
+        F: FnOnce(&mut Self) -> Result<R, E>,
+    {
+        transaction(self, f)
+    }
+
     /// Construct a vector with `count` elements removed from the
     /// start of the current vector.
     ///
@@ -1313,6 +1614,25 @@ impl<A: Clone> Vector<A> {
         left
     }
 
+    /// Construct a vector with `count` elements removed from the start of
+    /// the current vector, or return an error if `count` is greater than
+    /// its length.
+    ///
+    /// Time: O(log n)
+    pub fn try_skip(&self, count: usize) -> Result<Self, Error> {
+        self.clone().try_split_off(count)
+    }
+
+    /// Construct a vector of the first `count` elements from the current
+    /// vector, or return an error if `count` is greater than its length.
+    ///
+    /// Time: O(log n)
+    pub fn try_take(&self, count: usize) -> Result<Self, Error> {
+        let mut left = self.clone();
+        left.try_split_off(count)?;
+        Ok(left)
+    }
+
     /// Truncate a vector to the given size.
     ///
     /// Discards all elements in the vector beyond the given length.
@@ -1325,6 +1645,14 @@ impl<A: Clone> Vector<A> {
         self.split_off(len);
     }
 
+    /// Truncate a vector to the given size, or return an error if the new
+    /// length is greater than the current length.
+    ///
+    /// Time: O(log n)
+    pub fn try_truncate(&mut self, len: usize) -> Result<(), Error> {
+        self.try_split_off(len).map(|_| ())
+    }
+
     /// Extract a slice from a vector.
     ///
     /// Remove the elements from `start_index` until `end_index` in
@@ -1338,7 +1666,7 @@ impl<A: Clone> Vector<A> {
     {
         let r = to_range(&range, self.len());
         if r.start >= r.end || r.start >= self.len() {
-            return Vector::new();
+            return Self::with_node_size();
         }
         let mut middle = self.split_off(r.start);
         let right = middle.split_off(r.end - r.start);
@@ -1346,6 +1674,36 @@ impl<A: Clone> Vector<A> {
         middle
     }
 
+    /// Extract a slice from a vector, or return an error if the range
+    /// isn't inside the vector.
+    ///
+    /// Unlike [`slice`][Vector::slice], which returns an empty vector for
+    /// a range that's empty or starts past the end, this rejects any range
+    /// which runs backwards or ends past the end of the vector.
+    ///
+    /// Time: O(log n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::error::Error;
+    /// let mut vec = vector![1, 2, 3, 4, 5];
+    /// assert_eq!(Ok(vector![2, 3]), vec.try_slice(1..3));
+    /// assert_eq!(vector![1, 4, 5], vec);
+    /// assert_eq!(
+    ///     Err(Error::RangeOutOfBounds { start: 2, end: 4, len: 3 }),
+    ///     vec.try_slice(2..4)
+    /// );
+    /// ```
+    pub fn try_slice<R>(&mut self, range: R) -> Result<Self, Error>
+    where
+        R: RangeBounds<usize>,
+    {
+        let r = Error::check_range(&range, self.len())?;
+        Ok(self.slice(r))
+    }
+
     /// Insert an element into a vector.
     ///
     /// Insert an element at position `index`, shifting all elements
@@ -1382,7 +1740,7 @@ impl<A: Clone> Vector<A> {
             Inline(_, chunk) => {
                 chunk.insert(index, value);
             }
-            Single(pool, chunk) if chunk.len() < CHUNK_SIZE => {
+            Single(pool, chunk) if chunk.len() < N::USIZE => {
                 PoolRef::make_mut(&pool.value_pool, chunk).insert(index, value)
             }
             // TODO a lot of optimisations still possible here
@@ -1394,6 +1752,30 @@ impl<A: Clone> Vector<A> {
         }
     }
 
+    /// Insert an element into a vector, or return an error if the index
+    /// is past the end of the vector.
+    ///
+    /// The value is dropped if it can't be inserted. Running out of memory
+    /// still aborts; see the [`error`][crate::error] module.
+    ///
+    /// Time: O(log n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::error::Error;
+    /// let mut vec = vector![1, 2, 3];
+    /// assert_eq!(Ok(()), vec.try_insert(3, 4));
+    /// assert_eq!(Err(Error::IndexOutOfBounds { index: 5, len: 4 }), vec.try_insert(5, 5));
+    /// assert_eq!(vector![1, 2, 3, 4], vec);
+    /// ```
+    pub fn try_insert(&mut self, index: usize, value: A) -> Result<(), Error> {
+        Error::check_position(index, self.len())?;
+        self.insert(index, value);
+        Ok(())
+    }
+
     /// Remove an element from a vector.
     ///
     /// Remove the element from position 'index', shifting all
@@ -1431,6 +1813,15 @@ impl<A: Clone> Vector<A> {
         }
     }
 
+    /// Remove an element from a vector, or return an error if the index
+    /// is out of bounds.
+    ///
+    /// Time: O(log n)
+    pub fn try_remove(&mut self, index: usize) -> Result<A, Error> {
+        Error::check_index(index, self.len())?;
+        Ok(self.remove(index))
+    }
+
     /// Insert an element into a sorted vector.
     ///
     /// Insert an element into a vector in sorted order, assuming the vector is
@@ -1500,6 +1891,176 @@ impl<A: Clone> Vector<A> {
         }
     }
 
+    /// Test whether a vector's tree has drifted far enough from a dense,
+    /// balanced shape that it would be worth calling
+    /// [`compact`][compact] on it.
+    ///
+    /// This is true when the tree is taller than a dense tree of the
+    /// same length would be, when its leaves are on average less than
+    /// four fifths full, or when more than a quarter of its branch
+    /// nodes are relaxed nodes with size tables. A couple of relaxed
+    /// nodes per level are always allowed, as the edges of a tree
+    /// tend to be relaxed anyway.
+    ///
+    /// Time: O(n / 64)
+    ///
+    /// [compact]: #method.compact
+    #[must_use]
+    pub fn needs_compaction(&self) -> bool {
+        match &self.vector {
+            Full(_, tree) => tree.needs_compaction(),
+            _ => false,
+        }
+    }
+
+    /// Rebuild a vector into a dense, fully balanced tree.
+    ///
+    /// A long sequence of [`append`][append], [`split_off`][split_off]
+    /// and [`slice`][slice] operations can leave a vector with
+    /// underfull leaves and relaxed nodes, which make indexing slower
+    /// and use more memory than necessary. Compacting moves every
+    /// element into fresh, full chunks, so the vector no longer
+    /// shares any nodes with other vectors and stops keeping their
+    /// chunks alive.
+    ///
+    /// Time: O(n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::Vector;
+    /// let mut vec = Vector::new();
+    /// for i in 0..100 {
+    ///     vec.append((0..100).collect::<Vector<_>>().slice(..i));
+    /// }
+    /// let expected = vec.clone();
+    /// vec.compact();
+    /// assert!(!vec.needs_compaction());
+    /// assert_eq!(expected, vec);
+    /// ```
+    ///
+    /// [append]: #method.append
+    /// [split_off]: #method.split_off
+    /// [slice]: #method.slice
+    pub fn compact(&mut self) {
+        let pool = self.pool().clone();
+        let old = replace(
+            self,
+            Self {
+                vector: Inline(pool, InlineArray::new()),
+            },
+        );
+        self.extend(old);
+    }
+
+    /// Get the number of bytes of heap memory used by the vector's
+    /// nodes.
+    ///
+    /// Nodes shared with other vectors are counted in full. Heap memory
+    /// owned by the elements themselves isn't counted: use
+    /// [`heap_size_with`][heap_size_with] to include it.
+    ///
+    /// Time: O(n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::Vector;
+    /// let vec: Vector<i64> = (0..1000).collect();
+    /// assert!(vec.heap_size() >= 1000 * std::mem::size_of::<i64>());
+    /// ```
+    ///
+    /// [heap_size_with]: #method.heap_size_with
+    #[must_use]
+    pub fn heap_size(&self) -> usize {
+        self.heap_size_with(|_| 0)
+    }
+
+    /// Get the number of bytes of heap memory used by the vector's nodes,
+    /// plus the number of bytes `value_size` reports for each element.
+    ///
+    /// Time: O(n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::Vector;
+    /// let vec = vector![String::from("foo"), String::from("bar")];
+    /// assert_eq!(
+    ///     vec.heap_size() + 6,
+    ///     vec.heap_size_with(|string| string.capacity())
+    /// );
+    /// ```
+    pub fn heap_size_with<F>(&self, value_size: F) -> usize
+    where
+        F: FnMut(&A) -> usize,
+    {
+        let mut visitor = HeapSize::new(value_size);
+        self.visit(&mut visitor);
+        visitor.bytes()
+    }
+
+    /// Get the number of bytes of heap memory used by nodes which this
+    /// vector shares with `other`.
+    ///
+    /// Time: O(n)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::Vector;
+    /// let vec: Vector<i64> = (0..1000).collect();
+    /// let mut other = vec.clone();
+    /// assert_eq!(vec.heap_size(), vec.shared_with(&other));
+    /// other.set(0, -1);
+    /// assert!(vec.shared_with(&other) < vec.heap_size());
+    /// ```
+    #[must_use]
+    pub fn shared_with(&self, other: &Self) -> usize {
+        let mut report = MemoryReport::new();
+        report.add(self);
+        report.add(other);
+        report.shared_bytes()
+    }
+
+    /// Get a summary of the shape of the vector's tree.
+    ///
+    /// This method requires the `debug` feature flag.
+    ///
+    /// Time: O(n / 64)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::Vector;
+    /// let vec: Vector<i64> = (0..1000).collect();
+    /// let stats = vec.stats();
+    /// assert_eq!(1000, stats.len);
+    /// assert_eq!(0, stats.relaxed_nodes);
+    /// ```
+    #[cfg(any(test, feature = "debug"))]
+    #[must_use]
+    pub fn stats(&self) -> Stats {
+        Stats::collect(self.len(), self)
+    }
+
+    fn visit(&self, visitor: &mut dyn Visitor<A>) {
+        match &self.vector {
+            Inline(_, chunk) => {
+                for value in chunk.iter() {
+                    visitor.value(value);
+                }
+            }
+            Single(_, chunk) => visit_chunk(chunk, visitor),
+            Full(_, tree) => tree.visit(visitor),
+        }
+    }
+
     /// Verify the internal consistency of a vector.
     ///
     /// This method walks the RRB tree making up the current `Vector`
@@ -1509,16 +2070,101 @@ impl<A: Clone> Vector<A> {
     /// This method requires the `debug` feature flag.
     #[cfg(any(test, feature = "debug"))]
     pub fn assert_invariants(&self) {
-        if let Full(_, ref tree) = self.vector {
-            tree.assert_invariants();
+        if let Err(error) = self.check_invariants() {
+            panic!("{}", error);
+        }
+    }
+
+    /// Verify the internal consistency of a vector, returning a
+    /// description of the first problem found, if any.
+    ///
+    /// This method walks the RRB tree making up the current `Vector`
+    /// (if it has one) and verifies that its size tables and node
+    /// sizes are consistent with its contents.
+    ///
+    /// This method requires the `debug` feature flag.
+    ///
+    /// Time: O(n / 64)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate im;
+    /// # use im::Vector;
+    /// let vec: Vector<i64> = (0..1000).collect();
+    /// assert_eq!(Ok(()), vec.check_invariants());
+    /// ```
+    #[cfg(any(test, feature = "debug"))]
+    pub fn check_invariants(&self) -> Result<(), InvariantViolation> {
+        match self.vector {
+            Full(_, ref tree) => tree.check_invariants(),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A vector taken apart into the chunks and tree it's built from.
+#[cfg(any(test, feature = "serde"))]
+pub(crate) enum Parts<'a, A, N: VectorChunkSize<A>> {
+    Inline(&'a [A]),
+    Single(&'a Chunk<A, N>),
+    /// The middle tree's level and root, and the outer front, inner front,
+    /// inner back and outer back chunks.
+    Full(usize, &'a Node<A, N>, [&'a Chunk<A, N>; 4]),
+}
+
+#[cfg(any(test, feature = "serde"))]
+impl<A: Clone, N: VectorChunkSize<A>> Vector<A, N> {
+    pub(crate) fn parts(&self) -> Parts<'_, A, N> {
+        match self.vector {
+            Inline(_, ref values) => Parts::Inline(values),
+            Single(_, ref chunk) => Parts::Single(chunk),
+            Full(_, ref tree) => Parts::Full(
+                tree.middle_level,
+                &tree.middle,
+                [&tree.outer_f, &tree.inner_f, &tree.inner_b, &tree.outer_b],
+            ),
+        }
+    }
+
+    /// Construct a vector out of a single chunk which might be shared.
+    pub(crate) fn from_chunk(pool: &RRBPool<A, N>, chunk: PoolRef<Chunk<A, N>>) -> Self {
+        Self {
+            vector: Single(pool.clone(), chunk),
+        }
+    }
+
+    /// Construct a vector out of the parts returned by
+    /// [`parts`][Vector::parts], which might be shared.
+    pub(crate) fn from_tree(
+        pool: &RRBPool<A, N>,
+        middle_level: usize,
+        middle: Ref<Node<A, N>>,
+        chunks: [PoolRef<Chunk<A, N>>; 4],
+    ) -> Self {
+        let [outer_f, inner_f, inner_b, outer_b] = chunks;
+        let length = outer_f.len() + inner_f.len() + middle.len() + inner_b.len() + outer_b.len();
+        Self {
+            vector: Full(
+                pool.clone(),
+                Rrb {
+                    length,
+                    middle_level,
+                    outer_f,
+                    inner_f,
+                    middle,
+                    inner_b,
+                    outer_b,
+                },
+            ),
         }
     }
 }
 
 // Implementation details
 
-impl<A: Clone> Rrb<A> {
-    fn new(pool: &RRBPool<A>) -> Self {
+impl<A: Clone, N: VectorChunkSize<A>> Rrb<A, N> {
+    fn new(pool: &RRBPool<A, N>) -> Self {
         Rrb {
             length: 0,
             middle_level: 0,
@@ -1530,13 +2176,53 @@ impl<A: Clone> Rrb<A> {
         }
     }
 
+    fn visit(&self, visitor: &mut dyn Visitor<A>) {
+        visit_chunk(&self.outer_f, visitor);
+        visit_chunk(&self.inner_f, visitor);
+        if visitor.node(
+            memory::address(&*self.middle),
+            memory::size_of::<Node<A, N>>(),
+        ) {
+            self.middle.visit(visitor);
+        }
+        visit_chunk(&self.inner_b, visitor);
+        visit_chunk(&self.outer_b, visitor);
+    }
+
     #[cfg(any(test, feature = "debug"))]
-    fn assert_invariants(&self) {
-        let ml = self.middle.assert_invariants(self.middle_level);
-        assert_eq!(
-            self.length,
-            self.outer_f.len() + self.inner_f.len() + ml + self.inner_b.len() + self.outer_b.len()
-        );
+    fn check_invariants(&self) -> Result<(), InvariantViolation> {
+        let ml = self
+            .middle
+            .check_invariants(self.middle_level, &mut Vec::new())?;
+        let length =
+            self.outer_f.len() + self.inner_f.len() + ml + self.inner_b.len() + self.outer_b.len();
+        if self.length != length {
+            return Err(InvariantViolation::new(
+                "RRB tree",
+                &[],
+                format!(
+                    "vector length is {}, but it has {} values",
+                    self.length, length
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn needs_compaction(&self) -> bool {
+        let len = self.middle.len();
+        if len == 0 {
+            return false;
+        }
+        let mut dense_level = 0;
+        while N::USIZE.pow(dense_level as u32 + 1) < len {
+            dense_level += 1;
+        }
+        let dense_leaves = (len + N::USIZE - 1) / N::USIZE;
+        let (leaves, branches, relaxed) = self.middle.count_nodes(self.middle_level);
+        self.middle_level > dense_level
+            || leaves * 4 > dense_leaves * 5
+            || relaxed > (branches / 4).max(2 * self.middle_level)
     }
 
     fn prune(&mut self) {
@@ -1552,7 +2238,7 @@ impl<A: Clone> Rrb<A> {
         }
     }
 
-    fn pop_front(&mut self, pool: &RRBPool<A>) -> Option<A> {
+    fn pop_front(&mut self, pool: &RRBPool<A, N>) -> Option<A> {
         if self.length == 0 {
             return None;
         }
@@ -1576,7 +2262,7 @@ impl<A: Clone> Rrb<A> {
         Some(outer_f.pop_front())
     }
 
-    fn pop_back(&mut self, pool: &RRBPool<A>) -> Option<A> {
+    fn pop_back(&mut self, pool: &RRBPool<A, N>) -> Option<A> {
         if self.length == 0 {
             return None;
         }
@@ -1600,7 +2286,7 @@ impl<A: Clone> Rrb<A> {
         Some(outer_b.pop_back())
     }
 
-    fn push_front(&mut self, pool: &RRBPool<A>, value: A) {
+    fn push_front(&mut self, pool: &RRBPool<A, N>, value: A) {
         if self.outer_f.is_full() {
             swap(&mut self.outer_f, &mut self.inner_f);
             if !self.outer_f.is_empty() {
@@ -1614,7 +2300,7 @@ impl<A: Clone> Rrb<A> {
         outer_f.push_front(value)
     }
 
-    fn push_back(&mut self, pool: &RRBPool<A>, value: A) {
+    fn push_back(&mut self, pool: &RRBPool<A, N>, value: A) {
         if self.outer_b.is_full() {
             swap(&mut self.outer_b, &mut self.inner_b);
             if !self.outer_b.is_empty() {
@@ -1628,7 +2314,7 @@ impl<A: Clone> Rrb<A> {
         outer_b.push_back(value)
     }
 
-    fn push_middle(&mut self, pool: &RRBPool<A>, side: Side, chunk: PoolRef<Chunk<A>>) {
+    fn push_middle(&mut self, pool: &RRBPool<A, N>, side: Side, chunk: PoolRef<Chunk<A, N>>) {
         if chunk.is_empty() {
             return;
         }
@@ -1653,7 +2339,7 @@ impl<A: Clone> Rrb<A> {
         self.middle = new_middle;
     }
 
-    fn pop_middle(&mut self, pool: &RRBPool<A>, side: Side) -> Option<PoolRef<Chunk<A>>> {
+    fn pop_middle(&mut self, pool: &RRBPool<A, N>, side: Side) -> Option<PoolRef<Chunk<A, N>>> {
         let chunk = {
             let middle = Ref::make_mut(&mut self.middle);
             match middle.pop_chunk(pool, self.middle_level, side) {
@@ -1671,19 +2357,49 @@ impl<A: Clone> Rrb<A> {
 }
 
 #[inline]
-fn replace_pool_def<A: PoolDefault>(pool: &Pool<A>, dest: &mut PoolRef<A>) -> PoolRef<A> {
+fn replace_pool_def<A: PoolDefault + NodeType>(
+    pool: &Pool<A>,
+    dest: &mut PoolRef<A>,
+) -> PoolRef<A> {
     replace(dest, PoolRef::default(pool))
 }
 
 // Core traits
 
+#[cfg(any(test, feature = "debug"))]
+impl<A: Clone, N: VectorChunkSize<A>> Walk for Vector<A, N> {
+    fn walk(&self, walker: &mut dyn Walker) {
+        match &self.vector {
+            Inline(_, _) => {}
+            Single(_, chunk) => walk_chunk(chunk, walker, 0, 1),
+            Full(_, tree) => {
+                let front = [&tree.outer_f, &tree.inner_f];
+                let back = [&tree.inner_b, &tree.outer_b];
+                for chunk in front.iter().filter(|chunk| !chunk.is_empty()) {
+                    walk_chunk(chunk, walker, 0, 1);
+                }
+                tree.middle.walk(walker, 0, 1);
+                for chunk in back.iter().filter(|chunk| !chunk.is_empty()) {
+                    walk_chunk(chunk, walker, 0, 1);
+                }
+            }
+        }
+    }
+}
+
+impl<A: Clone, N: VectorChunkSize<A>> HeapNodes for Vector<A, N> {
+    fn visit_heap_nodes(&self, node: &mut dyn FnMut(usize, usize) -> bool) {
+        memory::visit_nodes(|visitor| self.visit(visitor), node)
+    }
+}
+
 impl<A: Clone> Default for Vector<A> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl<A: Clone> Clone for Vector<A> {
+impl<A: Clone, N: VectorChunkSize<A>> Clone for Vector<A, N> {
     /// Clone a vector.
     ///
     /// Time: O(1), or O(n) with a very small, bounded *n* for an inline vector.
@@ -1698,8 +2414,8 @@ impl<A: Clone> Clone for Vector<A> {
     }
 }
 
-impl<A: Clone + Debug> Debug for Vector<A> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+impl<A: Clone + Debug, N: VectorChunkSize<A>> Debug for Vector<A, N> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         f.debug_list().entries(self.iter()).finish()
         // match self {
         //     Full(rrb) => {
@@ -1713,73 +2429,59 @@ impl<A: Clone + Debug> Debug for Vector<A> {
 }
 
 #[cfg(not(has_specialisation))]
-impl<A: Clone + PartialEq> PartialEq for Vector<A> {
+impl<A: Clone + PartialEq, N: VectorChunkSize<A>> PartialEq for Vector<A, N> {
     fn eq(&self, other: &Self) -> bool {
         self.len() == other.len() && self.iter().eq(other.iter())
     }
 }
 
 #[cfg(has_specialisation)]
-impl<A: Clone + PartialEq> PartialEq for Vector<A> {
+impl<A: Clone + PartialEq, N: VectorChunkSize<A>> PartialEq for Vector<A, N> {
     default fn eq(&self, other: &Self) -> bool {
         self.len() == other.len() && self.iter().eq(other.iter())
     }
 }
 
+/// Leaves which the two vectors share at the same index aren't compared,
+/// as every value in them is equal to itself.
 #[cfg(has_specialisation)]
-impl<A: Clone + Eq> PartialEq for Vector<A> {
+impl<A: Clone + Eq, N: VectorChunkSize<A>> PartialEq for Vector<A, N> {
     fn eq(&self, other: &Self) -> bool {
-        fn cmp_chunk<A>(left: &PoolRef<Chunk<A>>, right: &PoolRef<Chunk<A>>) -> bool {
-            (left.is_empty() && right.is_empty()) || PoolRef::ptr_eq(left, right)
-        }
-
-        if std::ptr::eq(self, other) {
-            return true;
-        }
-
-        match (&self.vector, &other.vector) {
-            (Single(_, left), Single(_, right)) => {
-                if cmp_chunk(left, right) {
-                    return true;
-                }
-                self.iter().eq(other.iter())
-            }
-            (Full(_, left), Full(_, right)) => {
-                if left.length != right.length {
-                    return false;
-                }
-
-                if cmp_chunk(&left.outer_f, &right.outer_f)
-                    && cmp_chunk(&left.inner_f, &right.inner_f)
-                    && cmp_chunk(&left.inner_b, &right.inner_b)
-                    && cmp_chunk(&left.outer_b, &right.outer_b)
-                    && ((left.middle.is_empty() && right.middle.is_empty())
-                        || Ref::ptr_eq(&left.middle, &right.middle))
-                {
-                    return true;
-                }
-                self.iter().eq(other.iter())
-            }
-            _ => self.len() == other.len() && self.iter().eq(other.iter()),
-        }
+        self.len() == other.len()
+            && (self.ptr_eq(other)
+                || self
+                    .find_unshared(
+                        other,
+                        |left, right| if left == right { None } else { Some(()) },
+                    )
+                    .is_none())
     }
 }
 
-impl<A: Clone + Eq> Eq for Vector<A> {}
+impl<A: Clone + Eq, N: VectorChunkSize<A>> Eq for Vector<A, N> {}
 
-impl<A: Clone + PartialOrd> PartialOrd for Vector<A> {
+impl<A: Clone + PartialOrd, N: VectorChunkSize<A>> PartialOrd for Vector<A, N> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         self.iter().partial_cmp(other.iter())
     }
 }
 
-impl<A: Clone + Ord> Ord for Vector<A> {
+/// Leaves which the two vectors share at the same index aren't compared,
+/// as every value in them is equal to itself.
+impl<A: Clone + Ord, N: VectorChunkSize<A>> Ord for Vector<A, N> {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.iter().cmp(other.iter())
+        if self.ptr_eq(other) {
+            return Ordering::Equal;
+        }
+        self.find_unshared(other, |left, right| match left.cmp(right) {
+            Ordering::Equal => None,
+            ordering => Some(ordering),
+        })
+        .unwrap_or_else(|| self.len().cmp(&other.len()))
     }
 }
 
-impl<A: Clone + Hash> Hash for Vector<A> {
+impl<A: Clone + Hash, N: VectorChunkSize<A>> Hash for Vector<A, N> {
     fn hash<H: Hasher>(&self, state: &mut H) {
         for i in self {
             i.hash(state)
@@ -1787,17 +2489,17 @@ impl<A: Clone + Hash> Hash for Vector<A> {
     }
 }
 
-impl<A: Clone> Sum for Vector<A> {
+impl<A: Clone, N: VectorChunkSize<A>> Sum for Vector<A, N> {
     fn sum<I>(it: I) -> Self
     where
         I: Iterator<Item = Self>,
     {
-        it.fold(Self::new(), |a, b| a + b)
+        it.fold(Self::with_node_size(), |a, b| a + b)
     }
 }
 
-impl<A: Clone> Add for Vector<A> {
-    type Output = Vector<A>;
+impl<A: Clone, N: VectorChunkSize<A>> Add for Vector<A, N> {
+    type Output = Vector<A, N>;
 
     /// Concatenate two vectors.
     ///
@@ -1808,8 +2510,8 @@ impl<A: Clone> Add for Vector<A> {
     }
 }
 
-impl<'a, A: Clone> Add for &'a Vector<A> {
-    type Output = Vector<A>;
+impl<'a, A: Clone, N: VectorChunkSize<A>> Add for &'a Vector<A, N> {
+    type Output = Vector<A, N>;
 
     /// Concatenate two vectors.
     ///
@@ -1821,7 +2523,7 @@ impl<'a, A: Clone> Add for &'a Vector<A> {
     }
 }
 
-impl<A: Clone> Extend<A> for Vector<A> {
+impl<A: Clone, N: VectorChunkSize<A>> Extend<A> for Vector<A, N> {
     /// Add values to the end of a vector by consuming an iterator.
     ///
     /// Time: O(n)
@@ -1835,7 +2537,7 @@ impl<A: Clone> Extend<A> for Vector<A> {
     }
 }
 
-impl<A: Clone> Index<usize> for Vector<A> {
+impl<A: Clone, N: VectorChunkSize<A>> Index<usize> for Vector<A, N> {
     type Output = A;
     /// Get a reference to the value at index `index` in the vector.
     ///
@@ -1852,7 +2554,7 @@ impl<A: Clone> Index<usize> for Vector<A> {
     }
 }
 
-impl<A: Clone> IndexMut<usize> for Vector<A> {
+impl<A: Clone, N: VectorChunkSize<A>> IndexMut<usize> for Vector<A, N> {
     /// Get a mutable reference to the value at index `index` in the
     /// vector.
     ///
@@ -1867,17 +2569,17 @@ impl<A: Clone> IndexMut<usize> for Vector<A> {
 
 // Conversions
 
-impl<'a, A: Clone> IntoIterator for &'a Vector<A> {
+impl<'a, A: Clone, N: VectorChunkSize<A>> IntoIterator for &'a Vector<A, N> {
     type Item = &'a A;
-    type IntoIter = Iter<'a, A>;
+    type IntoIter = Iter<'a, A, N>;
     fn into_iter(self) -> Self::IntoIter {
         self.iter()
     }
 }
 
-impl<A: Clone> IntoIterator for Vector<A> {
+impl<A: Clone, N: VectorChunkSize<A>> IntoIterator for Vector<A, N> {
     type Item = A;
-    type IntoIter = ConsumingIter<A>;
+    type IntoIter = ConsumingIter<A, N>;
     fn into_iter(self) -> Self::IntoIter {
         ConsumingIter::new(self)
     }
@@ -1891,21 +2593,18 @@ impl<A: Clone> FromIterator<A> for Vector<A> {
     where
         I: IntoIterator<Item = A>,
     {
-        let mut seq = Self::new();
-        for item in iter {
-            seq.push_back(item)
-        }
-        seq
+        Self::from_iter_with_node_size(iter)
     }
 }
 
-impl<'s, 'a, A, OA> From<&'s Vector<&'a A>> for Vector<OA>
+impl<'s, 'a, A, OA, N> From<&'s Vector<&'a A, N>> for Vector<OA, N>
 where
     A: ToOwned<Owned = OA>,
     OA: Borrow<A> + Clone,
+    N: VectorChunkSize<&'a A> + VectorChunkSize<OA>,
 {
-    fn from(vec: &Vector<&A>) -> Self {
-        vec.iter().map(|a| (*a).to_owned()).collect()
+    fn from(vec: &'s Vector<&'a A, N>) -> Self {
+        Self::from_iter_with_node_size(vec.iter().map(|a| (*a).to_owned()))
     }
 }
 
@@ -1944,14 +2643,14 @@ impl<'a, A: Clone> From<&'a Vec<A>> for Vector<A> {
 /// To obtain one, use [`Vector::iter()`][iter].
 ///
 /// [iter]: enum.Vector.html#method.iter
-pub struct Iter<'a, A> {
-    focus: Focus<'a, A>,
+pub struct Iter<'a, A, N: VectorChunkSize<A> = DefaultVectorChunkSize> {
+    focus: Focus<'a, A, N>,
     front_index: usize,
     back_index: usize,
 }
 
-impl<'a, A: Clone> Iter<'a, A> {
-    fn new(seq: &'a Vector<A>) -> Self {
+impl<'a, A: Clone, N: VectorChunkSize<A>> Iter<'a, A, N> {
+    fn new(seq: &'a Vector<A, N>) -> Self {
         Iter {
             focus: seq.focus(),
             front_index: 0,
@@ -1959,7 +2658,7 @@ impl<'a, A: Clone> Iter<'a, A> {
         }
     }
 
-    fn from_focus(focus: Focus<'a, A>) -> Self {
+    fn from_focus(focus: Focus<'a, A, N>) -> Self {
         Iter {
             front_index: 0,
             back_index: focus.len(),
@@ -1968,7 +2667,7 @@ impl<'a, A: Clone> Iter<'a, A> {
     }
 }
 
-impl<'a, A: Clone> Iterator for Iter<'a, A> {
+impl<'a, A: Clone, N: VectorChunkSize<A>> Iterator for Iter<'a, A, N> {
     type Item = &'a A;
 
     /// Advance the iterator and return the next value.
@@ -1979,7 +2678,7 @@ impl<'a, A: Clone> Iterator for Iter<'a, A> {
             return None;
         }
         #[allow(unsafe_code)]
-        let focus: &'a mut Focus<'a, A> = unsafe { &mut *(&mut self.focus as *mut _) };
+        let focus: &'a mut Focus<'a, A, N> = unsafe { &mut *(&mut self.focus as *mut _) };
         let value = focus.get(self.front_index);
         self.front_index += 1;
         value
@@ -1991,7 +2690,7 @@ impl<'a, A: Clone> Iterator for Iter<'a, A> {
     }
 }
 
-impl<'a, A: Clone> DoubleEndedIterator for Iter<'a, A> {
+impl<'a, A: Clone, N: VectorChunkSize<A>> DoubleEndedIterator for Iter<'a, A, N> {
     /// Advance the iterator and return the next value.
     ///
     /// Time: O(1)*
@@ -2001,31 +2700,31 @@ impl<'a, A: Clone> DoubleEndedIterator for Iter<'a, A> {
         }
         self.back_index -= 1;
         #[allow(unsafe_code)]
-        let focus: &'a mut Focus<'a, A> = unsafe { &mut *(&mut self.focus as *mut _) };
+        let focus: &'a mut Focus<'a, A, N> = unsafe { &mut *(&mut self.focus as *mut _) };
         focus.get(self.back_index)
     }
 }
 
-impl<'a, A: Clone> ExactSizeIterator for Iter<'a, A> {}
+impl<'a, A: Clone, N: VectorChunkSize<A>> ExactSizeIterator for Iter<'a, A, N> {}
 
-impl<'a, A: Clone> FusedIterator for Iter<'a, A> {}
+impl<'a, A: Clone, N: VectorChunkSize<A>> FusedIterator for Iter<'a, A, N> {}
 
 /// A mutable iterator over vectors with values of type `A`.
 ///
 /// To obtain one, use [`Vector::iter_mut()`][iter_mut].
 ///
 /// [iter_mut]: enum.Vector.html#method.iter_mut
-pub struct IterMut<'a, A> {
-    focus: FocusMut<'a, A>,
+pub struct IterMut<'a, A, N: VectorChunkSize<A> = DefaultVectorChunkSize> {
+    focus: FocusMut<'a, A, N>,
     front_index: usize,
     back_index: usize,
 }
 
-impl<'a, A> IterMut<'a, A>
+impl<'a, A, N: VectorChunkSize<A>> IterMut<'a, A, N>
 where
     A: Clone,
 {
-    fn new(seq: &'a mut Vector<A>) -> Self {
+    fn new(seq: &'a mut Vector<A, N>) -> Self {
         let focus = seq.focus_mut();
         let len = focus.len();
         IterMut {
@@ -2035,7 +2734,7 @@ where
         }
     }
 
-    fn from_focus(focus: FocusMut<'a, A>) -> Self {
+    fn from_focus(focus: FocusMut<'a, A, N>) -> Self {
         IterMut {
             front_index: 0,
             back_index: focus.len(),
@@ -2044,7 +2743,7 @@ where
     }
 }
 
-impl<'a, A> Iterator for IterMut<'a, A>
+impl<'a, A, N: VectorChunkSize<A>> Iterator for IterMut<'a, A, N>
 where
     A: 'a + Clone,
 {
@@ -2058,7 +2757,7 @@ where
             return None;
         }
         #[allow(unsafe_code)]
-        let focus: &'a mut FocusMut<'a, A> = unsafe { &mut *(&mut self.focus as *mut _) };
+        let focus: &'a mut FocusMut<'a, A, N> = unsafe { &mut *(&mut self.focus as *mut _) };
         let value = focus.get_mut(self.front_index);
         self.front_index += 1;
         value
@@ -2070,7 +2769,7 @@ where
     }
 }
 
-impl<'a, A> DoubleEndedIterator for IterMut<'a, A>
+impl<'a, A, N: VectorChunkSize<A>> DoubleEndedIterator for IterMut<'a, A, N>
 where
     A: 'a + Clone,
 {
@@ -2083,27 +2782,27 @@ where
         }
         self.back_index -= 1;
         #[allow(unsafe_code)]
-        let focus: &'a mut FocusMut<'a, A> = unsafe { &mut *(&mut self.focus as *mut _) };
+        let focus: &'a mut FocusMut<'a, A, N> = unsafe { &mut *(&mut self.focus as *mut _) };
         focus.get_mut(self.back_index)
     }
 }
 
-impl<'a, A: Clone> ExactSizeIterator for IterMut<'a, A> {}
+impl<'a, A: Clone, N: VectorChunkSize<A>> ExactSizeIterator for IterMut<'a, A, N> {}
 
-impl<'a, A: Clone> FusedIterator for IterMut<'a, A> {}
+impl<'a, A: Clone, N: VectorChunkSize<A>> FusedIterator for IterMut<'a, A, N> {}
 
 /// A consuming iterator over vectors with values of type `A`.
-pub struct ConsumingIter<A> {
-    vector: Vector<A>,
+pub struct ConsumingIter<A, N: VectorChunkSize<A> = DefaultVectorChunkSize> {
+    vector: Vector<A, N>,
 }
 
-impl<A: Clone> ConsumingIter<A> {
-    fn new(vector: Vector<A>) -> Self {
+impl<A: Clone, N: VectorChunkSize<A>> ConsumingIter<A, N> {
+    fn new(vector: Vector<A, N>) -> Self {
         Self { vector }
     }
 }
 
-impl<A: Clone> Iterator for ConsumingIter<A> {
+impl<A: Clone, N: VectorChunkSize<A>> Iterator for ConsumingIter<A, N> {
     type Item = A;
 
     /// Advance the iterator and return the next value.
@@ -2119,7 +2818,7 @@ impl<A: Clone> Iterator for ConsumingIter<A> {
     }
 }
 
-impl<A: Clone> DoubleEndedIterator for ConsumingIter<A> {
+impl<A: Clone, N: VectorChunkSize<A>> DoubleEndedIterator for ConsumingIter<A, N> {
     /// Remove and return an element from the back of the iterator.
     ///
     /// Time: O(1)*
@@ -2128,23 +2827,23 @@ impl<A: Clone> DoubleEndedIterator for ConsumingIter<A> {
     }
 }
 
-impl<A: Clone> ExactSizeIterator for ConsumingIter<A> {}
+impl<A: Clone, N: VectorChunkSize<A>> ExactSizeIterator for ConsumingIter<A, N> {}
 
-impl<A: Clone> FusedIterator for ConsumingIter<A> {}
+impl<A: Clone, N: VectorChunkSize<A>> FusedIterator for ConsumingIter<A, N> {}
 
 /// An iterator over the leaf nodes of a vector.
 ///
 /// To obtain one, use [`Vector::chunks()`][chunks].
 ///
 /// [chunks]: enum.Vector.html#method.chunks
-pub struct Chunks<'a, A> {
-    focus: Focus<'a, A>,
+pub struct Chunks<'a, A, N: VectorChunkSize<A> = DefaultVectorChunkSize> {
+    focus: Focus<'a, A, N>,
     front_index: usize,
     back_index: usize,
 }
 
-impl<'a, A: Clone> Chunks<'a, A> {
-    fn new(seq: &'a Vector<A>) -> Self {
+impl<'a, A: Clone, N: VectorChunkSize<A>> Chunks<'a, A, N> {
+    fn new(seq: &'a Vector<A, N>) -> Self {
         Chunks {
             focus: seq.focus(),
             front_index: 0,
@@ -2153,7 +2852,7 @@ impl<'a, A: Clone> Chunks<'a, A> {
     }
 }
 
-impl<'a, A: Clone> Iterator for Chunks<'a, A> {
+impl<'a, A: Clone, N: VectorChunkSize<A>> Iterator for Chunks<'a, A, N> {
     type Item = &'a [A];
 
     /// Advance the iterator and return the next value.
@@ -2164,14 +2863,14 @@ impl<'a, A: Clone> Iterator for Chunks<'a, A> {
             return None;
         }
         #[allow(unsafe_code)]
-        let focus: &'a mut Focus<'a, A> = unsafe { &mut *(&mut self.focus as *mut _) };
+        let focus: &'a mut Focus<'a, A, N> = unsafe { &mut *(&mut self.focus as *mut _) };
         let (range, value) = focus.chunk_at(self.front_index);
         self.front_index = range.end;
         Some(value)
     }
 }
 
-impl<'a, A: Clone> DoubleEndedIterator for Chunks<'a, A> {
+impl<'a, A: Clone, N: VectorChunkSize<A>> DoubleEndedIterator for Chunks<'a, A, N> {
     /// Remove and return an element from the back of the iterator.
     ///
     /// Time: O(1)*
@@ -2181,28 +2880,28 @@ impl<'a, A: Clone> DoubleEndedIterator for Chunks<'a, A> {
         }
         self.back_index -= 1;
         #[allow(unsafe_code)]
-        let focus: &'a mut Focus<'a, A> = unsafe { &mut *(&mut self.focus as *mut _) };
+        let focus: &'a mut Focus<'a, A, N> = unsafe { &mut *(&mut self.focus as *mut _) };
         let (range, value) = focus.chunk_at(self.back_index);
         self.back_index = range.start;
         Some(value)
     }
 }
 
-impl<'a, A: Clone> FusedIterator for Chunks<'a, A> {}
+impl<'a, A: Clone, N: VectorChunkSize<A>> FusedIterator for Chunks<'a, A, N> {}
 
 /// A mutable iterator over the leaf nodes of a vector.
 ///
 /// To obtain one, use [`Vector::chunks_mut()`][chunks_mut].
 ///
 /// [chunks_mut]: enum.Vector.html#method.chunks_mut
-pub struct ChunksMut<'a, A> {
-    focus: FocusMut<'a, A>,
+pub struct ChunksMut<'a, A, N: VectorChunkSize<A> = DefaultVectorChunkSize> {
+    focus: FocusMut<'a, A, N>,
     front_index: usize,
     back_index: usize,
 }
 
-impl<'a, A: Clone> ChunksMut<'a, A> {
-    fn new(seq: &'a mut Vector<A>) -> Self {
+impl<'a, A: Clone, N: VectorChunkSize<A>> ChunksMut<'a, A, N> {
+    fn new(seq: &'a mut Vector<A, N>) -> Self {
         let len = seq.len();
         ChunksMut {
             focus: seq.focus_mut(),
@@ -2212,7 +2911,7 @@ impl<'a, A: Clone> ChunksMut<'a, A> {
     }
 }
 
-impl<'a, A: Clone> Iterator for ChunksMut<'a, A> {
+impl<'a, A: Clone, N: VectorChunkSize<A>> Iterator for ChunksMut<'a, A, N> {
     type Item = &'a mut [A];
 
     /// Advance the iterator and return the next value.
@@ -2223,14 +2922,14 @@ impl<'a, A: Clone> Iterator for ChunksMut<'a, A> {
             return None;
         }
         #[allow(unsafe_code)]
-        let focus: &'a mut FocusMut<'a, A> = unsafe { &mut *(&mut self.focus as *mut _) };
+        let focus: &'a mut FocusMut<'a, A, N> = unsafe { &mut *(&mut self.focus as *mut _) };
         let (range, value) = focus.chunk_at(self.front_index);
         self.front_index = range.end;
         Some(value)
     }
 }
 
-impl<'a, A: Clone> DoubleEndedIterator for ChunksMut<'a, A> {
+impl<'a, A: Clone, N: VectorChunkSize<A>> DoubleEndedIterator for ChunksMut<'a, A, N> {
     /// Remove and return an element from the back of the iterator.
     ///
     /// Time: O(1)*
@@ -2240,14 +2939,14 @@ impl<'a, A: Clone> DoubleEndedIterator for ChunksMut<'a, A> {
         }
         self.back_index -= 1;
         #[allow(unsafe_code)]
-        let focus: &'a mut FocusMut<'a, A> = unsafe { &mut *(&mut self.focus as *mut _) };
+        let focus: &'a mut FocusMut<'a, A, N> = unsafe { &mut *(&mut self.focus as *mut _) };
         let (range, value) = focus.chunk_at(self.back_index);
         self.back_index = range.start;
         Some(value)
     }
 }
 
-impl<'a, A: Clone> FusedIterator for ChunksMut<'a, A> {}
+impl<'a, A: Clone, N: VectorChunkSize<A>> FusedIterator for ChunksMut<'a, A, N> {}
 
 // Proptest
 #[cfg(any(test, feature = "proptest"))]
@@ -2269,6 +2968,68 @@ mod test {
     use ::proptest::collection::vec;
     use ::proptest::num::{i32, usize};
     use ::proptest::proptest;
+    use typenum::Unsigned;
+
+    #[test]
+    fn comparisons_skip_shared_leaves() {
+        let mut vec: Vector<f64> = (0..10_000).map(f64::from).collect();
+        vec.set(3, f64::NAN);
+        // A NaN isn't equal to itself, so only `Eq` values can skip the
+        // leaves two vectors share, however they're stored.
+        assert_ne!(vec, vec.clone());
+        assert_eq!(None, vec.partial_cmp(&vec.clone()));
+        let inline: Vector<f64> = vector![1.0, f64::NAN];
+        assert_ne!(inline, inline.clone());
+        let mut other = vec.clone();
+        other.set(9000, 9000.0);
+        assert_ne!(vec, other);
+        vec.set(3, 3.0);
+        other.set(3, 3.0);
+        assert_eq!(vec, other);
+        other.set(9000, 0.0);
+        assert_ne!(vec, other);
+        assert_eq!(Some(Ordering::Greater), vec.partial_cmp(&other));
+
+        let ints: Vector<i32> = (0..10_000).collect();
+        let mut versions = vec![ints.clone(), Vector::new()];
+        let mut shifted = ints.clone();
+        shifted.push_front(0);
+        shifted.pop_back();
+        versions.push(shifted);
+        let mut longer = ints.clone();
+        longer.push_back(0);
+        versions.push(longer);
+        versions.push(ints.take(5000));
+        versions.push(ints.update(5000, -1));
+        let mut rejoined = ints.take(3000);
+        rejoined.append(ints.skip(3000));
+        versions.push(rejoined);
+        for left in &versions {
+            for right in &versions {
+                assert_eq!(left.iter().cmp(right.iter()), left.cmp(right));
+                assert_eq!(left.iter().eq(right.iter()), left == right);
+            }
+        }
+    }
+
+    #[test]
+    fn transaction_rolls_back_on_panic() {
+        let mut vec: Vector<i32> = (0..10_000).collect();
+        let before = vec.clone();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            vec.transaction(|vec| -> Result<(), ()> {
+                vec.slice(1000..9000);
+                vec.push_front(-1);
+                panic!("half way through")
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(before, vec);
+        assert!(vec.ptr_eq(&before));
+    }
+
+    const CHUNK_SIZE: usize = DefaultVectorChunkSize::USIZE;
+    const NODE_SIZE: usize = CHUNK_SIZE;
 
     #[test]
     fn macro_allows_trailing_comma() {
@@ -2401,9 +3162,151 @@ mod test {
         assert_eq!(Some(&1), tail.get(0));
     }
 
+    #[test]
+    fn check_invariants_detects_wrong_length() {
+        let mut vec: Vector<i32> = (0..1000).collect();
+        assert_eq!(Ok(()), vec.check_invariants());
+        if let Full(_, ref mut tree) = vec.vector {
+            tree.length += 1;
+        }
+        let error = vec.check_invariants().unwrap_err();
+        assert_eq!("RRB tree", error.structure());
+        assert_eq!(
+            "vector length is 1001, but it has 1000 values",
+            error.message()
+        );
+    }
+
+    #[test]
+    fn small_chunks() {
+        let mut vec: Vector<usize, typenum::U8> = Vector::with_node_size();
+        for i in 0..1000 {
+            vec.push_back(i);
+        }
+        vec.assert_invariants();
+        let mut front: Vector<usize, typenum::U8> = Vector::with_node_size();
+        for i in (0..1000).rev() {
+            front.push_front(i);
+        }
+        front.assert_invariants();
+        assert_eq!(vec, front);
+        let right = vec.split_off(333);
+        vec.append(right.clone());
+        vec.append(right);
+        vec.assert_invariants();
+        assert_eq!(1667, vec.len());
+        assert!(vec.iter().cloned().eq((0..1000).chain(333..1000)));
+        vec.sort();
+        vec.assert_invariants();
+        assert_eq!(Some(&999), vec.last());
+    }
+
+    #[test]
+    fn fallible_methods_reject_bad_indices() {
+        let mut vec: Vector<usize> = (0..1000).collect();
+        let len = vec.len();
+        let oob = |index| Error::IndexOutOfBounds { index, len };
+        assert_eq!(Err(oob(1000)), vec.try_remove(1000));
+        assert_eq!(Err(oob(1001)), vec.try_insert(1001, 0));
+        assert_eq!(Err(oob(1000)), vec.try_set(1000, 0));
+        assert_eq!(Err(oob(1000)), vec.checked_swap(0, 1000));
+        assert_eq!(Err(oob(1001)), vec.try_split_off(1001).map(|_| ()));
+        assert_eq!(Err(oob(2000)), vec.try_truncate(2000));
+        assert_eq!(Err(oob(usize::MAX)), vec.try_skip(usize::MAX).map(|_| ()));
+        assert_eq!(
+            Err(Error::RangeOutOfBounds {
+                start: 10,
+                end: 5,
+                len
+            }),
+            vec.try_slice(10..5)
+        );
+        assert_eq!(
+            Err(Error::RangeOutOfBounds {
+                start: 0,
+                end: usize::MAX,
+                len
+            }),
+            vec.try_slice(..=usize::MAX)
+        );
+        assert!(vec.iter().cloned().eq(0..1000));
+
+        let mut focus = vec.focus();
+        assert_eq!(Err(oob(1000)), focus.try_index(1000).map(|_| ()));
+        assert_eq!(Ok(&999), focus.try_index(999));
+        let empty = vec.focus().try_narrow(500..500).unwrap();
+        assert!(empty.is_empty());
+        let mut focus = vec.focus_mut();
+        assert_eq!(
+            Err(Error::DuplicateIndex { index: 3 }),
+            focus.try_pair(3, 3, |_, _| ())
+        );
+        assert_eq!(Err(oob(1000)), focus.try_triplet(0, 1, 1000, |_, _, _| ()));
+        assert_eq!(Ok(()), focus.checked_swap(0, 999));
+
+        assert_eq!(Ok(()), vec.try_insert(1000, 1000));
+        assert_eq!(Ok(1000), vec.try_remove(1000));
+        assert_eq!(Ok(999), vec.try_remove(0));
+        assert_eq!(
+            Ok(vec![501, 502]),
+            vec.try_slice(500..=501).map(|v| v.into_iter().collect())
+        );
+        vec.assert_invariants();
+    }
+
+    #[test]
+    fn compact_fragmented_vector() {
+        let pool: RRBPool<usize> = RRBPool::default();
+        let mut leaves = Chunk::new();
+        for i in 0..NODE_SIZE {
+            let chunk = PoolRef::new(&pool.value_pool, Chunk::unit(i));
+            leaves.push_back(Node::from_chunk(&pool, 0, chunk));
+        }
+        let mut vec = Vector {
+            vector: Full(
+                pool.clone(),
+                Rrb {
+                    length: NODE_SIZE,
+                    middle_level: 1,
+                    outer_f: PoolRef::default(&pool.value_pool),
+                    inner_f: PoolRef::default(&pool.value_pool),
+                    middle: Ref::new(Node::parent(&pool, 1, leaves)),
+                    inner_b: PoolRef::default(&pool.value_pool),
+                    outer_b: PoolRef::default(&pool.value_pool),
+                },
+            ),
+        };
+        vec.assert_invariants();
+        assert!(vec.needs_compaction());
+        vec.compact();
+        vec.assert_invariants();
+        assert!(!vec.needs_compaction());
+        assert!(vec.iter().cloned().eq(0..NODE_SIZE));
+    }
+
+    #[test]
+    fn compacted_vector_is_dense() {
+        let source: Vector<usize> = (0..1000).collect();
+        let mut vec = Vector::new();
+        for i in 0..200 {
+            vec.append(source.clone().slice(i..i * 3));
+        }
+        let expected: Vec<usize> = vec.iter().cloned().collect();
+        vec.compact();
+        vec.assert_invariants();
+        assert!(!vec.needs_compaction());
+        assert!(vec.iter().cloned().eq(expected));
+        match vec.vector {
+            Full(_, ref tree) => {
+                let dense_leaves = (tree.middle.len() + CHUNK_SIZE - 1) / CHUNK_SIZE;
+                assert_eq!(dense_leaves, tree.middle.count_nodes(tree.middle_level).0);
+            }
+            _ => unreachable!(),
+        }
+    }
+
     #[test]
     fn issue_74_simple_size() {
-        use crate::nodes::rrb::NODE_SIZE;
         let mut x = Vector::new();
         for _ in 0..(CHUNK_SIZE
             * (
@@ -2539,6 +3442,24 @@ mod test {
             assert_eq!(vec.len(), seq.len());
         }
 
+        #[test]
+        fn compact(ref input in vec(i32::ANY, 0..10000), ref splits in vec(usize::ANY, 0..20)) {
+            let mut vector: Vector<i32> = input.iter().cloned().collect();
+            let mut pieces = Vec::new();
+            for split in splits {
+                let at = split % (vector.len() + 1);
+                pieces.push(vector.split_off(at));
+            }
+            for piece in pieces.into_iter().rev() {
+                vector.append(piece);
+            }
+            vector.compact();
+            vector.assert_invariants();
+            assert!(!vector.needs_compaction());
+            assert_eq!(input.len(), vector.len());
+            assert!(vector.iter().eq(input.iter()));
+        }
+
         #[test]
         fn push_front_mut(ref input in vec(i32::ANY, 0..1000)) {
             let mut vector = Vector::new();
@@ -2,19 +2,20 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use crate::config::POOL_SIZE;
-use crate::nodes::chunk::Chunk;
+use sized_chunks::Chunk;
+
+use crate::config::{DefaultVectorChunkSize, VectorChunkSize, POOL_SIZE};
 use crate::nodes::rrb::Node;
 use crate::util::Pool;
 
 /// A memory pool for `Vector`.
-pub struct RRBPool<A> {
-    pub(crate) node_pool: Pool<Chunk<Node<A>>>,
-    pub(crate) value_pool: Pool<Chunk<A>>,
-    pub(crate) size_pool: Pool<Chunk<usize>>,
+pub struct RRBPool<A, N: VectorChunkSize<A> = DefaultVectorChunkSize> {
+    pub(crate) node_pool: Pool<Chunk<Node<A, N>, N>>,
+    pub(crate) value_pool: Pool<Chunk<A, N>>,
+    pub(crate) size_pool: Pool<Chunk<usize, N>>,
 }
 
-impl<A> RRBPool<A> {
+impl<A, N: VectorChunkSize<A>> RRBPool<A, N> {
     /// Create a new memory pool with the given size.
     pub fn new(size: usize) -> Self {
         Self::with_sizes(size, size, size)
@@ -56,14 +57,14 @@ impl<A> RRBPool<A> {
     }
 }
 
-impl<A> Default for RRBPool<A> {
+impl<A, N: VectorChunkSize<A>> Default for RRBPool<A, N> {
     /// Construct a pool with a reasonable default pool size.
     fn default() -> Self {
         Self::new(POOL_SIZE)
     }
 }
 
-impl<A> Clone for RRBPool<A> {
+impl<A, N: VectorChunkSize<A>> Clone for RRBPool<A, N> {
     fn clone(&self) -> Self {
         Self {
             node_pool: self.node_pool.clone(),
@@ -0,0 +1,445 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A history of the versions of a value, with undo and redo.
+//!
+//! A [`Versioned`][Versioned] keeps every version of a value committed
+//! to it, numbered in the order they were committed. Each version of a
+//! collection shares every node it hasn't changed with the version
+//! before it, so keeping the whole history of a large collection costs
+//! little more than the changes made to it, and the number of versions
+//! kept can be limited for the rest.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[macro_use] extern crate im;
+//! # use im::versioned::Versioned;
+//! let mut history = Versioned::new(ordmap!{});
+//! let first = history.modify(|map| {
+//!     map.insert("a", 1);
+//! });
+//! history.tag("release");
+//! history.modify(|map| {
+//!     map.insert("b", 2);
+//! });
+//!
+//! history.undo();
+//! assert_eq!(&ordmap!{"a" => 1}, history.current());
+//! history.redo();
+//! assert_eq!(&ordmap!{"a" => 1, "b" => 2}, history.current());
+//!
+//! assert_eq!(Some(&ordmap!{"a" => 1}), history.as_of(first));
+//! assert_eq!(Some(&ordmap!{"a" => 1}), history.tagged("release"));
+//! ```
+//!
+//! [Versioned]: ./struct.Versioned.html
+
+use std::collections::{vec_deque, BTreeMap, VecDeque};
+use std::iter::FusedIterator;
+
+use crate::diff::Diff;
+
+/// A value together with the history of its versions.
+///
+/// Versions are numbered from 0, for the value the history starts
+/// with, and each version committed gets the next number. Undoing moves
+/// back to the previous version without forgetting the ones after it,
+/// so they can be redone, until a new version is committed in their
+/// place. Their numbers aren't used again.
+///
+/// A version can be given any number of names with [`tag`][tag]. When a
+/// version is forgotten, because it was replaced after an undo or
+/// because it's older than the history's limit allows, its tags go with
+/// it.
+///
+/// [tag]: #method.tag
+#[derive(Clone, Debug)]
+pub struct Versioned<T> {
+    versions: VecDeque<(u64, T)>,
+    current: usize,
+    next: u64,
+    limit: Option<usize>,
+    tags: BTreeMap<String, u64>,
+}
+
+impl<T> Versioned<T> {
+    /// Start a history with `value` as version 0.
+    #[must_use]
+    pub fn new(value: T) -> Self {
+        let mut versions = VecDeque::new();
+        versions.push_back((0, value));
+        Versioned {
+            versions,
+            current: 0,
+            next: 1,
+            limit: None,
+            tags: BTreeMap::new(),
+        }
+    }
+
+    /// Start a history with `value` as version 0, which keeps no more
+    /// than `limit` versions.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is 0.
+    #[must_use]
+    pub fn with_limit(value: T, limit: usize) -> Self {
+        let mut history = Self::new(value);
+        history.set_limit(Some(limit));
+        history
+    }
+
+    /// Get the number of versions the history keeps, if it's limited.
+    #[must_use]
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Limit the number of versions the history keeps, forgetting the
+    /// oldest versions if it already has more, or remove the limit.
+    ///
+    /// Versions which can be redone count towards the limit, but the
+    /// current version is never forgotten, nor any after it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is `Some(0)`.
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        assert!(
+            limit != Some(0),
+            "Versioned::set_limit: a history has to keep at least one version"
+        );
+        self.limit = limit;
+        self.trim();
+    }
+
+    // Forget the oldest versions until the history is within its limit.
+    fn trim(&mut self) {
+        if let Some(limit) = self.limit {
+            while self.versions.len() > limit && self.current > 0 {
+                self.versions.pop_front();
+                self.current -= 1;
+            }
+            self.forget_lost_tags();
+        }
+    }
+
+    // Remove the tags of versions the history no longer has.
+    fn forget_lost_tags(&mut self) {
+        let lost: Vec<String> = self
+            .tags
+            .iter()
+            .filter(|(_, version)| {
+                self.index_as_of(**version)
+                    .map_or(true, |index| self.versions[index].0 != **version)
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in lost {
+            self.tags.remove(&name);
+        }
+    }
+
+    /// Get the current version of the value.
+    #[must_use]
+    pub fn current(&self) -> &T {
+        &self.versions[self.current].1
+    }
+
+    /// Get the number of the current version.
+    #[must_use]
+    pub fn version(&self) -> u64 {
+        self.versions[self.current].0
+    }
+
+    /// Get the number of versions in the history, including any which
+    /// can be redone.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.versions.len()
+    }
+
+    /// Test whether the history is empty, which it never is, as it always
+    /// has a current version.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Commit a new version of the value, and return its number.
+    ///
+    /// Any versions which could have been redone are forgotten.
+    pub fn commit(&mut self, value: T) -> u64 {
+        if self.can_redo() {
+            self.versions.truncate(self.current + 1);
+            self.forget_lost_tags();
+        }
+        let version = self.next;
+        self.next += 1;
+        self.versions.push_back((version, value));
+        self.current += 1;
+        self.trim();
+        version
+    }
+
+    /// Commit a new version made by changing a copy of the current one,
+    /// and return its number.
+    ///
+    /// The copy shares all of its structure with the current version
+    /// until `f` changes it.
+    pub fn modify<F>(&mut self, f: F) -> u64
+    where
+        T: Clone,
+        F: FnOnce(&mut T),
+    {
+        let mut value = self.current().clone();
+        f(&mut value);
+        self.commit(value)
+    }
+
+    /// Test whether there's a version before the current one to go back
+    /// to.
+    #[must_use]
+    pub fn can_undo(&self) -> bool {
+        self.current > 0
+    }
+
+    /// Test whether there's a version after the current one to go
+    /// forward to.
+    #[must_use]
+    pub fn can_redo(&self) -> bool {
+        self.current + 1 < self.versions.len()
+    }
+
+    /// Go back to the version before the current one, and return it, or
+    /// return `None` if there's no earlier version.
+    pub fn undo(&mut self) -> Option<&T> {
+        if !self.can_undo() {
+            return None;
+        }
+        self.current -= 1;
+        Some(self.current())
+    }
+
+    /// Go forward to the version after the current one, and return it,
+    /// or return `None` if there's nothing to redo.
+    pub fn redo(&mut self) -> Option<&T> {
+        if !self.can_redo() {
+            return None;
+        }
+        self.current += 1;
+        Some(self.current())
+    }
+
+    // Find the index of the latest version numbered `version` or less.
+    fn index_as_of(&self, version: u64) -> Option<usize> {
+        let (mut low, mut high) = (0, self.versions.len());
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if self.versions[middle].0 <= version {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        low.checked_sub(1)
+    }
+
+    /// Get the value as it was at version `version`.
+    ///
+    /// This is the latest version numbered `version` or less which the
+    /// history still has, so it can be asked about a version which was
+    /// replaced after an undo, and answers with the version which was
+    /// current before it. It returns `None` if `version` is older than
+    /// every version the history has.
+    #[must_use]
+    pub fn as_of(&self, version: u64) -> Option<&T> {
+        self.index_as_of(version)
+            .map(|index| &self.versions[index].1)
+    }
+
+    /// Go back or forward to version `version`, as with
+    /// [`as_of`][as_of], and return it, or return `None` and stay at the
+    /// current version if `version` is older than every version the
+    /// history has.
+    ///
+    /// [as_of]: #method.as_of
+    pub fn go_to(&mut self, version: u64) -> Option<&T> {
+        self.current = self.index_as_of(version)?;
+        Some(self.current())
+    }
+
+    /// Give the current version a name, replacing any version which
+    /// already had it.
+    pub fn tag<S>(&mut self, name: S)
+    where
+        S: Into<String>,
+    {
+        let version = self.version();
+        self.tags.insert(name.into(), version);
+    }
+
+    /// Remove a name from the version which has it, and return that
+    /// version's number.
+    pub fn untag(&mut self, name: &str) -> Option<u64> {
+        self.tags.remove(name)
+    }
+
+    /// Get the number of the version with a name.
+    #[must_use]
+    pub fn tag_version(&self, name: &str) -> Option<u64> {
+        self.tags.get(name).cloned()
+    }
+
+    /// Get the version of the value with a name.
+    #[must_use]
+    pub fn tagged(&self, name: &str) -> Option<&T> {
+        self.as_of(self.tag_version(name)?)
+    }
+
+    /// Get an iterator over the versions in the history, oldest first,
+    /// with their numbers.
+    #[must_use]
+    pub fn versions(&self) -> Versions<'_, T> {
+        Versions {
+            it: self.versions.iter(),
+        }
+    }
+
+    /// Find the changes between two versions of the value, as
+    /// [`as_of`][as_of] finds them, or return `None` if the history has
+    /// neither.
+    ///
+    /// [as_of]: #method.as_of
+    #[must_use]
+    pub fn changes(&self, from: u64, to: u64) -> Option<Vec<T::Change>>
+    where
+        T: Diff,
+    {
+        Some(self.as_of(from)?.changes(self.as_of(to)?))
+    }
+}
+
+impl<T: Default> Default for Versioned<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+// Iterators
+
+/// An iterator over the versions in a history, with their numbers.
+pub struct Versions<'a, T> {
+    it: vec_deque::Iter<'a, (u64, T)>,
+}
+
+impl<'a, T> Iterator for Versions<'a, T> {
+    type Item = (u64, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.it.next().map(|(version, value)| (*version, value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.it.size_hint()
+    }
+}
+
+impl<'a, T> DoubleEndedIterator for Versions<'a, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.it
+            .next_back()
+            .map(|(version, value)| (*version, value))
+    }
+}
+
+impl<'a, T> ExactSizeIterator for Versions<'a, T> {}
+
+impl<'a, T> FusedIterator for Versions<'a, T> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::diff::MapChange;
+    use crate::memory::MemoryReport;
+    use crate::{OrdMap, Vector};
+
+    #[test]
+    fn commit_after_undo_forgets_redo() {
+        let mut history = Versioned::new(vector![1]);
+        history.modify(|vec| vec.push_back(2));
+        history.tag("two");
+        let three = history.modify(|vec| vec.push_back(3));
+        assert_eq!(Some(&vector![1, 2]), history.undo());
+        assert_eq!(Some(&vector![1]), history.undo());
+        assert_eq!(None, history.undo());
+        assert_eq!(Some(&vector![1, 2]), history.redo());
+        let four = history.modify(|vec| vec.push_back(4));
+        assert_eq!(3, four);
+        assert!(!history.can_redo());
+        assert_eq!(Some(&vector![1, 2]), history.as_of(three));
+        assert_eq!(Some(&vector![1, 2]), history.tagged("two"));
+        assert_eq!(
+            vec![0, 1, 3],
+            history
+                .versions()
+                .map(|(version, _)| version)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn limit_forgets_oldest_versions_and_their_tags() {
+        let mut history = Versioned::with_limit(Vector::new(), 3);
+        history.tag("empty");
+        for i in 0..5 {
+            history.modify(|vec| vec.push_back(i));
+        }
+        assert_eq!(3, history.len());
+        assert_eq!(None, history.as_of(2));
+        assert_eq!(Some(&vector![0, 1, 2]), history.as_of(3));
+        assert_eq!(None, history.tagged("empty"));
+        history.undo();
+        history.undo();
+        history.set_limit(Some(1));
+        // The current version and the ones after it stay.
+        assert_eq!(3, history.len());
+        assert_eq!(Some(&vector![0, 1, 2, 3]), history.redo());
+        assert_eq!(Some(&vector![0, 1, 2, 3, 4]), history.redo());
+    }
+
+    #[test]
+    fn go_to_and_changes() {
+        let mut history = Versioned::new(OrdMap::new());
+        for i in 0..10 {
+            history.modify(|map| {
+                map.insert(i, i);
+            });
+        }
+        assert_eq!(Some(&(0..3).map(|i| (i, i)).collect()), history.go_to(3));
+        assert_eq!(3, history.version());
+        assert_eq!(
+            Some(vec![MapChange::Add(3, 3), MapChange::Add(4, 4)]),
+            history.changes(3, 5)
+        );
+        assert_eq!(Some(&(0..10).map(|i| (i, i)).collect()), history.as_of(100));
+    }
+
+    #[test]
+    fn versions_share_structure() {
+        let mut history = Versioned::new((0..10_000).collect::<Vector<i32>>());
+        for i in 0..100 {
+            history.modify(|vec| {
+                vec.set(i * 100, -1);
+            });
+        }
+        let mut report = MemoryReport::new();
+        for (_, version) in history.versions() {
+            report.add(version);
+        }
+        assert!(report.total_bytes() < history.current().heap_size() * 10);
+    }
+}